@@ -0,0 +1,32 @@
+ALTER TABLE `bookings`
+    ADD `status` enum('pending', 'confirmed', 'cancelled') NOT NULL DEFAULT 'pending',
+    ADD `paying_since` DATETIME NULL;
+
+-- bookings created before payments were introduced have been confirmed instantly
+UPDATE `bookings` SET `status` = 'confirmed';
+
+CREATE TABLE `payments` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `user_id` INT(255) NOT NULL,
+    `offer_id` INT(255) NOT NULL,
+    `provider` VARCHAR(255) NOT NULL,
+    `reference` VARCHAR(255) UNIQUE NOT NULL,
+    `amount` FLOAT(9,2) NOT NULL,
+    `currency` enum('dollar', 'euro') NOT NULL,
+    `status` enum('authorized', 'captured', 'failed', 'refunded') NOT NULL,
+    `created` DATETIME NOT NULL,
+    PRIMARY KEY (`id`),
+    FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`) ON UPDATE CASCADE
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+-- cancelled bookings don't occupy any seats
+DROP VIEW `flights_offers_with_occupancy`;
+CREATE VIEW flights_offers_with_occupancy AS
+SELECT
+    fo.*,
+    COALESCE(sum(bookings.seats), 0) AS occupied,
+    COALESCE((SELECT flights.departure_icao FROM flights WHERE flights.offer_id = fo.id ORDER BY flights.departure_time LIMIT 1), '') AS departure_icao,
+    COALESCE((SELECT flights.arrival_icao FROM flights WHERE flights.offer_id = fo.id ORDER BY flights.arrival_time DESC LIMIT 1), '') AS arrival_icao
+FROM flights_offers AS fo
+LEFT JOIN bookings ON bookings.offer_id = fo.id AND bookings.status <> 'cancelled'
+GROUP BY fo.id
@@ -0,0 +1,28 @@
+ALTER TABLE `flights_offers`
+    ADD `refundable` BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD `cancelled` BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE `refunds` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `user_id` INT(255) NOT NULL,
+    `offer_id` INT(255) NOT NULL,
+    `amount` FLOAT(9,2) NOT NULL,
+    `reason` enum('customer_cancellation', 'flight_cancellation', 'manual') NOT NULL,
+    `status` enum('pending', 'approved', 'rejected') NOT NULL,
+    `created` DATETIME NOT NULL,
+    `updated` DATETIME NOT NULL,
+    PRIMARY KEY (`id`),
+    FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`) ON UPDATE CASCADE
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+-- recreate view to include the new columns of flights_offers
+DROP VIEW `flights_offers_with_occupancy`;
+CREATE VIEW flights_offers_with_occupancy AS
+SELECT
+    fo.*,
+    COALESCE(sum(bookings.seats), 0) AS occupied,
+    COALESCE((SELECT flights.departure_icao FROM flights WHERE flights.offer_id = fo.id ORDER BY flights.departure_time LIMIT 1), '') AS departure_icao,
+    COALESCE((SELECT flights.arrival_icao FROM flights WHERE flights.offer_id = fo.id ORDER BY flights.arrival_time DESC LIMIT 1), '') AS arrival_icao
+FROM flights_offers AS fo
+LEFT JOIN bookings ON bookings.offer_id = fo.id AND bookings.status <> 'cancelled'
+GROUP BY fo.id
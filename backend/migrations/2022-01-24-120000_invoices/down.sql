@@ -0,0 +1,3 @@
+DROP TABLE `invoices_items`;
+DROP TABLE `invoices`;
+ALTER TABLE `bookings` DROP COLUMN `reference`;
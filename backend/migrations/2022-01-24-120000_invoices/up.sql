@@ -0,0 +1,58 @@
+ALTER TABLE `bookings` ADD `reference` CHAR(6) NULL;
+-- existing bookings are numbered, and the numbers are scrambled by an odd factor modulo 32^6,
+-- so every booking gets a distinct reference from the characters of generated references
+UPDATE `bookings` JOIN (
+    SELECT `user_id`, `offer_id`,
+        (ROW_NUMBER() OVER (ORDER BY `user_id`, `offer_id`) * 1103515245) MOD 1073741824 AS `number`
+    FROM `bookings`
+) AS `numbered` USING (`user_id`, `offer_id`)
+SET `reference` = CONCAT(
+    SUBSTRING('ABCDEFGHJKLMNPQRSTUVWXYZ23456789', `number` MOD 32 + 1, 1),
+    SUBSTRING('ABCDEFGHJKLMNPQRSTUVWXYZ23456789', `number` DIV 32 MOD 32 + 1, 1),
+    SUBSTRING('ABCDEFGHJKLMNPQRSTUVWXYZ23456789', `number` DIV 1024 MOD 32 + 1, 1),
+    SUBSTRING('ABCDEFGHJKLMNPQRSTUVWXYZ23456789', `number` DIV 32768 MOD 32 + 1, 1),
+    SUBSTRING('ABCDEFGHJKLMNPQRSTUVWXYZ23456789', `number` DIV 1048576 MOD 32 + 1, 1),
+    SUBSTRING('ABCDEFGHJKLMNPQRSTUVWXYZ23456789', `number` DIV 33554432 MOD 32 + 1, 1)
+);
+ALTER TABLE `bookings` MODIFY `reference` CHAR(6) UNIQUE NOT NULL;
+
+CREATE TABLE `invoices` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `user_id` INT(255) NOT NULL,
+    `offer_id` INT(255) NOT NULL,
+    `booking_reference` CHAR(6) NOT NULL,
+    `issuer` VARCHAR(255) NOT NULL,
+    `buyer_name` VARCHAR(255) NOT NULL,
+    `buyer_email` VARCHAR(255) NOT NULL,
+    `buyer_address` VARCHAR(1024) NULL,
+    `net` FLOAT(9,2) NOT NULL,
+    `vat_rate` FLOAT(5,2) NOT NULL,
+    `vat` FLOAT(9,2) NOT NULL,
+    `total` FLOAT(9,2) NOT NULL,
+    `currency` enum('dollar', 'euro') NOT NULL,
+    `issued` DATETIME NOT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE (`user_id`, `offer_id`),
+    FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`) ON UPDATE CASCADE
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+CREATE TABLE `invoices_items` (
+    `invoice_id` INT(255) NOT NULL,
+    `position` INT(255) NOT NULL,
+    `description` VARCHAR(255) NOT NULL,
+    `quantity` INT(255) NOT NULL,
+    `unit_price` FLOAT(9,2) NOT NULL,
+    `amount` FLOAT(9,2) NOT NULL,
+    PRIMARY KEY (`invoice_id`, `position`),
+    FOREIGN KEY (`invoice_id`) REFERENCES `invoices` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+-- issued invoices must never change
+CREATE TRIGGER `invoices_no_update` BEFORE UPDATE ON `invoices` FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Invoices are immutable';
+CREATE TRIGGER `invoices_no_delete` BEFORE DELETE ON `invoices` FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Invoices are immutable';
+CREATE TRIGGER `invoices_items_no_update` BEFORE UPDATE ON `invoices_items` FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Invoices are immutable';
+CREATE TRIGGER `invoices_items_no_delete` BEFORE DELETE ON `invoices_items` FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Invoices are immutable';
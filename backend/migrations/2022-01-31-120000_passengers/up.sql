@@ -0,0 +1,9 @@
+CREATE TABLE `passengers` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `user_id` INT(255) NOT NULL,
+    `offer_id` INT(255) NOT NULL,
+    `firstname` VARCHAR(255) NOT NULL,
+    `lastname` VARCHAR(255) NOT NULL,
+    PRIMARY KEY (`id`),
+    FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`) ON UPDATE CASCADE
+) ENGINE=InnoDB ENCRYPTED=YES;
@@ -0,0 +1,7 @@
+CREATE TABLE `calendar_subscriptions` (
+    `user_id` INT(255) NOT NULL,
+    `token_hash` CHAR(64) UNIQUE NOT NULL,
+    `created` DATETIME NOT NULL,
+    PRIMARY KEY (`user_id`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
@@ -0,0 +1,12 @@
+CREATE TABLE `check_ins` (
+    `passenger_id` INT(255) NOT NULL,
+    `flight_id` INT(255) NOT NULL,
+    `seat` VARCHAR(4),
+    `sequence` INT(255) NOT NULL,
+    `checked_in` DATETIME NOT NULL,
+    PRIMARY KEY (`passenger_id`, `flight_id`),
+    UNIQUE KEY (`flight_id`, `seat`),
+    UNIQUE KEY (`flight_id`, `sequence`),
+    FOREIGN KEY (`passenger_id`) REFERENCES `passengers` (`id`),
+    FOREIGN KEY (`flight_id`) REFERENCES `flights` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
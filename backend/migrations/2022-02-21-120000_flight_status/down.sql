@@ -0,0 +1,8 @@
+ALTER TABLE `bookings`
+    DROP COLUMN `disrupted`;
+
+ALTER TABLE `flights`
+    DROP COLUMN `status`,
+    DROP COLUMN `estimated_departure_time`,
+    DROP COLUMN `estimated_arrival_time`,
+    DROP COLUMN `diverted_icao`;
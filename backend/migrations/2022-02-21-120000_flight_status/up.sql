@@ -0,0 +1,9 @@
+ALTER TABLE `flights`
+    ADD `status` enum('scheduled', 'delayed', 'boarding', 'departed', 'arrived', 'cancelled', 'diverted') NOT NULL DEFAULT 'scheduled',
+    ADD `estimated_departure_time` DATETIME,
+    ADD `estimated_arrival_time` DATETIME,
+    ADD `diverted_icao` VARCHAR(4);
+
+-- bookings of a cancelled flight are flagged, until they are rebooked or refunded
+ALTER TABLE `bookings`
+    ADD `disrupted` BOOLEAN NOT NULL DEFAULT FALSE;
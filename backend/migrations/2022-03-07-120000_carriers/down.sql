@@ -0,0 +1,9 @@
+DROP TABLE `flights_codeshares`;
+
+ALTER TABLE `flights` DROP FOREIGN KEY `flights_ibfk_2`;
+ALTER TABLE `flights`
+    DROP INDEX `flight_number`,
+    DROP COLUMN `carrier_id`,
+    DROP COLUMN `flight_number`;
+
+DROP TABLE `carriers`;
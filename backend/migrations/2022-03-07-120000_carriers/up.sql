@@ -0,0 +1,25 @@
+CREATE TABLE `carriers` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `iata` CHAR(2) UNIQUE NOT NULL,
+    `icao` CHAR(3) UNIQUE NOT NULL,
+    `name` VARCHAR(255) NOT NULL,
+    PRIMARY KEY (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+-- the flight number consists of the IATA designator of the carrier and the number, like `LH400`
+ALTER TABLE `flights`
+    ADD `carrier_id` INT(255) NULL,
+    ADD `flight_number` VARCHAR(7) NULL,
+    ADD FOREIGN KEY (`carrier_id`) REFERENCES `carriers` (`id`),
+    ADD INDEX (`flight_number`);
+
+-- marketing flight numbers of codeshare partners
+CREATE TABLE `flights_codeshares` (
+    `flight_id` INT(255) NOT NULL,
+    `carrier_id` INT(255) NOT NULL,
+    `flight_number` VARCHAR(7) NOT NULL,
+    PRIMARY KEY (`flight_id`, `flight_number`),
+    INDEX (`flight_number`),
+    FOREIGN KEY (`flight_id`) REFERENCES `flights` (`id`),
+    FOREIGN KEY (`carrier_id`) REFERENCES `carriers` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
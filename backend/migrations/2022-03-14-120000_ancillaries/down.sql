@@ -0,0 +1,2 @@
+DROP TABLE `bookings_ancillaries`;
+DROP TABLE `ancillaries`;
@@ -0,0 +1,22 @@
+-- additional services, that are sold along with the seats of an offer
+CREATE TABLE `ancillaries` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `offer_id` INT(255) NOT NULL,
+    `kind` enum('checked_bag', 'extra_legroom', 'meal', 'priority_boarding') NOT NULL,
+    `description` VARCHAR(255) NOT NULL,
+    `price` FLOAT(9,2) NOT NULL,
+    -- number of units for sale, unlimited if NULL
+    `inventory` INT(255) NULL,
+    PRIMARY KEY (`id`),
+    FOREIGN KEY (`offer_id`) REFERENCES `flights_offers` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+CREATE TABLE `bookings_ancillaries` (
+    `user_id` INT(255) NOT NULL,
+    `offer_id` INT(255) NOT NULL,
+    `ancillary_id` INT(255) NOT NULL,
+    `quantity` INT(255) NOT NULL,
+    PRIMARY KEY (`user_id`, `offer_id`, `ancillary_id`),
+    FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`) ON UPDATE CASCADE,
+    FOREIGN KEY (`ancillary_id`) REFERENCES `ancillaries` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
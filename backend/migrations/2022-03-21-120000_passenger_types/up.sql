@@ -0,0 +1,25 @@
+-- prices of children and infants, if they differ from the regular price
+ALTER TABLE `flights_offers`
+    ADD `child_price` FLOAT(9,2) NULL,
+    ADD `infant_price` FLOAT(9,2) NULL;
+
+-- seats include the seats of children, infants travel on the lap of an adult
+ALTER TABLE `bookings`
+    ADD `children` INT(255) NOT NULL DEFAULT 0,
+    ADD `infants` INT(255) NOT NULL DEFAULT 0;
+
+-- passengers added before passenger types were introduced are treated as adults
+ALTER TABLE `passengers`
+    ADD `birthday` DATE NULL;
+
+-- recreate view to include the new columns of flights_offers
+DROP VIEW `flights_offers_with_occupancy`;
+CREATE VIEW flights_offers_with_occupancy AS
+SELECT
+    fo.*,
+    COALESCE(sum(bookings.seats), 0) AS occupied,
+    COALESCE((SELECT flights.departure_icao FROM flights WHERE flights.offer_id = fo.id ORDER BY flights.departure_time LIMIT 1), '') AS departure_icao,
+    COALESCE((SELECT flights.arrival_icao FROM flights WHERE flights.offer_id = fo.id ORDER BY flights.arrival_time DESC LIMIT 1), '') AS arrival_icao
+FROM flights_offers AS fo
+LEFT JOIN bookings ON bookings.offer_id = fo.id AND bookings.status <> 'cancelled'
+GROUP BY fo.id
@@ -0,0 +1 @@
+DROP TABLE `travel_documents`;
@@ -0,0 +1,12 @@
+-- the document number is encrypted by the application, see `DOCUMENT_ENCRYPTION_KEY`
+CREATE TABLE `travel_documents` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `passenger_id` INT(255) NOT NULL,
+    `document_type` enum('passport', 'identity_card', 'visa') NOT NULL,
+    `number` VARCHAR(255) NOT NULL,
+    `issuing_country` CHAR(3) NOT NULL,
+    `nationality` CHAR(3) NOT NULL,
+    `expiry` DATE NOT NULL,
+    PRIMARY KEY (`id`),
+    FOREIGN KEY (`passenger_id`) REFERENCES `passengers` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
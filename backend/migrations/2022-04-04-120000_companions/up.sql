@@ -0,0 +1,9 @@
+CREATE TABLE `companions` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `user_id` INT(255) NOT NULL,
+    `firstname` VARCHAR(255) NOT NULL,
+    `lastname` VARCHAR(255) NOT NULL,
+    `birthday` DATE NOT NULL,
+    PRIMARY KEY (`id`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
@@ -0,0 +1,24 @@
+-- ledger of loyalty points, every change of a balance is a new entry
+CREATE TABLE `loyalty_transactions` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `user_id` INT(255) NOT NULL,
+    `offer_id` INT(255) NOT NULL,
+    -- set for points accrued by a completed flight
+    `flight_id` INT(255) NULL,
+    `kind` enum('accrual', 'redemption', 'reinstatement') NOT NULL,
+    -- positive for credited points, negative for redeemed points
+    `points` INT(255) NOT NULL,
+    `description` VARCHAR(255) NOT NULL,
+    `created` DATETIME NOT NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE (`user_id`, `flight_id`),
+    INDEX (`user_id`, `created`),
+    FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`) ON UPDATE CASCADE,
+    FOREIGN KEY (`flight_id`) REFERENCES `flights` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+-- the ledger is append-only, corrections are booked as new entries
+CREATE TRIGGER `loyalty_transactions_no_update` BEFORE UPDATE ON `loyalty_transactions` FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Loyalty transactions are append-only';
+CREATE TRIGGER `loyalty_transactions_no_delete` BEFORE DELETE ON `loyalty_transactions` FOR EACH ROW
+    SIGNAL SQLSTATE '45000' SET MESSAGE_TEXT = 'Loyalty transactions are append-only';
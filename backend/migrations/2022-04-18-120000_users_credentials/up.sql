@@ -0,0 +1,10 @@
+-- email/password login, as an alternative to OAuth
+CREATE TABLE `users_credentials` (
+    `user_id` INT(255) NOT NULL,
+    -- Argon2id hash in PHC string format, including salt and parameters
+    `password_hash` VARCHAR(255) NOT NULL,
+    `failed_attempts` INT(255) NOT NULL DEFAULT 0,
+    `locked_until` DATETIME NULL,
+    PRIMARY KEY (`user_id`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
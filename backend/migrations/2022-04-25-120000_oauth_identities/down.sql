@@ -0,0 +1,13 @@
+CREATE TABLE `users_oauth_github` (
+    `user_id` INT(255) NOT NULL UNIQUE,
+    `github_id` INT(255) NOT NULL UNIQUE,
+    PRIMARY KEY (`github_id`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+-- identities of other providers are lost
+INSERT INTO `users_oauth_github` (`user_id`, `github_id`)
+    SELECT `user_id`, CAST(`subject` AS SIGNED) FROM `users_oauth_identities`
+    WHERE `provider` = 'github';
+
+DROP TABLE `users_oauth_identities`;
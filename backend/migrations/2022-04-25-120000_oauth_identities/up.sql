@@ -0,0 +1,13 @@
+-- accounts of OAuth and OpenID Connect providers, identified by the subject the provider assigned
+CREATE TABLE `users_oauth_identities` (
+    `provider` VARCHAR(64) NOT NULL,
+    `subject` VARCHAR(255) NOT NULL,
+    `user_id` INT(255) NOT NULL,
+    PRIMARY KEY (`provider`, `subject`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+INSERT INTO `users_oauth_identities` (`provider`, `subject`, `user_id`)
+    SELECT 'github', CAST(`github_id` AS CHAR), `user_id` FROM `users_oauth_github`;
+
+DROP TABLE `users_oauth_github`;
@@ -0,0 +1,2 @@
+DROP TABLE `users_recovery_codes`;
+DROP TABLE `users_totp`;
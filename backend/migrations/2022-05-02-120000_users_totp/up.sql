@@ -0,0 +1,24 @@
+-- time-based one-time passwords (RFC 6238) as second factor
+CREATE TABLE `users_totp` (
+    `user_id` INT(255) NOT NULL,
+    -- shared secret, encrypted with the DOCUMENT_ENCRYPTION_KEY
+    `secret` VARCHAR(255) NOT NULL,
+    -- set once the user confirmed the enrolment with a valid code
+    `enabled` BOOLEAN NOT NULL DEFAULT FALSE,
+    -- time step of the last accepted code, so a code can't be used twice
+    `last_used_step` BIGINT NULL,
+    -- wrong second factors in a row, counted per user across logins
+    `failed_attempts` INT NOT NULL DEFAULT 0,
+    `locked_until` DATETIME NULL,
+    `created` DATETIME NOT NULL,
+    PRIMARY KEY (`user_id`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
+
+-- single use codes to log in without the authenticator, stored as SHA-256 hash
+CREATE TABLE `users_recovery_codes` (
+    `user_id` INT(255) NOT NULL,
+    `code_hash` CHAR(64) NOT NULL,
+    PRIMARY KEY (`user_id`, `code_hash`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
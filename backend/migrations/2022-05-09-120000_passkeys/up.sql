@@ -0,0 +1,16 @@
+-- WebAuthn credentials, to log in with passkeys
+CREATE TABLE `passkeys` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `user_id` INT(255) NOT NULL,
+    -- base64url encoded id, the authenticator assigned to the credential
+    `credential_id` VARCHAR(512) CHARACTER SET ascii NOT NULL,
+    -- base64 encoded public key as uncompressed SEC1 point
+    `public_key` VARCHAR(255) NOT NULL,
+    `sign_count` BIGINT NOT NULL DEFAULT 0,
+    `name` VARCHAR(255) NOT NULL,
+    `created` DATETIME NOT NULL,
+    `last_used` DATETIME NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE (`credential_id`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
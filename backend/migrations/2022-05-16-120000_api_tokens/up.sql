@@ -0,0 +1,16 @@
+-- personal API tokens for machine clients, sent as `Authorization: Bearer <token>`
+CREATE TABLE `api_tokens` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `user_id` INT(255) NOT NULL,
+    `name` VARCHAR(255) NOT NULL,
+    -- hex encoded SHA-256 hash, the token itself is only shown once
+    `token_hash` CHAR(64) NOT NULL,
+    -- comma separated scopes, like `read,write`
+    `scopes` VARCHAR(255) NOT NULL,
+    `created` DATETIME NOT NULL,
+    `expires` DATETIME NOT NULL,
+    `last_used` DATETIME NULL,
+    PRIMARY KEY (`id`),
+    UNIQUE (`token_hash`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
@@ -0,0 +1,3 @@
+DROP TABLE `users_email_verifications`;
+
+ALTER TABLE `users` DROP COLUMN `verified`;
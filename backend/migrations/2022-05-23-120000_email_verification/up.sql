@@ -0,0 +1,15 @@
+-- email addresses are unverified until the user follows the link sent to the address. Users, who
+-- registered before, keep being able to book.
+ALTER TABLE `users`
+    ADD `verified` BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE `users` SET `verified` = TRUE;
+
+-- the nonce of the last verification link sent to a user, deleted as soon as the link is used
+CREATE TABLE `users_email_verifications` (
+    `user_id` INT(255) NOT NULL,
+    `email` VARCHAR(255) NOT NULL,
+    `nonce` CHAR(32) NOT NULL,
+    `created` DATETIME NOT NULL,
+    PRIMARY KEY (`user_id`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
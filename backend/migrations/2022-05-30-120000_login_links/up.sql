@@ -0,0 +1,8 @@
+-- the nonce of the last login link sent to a user, deleted as soon as the link is used
+CREATE TABLE `users_login_links` (
+    `user_id` INT(255) NOT NULL,
+    `nonce` CHAR(32) NOT NULL,
+    `created` DATETIME NOT NULL,
+    PRIMARY KEY (`user_id`),
+    FOREIGN KEY (`user_id`) REFERENCES `users` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
@@ -0,0 +1,10 @@
+ALTER TABLE `loyalty_transactions` DROP FOREIGN KEY `loyalty_transactions_offer`;
+ALTER TABLE `loyalty_transactions`
+    ADD CONSTRAINT `loyalty_transactions_ibfk_1` FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`) ON UPDATE CASCADE;
+
+ALTER TABLE `invoices` DROP FOREIGN KEY `invoices_booking`;
+ALTER TABLE `invoices`
+    DROP INDEX `invoices_booking_reference`,
+    DROP INDEX `invoices_user_offer`,
+    ADD UNIQUE `user_id` (`user_id`, `offer_id`),
+    ADD CONSTRAINT `invoices_ibfk_1` FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`) ON UPDATE CASCADE;
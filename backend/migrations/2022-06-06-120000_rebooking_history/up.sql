@@ -0,0 +1,12 @@
+-- invoices and the loyalty ledger are records of the past, they must not follow a booking, that is
+-- moved to another offer. Invoices stay attached to the booking by its reference instead.
+ALTER TABLE `invoices` DROP FOREIGN KEY `invoices_ibfk_1`;
+ALTER TABLE `invoices`
+    DROP INDEX `user_id`,
+    ADD INDEX `invoices_user_offer` (`user_id`, `offer_id`),
+    ADD CONSTRAINT `invoices_booking_reference` UNIQUE (`booking_reference`),
+    ADD CONSTRAINT `invoices_booking` FOREIGN KEY (`booking_reference`) REFERENCES `bookings` (`reference`);
+
+ALTER TABLE `loyalty_transactions` DROP FOREIGN KEY `loyalty_transactions_ibfk_1`;
+ALTER TABLE `loyalty_transactions`
+    ADD CONSTRAINT `loyalty_transactions_offer` FOREIGN KEY (`offer_id`) REFERENCES `flights_offers` (`id`);
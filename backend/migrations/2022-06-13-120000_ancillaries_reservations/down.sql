@@ -0,0 +1 @@
+DROP TABLE `ancillaries_reservations`;
@@ -0,0 +1,16 @@
+-- ancillaries held for a purchase, until the payment of the purchase has been captured. Expired
+-- reservations don't count against the inventory anymore.
+CREATE TABLE `ancillaries_reservations` (
+    `id` INT(255) NOT NULL AUTO_INCREMENT,
+    `reference` CHAR(32) NOT NULL,
+    `user_id` INT(255) NOT NULL,
+    `offer_id` INT(255) NOT NULL,
+    `ancillary_id` INT(255) NOT NULL,
+    `quantity` INT(255) NOT NULL,
+    `expires` DATETIME NOT NULL,
+    PRIMARY KEY (`id`),
+    INDEX (`reference`),
+    INDEX (`ancillary_id`, `expires`),
+    FOREIGN KEY (`user_id`, `offer_id`) REFERENCES `bookings` (`user_id`, `offer_id`),
+    FOREIGN KEY (`ancillary_id`) REFERENCES `ancillaries` (`id`)
+) ENGINE=InnoDB ENCRYPTED=YES;
@@ -0,0 +1,4 @@
+ALTER TABLE `invoices`
+    DROP INDEX `invoices_supplement`,
+    ADD CONSTRAINT `invoices_booking_reference` UNIQUE (`booking_reference`);
+ALTER TABLE `invoices` DROP COLUMN `supplement`;
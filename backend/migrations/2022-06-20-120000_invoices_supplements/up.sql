@@ -0,0 +1,6 @@
+-- ancillaries bought after a booking has been paid get a supplementary invoice each, numbered per
+-- booking from 1 on, while the invoice of the booking itself has 0
+ALTER TABLE `invoices` ADD `supplement` INT(255) NOT NULL DEFAULT 0 AFTER `booking_reference`;
+ALTER TABLE `invoices`
+    DROP INDEX `invoices_booking_reference`,
+    ADD CONSTRAINT `invoices_supplement` UNIQUE (`booking_reference`, `supplement`);
@@ -0,0 +1,203 @@
+//! Encoder for the mandatory items of an IATA Bar Coded Boarding Pass (BCBP, Resolution 792) with
+//! a single flight leg.
+
+use chrono::{Datelike, NaiveDate};
+
+/// Data printed on a boarding pass
+pub struct BoardingPass {
+    pub firstname: String,
+    pub lastname: String,
+    /// Booking reference of the operating carrier
+    pub pnr: String,
+    /// Airport of departure
+    pub from: String,
+    /// Airport of arrival
+    pub to: String,
+    /// Designator of the operating carrier
+    pub carrier: String,
+    /// Flight number without the carrier designator, consisting of up to four digits and an
+    /// optional suffix
+    pub flight_number: String,
+    pub date: NaiveDate,
+    pub seat: Option<String>,
+    /// Check-in sequence number
+    pub sequence: Option<i32>,
+}
+
+/// Pad or truncate a value to a fixed width
+fn field(value: &str, width: usize) -> String {
+    format!("{:<width$}", value, width = width)
+        .chars()
+        .take(width)
+        .collect()
+}
+
+/// Format a flight number as four digits followed by the suffix
+fn flight_number_field(flight_number: &str) -> String {
+    let digits: String = flight_number
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    let suffix = &flight_number[digits.len()..];
+    format!("{:0>4}{}", field(&digits, 4).trim_end(), field(suffix, 1))
+}
+
+/// Transliteration of an uppercase letter with diacritics, following the recommendation of ICAO
+/// Doc 9303 which Resolution 792 refers to for names
+fn transliterate(c: char) -> Option<&'static str> {
+    let latin = match c {
+        'Ä' | 'Æ' => "AE",
+        'Å' => "AA",
+        'Ö' | 'Ø' | 'Œ' => "OE",
+        'Ü' => "UE",
+        'ẞ' => "SS",
+        'Þ' => "TH",
+        'Ĳ' => "IJ",
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+        'Ĥ' | 'Ħ' => "H",
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+        'Ĵ' => "J",
+        'Ķ' => "K",
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' | 'Ŋ' => "N",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+        'Ŕ' | 'Ŗ' | 'Ř' => "R",
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+        'Ţ' | 'Ť' | 'Ŧ' => "T",
+        'Ù' | 'Ú' | 'Û' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'Ŵ' => "W",
+        'Ý' | 'Ÿ' | 'Ŷ' => "Y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        _ => return None,
+    };
+    Some(latin)
+}
+
+/// Reduce a name to the characters allowed in the name field, transliterating letters with
+/// diacritics and dropping any other character
+fn sanitize_name(name: &str) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_uppercase) {
+        if c.is_ascii_uppercase() || c == ' ' || c == '-' {
+            sanitized.push(c);
+        } else if let Some(latin) = transliterate(c) {
+            sanitized.push_str(latin);
+        }
+    }
+    sanitized
+}
+
+/// SkyRocket stores airports by their ICAO code, while boarding passes use three letter codes. The
+/// last three letters of an ICAO code equal the IATA code for airports in the contiguous United
+/// States and Canada, for every other airport they are merely an identifier.
+fn airport_code(icao: &str) -> String {
+    let start = icao.len().saturating_sub(3);
+    field(&icao[start..], 3)
+}
+
+/// Encode a boarding pass with the mandatory items of the BCBP format `M`. The result always
+/// has a length of 60 characters.
+pub fn encode(pass: &BoardingPass) -> String {
+    let name = format!(
+        "{}/{}",
+        sanitize_name(&pass.lastname),
+        sanitize_name(&pass.firstname)
+    );
+    let seat = pass.seat.as_deref().unwrap_or("");
+    let sequence = pass
+        .sequence
+        .map(|s| format!("{:04}", s))
+        .unwrap_or_default();
+    // 0: passenger not checked in, 1: passenger checked in
+    let status = if pass.sequence.is_some() { '1' } else { '0' };
+
+    format!(
+        "M1{}E{}{}{}{}{}{:03}Y{}{}{}00",
+        field(&name, 20),
+        field(&pass.pnr, 7),
+        airport_code(&pass.from),
+        airport_code(&pass.to),
+        field(&pass.carrier, 3),
+        flight_number_field(&pass.flight_number),
+        pass.date.ordinal(),
+        field(seat, 4),
+        field(&sequence, 5),
+        status,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boarding_pass() -> BoardingPass {
+        BoardingPass {
+            firstname: "John".into(),
+            lastname: "Smith".into(),
+            pnr: "ABC123".into(),
+            from: "KJFK".into(),
+            to: "KLAX".into(),
+            carrier: "SR".into(),
+            flight_number: "42".into(),
+            date: NaiveDate::from_ymd(2022, 2, 14),
+            seat: None,
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn test_encode_not_checked_in() {
+        assert_eq!(
+            "M1SMITH/JOHN         EABC123 JFKLAXSR 0042 045Y         000",
+            encode(&boarding_pass())
+        );
+    }
+
+    #[test]
+    fn test_encode_checked_in() {
+        let mut pass = boarding_pass();
+        pass.seat = Some("012C".into());
+        pass.sequence = Some(7);
+
+        assert_eq!(
+            "M1SMITH/JOHN         EABC123 JFKLAXSR 0042 045Y012C0007 100",
+            encode(&pass)
+        );
+    }
+
+    #[test]
+    fn test_encode_flight_number_with_suffix() {
+        let mut pass = boarding_pass();
+        pass.carrier = "LH".into();
+        pass.flight_number = "400A".into();
+
+        let encoded = encode(&pass);
+        assert_eq!(60, encoded.len());
+        assert_eq!("LH 0400A", &encoded[36..44]);
+    }
+
+    #[test]
+    fn test_encode_long_name() {
+        let mut pass = boarding_pass();
+        pass.firstname = "Maximilian Alexander".into();
+        pass.lastname = "Müller-Lüdenscheidt".into();
+
+        let encoded = encode(&pass);
+        assert_eq!(60, encoded.len());
+        assert_eq!("MUELLER-LUEDENSCHEID", &encoded[2..22]);
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!("STRASSE", sanitize_name("Straße"));
+        assert_eq!("AEBELOE", sanitize_name("Æbelø"));
+        assert_eq!("AASE OEZTUERK", sanitize_name("Åse Öztürk"));
+        assert_eq!("FRANCOIS LUKASZ", sanitize_name("François Łukasz"));
+        assert_eq!("OCONNOR-SMITH", sanitize_name("O'Connor-Smith"));
+        assert_eq!("", sanitize_name("李"));
+    }
+}
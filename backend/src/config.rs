@@ -1,13 +1,58 @@
 use dotenv::dotenv;
 use once_cell::sync::Lazy;
 use std::env;
+use std::net::IpAddr;
 
 pub static CONFIG: Lazy<Config> = Lazy::new(Config::load);
 
+/// An OpenID Connect provider, configured with `OAUTH_<NAME>_CLIENT_ID`,
+/// `OAUTH_<NAME>_CLIENT_SECRET` and `OAUTH_<NAME>_DISCOVERY_URL`
+pub struct OidcProviderConfig {
+    pub name: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub discovery_url: String,
+}
+
+/// Base URL of the GitHub login, if `OAUTH_GITHUB_URL` isn't set
+const GITHUB_URL: &str = "https://github.com";
+/// Base URL of the GitHub API, if `OAUTH_GITHUB_API_URL` isn't set
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// The GitHub OAuth app, configured with `OAUTH_GITHUB_CLIENT_ID` and
+/// `OAUTH_GITHUB_CLIENT_SECRET`. It is managed by rocket instead of being read from `CONFIG`, so
+/// tests are able to point it to a fake server.
+#[derive(Clone, Debug)]
+pub struct GitHubConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Base URL of the login, like `https://github.com`
+    pub url: String,
+    /// Base URL of the API, like `https://api.github.com`
+    pub api_url: String,
+}
+
 pub struct Config {
-    pub oauth_github_client_id: Option<String>,
-    pub oauth_github_client_secret: Option<String>,
+    pub github: Option<GitHubConfig>,
+    pub oauth_providers: Vec<OidcProviderConfig>,
+    pub oauth_redirect_url: Option<String>,
     pub redis_url: Option<String>,
+    pub payment_provider: Option<String>,
+    pub payment_webhook_secret: Option<String>,
+    pub invoice_issuer: Option<String>,
+    pub invoice_vat_rate: Option<f32>,
+    pub checkin_opens_hours: Option<i64>,
+    pub checkin_closes_minutes: Option<i64>,
+    pub document_encryption_key: Option<String>,
+    pub webauthn_rp_id: Option<String>,
+    pub webauthn_origin: Option<String>,
+    pub webauthn_decoy_secret: Option<String>,
+    pub frontend_url: Option<String>,
+    pub mail_transport: Option<String>,
+    pub mail_token_secret: Option<String>,
+    pub mail_from: Option<String>,
+    pub smtp_url: Option<String>,
+    pub trusted_proxies: Vec<IpAddr>,
 }
 
 fn read_opt_from_env(key: &str) -> Option<String> {
@@ -17,12 +62,71 @@ fn read_opt_from_env(key: &str) -> Option<String> {
     }
 }
 
+/// Read the GitHub OAuth app, which is disabled without client id or secret
+fn read_github() -> Option<GitHubConfig> {
+    Some(GitHubConfig {
+        client_id: read_opt_from_env("OAUTH_GITHUB_CLIENT_ID")?,
+        client_secret: read_opt_from_env("OAUTH_GITHUB_CLIENT_SECRET")?,
+        url: read_opt_from_env("OAUTH_GITHUB_URL").unwrap_or_else(|| GITHUB_URL.into()),
+        api_url: read_opt_from_env("OAUTH_GITHUB_API_URL").unwrap_or_else(|| GITHUB_API_URL.into()),
+    })
+}
+
+/// Read the OpenID Connect providers listed in `OAUTH_PROVIDERS`, separated by commas. Providers
+/// with incomplete settings are skipped.
+fn read_oidc_providers() -> Vec<OidcProviderConfig> {
+    read_opt_from_env("OAUTH_PROVIDERS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| {
+            let key = |setting: &str| format!("OAUTH_{}_{}", name.to_uppercase(), setting);
+            Some(OidcProviderConfig {
+                client_id: read_opt_from_env(&key("CLIENT_ID"))?,
+                client_secret: read_opt_from_env(&key("CLIENT_SECRET"))?,
+                discovery_url: read_opt_from_env(&key("DISCOVERY_URL"))?,
+                name,
+            })
+        })
+        .collect()
+}
+
+/// Read the addresses of the reverse proxies listed in `TRUSTED_PROXIES`, separated by commas.
+/// Invalid addresses are skipped.
+fn read_trusted_proxies() -> Vec<IpAddr> {
+    read_opt_from_env("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|ip| ip.trim().parse().ok())
+        .collect()
+}
+
 impl Config {
     pub fn load() -> Self {
         Self {
-            oauth_github_client_id: read_opt_from_env("OAUTH_GITHUB_CLIENT_ID"),
-            oauth_github_client_secret: read_opt_from_env("OAUTH_GITHUB_CLIENT_SECRET"),
+            github: read_github(),
+            oauth_providers: read_oidc_providers(),
+            oauth_redirect_url: read_opt_from_env("OAUTH_REDIRECT_URL"),
             redis_url: read_opt_from_env("REDIS_URL"),
+            payment_provider: read_opt_from_env("PAYMENT_PROVIDER"),
+            payment_webhook_secret: read_opt_from_env("PAYMENT_WEBHOOK_SECRET"),
+            invoice_issuer: read_opt_from_env("INVOICE_ISSUER"),
+            invoice_vat_rate: read_opt_from_env("INVOICE_VAT_RATE").and_then(|v| v.parse().ok()),
+            checkin_opens_hours: read_opt_from_env("CHECKIN_OPENS_HOURS")
+                .and_then(|v| v.parse().ok()),
+            checkin_closes_minutes: read_opt_from_env("CHECKIN_CLOSES_MINUTES")
+                .and_then(|v| v.parse().ok()),
+            document_encryption_key: read_opt_from_env("DOCUMENT_ENCRYPTION_KEY"),
+            webauthn_rp_id: read_opt_from_env("WEBAUTHN_RP_ID"),
+            webauthn_origin: read_opt_from_env("WEBAUTHN_ORIGIN"),
+            webauthn_decoy_secret: read_opt_from_env("WEBAUTHN_DECOY_SECRET"),
+            frontend_url: read_opt_from_env("FRONTEND_URL"),
+            mail_transport: read_opt_from_env("MAIL_TRANSPORT"),
+            mail_token_secret: read_opt_from_env("MAIL_TOKEN_SECRET"),
+            mail_from: read_opt_from_env("MAIL_FROM"),
+            smtp_url: read_opt_from_env("SMTP_URL"),
+            trusted_proxies: read_trusted_proxies(),
         }
     }
 }
@@ -0,0 +1,114 @@
+//! Application level encryption of sensitive values, like the numbers of travel documents. Values
+//! are encrypted with AES-256-GCM using the key configured with `DOCUMENT_ENCRYPTION_KEY`, a
+//! base64 encoded sequence of 32 random bytes.
+
+use crate::CONFIG;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use once_cell::sync::Lazy;
+use rand::Rng;
+
+const KEY_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+
+static CIPHER: Lazy<Option<Aes256Gcm>> = Lazy::new(|| {
+    CONFIG.document_encryption_key.as_deref().map(|key| {
+        cipher_from_key(key).unwrap_or_else(|e| {
+            eprintln! { "Invalid DOCUMENT_ENCRYPTION_KEY: {}", e };
+            std::process::exit(1);
+        })
+    })
+});
+
+fn cipher_from_key(key: &str) -> Result<Aes256Gcm, String> {
+    let key = base64::decode(key).map_err(|e| e.to_string())?;
+    if key.len() != KEY_LENGTH {
+        return Err(format!("Key must be {} bytes long", KEY_LENGTH));
+    }
+    Ok(Aes256Gcm::new(Key::from_slice(&key)))
+}
+
+/// Encrypt a value with a random nonce. The nonce is prepended to the ciphertext, the result is
+/// base64 encoded.
+fn encrypt_with(cipher: &Aes256Gcm, plaintext: &str) -> Result<String, String> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill(&mut nonce);
+
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| "Encryption failed".to_string())?;
+    Ok(base64::encode([&nonce[..], &ciphertext[..]].concat()))
+}
+
+fn decrypt_with(cipher: &Aes256Gcm, encrypted: &str) -> Result<String, String> {
+    let data = base64::decode(encrypted).map_err(|e| e.to_string())?;
+    if data.len() < NONCE_LENGTH {
+        return Err("Encrypted value is too short".into());
+    }
+
+    let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Decryption failed".to_string())?;
+    String::from_utf8(plaintext).map_err(|e| e.to_string())
+}
+
+fn cipher() -> Result<&'static Aes256Gcm, String> {
+    CIPHER
+        .as_ref()
+        .ok_or_else(|| "DOCUMENT_ENCRYPTION_KEY is not configured".to_string())
+}
+
+pub fn encrypt(plaintext: &str) -> Result<String, String> {
+    encrypt_with(cipher()?, plaintext)
+}
+
+pub fn decrypt(encrypted: &str) -> Result<String, String> {
+    decrypt_with(cipher()?, encrypted)
+}
+
+/// Check the configured key at startup, instead of failing on the first encryption
+pub fn init() {
+    Lazy::force(&CIPHER);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let cipher = cipher_from_key(KEY).unwrap();
+        let encrypted = encrypt_with(&cipher, "C01X00T47").unwrap();
+
+        assert!(!encrypted.contains("C01X00T47"));
+        assert_eq!("C01X00T47", decrypt_with(&cipher, &encrypted).unwrap());
+    }
+
+    #[test]
+    fn test_encrypt_uses_random_nonce() {
+        let cipher = cipher_from_key(KEY).unwrap();
+        assert_ne!(
+            encrypt_with(&cipher, "C01X00T47").unwrap(),
+            encrypt_with(&cipher, "C01X00T47").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decrypt_tampered_value() {
+        let cipher = cipher_from_key(KEY).unwrap();
+        let mut data = base64::decode(encrypt_with(&cipher, "C01X00T47").unwrap()).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+
+        assert!(decrypt_with(&cipher, &base64::encode(data)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_key() {
+        assert!(cipher_from_key("c2hvcnQ=").is_err());
+        assert!(cipher_from_key("not base64!").is_err());
+    }
+}
@@ -0,0 +1,72 @@
+//! Records for the tests of the database models, which need the database of the `.env` file.
+//! Users and routes are random, so tests running in parallel don't see each other's records.
+
+use crate::db::models::{Currency, Gender, NewUser, User};
+use crate::db::schema::{flights, flights_offers};
+use crate::db::Db;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rand::Rng;
+
+no_arg_sql_function!(
+    last_insert_id,
+    diesel::sql_types::Unsigned<diesel::sql_types::BigInt>
+);
+
+/// Connection to the database, after the migrations have been run
+pub async fn db() -> Db {
+    let rocket = crate::rocket().ignite().await.expect("rocket");
+    Db::get_one(&rocket).await.expect("database connection")
+}
+
+/// A user with a random email address
+pub async fn user(db: &Db) -> User {
+    NewUser {
+        firstname: "Ada".into(),
+        lastname: "Lovelace".into(),
+        email: format!("test-{}@example.com", rand::random::<u32>()),
+        birthday: NaiveDate::from_ymd(1990, 12, 10),
+        gender: Gender::Female,
+    }
+    .save_and_return(db)
+    .await
+    .expect("user")
+    .into_inner()
+}
+
+/// A random route, as ICAO codes of the departure and arrival airport
+pub fn route() -> (String, String) {
+    let mut rng = rand::thread_rng();
+    let mut icao = || (0..4).map(|_| rng.gen_range(b'A'..=b'Z') as char).collect();
+    (icao(), icao())
+}
+
+/// An offer of 100 € per seat with a single flight on the route
+pub async fn offer(db: &Db, route: &(String, String), departure: NaiveDateTime, seats: i32) -> i32 {
+    let (departure_icao, arrival_icao) = route.clone();
+    db.run(move |conn| {
+        conn.transaction::<_, DieselError, _>(|| {
+            diesel::insert_into(flights_offers::table)
+                .values((
+                    flights_offers::seats.eq(seats),
+                    flights_offers::price.eq(100.0),
+                    flights_offers::currency.eq(Currency::Euro),
+                ))
+                .execute(conn)?;
+            let offer_id = diesel::select(last_insert_id).first::<u64>(conn)? as i32;
+            diesel::insert_into(flights::table)
+                .values((
+                    flights::offer_id.eq(offer_id),
+                    flights::departure_icao.eq(departure_icao),
+                    flights::departure_time.eq(departure),
+                    flights::arrival_icao.eq(arrival_icao),
+                    flights::arrival_time.eq(departure + Duration::hours(2)),
+                ))
+                .execute(conn)?;
+            Ok(offer_id)
+        })
+    })
+    .await
+    .expect("offer")
+}
@@ -1,3 +1,5 @@
+#[cfg(test)]
+pub(crate) mod fixtures;
 pub mod models;
 pub(self) mod schema;
 use rocket::fairing::AdHoc;
@@ -94,6 +94,15 @@ impl Address {
             .unwrap_or_else(|_| Vec::new())
     }
 
+    /// Format the address as it would be printed on a letter
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            format!("{} {}", self.street, self.house_number),
+            format!("{} {}", self.postal_code, self.town),
+            self.country.clone(),
+        ]
+    }
+
     pub async fn save(db: &Db, user_id: i32, new_addr: NewAddress) -> DbResult {
         db.run(move |conn| {
             diesel::insert_into(addresses::table)
@@ -0,0 +1,800 @@
+use crate::db::models::{BookingStatus, PriceItem};
+use crate::db::schema::{ancillaries, ancillaries_reservations, bookings, bookings_ancillaries};
+use crate::db::Db;
+use crate::routes::{error, ApiError, ApiResult};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::MysqlConnection;
+use diesel_derive_enum::DbEnum;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::Validate;
+
+/// Minutes ancillaries are held for a purchase, that awaits its payment
+const RESERVATION_MINUTES: i64 = 15;
+
+/// Kind of service, that is sold along with the seats of an offer
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, DbEnum, JsonSchema, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum AncillaryKind {
+    CheckedBag,
+    ExtraLegroom,
+    Meal,
+    PriorityBoarding,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct NewAncillary {
+    pub kind: AncillaryKind,
+    /// Description shown to customers and on invoices, like `Checked bag up to 23 kg`
+    #[validate(length(min = 1, max = 255))]
+    pub description: String,
+    #[validate(range(min = 0, max = 99999))]
+    pub price: f32,
+    /// Number of units for sale, unlimited if omitted
+    #[validate(range(min = 0, max = 100000))]
+    pub inventory: Option<i32>,
+}
+
+impl NewAncillary {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "ancillaries"]
+struct InsertableAncillary {
+    offer_id: i32,
+    kind: AncillaryKind,
+    description: String,
+    price: f32,
+    inventory: Option<i32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Identifiable, JsonSchema, Queryable, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[table_name = "ancillaries"]
+pub struct Ancillary {
+    pub id: i32,
+    pub offer_id: i32,
+    pub kind: AncillaryKind,
+    pub description: String,
+    pub price: f32,
+    pub inventory: Option<i32>,
+}
+
+/// An ancillary of an offer, along with the number of units that are still for sale
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct AncillaryAvailability {
+    #[serde(flatten)]
+    pub ancillary: Ancillary,
+    /// Remaining units, unlimited if null
+    pub available: Option<i64>,
+}
+
+/// Ancillary a customer wants to buy for a booking
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct NewBookingAncillary {
+    pub ancillary_id: i32,
+    #[validate(range(min = 1, max = 20))]
+    pub quantity: i32,
+}
+
+impl NewBookingAncillary {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// Ancillaries added to an existing booking. Bookings, that have been paid already, require a
+/// payment token of the payment provider, as the ancillaries are charged right away.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AncillaryPurchase {
+    pub ancillaries: Vec<NewBookingAncillary>,
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "ancillaries_reservations"]
+struct AncillaryReservation {
+    reference: String,
+    user_id: i32,
+    offer_id: i32,
+    ancillary_id: i32,
+    quantity: i32,
+    expires: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "bookings_ancillaries"]
+struct BookingAncillary {
+    user_id: i32,
+    offer_id: i32,
+    ancillary_id: i32,
+    quantity: i32,
+}
+
+/// An ancillary, that has been bought for a booking
+#[derive(Clone, Debug, Deserialize, JsonSchema, Queryable, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct BookedAncillary {
+    pub ancillary_id: i32,
+    pub kind: AncillaryKind,
+    pub description: String,
+    pub quantity: i32,
+    pub unit_price: f32,
+}
+
+/// Reasons the purchase of ancillaries is rejected
+pub(super) enum PurchaseError {
+    Unknown(i32),
+    SoldOut(String),
+    Database(DieselError),
+}
+
+impl From<DieselError> for PurchaseError {
+    fn from(e: DieselError) -> Self {
+        PurchaseError::Database(e)
+    }
+}
+
+impl From<PurchaseError> for ApiError {
+    fn from(e: PurchaseError) -> Self {
+        match e {
+            PurchaseError::Unknown(id) => error(
+                "",
+                Status::BadRequest,
+                &format!("Offer has no ancillary {}", id),
+            ),
+            PurchaseError::SoldOut(description) => error(
+                "",
+                Status::Conflict,
+                &format!("{} is sold out", description),
+            ),
+            PurchaseError::Database(e) => error(e, Status::InternalServerError, ""),
+        }
+    }
+}
+
+/// Units of an ancillary, that have been sold to bookings, that haven't been cancelled, or are
+/// reserved for a purchase awaiting its payment
+fn sold(conn: &MysqlConnection, ancillary_id: i32) -> QueryResult<i64> {
+    let booked = bookings_ancillaries::table
+        .inner_join(
+            bookings::table.on(bookings::user_id
+                .eq(bookings_ancillaries::user_id)
+                .and(bookings::offer_id.eq(bookings_ancillaries::offer_id))),
+        )
+        .filter(bookings_ancillaries::ancillary_id.eq(ancillary_id))
+        .filter(bookings::status.ne(BookingStatus::Cancelled))
+        .select(diesel::dsl::sum(bookings_ancillaries::quantity))
+        .first::<Option<i64>>(conn)?;
+    let reserved = ancillaries_reservations::table
+        .filter(ancillaries_reservations::ancillary_id.eq(ancillary_id))
+        .filter(ancillaries_reservations::expires.gt(Utc::now().naive_utc()))
+        .select(diesel::dsl::sum(ancillaries_reservations::quantity))
+        .first::<Option<i64>>(conn)?;
+    Ok(booked.unwrap_or(0) + reserved.unwrap_or(0))
+}
+
+/// Lock the ancillaries of an offer and check their inventory suffices for the items
+fn take(
+    conn: &MysqlConnection,
+    offer_id: i32,
+    items: &[NewBookingAncillary],
+) -> Result<Vec<Ancillary>, PurchaseError> {
+    let mut taken = Vec::new();
+    for item in items {
+        let ancillary = ancillaries::table
+            .find(item.ancillary_id)
+            .filter(ancillaries::offer_id.eq(offer_id))
+            .for_update()
+            .first::<Ancillary>(conn)
+            .optional()?
+            .ok_or(PurchaseError::Unknown(item.ancillary_id))?;
+
+        if let Some(inventory) = ancillary.inventory {
+            // units taken by earlier items of the same purchase
+            let pending: i64 = items
+                .iter()
+                .zip(&taken)
+                .filter(|(_, a)| a.id == ancillary.id)
+                .map(|(i, _)| i.quantity as i64)
+                .sum();
+            if sold(conn, ancillary.id)? + pending + item.quantity as i64 > inventory as i64 {
+                return Err(PurchaseError::SoldOut(ancillary.description));
+            }
+        }
+        taken.push(ancillary);
+    }
+    Ok(taken)
+}
+
+/// Add ancillaries to a booking without checking the inventory
+fn add(
+    conn: &MysqlConnection,
+    user_id: i32,
+    offer_id: i32,
+    items: &[NewBookingAncillary],
+) -> QueryResult<()> {
+    for item in items {
+        let key = (user_id, offer_id, item.ancillary_id);
+        let bought = bookings_ancillaries::table
+            .find(key)
+            .select(bookings_ancillaries::quantity)
+            .first::<i32>(conn)
+            .optional()?;
+        match bought {
+            Some(quantity) => {
+                diesel::update(bookings_ancillaries::table.find(key))
+                    .set(bookings_ancillaries::quantity.eq(quantity + item.quantity))
+                    .execute(conn)?;
+            }
+            None => {
+                diesel::insert_into(bookings_ancillaries::table)
+                    .values(&BookingAncillary {
+                        user_id,
+                        offer_id,
+                        ancillary_id: item.ancillary_id,
+                        quantity: item.quantity,
+                    })
+                    .execute(conn)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add ancillaries to a booking, as long as their inventory suffices. Must be called within a
+/// transaction, as the ancillaries are locked until it ends.
+pub(super) fn purchase(
+    conn: &MysqlConnection,
+    user_id: i32,
+    offer_id: i32,
+    items: &[NewBookingAncillary],
+) -> Result<(), PurchaseError> {
+    take(conn, offer_id, items)?;
+    add(conn, user_id, offer_id, items)?;
+    Ok(())
+}
+
+/// Ancillaries held for a booking, until the purchase has been paid
+pub(super) struct Reservation {
+    pub reference: String,
+    /// Price of all reserved units
+    pub amount: f32,
+    /// Reserved ancillaries as they are invoiced
+    pub items: Vec<PriceItem>,
+}
+
+/// Hold ancillaries for a booking, as long as their inventory suffices, without adding them to
+/// the booking yet. The reservation either gets confirmed or released, once the payment
+/// succeeded or failed. A reservation, that is left behind, stops counting against the inventory
+/// after [`RESERVATION_MINUTES`]. Must be called within a transaction, as the ancillaries are
+/// locked until it ends.
+pub(super) fn reserve(
+    conn: &MysqlConnection,
+    user_id: i32,
+    offer_id: i32,
+    items: &[NewBookingAncillary],
+) -> Result<Reservation, PurchaseError> {
+    release_expired(conn, user_id, offer_id)?;
+    let now = Utc::now().naive_utc();
+    let taken = take(conn, offer_id, items)?;
+    let reference: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+    let rows = items
+        .iter()
+        .map(|item| AncillaryReservation {
+            reference: reference.clone(),
+            user_id,
+            offer_id,
+            ancillary_id: item.ancillary_id,
+            quantity: item.quantity,
+            expires: now + Duration::minutes(RESERVATION_MINUTES),
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(ancillaries_reservations::table)
+        .values(&rows)
+        .execute(conn)?;
+
+    let items = items
+        .iter()
+        .zip(taken)
+        .map(|(item, ancillary)| PriceItem {
+            description: ancillary.description,
+            quantity: item.quantity,
+            unit_price: ancillary.price,
+        })
+        .collect::<Vec<PriceItem>>();
+    let amount = items.iter().map(PriceItem::amount).sum();
+    Ok(Reservation {
+        reference,
+        amount,
+        items,
+    })
+}
+
+/// Add the reserved ancillaries to their booking, after the purchase has been paid. Must be
+/// called within a transaction.
+pub(super) fn confirm(conn: &MysqlConnection, reference: &str) -> QueryResult<()> {
+    let reserved = ancillaries_reservations::table
+        .filter(ancillaries_reservations::reference.eq(reference))
+        .select((
+            ancillaries_reservations::user_id,
+            ancillaries_reservations::offer_id,
+            ancillaries_reservations::ancillary_id,
+            ancillaries_reservations::quantity,
+        ))
+        .load::<(i32, i32, i32, i32)>(conn)?;
+    for (user_id, offer_id, ancillary_id, quantity) in reserved {
+        add(
+            conn,
+            user_id,
+            offer_id,
+            &[NewBookingAncillary {
+                ancillary_id,
+                quantity,
+            }],
+        )?;
+    }
+    release(conn, reference)
+}
+
+/// Remove the reservations of a booking, that have been left behind
+pub(super) fn release_expired(
+    conn: &MysqlConnection,
+    user_id: i32,
+    offer_id: i32,
+) -> QueryResult<()> {
+    diesel::delete(
+        ancillaries_reservations::table
+            .filter(ancillaries_reservations::user_id.eq(user_id))
+            .filter(ancillaries_reservations::offer_id.eq(offer_id))
+            .filter(ancillaries_reservations::expires.le(Utc::now().naive_utc())),
+    )
+    .execute(conn)
+    .map(|_| ())
+}
+
+/// Give reserved ancillaries back to the inventory, after the payment of the purchase failed
+pub(super) fn release(conn: &MysqlConnection, reference: &str) -> QueryResult<()> {
+    diesel::delete(
+        ancillaries_reservations::table.filter(ancillaries_reservations::reference.eq(reference)),
+    )
+    .execute(conn)
+    .map(|_| ())
+}
+
+/// Ancillary of an offer, with the units that are still for sale
+struct Candidate {
+    id: i32,
+    kind: AncillaryKind,
+    available: Option<i64>,
+}
+
+/// Pick an ancillary of the same kind with enough units left for every booked ancillary. Returns
+/// `None`, if any of the booked ancillaries can't be replaced.
+fn pick_replacements(
+    booked: &[(AncillaryKind, i32)],
+    mut candidates: Vec<Candidate>,
+) -> Option<Vec<NewBookingAncillary>> {
+    let mut replacements: Vec<NewBookingAncillary> = Vec::new();
+    for &(kind, quantity) in booked {
+        let candidate = candidates.iter_mut().find(|c| {
+            c.kind == kind
+                && c.available
+                    .map_or(true, |available| available >= quantity as i64)
+        })?;
+        if let Some(available) = candidate.available.as_mut() {
+            *available -= quantity as i64;
+        }
+        match replacements
+            .iter_mut()
+            .find(|r| r.ancillary_id == candidate.id)
+        {
+            Some(replacement) => replacement.quantity += quantity,
+            None => replacements.push(NewBookingAncillary {
+                ancillary_id: candidate.id,
+                quantity,
+            }),
+        }
+    }
+    Some(replacements)
+}
+
+/// Find the ancillaries of another offer, that replace the ancillaries of a booking moved to that
+/// offer. Returns `None`, if the offer doesn't sell an equivalent for every booked ancillary. Must
+/// be called within a transaction, as the ancillaries of the offer are locked until it ends.
+pub(super) fn replacements(
+    conn: &MysqlConnection,
+    user_id: i32,
+    offer_id: i32,
+    to_offer_id: i32,
+) -> QueryResult<Option<Vec<NewBookingAncillary>>> {
+    let booked = bookings_ancillaries::table
+        .inner_join(ancillaries::table)
+        .filter(bookings_ancillaries::user_id.eq(user_id))
+        .filter(bookings_ancillaries::offer_id.eq(offer_id))
+        .select((ancillaries::kind, bookings_ancillaries::quantity))
+        .order(ancillaries::id)
+        .load::<(AncillaryKind, i32)>(conn)?;
+    if booked.is_empty() {
+        return Ok(Some(Vec::new()));
+    }
+
+    let candidates = ancillaries::table
+        .filter(ancillaries::offer_id.eq(to_offer_id))
+        .order(ancillaries::id)
+        .for_update()
+        .load::<Ancillary>(conn)?
+        .into_iter()
+        .map(|ancillary| {
+            let available = match ancillary.inventory {
+                Some(inventory) => Some(inventory as i64 - sold(conn, ancillary.id)?),
+                None => None,
+            };
+            Ok(Candidate {
+                id: ancillary.id,
+                kind: ancillary.kind,
+                available,
+            })
+        })
+        .collect::<QueryResult<Vec<_>>>()?;
+    Ok(pick_replacements(&booked, candidates))
+}
+
+/// Replace the ancillaries of a booking, without checking the inventory. Used for a booking, that
+/// has been moved to another offer, along with the ancillaries found by [`replacements`].
+pub(super) fn replace(
+    conn: &MysqlConnection,
+    user_id: i32,
+    offer_id: i32,
+    items: &[NewBookingAncillary],
+) -> QueryResult<()> {
+    diesel::delete(
+        bookings_ancillaries::table
+            .filter(bookings_ancillaries::user_id.eq(user_id))
+            .filter(bookings_ancillaries::offer_id.eq(offer_id)),
+    )
+    .execute(conn)?;
+    if items.is_empty() {
+        return Ok(());
+    }
+    let rows = items
+        .iter()
+        .map(|item| BookingAncillary {
+            user_id,
+            offer_id,
+            ancillary_id: item.ancillary_id,
+            quantity: item.quantity,
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(bookings_ancillaries::table)
+        .values(&rows)
+        .execute(conn)
+        .map(|_| ())
+}
+
+impl Ancillary {
+    pub async fn create(db: &Db, offer_id: i32, new_ancillary: NewAncillary) -> ApiResult<Self> {
+        new_ancillary.is_valid()?;
+
+        let ancillary = InsertableAncillary {
+            offer_id,
+            kind: new_ancillary.kind,
+            description: new_ancillary.description,
+            price: new_ancillary.price,
+            inventory: new_ancillary.inventory,
+        };
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                diesel::insert_into(ancillaries::table)
+                    .values(&ancillary)
+                    .execute(conn)?;
+                ancillaries::table
+                    .filter(ancillaries::offer_id.eq(offer_id))
+                    .order(ancillaries::id.desc())
+                    .first(conn)
+            })
+        })
+        .await
+        .map_err(|e| match e {
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                error(e, Status::NotFound, "Cannot find flight offer")
+            }
+            _ => error(e, Status::InternalServerError, ""),
+        })
+    }
+
+    /// List the ancillaries of an offer, along with their remaining inventory
+    pub async fn all_from_offer(db: &Db, offer_id: i32) -> Vec<AncillaryAvailability> {
+        db.run(move |conn| {
+            ancillaries::table
+                .filter(ancillaries::offer_id.eq(offer_id))
+                .order(ancillaries::id)
+                .load::<Ancillary>(conn)?
+                .into_iter()
+                .map(|ancillary| {
+                    let available = match ancillary.inventory {
+                        Some(inventory) => Some(inventory as i64 - sold(conn, ancillary.id)?),
+                        None => None,
+                    };
+                    Ok(AncillaryAvailability {
+                        ancillary,
+                        available,
+                    })
+                })
+                .collect::<QueryResult<Vec<_>>>()
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+}
+
+impl BookedAncillary {
+    pub async fn all_from_booking(db: &Db, user_id: i32, offer_id: i32) -> Vec<Self> {
+        db.run(move |conn| {
+            bookings_ancillaries::table
+                .inner_join(ancillaries::table)
+                .filter(bookings_ancillaries::user_id.eq(user_id))
+                .filter(bookings_ancillaries::offer_id.eq(offer_id))
+                .select((
+                    ancillaries::id,
+                    ancillaries::kind,
+                    ancillaries::description,
+                    bookings_ancillaries::quantity,
+                    ancillaries::price,
+                ))
+                .order(ancillaries::id)
+                .load(conn)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::{Booking, Invoice, NewBooking};
+    use crate::payment::PaymentGateway;
+    use chrono::NaiveDate;
+
+    async fn offer_with_bags(db: &Db, inventory: i32) -> (i32, Ancillary) {
+        let departure = NaiveDate::from_ymd(2022, 6, 13).and_hms(12, 0, 0);
+        let offer_id = fixtures::offer(db, &fixtures::route(), departure, 10).await;
+        let bag = Ancillary::create(
+            db,
+            offer_id,
+            NewAncillary {
+                kind: AncillaryKind::CheckedBag,
+                description: "Checked bag up to 23 kg".into(),
+                price: 30.0,
+                inventory: Some(inventory),
+            },
+        )
+        .await
+        .unwrap();
+        (offer_id, bag)
+    }
+
+    fn bags(bag_id: i32, quantity: i32) -> Vec<NewBookingAncillary> {
+        vec![NewBookingAncillary {
+            ancillary_id: bag_id,
+            quantity,
+        }]
+    }
+
+    /// Book a seat of the offer for a new user, along with the given ancillaries
+    async fn book(
+        db: &Db,
+        offer_id: i32,
+        ancillaries: Vec<NewBookingAncillary>,
+    ) -> ApiResult<Booking> {
+        let user = fixtures::user(db).await;
+        let new_booking = NewBooking {
+            ancillaries,
+            ..NewBooking::default()
+        };
+        Booking::create(db, user.id, offer_id, Some(1), None, None, new_booking).await?;
+        Ok(Booking::find(db, user.id, offer_id).await.unwrap())
+    }
+
+    async fn available(db: &Db, offer_id: i32) -> Option<i64> {
+        Ancillary::all_from_offer(db, offer_id).await[0].available
+    }
+
+    async fn reserve_bags(
+        db: &Db,
+        booking: &Booking,
+        bag_id: i32,
+        quantity: i32,
+    ) -> Result<Reservation, PurchaseError> {
+        let (user_id, offer_id) = (booking.user_id, booking.offer_id);
+        db.run(move |conn| {
+            conn.transaction::<_, PurchaseError, _>(|| {
+                reserve(conn, user_id, offer_id, &bags(bag_id, quantity))
+            })
+        })
+        .await
+    }
+
+    #[rocket::async_test]
+    async fn test_inventory_limits_purchases() {
+        let db = fixtures::db().await;
+        let (offer_id, bag) = offer_with_bags(&db, 3).await;
+
+        book(&db, offer_id, bags(bag.id, 2)).await.unwrap();
+        assert_eq!(Some(1), available(&db, offer_id).await);
+
+        let sold_out = book(&db, offer_id, bags(bag.id, 2)).await.unwrap_err();
+        assert_eq!(Status::Conflict, sold_out.0);
+
+        // items of a single purchase count together
+        let booking = book(&db, offer_id, Vec::new()).await.unwrap();
+        let user_id = booking.user_id;
+        let items = [bags(bag.id, 1), bags(bag.id, 1)].concat();
+        let purchased = db
+            .run(move |conn| {
+                conn.transaction::<_, PurchaseError, _>(|| {
+                    purchase(conn, user_id, offer_id, &items)
+                })
+            })
+            .await;
+        assert!(matches!(purchased, Err(PurchaseError::SoldOut(_))));
+        assert_eq!(Some(1), available(&db, offer_id).await);
+    }
+
+    #[rocket::async_test]
+    async fn test_reservations_hold_inventory() {
+        let db = fixtures::db().await;
+        let (offer_id, bag) = offer_with_bags(&db, 2).await;
+        let booking = book(&db, offer_id, Vec::new()).await.unwrap();
+        let user_id = booking.user_id;
+
+        let reservation = reserve_bags(&db, &booking, bag.id, 2).await.ok().unwrap();
+        assert_eq!(60.0, reservation.amount);
+        assert_eq!(Some(0), available(&db, offer_id).await);
+        assert!(BookedAncillary::all_from_booking(&db, user_id, offer_id)
+            .await
+            .is_empty());
+        assert!(matches!(
+            reserve_bags(&db, &booking, bag.id, 1).await,
+            Err(PurchaseError::SoldOut(_))
+        ));
+
+        // a released reservation goes back to the inventory
+        let reference = reservation.reference;
+        db.run(move |conn| release(conn, &reference)).await.unwrap();
+        assert_eq!(Some(2), available(&db, offer_id).await);
+
+        // a confirmed reservation is added to the booking
+        let reservation = reserve_bags(&db, &booking, bag.id, 1).await.ok().unwrap();
+        let reference = reservation.reference;
+        db.run(move |conn| confirm(conn, &reference)).await.unwrap();
+        assert_eq!(Some(1), available(&db, offer_id).await);
+        let booked = BookedAncillary::all_from_booking(&db, user_id, offer_id).await;
+        assert_eq!(1, booked.len());
+        assert_eq!(1, booked[0].quantity);
+    }
+
+    #[rocket::async_test]
+    async fn test_charge_ancillaries_of_paid_booking() {
+        let db = fixtures::db().await;
+        let gateway = PaymentGateway::mock();
+        let (offer_id, bag) = offer_with_bags(&db, 2).await;
+        let booking = book(&db, offer_id, Vec::new()).await.unwrap();
+        let user_id = booking.user_id;
+        Booking::set_status(&db, user_id, offer_id, BookingStatus::Confirmed)
+            .await
+            .unwrap();
+        let booking = Booking::find(&db, user_id, offer_id).await.unwrap();
+        let purchase_bags = |token: Option<&str>| AncillaryPurchase {
+            ancillaries: bags(bag.id, 2),
+            token: token.map(String::from),
+        };
+
+        let error = Booking::add_ancillaries(&db, &gateway, &booking, purchase_bags(None))
+            .await
+            .unwrap_err();
+        assert_eq!(Status::PaymentRequired, error.0);
+
+        // a declined payment leaves neither ancillaries nor a reservation behind
+        let declined = purchase_bags(Some("tok_declined"));
+        let error = Booking::add_ancillaries(&db, &gateway, &booking, declined)
+            .await
+            .unwrap_err();
+        assert_eq!(Status::PaymentRequired, error.0);
+        assert!(BookedAncillary::all_from_booking(&db, user_id, offer_id)
+            .await
+            .is_empty());
+        assert_eq!(Some(2), available(&db, offer_id).await);
+
+        let paid = purchase_bags(Some("tok_visa"));
+        let booked = Booking::add_ancillaries(&db, &gateway, &booking, paid)
+            .await
+            .unwrap();
+        assert_eq!(1, booked.len());
+        assert_eq!(2, booked[0].quantity);
+        assert_eq!(Some(0), available(&db, offer_id).await);
+
+        // the bags are billed on a supplementary invoice, the booking's invoice stays as it was
+        let invoices = Invoice::all_from_booking(&db, user_id, offer_id).await;
+        assert_eq!(
+            vec![0, 1],
+            invoices.iter().map(|i| i.supplement).collect::<Vec<_>>()
+        );
+        assert!(invoices[0]
+            .items
+            .iter()
+            .all(|item| item.description != bag.description));
+        assert_eq!(1, invoices[1].items.len());
+        assert_eq!(bag.description, invoices[1].items[0].description);
+        assert_eq!(2, invoices[1].items[0].quantity);
+        assert_eq!(60.0, invoices[1].total);
+    }
+
+    #[test]
+    fn test_pick_replacements_of_same_kind() {
+        let replacements = pick_replacements(
+            &[(AncillaryKind::CheckedBag, 2), (AncillaryKind::Meal, 1)],
+            vec![
+                candidate(1, AncillaryKind::Meal, None),
+                candidate(2, AncillaryKind::CheckedBag, Some(1)),
+                candidate(3, AncillaryKind::CheckedBag, Some(2)),
+            ],
+        )
+        .unwrap();
+        let picked = replacements
+            .iter()
+            .map(|r| (r.ancillary_id, r.quantity))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(3, 2), (1, 1)], picked);
+    }
+
+    #[test]
+    fn test_pick_replacements_merges_quantities() {
+        let replacements = pick_replacements(
+            &[(AncillaryKind::Meal, 1), (AncillaryKind::Meal, 2)],
+            vec![candidate(1, AncillaryKind::Meal, Some(3))],
+        )
+        .unwrap();
+        assert_eq!(1, replacements.len());
+        assert_eq!(3, replacements[0].quantity);
+    }
+
+    #[test]
+    fn test_pick_replacements_without_equivalent() {
+        assert!(pick_replacements(
+            &[(AncillaryKind::ExtraLegroom, 1)],
+            vec![candidate(1, AncillaryKind::Meal, None)],
+        )
+        .is_none());
+        assert!(pick_replacements(
+            &[(AncillaryKind::Meal, 2), (AncillaryKind::Meal, 2)],
+            vec![candidate(1, AncillaryKind::Meal, Some(3))],
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,552 @@
+use crate::db::models::{AuthUser, Role, Totp};
+use crate::db::schema::api_tokens;
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use oso::PolarClass;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::{Method, Status};
+use rocket::request::Outcome;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::Request;
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use validator::{Validate, ValidationError};
+
+/// Prefix of every token, so leaked tokens are easy to spot, like by secret scanners
+const TOKEN_PREFIX: &str = "skr_";
+/// Length of the random part of a token
+const TOKEN_LENGTH: usize = 40;
+/// Days a token is valid at most
+const MAX_EXPIRES_IN_DAYS: i64 = 365;
+
+/// Tokens are stored as hex encoded SHA-256 hash, so a leaked database doesn't expose them
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// What a token is allowed to do, which is checked in addition to the oso policies
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "lowercase")]
+pub enum ApiScope {
+    /// Requests, that don't change anything, like `GET`
+    Read,
+    /// Requests, that change something, like `POST`, `PUT` and `DELETE`
+    Write,
+    /// The admin role of the user, if the user has it
+    Admin,
+}
+
+impl ApiScope {
+    /// The scope a request with the given method requires
+    fn for_method(method: Method) -> Self {
+        match method {
+            Method::Get | Method::Head | Method::Options => ApiScope::Read,
+            _ => ApiScope::Write,
+        }
+    }
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiScope::Read => write!(f, "read"),
+            ApiScope::Write => write!(f, "write"),
+            ApiScope::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(ApiScope::Read),
+            "write" => Ok(ApiScope::Write),
+            "admin" => Ok(ApiScope::Admin),
+            _ => Err(format!("Unknown scope {}", s)),
+        }
+    }
+}
+
+fn parse_scopes(scopes: &str) -> Vec<ApiScope> {
+    scopes.split(',').filter_map(|s| s.parse().ok()).collect()
+}
+
+fn format_scopes(scopes: &[ApiScope]) -> String {
+    scopes
+        .iter()
+        .map(ApiScope::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn has_scopes(scopes: &[ApiScope]) -> Result<(), ValidationError> {
+    if scopes.is_empty() {
+        return Err(ValidationError::new("at least one scope is required"));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct NewApiToken {
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+    #[validate(custom = "has_scopes")]
+    pub scopes: Vec<ApiScope>,
+    #[validate(range(min = 1, max = 365))]
+    pub expires_in_days: i64,
+}
+
+impl NewApiToken {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "api_tokens"]
+struct InsertableApiToken {
+    user_id: i32,
+    name: String,
+    token_hash: String,
+    scopes: String,
+    created: NaiveDateTime,
+    expires: NaiveDateTime,
+}
+
+/// All columns except the token hash, which never leaves the database
+const API_TOKEN_COLUMNS: (
+    api_tokens::id,
+    api_tokens::user_id,
+    api_tokens::name,
+    api_tokens::scopes,
+    api_tokens::created,
+    api_tokens::expires,
+    api_tokens::last_used,
+) = (
+    api_tokens::id,
+    api_tokens::user_id,
+    api_tokens::name,
+    api_tokens::scopes,
+    api_tokens::created,
+    api_tokens::expires,
+    api_tokens::last_used,
+);
+
+#[derive(Clone, Debug, Queryable)]
+struct ApiTokenRow {
+    id: i32,
+    user_id: i32,
+    name: String,
+    scopes: String,
+    created: NaiveDateTime,
+    expires: NaiveDateTime,
+    last_used: Option<NaiveDateTime>,
+}
+
+/// A personal API token of a user. The token itself is only returned once, when it is created.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PolarClass, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct ApiToken {
+    pub id: i32,
+    #[polar(attribute)]
+    pub user_id: i32,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub created: NaiveDateTime,
+    pub expires: NaiveDateTime,
+    pub last_used: Option<NaiveDateTime>,
+}
+
+impl From<ApiTokenRow> for ApiToken {
+    fn from(row: ApiTokenRow) -> Self {
+        ApiToken {
+            id: row.id,
+            user_id: row.user_id,
+            name: row.name,
+            scopes: parse_scopes(&row.scopes),
+            created: row.created,
+            expires: row.expires,
+            last_used: row.last_used,
+        }
+    }
+}
+
+/// A token, that has just been created, along with the secret token to send as bearer token
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedApiToken {
+    pub token: String,
+    #[serde(flatten)]
+    pub api_token: ApiToken,
+}
+
+impl ApiToken {
+    /// Create a dummy token for a user with the given id. Used within oso policies
+    pub fn dummy_for_user(user_id: i32) -> Self {
+        ApiToken {
+            id: 0,
+            user_id,
+            name: String::new(),
+            scopes: Vec::new(),
+            created: NaiveDateTime::from_timestamp(0, 0),
+            expires: NaiveDateTime::from_timestamp(0, 0),
+            last_used: None,
+        }
+    }
+
+    pub async fn all_from_user(db: &Db, user_id: i32) -> Vec<Self> {
+        db.run(move |conn| {
+            api_tokens::table
+                .filter(api_tokens::user_id.eq(user_id))
+                .order(api_tokens::created)
+                .select(API_TOKEN_COLUMNS)
+                .load::<ApiTokenRow>(conn)
+        })
+        .await
+        .map(|rows| rows.into_iter().map(ApiToken::from).collect())
+        .unwrap_or_else(|_| Vec::new())
+    }
+
+    pub async fn create(
+        db: &Db,
+        user_id: i32,
+        new_token: NewApiToken,
+    ) -> ApiResult<CreatedApiToken> {
+        new_token.is_valid()?;
+
+        let random: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        let token = format!("{}{}", TOKEN_PREFIX, random);
+        let created = Utc::now().naive_utc();
+        let insertable = InsertableApiToken {
+            user_id,
+            name: new_token.name,
+            token_hash: hash_token(&token),
+            scopes: format_scopes(&new_token.scopes),
+            created,
+            expires: created + Duration::days(new_token.expires_in_days.min(MAX_EXPIRES_IN_DAYS)),
+        };
+
+        let row = db
+            .run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    diesel::insert_into(api_tokens::table)
+                        .values(&insertable)
+                        .execute(conn)?;
+                    api_tokens::table
+                        .filter(api_tokens::token_hash.eq(&insertable.token_hash))
+                        .select(API_TOKEN_COLUMNS)
+                        .first::<ApiTokenRow>(conn)
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        Ok(CreatedApiToken {
+            token,
+            api_token: row.into(),
+        })
+    }
+
+    pub async fn revoke(db: &Db, user_id: i32, token_id: i32) -> ApiResult<()> {
+        let deleted = db
+            .run(move |conn| {
+                diesel::delete(
+                    api_tokens::table
+                        .find(token_id)
+                        .filter(api_tokens::user_id.eq(user_id)),
+                )
+                .execute(conn)
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        if deleted == 0 {
+            return Err(error("", Status::NotFound, "Cannot find API token"));
+        }
+        Ok(())
+    }
+
+    /// Find the token, that hasn't expired yet, and remember its use
+    async fn find_valid(db: &Db, token: String) -> Option<Self> {
+        let token_hash = hash_token(&token);
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                let now = Utc::now().naive_utc();
+                let row = api_tokens::table
+                    .filter(api_tokens::token_hash.eq(token_hash))
+                    .filter(api_tokens::expires.gt(now))
+                    .select(API_TOKEN_COLUMNS)
+                    .first::<ApiTokenRow>(conn)?;
+                diesel::update(api_tokens::table.find(row.id))
+                    .set(api_tokens::last_used.eq(now))
+                    .execute(conn)?;
+                Ok(row)
+            })
+        })
+        .await
+        .map(ApiToken::from)
+        .ok()
+    }
+}
+
+/// Outcome of the bearer token authentication, cached for the lifetime of a request
+struct TokenAuthentication(Option<Result<AuthUser, (Status, String)>>);
+
+/// Authenticate a request by its `Authorization: Bearer` header, if there is one. The token is
+/// looked up only once per request, even if several guards ask for the user.
+pub(super) async fn user_from_bearer_token(
+    request: &Request<'_>,
+) -> Option<Result<AuthUser, (Status, String)>> {
+    request
+        .local_cache_async(async {
+            let token = request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|value| value.strip_prefix("Bearer "));
+            TokenAuthentication(match token {
+                Some(token) => Some(user_from_token(request, token.trim()).await),
+                None => None,
+            })
+        })
+        .await
+        .0
+        .clone()
+}
+
+/// The user has the admin role only, if the token has the admin scope and the user enabled
+/// two-factor authentication.
+async fn user_from_token(request: &Request<'_>, token: &str) -> Result<AuthUser, (Status, String)> {
+    let db = match request.guard::<Db>().await {
+        Outcome::Success(db) => db,
+        _ => return Err((Status::InternalServerError, "No database".to_owned())),
+    };
+    let api_token = ApiToken::find_valid(&db, token.to_owned())
+        .await
+        .ok_or_else(|| {
+            (
+                Status::Unauthorized,
+                "Invalid or expired API token".to_owned(),
+            )
+        })?;
+
+    let required = ApiScope::for_method(request.method());
+    if !api_token.scopes.contains(&required) {
+        return Err((
+            Status::Forbidden,
+            format!("API token lacks the {} scope", required),
+        ));
+    }
+
+    let mut user = AuthUser::by_user_id(&db, api_token.user_id)
+        .await
+        .ok_or_else(|| {
+            (
+                Status::Unauthorized,
+                "Invalid or expired API token".to_owned(),
+            )
+        })?;
+    if !api_token.scopes.contains(&ApiScope::Admin)
+        || !Totp::is_enabled(&db, api_token.user_id)
+            .await
+            .map_err(|e| (e.0, "Error checking two-factor authentication".to_owned()))?
+    {
+        user.roles.retain(|role| *role != Role::Admin);
+    }
+    user.token_scopes = Some(api_token.scopes);
+    Ok(user)
+}
+
+pub(super) fn register_polar_classes(oso: &mut oso::Oso) -> oso::Result<()> {
+    oso.register_class(ApiToken::get_polar_class())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::{NewUser, User};
+    use crate::db::schema::users_totp;
+    use rocket::http::Header;
+    use rocket::local::asynchronous::Client;
+
+    async fn token(db: &Db, user_id: i32, scopes: Vec<ApiScope>) -> String {
+        let new_token = NewApiToken {
+            name: "tests".into(),
+            scopes,
+            expires_in_days: 1,
+        };
+        ApiToken::create(db, user_id, new_token)
+            .await
+            .expect("token")
+            .token
+    }
+
+    fn bearer(token: &str) -> Header<'static> {
+        Header::new("Authorization", format!("Bearer {}", token))
+    }
+
+    /// The profile of the user of the token, or the status it was rejected with
+    async fn profile(client: &Client, token: &str) -> Result<AuthUser, Status> {
+        let response = client
+            .get("/v1/users/profile")
+            .header(bearer(token))
+            .dispatch()
+            .await;
+        match response.status() {
+            Status::Ok => Ok(response.into_json().await.expect("profile")),
+            status => Err(status),
+        }
+    }
+
+    async fn update(client: &Client, token: &str, user: &User, email: &str) -> Status {
+        client
+            .put(format!("/v1/users/{}", user.id))
+            .header(bearer(token))
+            .json(&NewUser {
+                firstname: user.firstname.clone(),
+                lastname: user.lastname.clone(),
+                email: email.into(),
+                birthday: user.birthday,
+                gender: user.gender.clone(),
+            })
+            .dispatch()
+            .await
+            .status()
+    }
+
+    #[rocket::async_test]
+    async fn test_bearer_scopes_and_expiry() {
+        let client = Client::untracked(crate::rocket()).await.expect("client");
+        let db = Db::get_one(client.rocket())
+            .await
+            .expect("database connection");
+        let user = fixtures::user(&db).await;
+        let read = token(&db, user.id, vec![ApiScope::Read]).await;
+        let write = token(&db, user.id, vec![ApiScope::Read, ApiScope::Write]).await;
+
+        let actor = profile(&client, &read).await.unwrap();
+        assert_eq!(user.id, actor.id);
+        assert_eq!(
+            Err(Status::Unauthorized),
+            profile(&client, "skr_unknown").await.map(|_| ())
+        );
+
+        // writing needs the write scope, the email address can't be changed with a token at all
+        assert_eq!(
+            Status::Forbidden,
+            update(&client, &read, &user, &user.email).await
+        );
+        assert_eq!(
+            Status::Ok,
+            update(&client, &write, &user, &user.email).await
+        );
+        let email = format!("changed-{}", user.email);
+        assert_eq!(
+            Status::Forbidden,
+            update(&client, &write, &user, &email).await
+        );
+
+        let token_hash = hash_token(&read);
+        db.run(move |conn| {
+            diesel::update(api_tokens::table.filter(api_tokens::token_hash.eq(token_hash)))
+                .set(api_tokens::expires.eq(Utc::now().naive_utc() - Duration::minutes(1)))
+                .execute(conn)
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            Err(Status::Unauthorized),
+            profile(&client, &read).await.map(|_| ())
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_bearer_admin_role() {
+        let client = Client::untracked(crate::rocket()).await.expect("client");
+        let db = Db::get_one(client.rocket())
+            .await
+            .expect("database connection");
+        let user = fixtures::user(&db).await;
+        user.attach_role(&db, Role::Admin).await.unwrap();
+        let read = token(&db, user.id, vec![ApiScope::Read]).await;
+        let admin = token(&db, user.id, vec![ApiScope::Read, ApiScope::Admin]).await;
+
+        let is_admin = |actor: AuthUser| actor.roles.contains(&Role::Admin);
+        // without two-factor authentication, not even the admin scope keeps the role
+        assert!(!is_admin(profile(&client, &read).await.unwrap()));
+        assert!(!is_admin(profile(&client, &admin).await.unwrap()));
+
+        let user_id = user.id;
+        db.run(move |conn| {
+            diesel::insert_into(users_totp::table)
+                .values((
+                    users_totp::user_id.eq(user_id),
+                    users_totp::secret.eq(""),
+                    users_totp::enabled.eq(true),
+                    users_totp::created.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)
+        })
+        .await
+        .unwrap();
+        assert!(!is_admin(profile(&client, &read).await.unwrap()));
+        assert!(is_admin(profile(&client, &admin).await.unwrap()));
+    }
+
+    #[test]
+    fn test_scope_for_method() {
+        assert_eq!(ApiScope::Read, ApiScope::for_method(Method::Get));
+        assert_eq!(ApiScope::Write, ApiScope::for_method(Method::Post));
+        assert_eq!(ApiScope::Write, ApiScope::for_method(Method::Delete));
+    }
+
+    #[test]
+    fn test_parse_and_format_scopes() {
+        let scopes = vec![ApiScope::Read, ApiScope::Admin];
+        assert_eq!("read,admin", format_scopes(&scopes));
+        assert_eq!(scopes, parse_scopes("read,admin"));
+        assert_eq!(vec![ApiScope::Write], parse_scopes("write,unknown"));
+    }
+
+    #[test]
+    fn test_validate_new_token() {
+        let token = NewApiToken {
+            name: "deploy script".into(),
+            scopes: vec![ApiScope::Read],
+            expires_in_days: 30,
+        };
+        assert!(token.validate().is_ok());
+        assert!(NewApiToken {
+            scopes: Vec::new(),
+            ..token.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(NewApiToken {
+            expires_in_days: 366,
+            ..token
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -1,14 +1,36 @@
-use crate::db::models::{FlightOffer, FlightOfferWithOccupancy, User};
-use crate::db::schema::bookings;
+use super::ancillary::{self, PurchaseError};
+use super::loyalty::POINT_VALUE;
+use super::passenger::{self, check_composition};
+use crate::db::models::{
+    AncillaryPurchase, BookedAncillary, Currency, Flight, FlightOffer, FlightOfferWithOccupancy,
+    FlightStatus, Invoice, LoyaltyTransaction, NewBookingAncillary, NewPayment, PassengerDetails,
+    PassengerType, Payment, PaymentStatus, Refund, RefundReason, User,
+};
+use crate::db::schema::{bookings, payments};
 use crate::db::Db;
-use crate::routes::{error, ApiResult};
+use crate::ical::{Calendar, Event};
+use crate::payment::{PaymentGateway, PaymentRequest, WebhookEvent};
+use crate::routes::{error, ApiError, ApiResult};
+use chrono::{Duration, NaiveDateTime, Utc};
 use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel_derive_enum::DbEnum;
 use oso::PolarClass;
+use rand::Rng;
 use rocket::http::Status;
 use rocket::serde::{Deserialize, Serialize};
 use rocket_okapi::okapi::schemars;
 use rocket_okapi::okapi::schemars::JsonSchema;
 
+/// A booking stays pending until its payment has been captured
+#[derive(Debug, Clone, Deserialize, Serialize, DbEnum, JsonSchema, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum BookingStatus {
+    Pending,
+    Confirmed,
+    Cancelled,
+}
+
 #[derive(
     Associations,
     Clone,
@@ -29,9 +51,95 @@ use rocket_okapi::okapi::schemars::JsonSchema;
 #[table_name = "bookings"]
 pub struct Booking {
     #[polar(attribute)]
-    user_id: i32,
-    offer_id: i32,
-    seats: i32,
+    pub user_id: i32,
+    pub offer_id: i32,
+    pub seats: i32,
+    pub status: BookingStatus,
+    pub reference: String,
+    /// Set if a flight of the booked offer has been cancelled, until the booking gets rebooked or
+    /// cancelled with a full refund
+    pub disrupted: bool,
+    /// Seats booked for children, included in `seats`
+    pub children: i32,
+    /// Infants travelling on the lap of an adult, who don't occupy a seat
+    pub infants: i32,
+    /// Set while the seats of the booking are being charged, so they are charged only once
+    #[serde(skip)]
+    pub paying_since: Option<NaiveDateTime>,
+}
+
+/// Passengers and ancillaries of a new booking
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewBooking {
+    /// Passengers of the booking. If given, the booked seats, children and infants are derived
+    /// from the age of the passengers.
+    #[serde(default)]
+    pub passengers: Vec<PassengerDetails>,
+    #[serde(default)]
+    pub ancillaries: Vec<NewBookingAncillary>,
+}
+
+/// Characters used for booking references. Characters that are easily confused, like `0` and
+/// `O`, are left out.
+const REFERENCE_CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const REFERENCE_LENGTH: usize = 6;
+/// Number of references tried for a new booking, before giving up on finding a free one
+const REFERENCE_ATTEMPTS: usize = 5;
+/// Minutes a payment of the seats may take, before another attempt may charge the booking
+const PAYMENT_CLAIM_MINUTES: i64 = 5;
+
+fn generate_reference() -> String {
+    let mut rng = rand::thread_rng();
+    (0..REFERENCE_LENGTH)
+        .map(|_| REFERENCE_CHARSET[rng.gen_range(0..REFERENCE_CHARSET.len())] as char)
+        .collect()
+}
+
+/// A single position of the price of a booking
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct PriceItem {
+    pub description: String,
+    pub quantity: i32,
+    pub unit_price: f32,
+}
+
+/// A booking along with the ancillaries bought for it and its total price
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct BookingWithAncillaries {
+    #[serde(flatten)]
+    pub booking: Booking,
+    pub ancillaries: Vec<BookedAncillary>,
+    pub total: f32,
+    pub currency: Currency,
+}
+
+impl BookingWithAncillaries {
+    pub async fn from_bookings(db: &Db, bookings: Vec<Booking>) -> ApiResult<Vec<Self>> {
+        let mut result = Vec::new();
+        for booking in bookings {
+            let ancillaries =
+                BookedAncillary::all_from_booking(db, booking.user_id, booking.offer_id).await;
+            let (total, currency) = booking.total(db).await?;
+            result.push(BookingWithAncillaries {
+                booking,
+                ancillaries,
+                total,
+                currency,
+            });
+        }
+        Ok(result)
+    }
+}
+
+impl PriceItem {
+    pub fn amount(&self) -> f32 {
+        self.unit_price * self.quantity as f32
+    }
 }
 
 impl Booking {
@@ -41,40 +149,568 @@ impl Booking {
             user_id,
             offer_id: 0,
             seats: 0,
+            status: BookingStatus::Pending,
+            reference: String::new(),
+            disrupted: false,
+            children: 0,
+            infants: 0,
+            paying_since: None,
+        }
+    }
+
+    /// Book seats of an offer, along with ancillaries of the offer. Without passengers, the
+    /// number of seats must be given, the number of children and infants defaults to zero.
+    pub async fn create(
+        db: &Db,
+        user_id: i32,
+        offer_id: i32,
+        seats: Option<i32>,
+        children: Option<i32>,
+        infants: Option<i32>,
+        new_booking: NewBooking,
+    ) -> ApiResult<()> {
+        let mut passengers = Vec::new();
+        for details in new_booking.passengers {
+            let passenger = details.resolve(db, user_id).await?;
+            passenger.is_valid()?;
+            passengers.push(passenger);
+        }
+        let ancillaries = new_booking.ancillaries;
+        for item in &ancillaries {
+            item.is_valid()?;
+        }
+
+        let offer = FlightOfferWithOccupancy::from_offer_id(db, offer_id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find flight offer"))?;
+
+        if offer.cancelled {
+            return Err(error(
+                "",
+                Status::BadRequest,
+                "Flight offer has been cancelled",
+            ));
+        }
+
+        if Flight::all_from_offer(db, offer_id)
+            .await
+            .iter()
+            .any(|f| f.status == FlightStatus::Cancelled)
+        {
+            return Err(error(
+                "",
+                Status::BadRequest,
+                "A flight of this offer has been cancelled",
+            ));
+        }
+
+        let (seats, children, infants) = if passengers.is_empty() {
+            (
+                seats.ok_or_else(|| error("", Status::BadRequest, "Bad number of seats"))?,
+                children.unwrap_or(0),
+                infants.unwrap_or(0),
+            )
+        } else {
+            let departure = Flight::first_departure(db, offer_id)
+                .await
+                .unwrap_or_else(|| Utc::now().naive_utc())
+                .date();
+            let types = passengers
+                .iter()
+                .map(|p| PassengerType::from_birthday(p.birthday, departure))
+                .collect::<Vec<_>>();
+            let count = |t: PassengerType| types.iter().filter(|&&p| p == t).count() as i32;
+            let derived = (
+                count(PassengerType::Adult) + count(PassengerType::Child),
+                count(PassengerType::Child),
+                count(PassengerType::Infant),
+            );
+            if seats.map_or(false, |s| s != derived.0)
+                || children.map_or(false, |c| c != derived.1)
+                || infants.map_or(false, |i| i != derived.2)
+            {
+                return Err(error(
+                    "",
+                    Status::BadRequest,
+                    "Number of seats doesn't match the passengers",
+                ));
+            }
+            derived
+        };
+        check_composition(seats, children, infants).map_err(|e| error(e, Status::BadRequest, e))?;
+
+        // offers may be sold above capacity, according to their overbooking policy
+        if offer.sellable_seats() - (offer.occupied as i32) < seats {
+            return Err(error("", Status::BadRequest, "Bad number of seats"));
         }
+
+        let mut booking = Booking {
+            user_id,
+            offer_id,
+            seats,
+            status: BookingStatus::Pending,
+            reference: generate_reference(),
+            disrupted: false,
+            children,
+            infants,
+            paying_since: None,
+        };
+        db.run(move |conn| {
+            let mut attempts = 1;
+            loop {
+                let result = conn.transaction::<_, PurchaseError, _>(|| {
+                    diesel::insert_into(bookings::table)
+                        .values(&booking)
+                        .execute(conn)?;
+                    passenger::insert(conn, user_id, offer_id, &passengers)?;
+                    ancillary::purchase(conn, user_id, offer_id, &ancillaries)
+                });
+                // the reference is random, so another booking may hold it already
+                if let Err(PurchaseError::Database(DieselError::DatabaseError(
+                    DatabaseErrorKind::UniqueViolation,
+                    _,
+                ))) = result
+                {
+                    let taken = bookings::table
+                        .filter(bookings::reference.eq(&booking.reference))
+                        .count()
+                        .get_result::<i64>(conn)?;
+                    if taken > 0 && attempts < REFERENCE_ATTEMPTS {
+                        booking.reference = generate_reference();
+                        attempts += 1;
+                        continue;
+                    }
+                }
+                return result;
+            }
+        })
+        .await
+        .map_err(ApiError::from)
+    }
+
+    /// Add ancillaries to a booking, that hasn't been cancelled. The ancillaries of a paid booking
+    /// are charged right away and only added once the payment has been captured, otherwise they
+    /// are paid along with the seats.
+    pub async fn add_ancillaries(
+        db: &Db,
+        gateway: &PaymentGateway,
+        booking: &Booking,
+        purchase: AncillaryPurchase,
+    ) -> ApiResult<Vec<BookedAncillary>> {
+        for item in &purchase.ancillaries {
+            item.is_valid()?;
+        }
+
+        let (user_id, offer_id) = (booking.user_id, booking.offer_id);
+        let items = purchase.ancillaries;
+        let token = match booking.status {
+            BookingStatus::Cancelled => {
+                return Err(error("", Status::Conflict, "Booking has been cancelled"))
+            }
+            BookingStatus::Pending => {
+                db.run(move |conn| {
+                    conn.transaction::<_, PurchaseError, _>(|| {
+                        ancillary::purchase(conn, user_id, offer_id, &items)
+                    })
+                })
+                .await?;
+                return Ok(BookedAncillary::all_from_booking(db, user_id, offer_id).await);
+            }
+            BookingStatus::Confirmed => purchase.token.ok_or_else(|| {
+                error(
+                    "",
+                    Status::PaymentRequired,
+                    "Booking has been paid already, a payment token is required",
+                )
+            })?,
+        };
+        // the invoice of the booking is issued first, so it doesn't cover the ancillaries, which get
+        // a supplementary invoice
+        Invoice::find_or_issue(db, user_id, offer_id).await?;
+
+        // the ancillaries are held until the payment has been captured, so they are neither added
+        // to the booking unpaid nor sold to anyone else in the meantime
+        let reservation = db
+            .run(move |conn| {
+                conn.transaction::<_, PurchaseError, _>(|| {
+                    ancillary::reserve(conn, user_id, offer_id, &items)
+                })
+            })
+            .await?;
+        let (_, currency) = booking.total(db).await?;
+        let request = PaymentRequest {
+            amount: reservation.amount,
+            currency: currency.clone(),
+            token,
+            description: format!("SkyRocket ancillaries, booking {}", booking.reference),
+        };
+        let charged = Booking::charge(db, gateway, user_id, offer_id, request).await;
+
+        let paid = charged.is_ok();
+        let (reference, items) = (reservation.reference, reservation.items);
+        db.run(move |conn| {
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                if paid {
+                    ancillary::confirm(conn, &reference)
+                } else {
+                    ancillary::release(conn, &reference)
+                }
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        charged?;
+        Invoice::issue_supplement(db, booking, items, currency).await?;
+
+        Ok(BookedAncillary::all_from_booking(db, user_id, offer_id).await)
+    }
+
+    pub async fn find(db: &Db, user_id: i32, offer_id: i32) -> Option<Self> {
+        db.run(move |conn| bookings::table.find((user_id, offer_id)).first(conn))
+            .await
+            .ok()
+    }
+
+    pub async fn find_by_reference(db: &Db, user_id: i32, reference: String) -> Option<Self> {
+        db.run(move |conn| {
+            bookings::table
+                .filter(bookings::user_id.eq(user_id))
+                .filter(bookings::reference.eq(reference))
+                .first(conn)
+        })
+        .await
+        .ok()
     }
 
-    async fn save(db: &Db, booking: Booking) -> super::DbResult {
+    pub async fn set_status(
+        db: &Db,
+        user_id: i32,
+        offer_id: i32,
+        status: BookingStatus,
+    ) -> super::DbResult {
         db.run(move |conn| {
-            diesel::insert_into(bookings::table)
-                .values(&booking)
+            diesel::update(bookings::table.find((user_id, offer_id)))
+                .set(bookings::status.eq(status))
                 .execute(conn)
         })
         .await
     }
 
-    pub async fn create(db: &Db, user_id: i32, offer_id: i32, seats: i32) -> ApiResult<()> {
-        let offer = FlightOfferWithOccupancy::from_offer_id(db, offer_id)
+    /// Cancel a booking and request a refund according to the fare rules. Returns the refund, if
+    /// the customer gets any money back.
+    pub async fn cancel(
+        db: &Db,
+        user_id: i32,
+        offer_id: i32,
+        reason: RefundReason,
+    ) -> ApiResult<Option<Refund>> {
+        let booking = Booking::find(db, user_id, offer_id)
             .await
-            .ok_or_else(|| error("", Status::NotFound, "Cannot find flight offer"))?;
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
 
-        if seats < 1 || offer.seats - (offer.occupied as i32) < seats {
-            return Err(error("", Status::BadRequest, "Bad number of seats"));
+        // only the request, that actually changes the status, credits the points back
+        let reinstated = booking.clone();
+        let cancelled = db
+            .run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    let updated = diesel::update(
+                        bookings::table
+                            .find((user_id, offer_id))
+                            .filter(bookings::status.ne(BookingStatus::Cancelled)),
+                    )
+                    .set(bookings::status.eq(BookingStatus::Cancelled))
+                    .execute(conn)?;
+                    if updated == 0 {
+                        return Ok(false);
+                    }
+                    LoyaltyTransaction::reinstate(conn, &reinstated)?;
+                    Ok(true)
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        if !cancelled {
+            return Err(error("", Status::Conflict, "Booking is already cancelled"));
         }
 
-        Booking::save(
-            db,
-            Booking {
-                user_id,
+        // customers of disrupted bookings get the full amount paid back
+        let reason = if booking.disrupted {
+            RefundReason::FlightCancellation
+        } else {
+            reason
+        };
+        Refund::request(db, user_id, offer_id, reason).await
+    }
+
+    /// Cancel a flight offer together with all of its bookings. Every customer gets the full
+    /// amount paid back.
+    pub async fn cancel_offer(db: &Db, offer_id: i32) -> ApiResult<Vec<Refund>> {
+        FlightOffer::cancel(db, offer_id)
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        let mut refunds = Vec::new();
+        for booking in Booking::all_from_offer(db, offer_id).await {
+            if booking.status == BookingStatus::Cancelled {
+                continue;
+            }
+            let refund = Booking::cancel(
+                db,
+                booking.user_id,
                 offer_id,
-                seats,
-            },
+                RefundReason::FlightCancellation,
+            )
+            .await?;
+            refunds.extend(refund);
+        }
+
+        Ok(refunds)
+    }
+
+    /// Flag all active bookings of an offer as disrupted, after one of its flights has been
+    /// cancelled
+    pub(super) fn disrupt_offer(conn: &MysqlConnection, offer_id: i32) -> QueryResult<usize> {
+        diesel::update(
+            bookings::table
+                .filter(bookings::offer_id.eq(offer_id))
+                .filter(bookings::status.ne(BookingStatus::Cancelled)),
         )
+        .set(bookings::disrupted.eq(true))
+        .execute(conn)
+    }
+
+    /// List all positions, that make up the price of this booking
+    pub async fn price_items(&self, db: &Db) -> ApiResult<(Vec<PriceItem>, Currency)> {
+        let offer = FlightOfferWithOccupancy::from_offer_id(db, self.offer_id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find flight offer"))?;
+
+        // the seats of adults come first, followed by the seats of children
+        let adults = self.seats - self.children;
+        let mut items: Vec<PriceItem> = (1..=self.seats)
+            .map(|seat| {
+                let (passenger_type, label) = if seat <= adults {
+                    (PassengerType::Adult, "Seat")
+                } else {
+                    (PassengerType::Child, "Child seat")
+                };
+                PriceItem {
+                    description: format!(
+                        "{} {} of {}, {} - {}",
+                        label, seat, self.seats, offer.departure_icao, offer.arrival_icao
+                    ),
+                    quantity: 1,
+                    unit_price: offer.price_for(passenger_type),
+                }
+            })
+            .collect();
+        items.extend((1..=self.infants).map(|infant| PriceItem {
+            description: format!(
+                "Infant {} of {}, {} - {}",
+                infant, self.infants, offer.departure_icao, offer.arrival_icao
+            ),
+            quantity: 1,
+            unit_price: offer.price_for(PassengerType::Infant),
+        }));
+        items.extend(
+            BookedAncillary::all_from_booking(db, self.user_id, self.offer_id)
+                .await
+                .into_iter()
+                .map(|a| PriceItem {
+                    description: a.description,
+                    quantity: a.quantity,
+                    unit_price: a.unit_price,
+                }),
+        );
+        let points = LoyaltyTransaction::redeemed_points(db, self.user_id, self.offer_id).await;
+        if points > 0 {
+            items.push(PriceItem {
+                description: format!("{} loyalty points redeemed", points),
+                quantity: 1,
+                unit_price: -(points as f32 * POINT_VALUE),
+            });
+        }
+
+        Ok((items, offer.currency))
+    }
+
+    /// Calculate the total price of this booking
+    pub async fn total(&self, db: &Db) -> ApiResult<(f32, Currency)> {
+        let (items, currency) = self.price_items(db).await?;
+        Ok((items.iter().map(PriceItem::amount).sum(), currency))
+    }
+
+    /// Charge the total price of a pending booking and confirm it, once the payment provider
+    /// captured the amount
+    pub async fn pay(
+        db: &Db,
+        gateway: &PaymentGateway,
+        user_id: i32,
+        offer_id: i32,
+        new_payment: NewPayment,
+    ) -> ApiResult<Payment> {
+        let booking = Booking::find(db, user_id, offer_id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+
+        if booking.status != BookingStatus::Pending {
+            return Err(error(
+                "",
+                Status::Conflict,
+                "Booking is not awaiting payment",
+            ));
+        }
+
+        let (amount, currency) = booking.total(db).await?;
+        let request = PaymentRequest {
+            amount,
+            currency,
+            token: new_payment.token,
+            description: format!("SkyRocket flight offer {}", offer_id),
+        };
+
+        // the booking is claimed before it is charged, so concurrent payments don't charge it
+        // twice. A claim expires, in case the payment never finished.
+        let now = Utc::now().naive_utc();
+        let expired = now - Duration::minutes(PAYMENT_CLAIM_MINUTES);
+        let claimed = db
+            .run(move |conn| {
+                diesel::update(
+                    bookings::table
+                        .find((user_id, offer_id))
+                        .filter(bookings::status.eq(BookingStatus::Pending))
+                        .filter(
+                            bookings::paying_since
+                                .is_null()
+                                .or(bookings::paying_since.lt(expired)),
+                        ),
+                )
+                .set(bookings::paying_since.eq(now))
+                .execute(conn)
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        if claimed == 0 {
+            return Err(error("", Status::Conflict, "Booking is already being paid"));
+        }
+
+        let charged = Booking::charge(db, gateway, user_id, offer_id, request).await;
+        db.run(move |conn| {
+            diesel::update(bookings::table.find((user_id, offer_id)))
+                .set(bookings::paying_since.eq(None::<NaiveDateTime>))
+                .execute(conn)
+        })
         .await
-        .map_or_else(
-            |e| Err(error(e, Status::InternalServerError, "")),
-            |_res| Ok(()),
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        let reference = charged?;
+        Payment::find_by_reference(db, reference)
+            .await
+            .ok_or_else(|| error("", Status::InternalServerError, ""))
+    }
+
+    /// Authorize and capture an amount for a booking with the payment provider. The booking gets
+    /// confirmed, once the payment has been captured. Returns the reference of the payment.
+    async fn charge(
+        db: &Db,
+        gateway: &PaymentGateway,
+        user_id: i32,
+        offer_id: i32,
+        request: PaymentRequest,
+    ) -> ApiResult<String> {
+        let currency = request.currency.clone();
+        let authorization = gateway
+            .authorize(&request)
+            .await
+            .map_err(|e| error(e, Status::PaymentRequired, "Payment was declined"))?;
+
+        Payment::save(
+            db,
+            user_id,
+            offer_id,
+            gateway.name(),
+            &authorization,
+            currency,
         )
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        if let Err(e) = gateway
+            .capture(&authorization.reference, authorization.amount)
+            .await
+        {
+            Payment::set_status(db, authorization.reference, PaymentStatus::Failed)
+                .await
+                .map_err(|e| error(e, Status::InternalServerError, ""))?;
+            return Err(error(e, Status::PaymentRequired, "Payment failed"));
+        }
+
+        Booking::confirm_payment(db, authorization.reference.clone()).await?;
+        Ok(authorization.reference)
+    }
+
+    /// Mark the payment with the given reference as captured and confirm the booking it belongs
+    /// to. Only authorized payments and pending bookings change, so a late or replayed event
+    /// neither revives a refunded payment nor a cancelled booking.
+    async fn confirm_payment(db: &Db, reference: String) -> ApiResult<()> {
+        let payment = Payment::find_by_reference(db, reference.clone())
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find payment"))?;
+
+        let (user_id, offer_id) = (payment.user_id, payment.offer_id);
+        let confirmed = db
+            .run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    let captured = diesel::update(
+                        payments::table
+                            .filter(payments::reference.eq(reference))
+                            .filter(payments::status.eq(PaymentStatus::Authorized)),
+                    )
+                    .set(payments::status.eq(PaymentStatus::Captured))
+                    .execute(conn)?;
+                    if captured == 0 {
+                        return Ok(false);
+                    }
+                    let updated = diesel::update(
+                        bookings::table
+                            .find((user_id, offer_id))
+                            .filter(bookings::status.eq(BookingStatus::Pending)),
+                    )
+                    .set(bookings::status.eq(BookingStatus::Confirmed))
+                    .execute(conn)?;
+                    Ok(updated > 0)
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        if !confirmed {
+            return Ok(());
+        }
+
+        Invoice::find_or_issue(db, user_id, offer_id)
+            .await
+            .map(|_invoice| ())
+    }
+
+    /// Update payments and bookings by an event, that was sent by the payment provider
+    pub async fn apply_webhook_event(db: &Db, event: WebhookEvent) -> ApiResult<()> {
+        let (reference, status) = match event {
+            WebhookEvent::Captured { reference } => {
+                return Booking::confirm_payment(db, reference).await
+            }
+            WebhookEvent::Failed { reference } => (reference, PaymentStatus::Failed),
+            WebhookEvent::Refunded { reference, .. } => (reference, PaymentStatus::Refunded),
+        };
+
+        Payment::find_by_reference(db, reference.clone())
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find payment"))?;
+
+        Payment::set_status(db, reference, status)
+            .await
+            .map_or_else(
+                |e| Err(error(e, Status::InternalServerError, "")),
+                |_res| Ok(()),
+            )
     }
 
     pub async fn all_from_offer(db: &Db, offer_id: i32) -> Vec<Self> {
@@ -88,8 +724,137 @@ impl Booking {
             .await
             .unwrap_or_else(|_| Vec::new())
     }
+
+    /// Render every flight of the bookings of a user as iCalendar, skipping cancelled bookings
+    pub async fn calendar_from_user(db: &Db, user_id: i32) -> String {
+        let mut calendar = Calendar::new("SkyRocket Flights");
+
+        for booking in Booking::all_from_user(db, user_id).await {
+            if booking.status == BookingStatus::Cancelled {
+                continue;
+            }
+
+            for flight in Flight::all_from_offer(db, booking.offer_id).await {
+                calendar.add_event(Event {
+                    uid: format!("{}-{}@skyrocket", booking.reference, flight.id),
+                    start: flight.departure_time,
+                    end: flight.arrival_time,
+                    summary: format!("Flight {} - {}", flight.departure_icao, flight.arrival_icao),
+                    location: format!("{} - {}", flight.departure_icao, flight.arrival_icao),
+                    description: format!("Booking reference: {}", booking.reference),
+                });
+            }
+        }
+
+        calendar.to_ics(&Utc::now().naive_utc())
+    }
 }
 
 pub(super) fn register_polar_classes(oso: &mut oso::Oso) -> oso::Result<()> {
     oso.register_class(Booking::get_polar_class())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use chrono::NaiveDate;
+
+    #[rocket::async_test]
+    async fn test_pay_confirms_booking() {
+        let db = fixtures::db().await;
+        let gateway = PaymentGateway::mock();
+        let user = fixtures::user(&db).await;
+        let departure = NaiveDate::from_ymd(2022, 1, 10).and_hms(12, 0, 0);
+        let offer_id = fixtures::offer(&db, &fixtures::route(), departure, 10).await;
+        Booking::create(
+            &db,
+            user.id,
+            offer_id,
+            Some(2),
+            None,
+            None,
+            NewBooking::default(),
+        )
+        .await
+        .unwrap();
+        let payment = |token: &str| NewPayment {
+            token: token.into(),
+        };
+
+        let declined = Booking::pay(&db, &gateway, user.id, offer_id, payment("tok_declined"))
+            .await
+            .unwrap_err();
+        assert_eq!(Status::PaymentRequired, declined.0);
+        let booking = Booking::find(&db, user.id, offer_id).await.unwrap();
+        assert_eq!(BookingStatus::Pending, booking.status);
+        assert!(Invoice::find(&db, user.id, offer_id, 0).await.is_none());
+
+        let paid = Booking::pay(&db, &gateway, user.id, offer_id, payment("tok_visa"))
+            .await
+            .unwrap();
+        assert_eq!(PaymentStatus::Captured, paid.status);
+        assert_eq!(200.0, paid.amount);
+        let booking = Booking::find(&db, user.id, offer_id).await.unwrap();
+        assert_eq!(BookingStatus::Confirmed, booking.status);
+        let invoice = Invoice::find(&db, user.id, offer_id, 0).await.unwrap();
+        assert_eq!(200.0, invoice.total);
+
+        let twice = Booking::pay(&db, &gateway, user.id, offer_id, payment("tok_visa"))
+            .await
+            .unwrap_err();
+        assert_eq!(Status::Conflict, twice.0);
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_payments_charge_once() {
+        let db = fixtures::db().await;
+        let other = fixtures::db().await;
+        let gateway = PaymentGateway::mock();
+        let user = fixtures::user(&db).await;
+        let departure = NaiveDate::from_ymd(2022, 1, 10).and_hms(12, 0, 0);
+        let offer_id = fixtures::offer(&db, &fixtures::route(), departure, 10).await;
+        Booking::create(
+            &db,
+            user.id,
+            offer_id,
+            Some(2),
+            None,
+            None,
+            NewBooking::default(),
+        )
+        .await
+        .unwrap();
+        let payment = || NewPayment {
+            token: "tok_visa".into(),
+        };
+
+        let (first, second) = rocket::futures::join!(
+            Booking::pay(&db, &gateway, user.id, offer_id, payment()),
+            Booking::pay(&other, &gateway, user.id, offer_id, payment()),
+        );
+        assert!(first.is_ok() != second.is_ok());
+        let error = [first, second].into_iter().find_map(Result::err).unwrap();
+        assert_eq!(Status::Conflict, error.0);
+        assert_eq!(
+            1,
+            Payment::all_from_booking(&db, user.id, offer_id)
+                .await
+                .len()
+        );
+
+        // a replayed capture doesn't confirm the booking again, once it has been cancelled
+        let paid = Payment::all_from_booking(&db, user.id, offer_id)
+            .await
+            .remove(0);
+        Booking::cancel(&db, user.id, offer_id, RefundReason::CustomerCancellation)
+            .await
+            .unwrap();
+        let event = WebhookEvent::Captured {
+            reference: paid.reference,
+        };
+        Booking::apply_webhook_event(&db, event).await.unwrap();
+        let booking = Booking::find(&db, user.id, offer_id).await.unwrap();
+        assert_eq!(BookingStatus::Cancelled, booking.status);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::db::schema::calendar_subscriptions;
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use sha2::{Digest, Sha256};
+
+/// Length of the secret token, that is part of a subscription URL
+const TOKEN_LENGTH: usize = 40;
+
+/// Tokens are stored as hex encoded SHA-256 hash, so a leaked database doesn't expose calendars
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "calendar_subscriptions"]
+struct InsertableCalendarSubscription {
+    user_id: i32,
+    token_hash: String,
+    created: NaiveDateTime,
+}
+
+/// A secret URL, that allows calendar applications to subscribe to the bookings of a user without
+/// a session. The URL is only returned once, when it is created.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarSubscription {
+    pub url: String,
+    pub created: NaiveDateTime,
+}
+
+impl CalendarSubscription {
+    /// Create a new subscription URL for a user. A previous URL of the user stops working.
+    pub async fn rotate(db: &Db, user_id: i32) -> ApiResult<Self> {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        let subscription = InsertableCalendarSubscription {
+            user_id,
+            token_hash: hash_token(&token),
+            created: Utc::now().naive_utc(),
+        };
+        let created = subscription.created;
+
+        db.run(move |conn| {
+            diesel::replace_into(calendar_subscriptions::table)
+                .values(&subscription)
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        Ok(CalendarSubscription {
+            url: format!("/v1/users/{}/bookings.ics?token={}", user_id, token),
+            created,
+        })
+    }
+
+    /// Disable the subscription URL of a user
+    pub async fn revoke(db: &Db, user_id: i32) -> ApiResult<()> {
+        db.run(move |conn| {
+            diesel::delete(calendar_subscriptions::table.find(user_id)).execute(conn)
+        })
+        .await
+        .map_or_else(
+            |e| Err(error(e, Status::InternalServerError, "")),
+            |_res| Ok(()),
+        )
+    }
+
+    /// Check whether a token belongs to the current subscription URL of a user
+    pub async fn verify(db: &Db, user_id: i32, token: String) -> bool {
+        let token_hash = hash_token(&token);
+        db.run(move |conn| {
+            calendar_subscriptions::table
+                .filter(calendar_subscriptions::user_id.eq(user_id))
+                .filter(calendar_subscriptions::token_hash.eq(token_hash))
+                .count()
+                .get_result::<i64>(conn)
+        })
+        .await
+        .map_or(false, |count| count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_token() {
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            hash_token("")
+        );
+    }
+}
@@ -0,0 +1,159 @@
+use crate::db::schema::{carriers, flights_codeshares};
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::{Validate, ValidationError};
+
+/// Regex to validate an IATA airline designator. Designators consist of two letters or digits,
+/// but never two digits.
+static RE_IATA: Lazy<Regex> = Lazy::new(|| Regex::new(r"^([A-Z][A-Z0-9]|[0-9][A-Z])$").unwrap());
+/// Regex to validate an ICAO airline designator
+static RE_ICAO: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z]{3}$").unwrap());
+/// Regex to validate a flight number, consisting of the IATA designator of the carrier, up to
+/// four digits and an optional suffix
+pub static RE_FLIGHT_NUMBER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^([A-Z][A-Z0-9]|[0-9][A-Z])([0-9]{1,4}[A-Z]?)$").unwrap());
+
+/// Split a flight number into the IATA designator of the carrier and the number
+pub fn split_flight_number(flight_number: &str) -> Option<(&str, &str)> {
+    RE_FLIGHT_NUMBER.captures(flight_number).map(|c| {
+        (
+            c.get(1).map_or("", |m| m.as_str()),
+            c.get(2).map_or("", |m| m.as_str()),
+        )
+    })
+}
+
+/// Custom validator function for a list of flight numbers
+pub fn valid_flight_numbers(flight_numbers: &[String]) -> Result<(), ValidationError> {
+    if flight_numbers.iter().all(|n| RE_FLIGHT_NUMBER.is_match(n)) {
+        Ok(())
+    } else {
+        Err(ValidationError::new(
+            "Invalid flight number: Must look like LH400",
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Insertable, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+#[table_name = "carriers"]
+pub struct NewCarrier {
+    /// Two character IATA designator, like `LH`
+    #[validate(regex = "RE_IATA")]
+    pub iata: String,
+    /// Three letter ICAO designator, like `DLH`
+    #[validate(regex = "RE_ICAO")]
+    pub icao: String,
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+}
+
+impl NewCarrier {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// An airline, operating or marketing flights
+#[derive(Clone, Debug, Deserialize, Identifiable, JsonSchema, Queryable, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[table_name = "carriers"]
+pub struct Carrier {
+    pub id: i32,
+    pub iata: String,
+    pub icao: String,
+    pub name: String,
+}
+
+impl Carrier {
+    pub async fn get_all(db: &Db) -> Vec<Self> {
+        db.run(move |conn| carriers::table.order(carriers::iata).load(conn))
+            .await
+            .unwrap_or_else(|_| Vec::new())
+    }
+
+    pub async fn find_by_iata(db: &Db, iata: String) -> Option<Self> {
+        db.run(move |conn| carriers::table.filter(carriers::iata.eq(iata)).first(conn))
+            .await
+            .ok()
+    }
+
+    pub async fn create(db: &Db, new_carrier: NewCarrier) -> ApiResult<Self> {
+        new_carrier.is_valid()?;
+
+        let iata = new_carrier.iata.clone();
+        db.run(move |conn| {
+            diesel::insert_into(carriers::table)
+                .values(&new_carrier)
+                .execute(conn)?;
+            carriers::table.filter(carriers::iata.eq(iata)).first(conn)
+        })
+        .await
+        .map_err(|e| match e {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                error(e, Status::Conflict, "Carrier already exists")
+            }
+            _ => error(e, Status::InternalServerError, ""),
+        })
+    }
+}
+
+/// Marketing flight number of a codeshare partner on a flight
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "flights_codeshares"]
+pub struct FlightCodeshare {
+    pub flight_id: i32,
+    pub carrier_id: i32,
+    pub flight_number: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_flight_number() {
+        assert_eq!(Some(("LH", "400")), split_flight_number("LH400"));
+        assert_eq!(Some(("U2", "1234")), split_flight_number("U21234"));
+        assert_eq!(Some(("9W", "7A")), split_flight_number("9W7A"));
+    }
+
+    #[test]
+    fn test_split_invalid_flight_number() {
+        assert_eq!(None, split_flight_number("12400"));
+        assert_eq!(None, split_flight_number("LH12345"));
+        assert_eq!(None, split_flight_number("lh400"));
+        assert_eq!(None, split_flight_number("LH"));
+    }
+
+    #[test]
+    fn test_new_carrier_designators() {
+        let carrier = NewCarrier {
+            iata: "LH".into(),
+            icao: "DLH".into(),
+            name: "Lufthansa".into(),
+        };
+        assert!(carrier.validate().is_ok());
+        assert!(NewCarrier {
+            iata: "L".into(),
+            ..carrier.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(NewCarrier {
+            icao: "DL".into(),
+            ..carrier
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,423 @@
+use crate::db::models::{
+    Booking, BookingStatus, Flight, FlightOfferWithOccupancy, FlightStatus, Passenger,
+    PassengerType,
+};
+use crate::db::schema::{bookings, check_ins, flights, passengers};
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use crate::CONFIG;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::Validate;
+
+/// Hours before departure the check-in opens, used if `CHECKIN_OPENS_HOURS` isn't configured
+const DEFAULT_OPENS_HOURS: i64 = 24;
+/// Minutes before departure the check-in closes, used if `CHECKIN_CLOSES_MINUTES` isn't
+/// configured
+const DEFAULT_CLOSES_MINUTES: i64 = 45;
+/// Number of seats in every row of the cabin, named `A` to `F`
+const SEATS_PER_ROW: i32 = 6;
+
+/// Regex to validate a seat, consisting of the row number and the seat letter
+static RE_SEAT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[1-9][0-9]{0,2}[A-F]$").unwrap());
+
+/// Time span before departure, in which passengers are able to check in
+pub(super) fn window(departure: NaiveDateTime) -> (NaiveDateTime, NaiveDateTime) {
+    let opens = CONFIG.checkin_opens_hours.unwrap_or(DEFAULT_OPENS_HOURS);
+    let closes = CONFIG
+        .checkin_closes_minutes
+        .unwrap_or(DEFAULT_CLOSES_MINUTES);
+    (
+        departure - Duration::hours(opens),
+        departure - Duration::minutes(closes),
+    )
+}
+
+/// Check whether a seat exists on a flight offering the given number of seats. Seats are
+/// numbered row by row, so the last row only has the letters of the remaining seats.
+fn seat_exists(seat: &str, seats: i32) -> bool {
+    let letter = match seat.chars().last() {
+        Some(letter @ 'A'..='F') => letter as i32 - 'A' as i32,
+        _ => return false,
+    };
+    let row: i32 = seat[..seat.len() - 1].parse().unwrap_or(0);
+    row >= 1 && (row - 1) * SEATS_PER_ROW + letter < seats
+}
+
+/// Outcome of checking in a passenger, decided while the flight is locked
+enum Seating {
+    CheckedIn(CheckIn),
+    AlreadyCheckedIn,
+    SeatTaken,
+}
+
+impl Seating {
+    fn into_result(self) -> ApiResult<CheckIn> {
+        match self {
+            Seating::CheckedIn(check_in) => Ok(check_in),
+            Seating::AlreadyCheckedIn => Err(error(
+                "",
+                Status::Conflict,
+                "Passenger has already checked in for this flight",
+            )),
+            Seating::SeatTaken => Err(error("", Status::Conflict, "Seat has already been taken")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct NewCheckIn {
+    /// Seat picked by the passenger, like `12C`. A seat is assigned at the gate, if omitted.
+    #[validate(regex = "RE_SEAT")]
+    pub seat: Option<String>,
+}
+
+impl NewCheckIn {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// Check-in of a single passenger for a single flight
+#[derive(Clone, Debug, Deserialize, Insertable, JsonSchema, Queryable, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[table_name = "check_ins"]
+pub struct CheckIn {
+    pub passenger_id: i32,
+    pub flight_id: i32,
+    pub seat: Option<String>,
+    /// Order in which passengers checked in for the flight, starting with 1
+    pub sequence: i32,
+    pub checked_in: NaiveDateTime,
+}
+
+/// A passenger on the manifest of a flight, along with the check-in if there is one
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestEntry {
+    pub passenger_id: i32,
+    pub firstname: String,
+    pub lastname: String,
+    pub booking_reference: String,
+    pub check_in: Option<CheckIn>,
+}
+
+impl CheckIn {
+    pub async fn all_from_booking(db: &Db, user_id: i32, offer_id: i32) -> Vec<Self> {
+        db.run(move |conn| {
+            check_ins::table
+                .inner_join(passengers::table)
+                .filter(passengers::user_id.eq(user_id))
+                .filter(passengers::offer_id.eq(offer_id))
+                .select(check_ins::all_columns)
+                .load(conn)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+
+    /// Check in a passenger of a paid booking for one of the flights of the booked offer
+    pub async fn create(
+        db: &Db,
+        booking: &Booking,
+        passenger_id: i32,
+        flight_id: i32,
+        new_check_in: NewCheckIn,
+    ) -> ApiResult<Self> {
+        new_check_in.is_valid()?;
+
+        if booking.status != BookingStatus::Confirmed {
+            return Err(error("", Status::Conflict, "Booking hasn't been paid yet"));
+        }
+        let passenger = Passenger::all_from_booking(db, booking.user_id, booking.offer_id)
+            .await
+            .into_iter()
+            .find(|p| p.id == passenger_id)
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find passenger"))?;
+        let flight = Flight::find(db, flight_id)
+            .await
+            .filter(|f| f.offer_id == booking.offer_id)
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find flight"))?;
+        let closed = match flight.status {
+            FlightStatus::Cancelled => Some("Flight has been cancelled"),
+            FlightStatus::Diverted => Some("Flight has been diverted"),
+            FlightStatus::Departed | FlightStatus::Arrived => Some("Flight has already departed"),
+            FlightStatus::Scheduled | FlightStatus::Delayed | FlightStatus::Boarding => None,
+        };
+        if let Some(message) = closed {
+            return Err(error("", Status::Conflict, message));
+        }
+
+        let departure = Flight::first_departure(db, booking.offer_id)
+            .await
+            .unwrap_or(flight.departure_time);
+        if new_check_in.seat.is_some()
+            && passenger.passenger_type(departure.date()) == PassengerType::Infant
+        {
+            return Err(error(
+                "",
+                Status::BadRequest,
+                "Infants travel on the lap of an adult and don't get a seat",
+            ));
+        }
+
+        let now = Utc::now().naive_utc();
+        let (opens, closes) = window(flight.departure_time);
+        if now < opens {
+            return Err(error(
+                "",
+                Status::Conflict,
+                &format!("Check-in opens at {}", opens.format("%Y-%m-%d %H:%M UTC")),
+            ));
+        }
+        if now >= closes {
+            return Err(error("", Status::Conflict, "Check-in has been closed"));
+        }
+
+        if let Some(seat) = &new_check_in.seat {
+            let offer = FlightOfferWithOccupancy::from_offer_id(db, booking.offer_id)
+                .await
+                .ok_or_else(|| error("", Status::NotFound, "Cannot find offer"))?;
+            if !seat_exists(seat, offer.seats) {
+                return Err(error("", Status::BadRequest, "Seat doesn't exist"));
+            }
+        }
+
+        let seat = new_check_in.seat;
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                // the lock of the flight lets check-ins for it take the next sequence one by one
+                flights::table
+                    .find(flight_id)
+                    .select(flights::id)
+                    .for_update()
+                    .first::<i32>(conn)?;
+
+                let checked_in = check_ins::table
+                    .find((passenger_id, flight_id))
+                    .first::<CheckIn>(conn)
+                    .optional()?;
+                if checked_in.is_some() {
+                    return Ok(Seating::AlreadyCheckedIn);
+                }
+                if let Some(seat) = &seat {
+                    let taken = check_ins::table
+                        .filter(check_ins::flight_id.eq(flight_id))
+                        .filter(check_ins::seat.eq(seat))
+                        .count()
+                        .get_result::<i64>(conn)?;
+                    if taken > 0 {
+                        return Ok(Seating::SeatTaken);
+                    }
+                }
+
+                let sequence = check_ins::table
+                    .filter(check_ins::flight_id.eq(flight_id))
+                    .select(diesel::dsl::max(check_ins::sequence))
+                    .first::<Option<i32>>(conn)?
+                    .unwrap_or(0)
+                    + 1;
+                let check_in = CheckIn {
+                    passenger_id,
+                    flight_id,
+                    seat,
+                    sequence,
+                    checked_in: now,
+                };
+                diesel::insert_into(check_ins::table)
+                    .values(&check_in)
+                    .execute(conn)?;
+                Ok(Seating::CheckedIn(check_in))
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_result()
+    }
+
+    /// List every passenger of the paid bookings of a flight, along with their check-in
+    pub async fn manifest(db: &Db, offer_id: i32, flight_id: i32) -> ApiResult<Vec<ManifestEntry>> {
+        Flight::find(db, flight_id)
+            .await
+            .filter(|f| f.offer_id == offer_id)
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find flight"))?;
+
+        db.run(move |conn| {
+            let passengers = passengers::table
+                .inner_join(
+                    bookings::table.on(bookings::user_id
+                        .eq(passengers::user_id)
+                        .and(bookings::offer_id.eq(passengers::offer_id))),
+                )
+                .filter(bookings::offer_id.eq(offer_id))
+                .filter(bookings::status.eq(BookingStatus::Confirmed))
+                .order((passengers::lastname, passengers::firstname))
+                .select((passengers::all_columns, bookings::reference))
+                .load::<(Passenger, String)>(conn)?;
+            let check_ins = check_ins::table
+                .filter(check_ins::flight_id.eq(flight_id))
+                .load::<CheckIn>(conn)?;
+
+            Ok::<_, DieselError>(
+                passengers
+                    .into_iter()
+                    .map(|(passenger, booking_reference)| ManifestEntry {
+                        check_in: check_ins
+                            .iter()
+                            .find(|c| c.passenger_id == passenger.id)
+                            .cloned(),
+                        passenger_id: passenger.id,
+                        firstname: passenger.firstname,
+                        lastname: passenger.lastname,
+                        booking_reference,
+                    })
+                    .collect(),
+            )
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::{NewBooking, NewPassenger, PassengerDetails};
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_window() {
+        let departure = NaiveDate::from_ymd(2022, 2, 14).and_hms(12, 0, 0);
+        let (opens, closes) = window(departure);
+        assert!(opens < closes);
+        assert!(closes < departure);
+    }
+
+    #[test]
+    fn test_seat_exists() {
+        assert!(seat_exists("1A", 6));
+        assert!(seat_exists("1F", 6));
+        assert!(seat_exists("2A", 7));
+        assert!(!seat_exists("2B", 7));
+        assert!(!seat_exists("2F", 7));
+        assert!(!seat_exists("2A", 6));
+        assert!(!seat_exists("0A", 6));
+        assert!(!seat_exists("1G", 6));
+    }
+
+    /// A paid booking of three adults, for a flight departing in three hours
+    async fn booking(db: &Db) -> (Booking, i32, Vec<i32>) {
+        let user = fixtures::user(db).await;
+        let departure = Utc::now().naive_utc() + Duration::hours(3);
+        let offer_id = fixtures::offer(db, &fixtures::route(), departure, 10).await;
+        let passengers = ["Ada", "Charles", "Mary"]
+            .iter()
+            .map(|firstname| {
+                PassengerDetails::New(NewPassenger {
+                    firstname: firstname.to_string(),
+                    lastname: "Lovelace".into(),
+                    birthday: NaiveDate::from_ymd(1990, 12, 10),
+                })
+            })
+            .collect();
+        let new_booking = NewBooking {
+            passengers,
+            ..NewBooking::default()
+        };
+        Booking::create(db, user.id, offer_id, None, None, None, new_booking)
+            .await
+            .unwrap();
+
+        let user_id = user.id;
+        db.run(move |conn| {
+            diesel::update(bookings::table.find((user_id, offer_id)))
+                .set(bookings::status.eq(BookingStatus::Confirmed))
+                .execute(conn)
+        })
+        .await
+        .unwrap();
+        let flight_id = Flight::all_from_offer(db, offer_id).await[0].id;
+        let passenger_ids = Passenger::all_from_booking(db, user_id, offer_id)
+            .await
+            .iter()
+            .map(|p| p.id)
+            .collect();
+        (
+            Booking::find(db, user_id, offer_id).await.unwrap(),
+            flight_id,
+            passenger_ids,
+        )
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_check_ins() {
+        let db = fixtures::db().await;
+        let other = fixtures::db().await;
+        let (booking, flight_id, passenger_ids) = booking(&db).await;
+        let seat = |seat: &str| NewCheckIn {
+            seat: Some(seat.into()),
+        };
+
+        // both check-ins get a sequence of their own
+        let (first, second) = rocket::futures::join!(
+            CheckIn::create(&db, &booking, passenger_ids[0], flight_id, seat("1A")),
+            CheckIn::create(&other, &booking, passenger_ids[1], flight_id, seat("1B")),
+        );
+        let mut sequences = vec![first.unwrap().sequence, second.unwrap().sequence];
+        sequences.sort_unstable();
+        assert_eq!(vec![1, 2], sequences);
+
+        let error = CheckIn::create(&db, &booking, passenger_ids[2], flight_id, seat("1A"))
+            .await
+            .unwrap_err();
+        assert_eq!(Status::Conflict, error.0);
+        assert_eq!("Seat has already been taken", error.1.error);
+        let error = CheckIn::create(&db, &booking, passenger_ids[0], flight_id, seat("1C"))
+            .await
+            .unwrap_err();
+        assert_eq!(Status::Conflict, error.0);
+
+        let check_in = CheckIn::create(&db, &booking, passenger_ids[2], flight_id, seat("1C"))
+            .await
+            .unwrap();
+        assert_eq!(3, check_in.sequence);
+
+        // nobody checks in for a flight, that has departed
+        db.run(move |conn| {
+            diesel::update(flights::table.find(flight_id))
+                .set(flights::status.eq(FlightStatus::Departed))
+                .execute(conn)
+        })
+        .await
+        .unwrap();
+        let error = CheckIn::create(&db, &booking, passenger_ids[0], flight_id, seat("2A"))
+            .await
+            .unwrap_err();
+        assert_eq!(Status::Conflict, error.0);
+        assert_eq!("Flight has already departed", error.1.error);
+    }
+
+    #[test]
+    fn test_new_check_in_seat() {
+        let mut new_check_in = NewCheckIn::default();
+        assert!(new_check_in.validate().is_ok());
+        new_check_in.seat = Some("12C".into());
+        assert!(new_check_in.validate().is_ok());
+        new_check_in.seat = Some("12G".into());
+        assert!(new_check_in.validate().is_err());
+        new_check_in.seat = Some("C12".into());
+        assert!(new_check_in.validate().is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use super::passenger::is_born;
+use crate::db::models::User;
+use crate::db::schema::companions;
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use chrono::NaiveDate;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use oso::PolarClass;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::Validate;
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct NewCompanion {
+    #[validate(length(min = 1, max = 255))]
+    pub firstname: String,
+    #[validate(length(min = 1, max = 255))]
+    pub lastname: String,
+    #[validate(custom = "is_born")]
+    pub birthday: NaiveDate,
+}
+
+impl NewCompanion {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+#[derive(AsChangeset, Clone, Debug, Insertable)]
+#[table_name = "companions"]
+struct InsertableCompanion {
+    user_id: i32,
+    firstname: String,
+    lastname: String,
+    birthday: NaiveDate,
+}
+
+impl InsertableCompanion {
+    fn new(companion: NewCompanion, user_id: i32) -> Self {
+        InsertableCompanion {
+            user_id,
+            firstname: companion.firstname,
+            lastname: companion.lastname,
+            birthday: companion.birthday,
+        }
+    }
+}
+
+/// A saved co-traveller of a user, who can be added as passenger to the bookings of the user
+#[derive(
+    Associations,
+    Clone,
+    Debug,
+    Deserialize,
+    Identifiable,
+    JsonSchema,
+    PolarClass,
+    Queryable,
+    Serialize,
+)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "companions"]
+pub struct Companion {
+    pub id: i32,
+    #[polar(attribute)]
+    pub user_id: i32,
+    pub firstname: String,
+    pub lastname: String,
+    pub birthday: NaiveDate,
+}
+
+impl Companion {
+    /// Create a dummy companion for a user with the given id. Used within oso policies
+    pub fn dummy_for_user(user_id: i32) -> Self {
+        Companion {
+            id: 0,
+            user_id,
+            firstname: "".into(),
+            lastname: "".into(),
+            birthday: NaiveDate::from_ymd(1970, 1, 1),
+        }
+    }
+
+    pub async fn all_from_user(db: &Db, user_id: i32) -> Vec<Self> {
+        db.run(move |conn| {
+            Companion::belonging_to(&User::dummy(user_id))
+                .order(companions::id)
+                .load(conn)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+
+    pub async fn find(db: &Db, user_id: i32, id: i32) -> Option<Self> {
+        db.run(move |conn| {
+            companions::table
+                .find(id)
+                .filter(companions::user_id.eq(user_id))
+                .first(conn)
+        })
+        .await
+        .ok()
+    }
+
+    pub async fn create(db: &Db, user_id: i32, new_companion: NewCompanion) -> ApiResult<Self> {
+        new_companion.is_valid()?;
+
+        let companion = InsertableCompanion::new(new_companion, user_id);
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                diesel::insert_into(companions::table)
+                    .values(&companion)
+                    .execute(conn)?;
+                companions::table
+                    .filter(companions::user_id.eq(user_id))
+                    .order(companions::id.desc())
+                    .first(conn)
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))
+    }
+
+    pub async fn update(
+        db: &Db,
+        user_id: i32,
+        id: i32,
+        new_companion: NewCompanion,
+    ) -> ApiResult<Self> {
+        new_companion.is_valid()?;
+        Companion::find(db, user_id, id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find companion"))?;
+
+        let companion = InsertableCompanion::new(new_companion, user_id);
+        db.run(move |conn| {
+            diesel::update(companions::table.find(id))
+                .set(&companion)
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        Companion::find(db, user_id, id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find companion"))
+    }
+
+    /// Delete a companion. Passengers added from the companion keep their details.
+    pub async fn delete(db: &Db, user_id: i32, id: i32) -> ApiResult<()> {
+        Companion::find(db, user_id, id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find companion"))?;
+
+        db.run(move |conn| diesel::delete(companions::table.find(id)).execute(conn))
+            .await
+            .map_or_else(
+                |e| Err(error(e, Status::InternalServerError, "")),
+                |_res| Ok(()),
+            )
+    }
+}
+
+pub(super) fn register_polar_classes(oso: &mut oso::Oso) -> oso::Result<()> {
+    oso.register_class(Companion::get_polar_class())
+}
@@ -0,0 +1,263 @@
+use crate::db::models::oauth_identity::{count_login_methods, Unlink};
+use crate::db::models::{NewUser, User};
+use crate::db::schema::{users, users_credentials};
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::Validate;
+
+/// Failed logins in a row, after which the credentials get locked
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+/// Minutes the credentials stay locked after too many failed logins
+const LOCKOUT_MINUTES: i64 = 15;
+
+/// Hash a password with Argon2id and a random salt, in PHC string format
+fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+}
+
+/// Check a password against a hash in PHC string format
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// A user registering with email and password instead of OAuth
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct NewCredentials {
+    #[serde(flatten)]
+    #[validate]
+    pub user: NewUser,
+    #[validate(length(min = 10, max = 128))]
+    pub password: String,
+}
+
+impl NewCredentials {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// A password an existing user adds as a login method
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct NewPassword {
+    #[validate(length(min = 10, max = 128))]
+    pub password: String,
+}
+
+impl NewPassword {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LoginCredentials {
+    pub email: String,
+    pub password: String,
+}
+
+/// Password hash of a user, along with the state of the lockout
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "users_credentials"]
+pub struct Credentials {
+    user_id: i32,
+    password_hash: String,
+    failed_attempts: i32,
+    locked_until: Option<NaiveDateTime>,
+}
+
+/// Outcome of a password check, which is evaluated within a transaction
+enum Attempt {
+    Success(i32),
+    Failure,
+    Locked,
+}
+
+impl Credentials {
+    /// Register a new user along with the hash of the password. Returns the new user.
+    pub async fn register(db: &Db, new_credentials: NewCredentials) -> ApiResult<User> {
+        new_credentials.is_valid()?;
+
+        db.run(move |conn| {
+            let password_hash = hash_password(&new_credentials.password)
+                .map_err(|e| error(e, Status::InternalServerError, ""))?;
+            conn.transaction::<_, DieselError, _>(|| {
+                diesel::insert_into(users::table)
+                    .values(&new_credentials.user)
+                    .execute(conn)?;
+                let user: User = users::table
+                    .filter(users::email.eq(&new_credentials.user.email))
+                    .first(conn)?;
+                diesel::insert_into(users_credentials::table)
+                    .values(&Credentials {
+                        user_id: user.id,
+                        password_hash,
+                        failed_attempts: 0,
+                        locked_until: None,
+                    })
+                    .execute(conn)?;
+                Ok(user)
+            })
+            .map_err(|e| match e {
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    error(e, Status::Conflict, "Email is already registered")
+                }
+                e => error(e, Status::InternalServerError, ""),
+            })
+        })
+        .await
+    }
+
+    /// Add a password to an existing user, who is logging in with a provider so far
+    pub async fn link(db: &Db, user_id: i32, new_password: NewPassword) -> ApiResult<()> {
+        new_password.is_valid()?;
+
+        db.run(move |conn| {
+            let password_hash = hash_password(&new_password.password)
+                .map_err(|e| error(e, Status::InternalServerError, ""))?;
+            diesel::insert_into(users_credentials::table)
+                .values(&Credentials {
+                    user_id,
+                    password_hash,
+                    failed_attempts: 0,
+                    locked_until: None,
+                })
+                .execute(conn)
+                .map_err(|e| match e {
+                    DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                        error(e, Status::Conflict, "Password is already set")
+                    }
+                    e => error(e, Status::InternalServerError, ""),
+                })?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove the password of a user, unless it is the last login method
+    pub async fn unlink(db: &Db, user_id: i32) -> ApiResult<()> {
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                if count_login_methods(conn, user_id)? <= 1 {
+                    return Ok(Unlink::LastMethod);
+                }
+                let deleted =
+                    diesel::delete(users_credentials::table.find(user_id)).execute(conn)?;
+                Ok(if deleted == 0 {
+                    Unlink::NotFound
+                } else {
+                    Unlink::Done
+                })
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_result()
+    }
+
+    /// Check the password of a user by email. After `MAX_FAILED_ATTEMPTS` failed attempts in a
+    /// row, the credentials are locked for `LOCKOUT_MINUTES`. Returns the id of the user.
+    pub async fn verify(db: &Db, login: LoginCredentials) -> ApiResult<i32> {
+        let attempt = db
+            .run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    let credentials = users_credentials::table
+                        .inner_join(users::table)
+                        .filter(users::email.eq(&login.email))
+                        .select(users_credentials::all_columns)
+                        .for_update()
+                        .first::<Credentials>(conn)
+                        .optional()?;
+                    let credentials = match credentials {
+                        Some(credentials) => credentials,
+                        None => return Ok(Attempt::Failure),
+                    };
+
+                    let now = Utc::now().naive_utc();
+                    if credentials.locked_until.map_or(false, |until| until > now) {
+                        return Ok(Attempt::Locked);
+                    }
+
+                    let row = users_credentials::table.find(credentials.user_id);
+                    if verify_password(&login.password, &credentials.password_hash) {
+                        diesel::update(row)
+                            .set((
+                                users_credentials::failed_attempts.eq(0),
+                                users_credentials::locked_until.eq(None::<NaiveDateTime>),
+                            ))
+                            .execute(conn)?;
+                        return Ok(Attempt::Success(credentials.user_id));
+                    }
+
+                    let failed_attempts = credentials.failed_attempts + 1;
+                    if failed_attempts >= MAX_FAILED_ATTEMPTS {
+                        diesel::update(row)
+                            .set((
+                                users_credentials::failed_attempts.eq(0),
+                                users_credentials::locked_until
+                                    .eq(now + Duration::minutes(LOCKOUT_MINUTES)),
+                            ))
+                            .execute(conn)?;
+                    } else {
+                        diesel::update(row)
+                            .set(users_credentials::failed_attempts.eq(failed_attempts))
+                            .execute(conn)?;
+                    }
+                    Ok(Attempt::Failure)
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        match attempt {
+            Attempt::Success(user_id) => Ok(user_id),
+            Attempt::Failure => Err(error("", Status::Unauthorized, "Invalid email or password")),
+            Attempt::Locked => Err(error(
+                "",
+                Status::TooManyRequests,
+                "Too many failed logins, please try again later",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify_password() {
+        let hash = hash_password("correct horse battery").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse battery", &hash));
+        assert!(!verify_password("wrong horse battery", &hash));
+        assert!(!verify_password("correct horse battery", "not a hash"));
+    }
+
+    #[test]
+    fn test_password_hashes_are_salted() {
+        assert_ne!(
+            hash_password("correct horse battery").unwrap(),
+            hash_password("correct horse battery").unwrap()
+        );
+    }
+}
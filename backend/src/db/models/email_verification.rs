@@ -0,0 +1,231 @@
+use crate::db::models::User;
+use crate::db::schema::{users, users_email_verifications};
+use crate::db::Db;
+use crate::mail::{self, Mail, MailToken, Mailer, TokenPurpose};
+use crate::routes::{error, ApiResult};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+
+/// Hours a verification link is valid
+const VERIFICATION_VALID_HOURS: i64 = 24;
+
+/// The nonce of the last verification link sent to a user. Only this link is accepted, so
+/// requesting a new one invalidates the previous links.
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "users_email_verifications"]
+pub struct EmailVerification {
+    user_id: i32,
+    email: String,
+    nonce: String,
+    created: NaiveDateTime,
+}
+
+/// The token of a verification link
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct EmailConfirmation {
+    pub token: String,
+}
+
+/// Outcome of a verification, which is evaluated within a transaction
+enum Confirmation {
+    Verified(User),
+    Rejected,
+}
+
+impl EmailVerification {
+    /// Send a verification link to the current email address of a user
+    pub async fn request(db: &Db, mailer: &Mailer, user_id: i32) -> ApiResult<()> {
+        let user = User::find_by_id(db, user_id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find user"))?
+            .into_inner();
+        if user.verified {
+            return Err(error(
+                "",
+                Status::Conflict,
+                "Email address is already verified",
+            ));
+        }
+
+        let token = MailToken::new(
+            TokenPurpose::VerifyEmail,
+            user.id,
+            user.email.clone(),
+            Duration::hours(VERIFICATION_VALID_HOURS),
+        );
+        let verification = EmailVerification {
+            user_id: user.id,
+            email: user.email.clone(),
+            nonce: token.nonce.clone(),
+            created: Utc::now().naive_utc(),
+        };
+        db.run(move |conn| {
+            diesel::replace_into(users_email_verifications::table)
+                .values(&verification)
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        let mail = Mail {
+            to: user.email,
+            subject: "Verify your email address".into(),
+            body: format!(
+                "Hello {},\n\nplease verify your email address within {} hours:\n\n{}\n",
+                user.firstname,
+                VERIFICATION_VALID_HOURS,
+                mail::link("/verify-email", &token.sign())
+            ),
+        };
+        mailer.send(&mail).await.map_err(|e| {
+            error(
+                e,
+                Status::InternalServerError,
+                "Error sending mail, please try again later",
+            )
+        })
+    }
+
+    /// Mark the email address of a user as verified. The link has to be the last one sent to the
+    /// user and the address must not have changed since.
+    pub async fn confirm(db: &Db, confirmation: EmailConfirmation) -> ApiResult<User> {
+        let token =
+            MailToken::verify(&confirmation.token, TokenPurpose::VerifyEmail).map_err(|e| {
+                error(
+                    e,
+                    Status::BadRequest,
+                    "Invalid or expired verification link",
+                )
+            })?;
+
+        let confirmation = db
+            .run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    let verification = users_email_verifications::table
+                        .find(token.user_id)
+                        .for_update()
+                        .first::<EmailVerification>(conn)
+                        .optional()?;
+                    let user = users::table
+                        .find(token.user_id)
+                        .first::<User>(conn)
+                        .optional()?;
+                    let user = match (verification, user) {
+                        (Some(verification), Some(user))
+                            if verification.nonce == token.nonce
+                                && verification.email == token.email
+                                && user.email == token.email =>
+                        {
+                            user
+                        }
+                        _ => return Ok(Confirmation::Rejected),
+                    };
+
+                    diesel::delete(users_email_verifications::table.find(token.user_id))
+                        .execute(conn)?;
+                    diesel::update(users::table.find(token.user_id))
+                        .set(users::verified.eq(true))
+                        .execute(conn)?;
+                    Ok(Confirmation::Verified(User {
+                        verified: true,
+                        ..user
+                    }))
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        match confirmation {
+            Confirmation::Verified(user) => Ok(user),
+            Confirmation::Rejected => Err(error(
+                "",
+                Status::BadRequest,
+                "Invalid or expired verification link",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::NewUser;
+    use crate::mail::Outbox;
+
+    async fn confirm(db: &Db, token: String) -> ApiResult<User> {
+        EmailVerification::confirm(db, EmailConfirmation { token }).await
+    }
+
+    #[rocket::async_test]
+    async fn test_confirm_link_once() {
+        let db = fixtures::db().await;
+        let outbox = Outbox::default();
+        let mailer = Mailer::outbox(&outbox);
+        let user = fixtures::user(&db).await;
+        assert!(!user.verified);
+
+        EmailVerification::request(&db, &mailer, user.id)
+            .await
+            .unwrap();
+        let token = outbox.last_token();
+        assert!(confirm(&db, token.clone()).await.unwrap().verified);
+        assert!(User::is_verified(&db, user.id).await);
+
+        let error = confirm(&db, token).await.unwrap_err();
+        assert_eq!(Status::BadRequest, error.0);
+    }
+
+    #[rocket::async_test]
+    async fn test_reject_superseded_link() {
+        let db = fixtures::db().await;
+        let outbox = Outbox::default();
+        let mailer = Mailer::outbox(&outbox);
+        let user = fixtures::user(&db).await;
+
+        EmailVerification::request(&db, &mailer, user.id)
+            .await
+            .unwrap();
+        let superseded = outbox.last_token();
+        EmailVerification::request(&db, &mailer, user.id)
+            .await
+            .unwrap();
+        let latest = outbox.last_token();
+
+        assert!(confirm(&db, superseded).await.is_err());
+        assert!(!User::is_verified(&db, user.id).await);
+        assert!(confirm(&db, latest).await.is_ok());
+    }
+
+    #[rocket::async_test]
+    async fn test_reject_link_to_previous_address() {
+        let db = fixtures::db().await;
+        let outbox = Outbox::default();
+        let mailer = Mailer::outbox(&outbox);
+        let user = fixtures::user(&db).await;
+
+        EmailVerification::request(&db, &mailer, user.id)
+            .await
+            .unwrap();
+        let token = outbox.last_token();
+        let changed = NewUser {
+            firstname: user.firstname.clone(),
+            lastname: user.lastname.clone(),
+            email: format!("changed-{}", user.email),
+            birthday: user.birthday,
+            gender: user.gender.clone(),
+        };
+        User::update_and_return(&db, user.id, changed)
+            .await
+            .unwrap();
+
+        assert!(confirm(&db, token).await.is_err());
+        assert!(!User::is_verified(&db, user.id).await);
+    }
+}
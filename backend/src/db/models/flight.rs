@@ -1,5 +1,11 @@
-use crate::db::models::DbResult;
-use crate::db::schema::{bookings, flights, flights_offers, flights_offers_with_occupancy};
+use super::carrier::{split_flight_number, valid_flight_numbers, RE_FLIGHT_NUMBER};
+use super::check_in;
+use crate::db::models::{
+    Booking, Carrier, DbResult, FlightCodeshare, LoyaltyTransaction, PassengerType,
+};
+use crate::db::schema::{
+    bookings, check_ins, flights, flights_codeshares, flights_offers, flights_offers_with_occupancy,
+};
 use crate::db::Db;
 use crate::routes::OfferFilter;
 use crate::routes::{error, ApiResult};
@@ -13,6 +19,7 @@ use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
 use rocket_okapi::okapi::schemars;
 use rocket_okapi::okapi::schemars::JsonSchema;
+use std::collections::HashMap;
 use validator::{Validate, ValidationError};
 
 #[derive(Debug, Clone, Deserialize, Serialize, DbEnum, JsonSchema)]
@@ -22,6 +29,48 @@ pub enum Currency {
     Euro,
 }
 
+impl Currency {
+    /// ISO 4217 code of the currency
+    pub fn code(&self) -> &'static str {
+        match self {
+            Currency::Dollar => "USD",
+            Currency::Euro => "EUR",
+        }
+    }
+}
+
+/// Operational status of a single flight
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, DbEnum, JsonSchema, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum FlightStatus {
+    Scheduled,
+    /// The estimated times of the flight differ from the scheduled times
+    Delayed,
+    Boarding,
+    Departed,
+    Arrived,
+    Cancelled,
+    /// The flight lands at an airport other than its scheduled destination
+    Diverted,
+}
+
+impl FlightStatus {
+    /// Check whether a flight with this status may change to the given one. Arrived and cancelled
+    /// flights are final, the other statuses may be updated again, like with new estimates.
+    pub fn can_become(self, next: FlightStatus) -> bool {
+        use FlightStatus::*;
+        match self {
+            Scheduled | Delayed => {
+                matches!(next, Scheduled | Delayed | Boarding | Departed | Cancelled)
+            }
+            Boarding => matches!(next, Delayed | Boarding | Departed | Cancelled),
+            Departed => matches!(next, Departed | Arrived | Diverted),
+            Diverted => matches!(next, Diverted | Arrived),
+            Arrived | Cancelled => false,
+        }
+    }
+}
+
 /// Regex to validate the ICAO of a given flight
 static RE_ICAO: Lazy<Regex> = Lazy::new(|| Regex::new(r"[A-Z]{4}$").unwrap());
 
@@ -38,6 +87,13 @@ pub struct NewFlight {
     pub arrival_icao: String,
     /// Must be formatted like `2015-07-01 08:59:60 +0000`
     pub arrival_time: DateTime<Utc>,
+    /// Flight number of the operating carrier, like `LH400`
+    #[validate(regex = "RE_FLIGHT_NUMBER")]
+    pub flight_number: Option<String>,
+    /// Marketing flight numbers of codeshare partners
+    #[serde(default)]
+    #[validate(custom = "valid_flight_numbers")]
+    pub codeshares: Vec<String>,
 }
 
 impl NewFlight {
@@ -67,16 +123,20 @@ pub struct InsertableFlight {
     pub departure_time: NaiveDateTime,
     pub arrival_icao: String,
     pub arrival_time: NaiveDateTime,
+    pub carrier_id: Option<i32>,
+    pub flight_number: Option<String>,
 }
 
 impl InsertableFlight {
-    pub fn new(new_flight: &NewFlight, offer_id: i32) -> Self {
+    pub fn new(new_flight: &NewFlight, offer_id: i32, carrier_id: Option<i32>) -> Self {
         InsertableFlight {
             offer_id,
             departure_icao: new_flight.departure_icao.clone(),
             departure_time: new_flight.departure_time.naive_utc(),
             arrival_icao: new_flight.arrival_icao.clone(),
             arrival_time: new_flight.arrival_time.naive_utc(),
+            carrier_id,
+            flight_number: new_flight.flight_number.clone(),
         }
     }
 }
@@ -89,24 +149,221 @@ impl InsertableFlight {
 #[serde(rename_all = "camelCase")]
 #[table_name = "flights"]
 pub struct Flight {
-    id: i32,
-    offer_id: i32,
+    pub id: i32,
+    pub offer_id: i32,
     pub departure_icao: String,
     pub departure_time: NaiveDateTime,
     pub arrival_icao: String,
     pub arrival_time: NaiveDateTime,
+    pub status: FlightStatus,
+    pub estimated_departure_time: Option<NaiveDateTime>,
+    pub estimated_arrival_time: Option<NaiveDateTime>,
+    /// Airport the flight has been diverted to
+    pub diverted_icao: Option<String>,
+    /// Operating carrier of the flight
+    pub carrier_id: Option<i32>,
+    /// Flight number of the operating carrier, like `LH400`
+    pub flight_number: Option<String>,
+}
+
+/// A flight along with the marketing flight numbers of its codeshare partners
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct FlightWithCodeshares {
+    #[serde(flatten)]
+    pub flight: Flight,
+    pub codeshares: Vec<String>,
+}
+
+/// Status update of a flight, issued by an administrator
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema, Validate)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[validate(schema(function = "status_details_given"))]
+pub struct FlightStatusUpdate {
+    pub status: FlightStatus,
+    /// Must be formatted like `2015-07-01 08:59:60 +0000`
+    pub estimated_departure_time: Option<DateTime<Utc>>,
+    /// Must be formatted like `2015-07-01 08:59:60 +0000`
+    pub estimated_arrival_time: Option<DateTime<Utc>>,
+    #[validate(regex = "RE_ICAO")]
+    pub diverted_icao: Option<String>,
+}
+
+impl FlightStatusUpdate {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// Custom validator function to make sure delays come with an estimated time and diversions with
+/// the new destination
+fn status_details_given(update: &FlightStatusUpdate) -> Result<(), ValidationError> {
+    match update.status {
+        FlightStatus::Delayed
+            if update.estimated_departure_time.is_none()
+                && update.estimated_arrival_time.is_none() =>
+        {
+            Err(ValidationError::new(
+                "Invalid status: Delayed flights need an estimated time",
+            ))
+        }
+        FlightStatus::Diverted if update.diverted_icao.is_none() => Err(ValidationError::new(
+            "Invalid status: Diverted flights need the ICAO of the new destination",
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[derive(AsChangeset)]
+#[table_name = "flights"]
+#[changeset_options(treat_none_as_null = "true")]
+struct FlightStatusChangeset {
+    status: FlightStatus,
+    estimated_departure_time: Option<NaiveDateTime>,
+    estimated_arrival_time: Option<NaiveDateTime>,
+    diverted_icao: Option<String>,
 }
 
 impl Flight {
+    pub async fn find(db: &Db, id: i32) -> Option<Flight> {
+        db.run(move |conn| flights::table.find(id).first(conn))
+            .await
+            .ok()
+    }
+
     pub async fn all_from_offer(db: &Db, offer_id: i32) -> Vec<Flight> {
         db.run(move |conn| Flight::belonging_to(&FlightOffer::dummy(offer_id)).load(conn))
             .await
             .unwrap_or_else(|_| Vec::new())
     }
+
+    /// List the flights of an offer in order of departure, along with their codeshares
+    pub async fn all_from_offer_with_codeshares(
+        db: &Db,
+        offer_id: i32,
+    ) -> Vec<FlightWithCodeshares> {
+        let mut flights = Flight::all_from_offer(db, offer_id).await;
+        flights.sort_by_key(|f| f.departure_time);
+        let flight_ids = flights.iter().map(|f| f.id).collect::<Vec<i32>>();
+        let codeshares = db
+            .run(move |conn| {
+                flights_codeshares::table
+                    .filter(flights_codeshares::flight_id.eq_any(flight_ids))
+                    .order(flights_codeshares::flight_number)
+                    .load::<FlightCodeshare>(conn)
+            })
+            .await
+            .unwrap_or_else(|_| Vec::new());
+
+        flights
+            .into_iter()
+            .map(|flight| FlightWithCodeshares {
+                codeshares: codeshares
+                    .iter()
+                    .filter(|c| c.flight_id == flight.id)
+                    .map(|c| c.flight_number.clone())
+                    .collect(),
+                flight,
+            })
+            .collect()
+    }
+
+    /// Departure time of the first flight of an offer
+    pub async fn first_departure(db: &Db, offer_id: i32) -> Option<NaiveDateTime> {
+        db.run(move |conn| {
+            flights::table
+                .filter(flights::offer_id.eq(offer_id))
+                .select(diesel::dsl::min(flights::departure_time))
+                .first::<Option<NaiveDateTime>>(conn)
+        })
+        .await
+        .ok()?
+    }
+
+    /// Update the operational status of a flight of an offer, if the flight may change to the
+    /// new status. Cancelled flights can't be updated anymore, and flag all bookings of the offer
+    /// as disrupted. Arrived flights credit loyalty points to the customers of the offer.
+    pub async fn update_status(
+        db: &Db,
+        offer_id: i32,
+        flight_id: i32,
+        update: FlightStatusUpdate,
+    ) -> ApiResult<Flight> {
+        update.is_valid()?;
+
+        Flight::find(db, flight_id)
+            .await
+            .filter(|f| f.offer_id == offer_id)
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find flight"))?;
+
+        let status = update.status;
+        let changeset = FlightStatusChangeset {
+            status,
+            estimated_departure_time: update.estimated_departure_time.map(|t| t.naive_utc()),
+            estimated_arrival_time: update.estimated_arrival_time.map(|t| t.naive_utc()),
+            diverted_icao: update.diverted_icao,
+        };
+        // the bookings are flagged along with the cancellation, so neither happens without the
+        // other
+        let current = db
+            .run(move |conn| {
+                conn.transaction::<_, diesel::result::Error, _>(|| {
+                    let current = flights::table
+                        .find(flight_id)
+                        .select(flights::status)
+                        .for_update()
+                        .first::<FlightStatus>(conn)?;
+                    if !current.can_become(status) {
+                        return Ok(current);
+                    }
+                    diesel::update(flights::table.find(flight_id))
+                        .set(&changeset)
+                        .execute(conn)?;
+                    if status == FlightStatus::Cancelled {
+                        Booking::disrupt_offer(conn, offer_id)?;
+                    }
+                    Ok(status)
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        if current != status {
+            let message = match current {
+                FlightStatus::Cancelled => "Flight has been cancelled".into(),
+                _ => format!("Flight can't change from {:?} to {:?}", current, status),
+            };
+            return Err(error("", Status::Conflict, &message));
+        }
+
+        if update.status == FlightStatus::Arrived {
+            LoyaltyTransaction::accrue_flight(db, offer_id, flight_id).await?;
+        }
+
+        Flight::find(db, flight_id)
+            .await
+            .ok_or_else(|| error("", Status::InternalServerError, ""))
+    }
+}
+
+/// Calculate the number of seats, that can be sold with the given overbooking policy. If both a
+/// percentage and an absolute limit are given, the stricter one applies.
+pub fn sellable_seats(seats: i32, percentage: Option<f32>, limit: Option<i32>) -> i32 {
+    let by_percentage = percentage.map(|p| (seats as f32 * p / 100.0).floor() as i32);
+    let extra = match (by_percentage, limit) {
+        (Some(p), Some(l)) => p.min(l),
+        (Some(p), None) => p,
+        (None, Some(l)) => l,
+        (None, None) => 0,
+    };
+    seats + extra.max(0)
 }
 
 #[derive(Debug, Clone, Insertable, Deserialize, Serialize, JsonSchema, Validate)]
 #[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
 #[table_name = "flights_offers"]
 pub struct NewFlightOffer {
     #[validate(range(min = 1, max = 2000))]
@@ -114,6 +371,25 @@ pub struct NewFlightOffer {
     #[validate(range(min = 1, max = 99999))]
     price: f32,
     currency: Currency,
+    /// Whether customers get money back when cancelling their booking
+    #[serde(default = "refundable_by_default")]
+    refundable: bool,
+    /// Seats sold above capacity, in percent of the seats
+    #[validate(range(min = 0, max = 100))]
+    overbooking_percentage: Option<f32>,
+    /// Maximum number of seats sold above capacity
+    #[validate(range(min = 0, max = 2000))]
+    overbooking_limit: Option<i32>,
+    /// Price for children, the regular price applies if omitted
+    #[validate(range(min = 0, max = 99999))]
+    child_price: Option<f32>,
+    /// Price for infants on the lap of an adult, they travel for free if omitted
+    #[validate(range(min = 0, max = 99999))]
+    infant_price: Option<f32>,
+}
+
+fn refundable_by_default() -> bool {
+    true
 }
 
 impl NewFlightOffer {
@@ -125,12 +401,42 @@ impl NewFlightOffer {
 
 #[derive(Debug, Clone, Deserialize, Serialize, Identifiable, Queryable, JsonSchema)]
 #[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
 #[table_name = "flights_offers"]
 pub struct FlightOffer {
     id: i32,
     seats: i32,
     price: f32,
     currency: Currency,
+    refundable: bool,
+    cancelled: bool,
+    overbooking_percentage: Option<f32>,
+    overbooking_limit: Option<i32>,
+    child_price: Option<f32>,
+    infant_price: Option<f32>,
+}
+
+/// Overbooking policy of an offer. Without percentage and limit, no seats are sold above
+/// capacity.
+#[derive(Debug, Clone, AsChangeset, Deserialize, Serialize, JsonSchema, Validate)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[table_name = "flights_offers"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct OverbookingPolicy {
+    /// Seats sold above capacity, in percent of the seats
+    #[validate(range(min = 0, max = 100))]
+    pub overbooking_percentage: Option<f32>,
+    /// Maximum number of seats sold above capacity
+    #[validate(range(min = 0, max = 2000))]
+    pub overbooking_limit: Option<i32>,
+}
+
+impl OverbookingPolicy {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
 }
 
 impl FlightOffer {
@@ -153,12 +459,104 @@ pub struct FlightOfferWithOccupancy {
     pub currency: Currency,
     pub departure_icao: String,
     pub arrival_icao: String,
+    pub refundable: bool,
+    pub cancelled: bool,
+    pub overbooking_percentage: Option<f32>,
+    pub overbooking_limit: Option<i32>,
+    pub child_price: Option<f32>,
+    pub infant_price: Option<f32>,
+}
+
+/// An offer with more booked seats than physical seats, whose check-in has been opened
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct OversoldOffer {
+    pub offer_id: i32,
+    pub seats: i32,
+    pub occupied: i64,
+    /// Number of passengers, that may be denied boarding
+    pub oversold: i64,
+    /// Passengers checked in for the first flight of the offer
+    pub checked_in: i64,
+    pub departure_time: NaiveDateTime,
 }
 
 impl FlightOfferWithOccupancy {
+    /// List all offers, that are currently oversold and whose first flight is open for check-in
+    pub async fn oversold(db: &Db) -> Vec<OversoldOffer> {
+        let offers = db
+            .run(move |conn| {
+                flights_offers_with_occupancy::table
+                    .filter(flights_offers_with_occupancy::cancelled.eq(false))
+                    .load::<FlightOfferWithOccupancy>(conn)
+            })
+            .await
+            .unwrap_or_else(|_| Vec::new())
+            .into_iter()
+            .filter(|o| o.occupied > o.seats as i64);
+
+        let now = Utc::now().naive_utc();
+        let mut oversold = Vec::new();
+        for offer in offers {
+            let first_flight = Flight::all_from_offer(db, offer.id)
+                .await
+                .into_iter()
+                .min_by_key(|f| f.departure_time);
+            let flight = match first_flight {
+                Some(flight) => flight,
+                None => continue,
+            };
+            let (opens, _closes) = check_in::window(flight.departure_time);
+            if now < opens || now >= flight.departure_time {
+                continue;
+            }
+
+            let flight_id = flight.id;
+            let checked_in = db
+                .run(move |conn| {
+                    check_ins::table
+                        .filter(check_ins::flight_id.eq(flight_id))
+                        .count()
+                        .get_result::<i64>(conn)
+                })
+                .await
+                .unwrap_or(0);
+            oversold.push(OversoldOffer {
+                offer_id: offer.id,
+                seats: offer.seats,
+                occupied: offer.occupied,
+                oversold: offer.occupied - offer.seats as i64,
+                checked_in,
+                departure_time: flight.departure_time,
+            });
+        }
+        oversold
+    }
+
+    /// Number of seats, that can be sold including overbooking
+    pub fn sellable_seats(&self) -> i32 {
+        sellable_seats(
+            self.seats,
+            self.overbooking_percentage,
+            self.overbooking_limit,
+        )
+    }
+
+    /// Price of a single passenger of the given type
+    pub fn price_for(&self, passenger_type: PassengerType) -> f32 {
+        match passenger_type {
+            PassengerType::Adult => self.price,
+            PassengerType::Child => self.child_price.unwrap_or(self.price),
+            PassengerType::Infant => self.infant_price.unwrap_or(0.0),
+        }
+    }
+
     pub async fn get_all(db: &Db, filter: OfferFilter) -> Vec<FlightOfferWithOccupancy> {
         db.run(move |conn| {
-            let mut query = flights_offers_with_occupancy::table.into_boxed();
+            let mut query = flights_offers_with_occupancy::table
+                .filter(flights_offers_with_occupancy::cancelled.eq(false))
+                .into_boxed();
 
             if let Some(departure_icao) = filter.departure_icao {
                 query =
@@ -169,6 +567,22 @@ impl FlightOfferWithOccupancy {
                 query = query.filter(flights_offers_with_occupancy::arrival_icao.eq(arrival_icao));
             }
 
+            // match operating as well as codeshare flight numbers
+            if let Some(flight_number) = filter.flight_number {
+                let operated = flights::table
+                    .filter(flights::flight_number.eq(flight_number.clone()))
+                    .select(flights::offer_id);
+                let marketed = flights::table
+                    .inner_join(flights_codeshares::table)
+                    .filter(flights_codeshares::flight_number.eq(flight_number))
+                    .select(flights::offer_id);
+                query = query.filter(
+                    flights_offers_with_occupancy::id
+                        .eq_any(operated)
+                        .or(flights_offers_with_occupancy::id.eq_any(marketed)),
+                );
+            }
+
             query.load(conn)
         })
         .await
@@ -193,9 +607,44 @@ impl FlightOffer {
             seats: 0,
             price: 0.0,
             currency: Currency::Euro,
+            refundable: true,
+            cancelled: false,
+            overbooking_percentage: None,
+            overbooking_limit: None,
+            child_price: None,
+            infant_price: None,
         }
     }
 
+    /// Mark an offer as cancelled, so that it can't be booked anymore
+    pub async fn cancel(db: &Db, id: i32) -> DbResult {
+        db.run(move |conn| {
+            diesel::update(flights_offers::table.find(id))
+                .set(flights_offers::cancelled.eq(true))
+                .execute(conn)
+        })
+        .await
+    }
+
+    pub async fn set_overbooking(db: &Db, id: i32, policy: OverbookingPolicy) -> ApiResult<()> {
+        policy.is_valid()?;
+
+        db.run(move |conn| {
+            diesel::update(flights_offers::table.find(id))
+                .set(&policy)
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))
+        .and_then(|updated| {
+            if updated == 0 {
+                Err(error("", Status::NotFound, "Cannot find flight offer"))
+            } else {
+                Ok(())
+            }
+        })
+    }
+
     pub async fn booked_seats(db: &Db, id: i32) -> Option<i64> {
         db.run(move |conn| {
             flights_offers::table
@@ -219,18 +668,65 @@ impl FlightOffer {
         .await
     }
 
-    pub async fn save_flights(db: &Db, offer_id: i32, flights: Json<Vec<NewFlight>>) -> DbResult {
-        let insertable = flights
+    /// Save the flights of an offer along with their codeshares. Every carrier referenced by a
+    /// flight number must exist.
+    pub async fn save_flights(db: &Db, offer_id: i32, flights: Vec<NewFlight>) -> ApiResult<()> {
+        let mut carrier_ids = HashMap::new();
+        for flight_number in flights
             .iter()
-            .map(|flight| InsertableFlight::new(flight, offer_id))
-            .collect::<Vec<InsertableFlight>>();
+            .flat_map(|f| f.flight_number.iter().chain(f.codeshares.iter()))
+        {
+            let (iata, _number) = split_flight_number(flight_number)
+                .ok_or_else(|| error("", Status::BadRequest, "Invalid flight number"))?;
+            if !carrier_ids.contains_key(iata) {
+                let carrier = Carrier::find_by_iata(db, iata.to_string())
+                    .await
+                    .ok_or_else(|| {
+                        error("", Status::BadRequest, &format!("Unknown carrier {}", iata))
+                    })?;
+                carrier_ids.insert(iata.to_string(), carrier.id);
+            }
+        }
+        let carrier_of = move |flight_number: &str| {
+            split_flight_number(flight_number).and_then(|(iata, _)| carrier_ids.get(iata).copied())
+        };
 
         db.run(move |conn| {
-            diesel::insert_into(flights::table)
-                .values(&insertable)
-                .execute(conn)
+            conn.transaction::<_, diesel::result::Error, _>(|| {
+                for flight in flights.iter() {
+                    let carrier_id = flight.flight_number.as_deref().and_then(&carrier_of);
+                    diesel::insert_into(flights::table)
+                        .values(&InsertableFlight::new(flight, offer_id, carrier_id))
+                        .execute(conn)?;
+                    if flight.codeshares.is_empty() {
+                        continue;
+                    }
+
+                    let flight_id = flights::table
+                        .filter(flights::offer_id.eq(offer_id))
+                        .select(diesel::dsl::max(flights::id))
+                        .first::<Option<i32>>(conn)?
+                        .ok_or(diesel::result::Error::NotFound)?;
+                    let codeshares = flight
+                        .codeshares
+                        .iter()
+                        .filter_map(|n| {
+                            carrier_of(n.as_str()).map(|carrier_id| FlightCodeshare {
+                                flight_id,
+                                carrier_id,
+                                flight_number: n.clone(),
+                            })
+                        })
+                        .collect::<Vec<FlightCodeshare>>();
+                    diesel::insert_into(flights_codeshares::table)
+                        .values(&codeshares)
+                        .execute(conn)?;
+                }
+                Ok(())
+            })
         })
         .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))
     }
 
     pub async fn last_inserted(db: &Db) -> Option<Json<FlightOffer>> {
@@ -244,3 +740,45 @@ impl FlightOffer {
         .ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_transitions() {
+        use FlightStatus::*;
+        assert!(Scheduled.can_become(Delayed));
+        assert!(Delayed.can_become(Delayed));
+        assert!(Boarding.can_become(Departed));
+        assert!(Departed.can_become(Diverted));
+        assert!(Diverted.can_become(Arrived));
+        assert!(!Arrived.can_become(Scheduled));
+        assert!(!Arrived.can_become(Arrived));
+        assert!(!Cancelled.can_become(Boarding));
+        assert!(!Scheduled.can_become(Arrived));
+        assert!(!Departed.can_become(Cancelled));
+    }
+
+    #[test]
+    fn test_sellable_seats_without_overbooking() {
+        assert_eq!(100, sellable_seats(100, None, None));
+    }
+
+    #[test]
+    fn test_sellable_seats_by_percentage() {
+        assert_eq!(105, sellable_seats(100, Some(5.0), None));
+        assert_eq!(10, sellable_seats(10, Some(5.0), None));
+    }
+
+    #[test]
+    fn test_sellable_seats_by_limit() {
+        assert_eq!(103, sellable_seats(100, None, Some(3)));
+    }
+
+    #[test]
+    fn test_sellable_seats_stricter_policy_applies() {
+        assert_eq!(103, sellable_seats(100, Some(10.0), Some(3)));
+        assert_eq!(102, sellable_seats(100, Some(2.0), Some(8)));
+    }
+}
@@ -0,0 +1,564 @@
+use crate::db::models::{Address, Booking, BookingStatus, Currency, PriceItem, User};
+use crate::db::schema::{bookings, invoices, invoices_items};
+use crate::db::Db;
+use crate::pdf::{Document, Font, Page};
+use crate::routes::{error, ApiResult};
+use crate::CONFIG;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+
+/// VAT rate in percent, used if `INVOICE_VAT_RATE` isn't configured
+const DEFAULT_VAT_RATE: f32 = 19.0;
+/// Issuer printed on invoices, used if `INVOICE_ISSUER` isn't configured
+const DEFAULT_ISSUER: &str = "SkyRocket";
+/// Number of items printed on a single page of the PDF
+const ITEMS_PER_PAGE: usize = 25;
+
+/// Round an amount to cents
+fn round(amount: f32) -> f32 {
+    (amount * 100.0).round() / 100.0
+}
+
+#[derive(Clone, Debug, Queryable)]
+struct InvoiceRecord {
+    id: i32,
+    user_id: i32,
+    offer_id: i32,
+    booking_reference: String,
+    supplement: i32,
+    issuer: String,
+    buyer_name: String,
+    buyer_email: String,
+    buyer_address: Option<String>,
+    net: f32,
+    vat_rate: f32,
+    vat: f32,
+    total: f32,
+    currency: Currency,
+    issued: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "invoices"]
+struct InsertableInvoice {
+    user_id: i32,
+    offer_id: i32,
+    booking_reference: String,
+    supplement: i32,
+    issuer: String,
+    buyer_name: String,
+    buyer_email: String,
+    buyer_address: Option<String>,
+    net: f32,
+    vat_rate: f32,
+    vat: f32,
+    total: f32,
+    currency: Currency,
+    issued: NaiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Insertable, JsonSchema, Queryable, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[table_name = "invoices_items"]
+pub struct InvoiceItem {
+    #[serde(skip)]
+    invoice_id: i32,
+    pub position: i32,
+    pub description: String,
+    pub quantity: i32,
+    pub unit_price: f32,
+    pub amount: f32,
+}
+
+/// An invoice for a paid booking, or a supplementary invoice for ancillaries bought after the
+/// booking has been paid. Invoices are numbered sequentially and can't be changed once they have
+/// been issued.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct Invoice {
+    pub number: String,
+    pub issuer: String,
+    pub user_id: i32,
+    /// Offer of the booking at the time the invoice was issued, which may differ from the current
+    /// offer of a rebooked booking
+    pub offer_id: i32,
+    pub booking_reference: String,
+    /// 0 for the invoice of the booking, counting up for its supplementary invoices
+    pub supplement: i32,
+    pub buyer_name: String,
+    pub buyer_email: String,
+    pub buyer_address: Vec<String>,
+    pub items: Vec<InvoiceItem>,
+    pub net: f32,
+    /// VAT rate in percent
+    pub vat_rate: f32,
+    pub vat: f32,
+    pub total: f32,
+    pub currency: Currency,
+    pub issued: NaiveDateTime,
+}
+
+impl Invoice {
+    fn new(record: InvoiceRecord, items: Vec<InvoiceItem>) -> Self {
+        Invoice {
+            number: format!("SR-{:08}", record.id),
+            issuer: record.issuer,
+            user_id: record.user_id,
+            offer_id: record.offer_id,
+            booking_reference: record.booking_reference,
+            supplement: record.supplement,
+            buyer_name: record.buyer_name,
+            buyer_email: record.buyer_email,
+            buyer_address: record
+                .buyer_address
+                .map(|a| a.lines().map(String::from).collect())
+                .unwrap_or_default(),
+            items,
+            net: record.net,
+            vat_rate: record.vat_rate,
+            vat: record.vat,
+            total: record.total,
+            currency: record.currency,
+            issued: record.issued,
+        }
+    }
+
+    /// Find the invoices of a booking by its reference, so they are still found after the booking
+    /// has been moved to another offer. Without a supplement, every invoice is returned.
+    async fn find_by_booking(
+        db: &Db,
+        user_id: i32,
+        offer_id: i32,
+        supplement: Option<i32>,
+    ) -> Vec<Self> {
+        db.run(move |conn| {
+            let reference = bookings::table
+                .find((user_id, offer_id))
+                .select(bookings::reference)
+                .first::<String>(conn)?;
+            let mut query = invoices::table
+                .filter(invoices::booking_reference.eq(reference))
+                .into_boxed();
+            if let Some(supplement) = supplement {
+                query = query.filter(invoices::supplement.eq(supplement));
+            }
+            let records: Vec<InvoiceRecord> = query.order(invoices::supplement).load(conn)?;
+
+            let mut invoices = Vec::new();
+            for record in records {
+                let items = invoices_items::table
+                    .filter(invoices_items::invoice_id.eq(record.id))
+                    .order(invoices_items::position)
+                    .load(conn)?;
+                invoices.push(Invoice::new(record, items));
+            }
+            Ok::<_, DieselError>(invoices)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+
+    /// Find the invoice of a booking, or one of its supplementary invoices
+    pub async fn find(db: &Db, user_id: i32, offer_id: i32, supplement: i32) -> Option<Self> {
+        Invoice::find_by_booking(db, user_id, offer_id, Some(supplement))
+            .await
+            .pop()
+    }
+
+    /// The invoice of a booking followed by its supplementary invoices
+    pub async fn all_from_booking(db: &Db, user_id: i32, offer_id: i32) -> Vec<Self> {
+        Invoice::find_by_booking(db, user_id, offer_id, None).await
+    }
+
+    /// Issue an invoice for a booking with the given items and the buyer as it is right now.
+    /// Supplementary invoices get the next number of the booking, otherwise the invoice of the
+    /// booking itself is issued.
+    async fn issue(
+        db: &Db,
+        booking: &Booking,
+        items: Vec<PriceItem>,
+        currency: Currency,
+        supplementary: bool,
+    ) -> ApiResult<()> {
+        let user = User::find_by_id(db, booking.user_id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find user"))?;
+        let address = Address::all_from_user(db, booking.user_id)
+            .await
+            .first()
+            .map(|a| a.lines().join("\n"));
+
+        let total = round(items.iter().map(PriceItem::amount).sum());
+        let vat_rate = CONFIG.invoice_vat_rate.unwrap_or(DEFAULT_VAT_RATE);
+        let net = round(total / (1.0 + vat_rate / 100.0));
+
+        let (user_id, offer_id) = (booking.user_id, booking.offer_id);
+        let reference = booking.reference.clone();
+        let mut invoice = InsertableInvoice {
+            user_id,
+            offer_id,
+            booking_reference: booking.reference.clone(),
+            supplement: 0,
+            issuer: CONFIG
+                .invoice_issuer
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ISSUER.into()),
+            buyer_name: format!("{} {}", user.firstname, user.lastname),
+            buyer_email: user.email.clone(),
+            buyer_address: address,
+            net,
+            vat_rate,
+            vat: round(total - net),
+            total,
+            currency,
+            issued: Utc::now().naive_utc(),
+        };
+
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                if supplementary {
+                    // the lock of the booking lets supplementary invoices take their numbers one
+                    // by one
+                    bookings::table
+                        .find((user_id, offer_id))
+                        .select(bookings::reference)
+                        .for_update()
+                        .first::<String>(conn)?;
+                    invoice.supplement = invoices::table
+                        .filter(invoices::booking_reference.eq(&reference))
+                        .select(diesel::dsl::max(invoices::supplement))
+                        .first::<Option<i32>>(conn)?
+                        .unwrap_or(0)
+                        + 1;
+                }
+                diesel::insert_into(invoices::table)
+                    .values(&invoice)
+                    .execute(conn)?;
+
+                let invoice_id = invoices::table
+                    .filter(invoices::booking_reference.eq(&reference))
+                    .filter(invoices::supplement.eq(invoice.supplement))
+                    .select(invoices::id)
+                    .first::<i32>(conn)?;
+                let items = items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| InvoiceItem {
+                        invoice_id,
+                        position: i as i32 + 1,
+                        description: item.description.clone(),
+                        quantity: item.quantity,
+                        unit_price: round(item.unit_price),
+                        amount: round(item.amount()),
+                    })
+                    .collect::<Vec<InvoiceItem>>();
+
+                diesel::insert_into(invoices_items::table)
+                    .values(&items)
+                    .execute(conn)
+            })
+        })
+        .await
+        .map_or_else(
+            |e| match e {
+                // a concurrent request issued the invoice of the booking first
+                DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)
+                    if !supplementary =>
+                {
+                    Ok(())
+                }
+                _ => Err(error(e, Status::InternalServerError, "")),
+            },
+            |_res| Ok(()),
+        )
+    }
+
+    /// Find the invoice of a booking. Confirmed bookings without an invoice, e.g. bookings made
+    /// before invoices were introduced, get their invoice issued on the fly.
+    pub async fn find_or_issue(db: &Db, user_id: i32, offer_id: i32) -> ApiResult<Self> {
+        if let Some(invoice) = Invoice::find(db, user_id, offer_id, 0).await {
+            return Ok(invoice);
+        }
+
+        let booking = Booking::find(db, user_id, offer_id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+        if booking.status != BookingStatus::Confirmed {
+            return Err(error("", Status::NotFound, "Booking hasn't been paid yet"));
+        }
+
+        let (items, currency) = booking.price_items(db).await?;
+        Invoice::issue(db, &booking, items, currency, false).await?;
+        Invoice::find(db, user_id, offer_id, 0)
+            .await
+            .ok_or_else(|| error("", Status::InternalServerError, ""))
+    }
+
+    /// Issue a supplementary invoice for items bought after the booking has been paid
+    pub(super) async fn issue_supplement(
+        db: &Db,
+        booking: &Booking,
+        items: Vec<PriceItem>,
+        currency: Currency,
+    ) -> ApiResult<()> {
+        Invoice::issue(db, booking, items, currency, true).await
+    }
+
+    fn format_amount(&self, amount: f32) -> String {
+        format!("{:.2} {}", amount, self.currency.code())
+    }
+
+    /// Render the invoice as PDF document
+    pub fn to_pdf(&self) -> Vec<u8> {
+        let mut document = Document::new();
+        let pages = self.items.chunks(ITEMS_PER_PAGE).count().max(1);
+
+        for page_number in 0..pages {
+            let mut page = Page::new();
+            let title = if self.supplement > 0 {
+                "Supplementary invoice"
+            } else {
+                "Invoice"
+            };
+            page.text(50.0, 70.0, 20.0, Font::Bold, title);
+            page.text(400.0, 70.0, 12.0, Font::Bold, &self.issuer);
+            page.text(
+                400.0,
+                90.0,
+                9.0,
+                Font::Regular,
+                &format!("Page {} of {}", page_number + 1, pages),
+            );
+
+            page.text(
+                50.0,
+                110.0,
+                10.0,
+                Font::Regular,
+                &format!("Invoice number: {}", self.number),
+            );
+            page.text(
+                50.0,
+                125.0,
+                10.0,
+                Font::Regular,
+                &format!("Date: {}", self.issued.format("%Y-%m-%d")),
+            );
+            page.text(
+                50.0,
+                140.0,
+                10.0,
+                Font::Regular,
+                &format!("Booking reference: {}", self.booking_reference),
+            );
+
+            let mut y = 180.0;
+            for line in [&self.buyer_name, &self.buyer_email]
+                .into_iter()
+                .chain(self.buyer_address.iter())
+            {
+                page.text(50.0, y, 10.0, Font::Regular, line);
+                y += 15.0;
+            }
+
+            y = 280.0;
+            page.text(50.0, y, 10.0, Font::Bold, "Pos");
+            page.text(85.0, y, 10.0, Font::Bold, "Description");
+            page.text(360.0, y, 10.0, Font::Bold, "Qty");
+            page.text(400.0, y, 10.0, Font::Bold, "Unit price");
+            page.text(480.0, y, 10.0, Font::Bold, "Amount");
+            page.line(50.0, y + 5.0, 495.0);
+
+            for item in self
+                .items
+                .iter()
+                .skip(page_number * ITEMS_PER_PAGE)
+                .take(ITEMS_PER_PAGE)
+            {
+                y += 18.0;
+                page.text(50.0, y, 10.0, Font::Regular, &item.position.to_string());
+                page.text(85.0, y, 10.0, Font::Regular, &item.description);
+                page.text(360.0, y, 10.0, Font::Regular, &item.quantity.to_string());
+                page.text(
+                    400.0,
+                    y,
+                    10.0,
+                    Font::Regular,
+                    &self.format_amount(item.unit_price),
+                );
+                page.text(
+                    480.0,
+                    y,
+                    10.0,
+                    Font::Regular,
+                    &self.format_amount(item.amount),
+                );
+            }
+
+            if page_number + 1 == pages {
+                y += 10.0;
+                page.line(50.0, y, 495.0);
+                let totals = [
+                    ("Net".to_string(), self.net, Font::Regular),
+                    (
+                        format!("VAT ({:.2} %)", self.vat_rate),
+                        self.vat,
+                        Font::Regular,
+                    ),
+                    ("Total".to_string(), self.total, Font::Bold),
+                ];
+                for (label, amount, font) in totals {
+                    y += 18.0;
+                    page.text(360.0, y, 10.0, font, &label);
+                    page.text(480.0, y, 10.0, font, &self.format_amount(amount));
+                }
+            }
+
+            document.add_page(page);
+        }
+
+        document.to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::NewBooking;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_round() {
+        assert_eq!(168.07, round(168.0672));
+        assert_eq!(2.5, round(2.499));
+    }
+
+    #[test]
+    fn test_pdf_pages() {
+        let record = InvoiceRecord {
+            id: 42,
+            user_id: 1,
+            offer_id: 1,
+            booking_reference: "ABC234".into(),
+            supplement: 0,
+            issuer: "SkyRocket".into(),
+            buyer_name: "Ada Lovelace".into(),
+            buyer_email: "ada@example.com".into(),
+            buyer_address: Some("12 St James's Square\nLondon".into()),
+            net: 2521.01,
+            vat_rate: 19.0,
+            vat: 478.99,
+            total: 3000.0,
+            currency: Currency::Euro,
+            issued: NaiveDate::from_ymd(2022, 1, 24).and_hms(12, 0, 0),
+        };
+        let items = (1..=30)
+            .map(|position| InvoiceItem {
+                invoice_id: 42,
+                position,
+                description: "Seat".into(),
+                quantity: 1,
+                unit_price: 100.0,
+                amount: 100.0,
+            })
+            .collect();
+        let invoice = Invoice::new(record, items);
+        assert_eq!("SR-00000042", invoice.number);
+        assert_eq!(
+            vec!["12 St James's Square", "London"],
+            invoice.buyer_address
+        );
+
+        let pdf = String::from_utf8_lossy(&invoice.to_pdf()).into_owned();
+        assert!(pdf.contains("/Count 2"));
+        assert!(pdf.contains("(Page 2 of 2) Tj"));
+        assert!(pdf.contains("(Booking reference: ABC234) Tj"));
+        assert_eq!(1, pdf.matches("(Total) Tj").count());
+    }
+
+    #[rocket::async_test]
+    async fn test_issue_invoice_once() {
+        let db = fixtures::db().await;
+        let user = fixtures::user(&db).await;
+        let departure = NaiveDate::from_ymd(2022, 4, 11).and_hms(12, 0, 0);
+        let offer_id = fixtures::offer(&db, &fixtures::route(), departure, 10).await;
+        Booking::create(
+            &db,
+            user.id,
+            offer_id,
+            Some(2),
+            None,
+            None,
+            NewBooking::default(),
+        )
+        .await
+        .unwrap();
+
+        // pending bookings don't get an invoice
+        let error = Invoice::find_or_issue(&db, user.id, offer_id)
+            .await
+            .unwrap_err();
+        assert_eq!(Status::NotFound, error.0);
+
+        let user_id = user.id;
+        db.run(move |conn| {
+            diesel::update(bookings::table.find((user_id, offer_id)))
+                .set(bookings::status.eq(BookingStatus::Confirmed))
+                .execute(conn)
+        })
+        .await
+        .unwrap();
+        let booking = Booking::find(&db, user.id, offer_id).await.unwrap();
+
+        // concurrent first requests both get the invoice, that one of them issued
+        let other = fixtures::db().await;
+        let (invoice, concurrent) = rocket::futures::join!(
+            Invoice::find_or_issue(&db, user.id, offer_id),
+            Invoice::find_or_issue(&other, user.id, offer_id),
+        );
+        let invoice = invoice.unwrap();
+        assert_eq!(invoice.number, concurrent.unwrap().number);
+        assert_eq!(booking.reference, invoice.booking_reference);
+        assert_eq!(user.email, invoice.buyer_email);
+        assert_eq!(
+            CONFIG.invoice_issuer.as_deref().unwrap_or(DEFAULT_ISSUER),
+            invoice.issuer
+        );
+        assert_eq!(200.0, invoice.total);
+        assert_eq!(
+            invoice.total,
+            invoice.items.iter().map(|item| item.amount).sum::<f32>()
+        );
+        assert_eq!(invoice.total, round(invoice.net + invoice.vat));
+        assert_eq!(
+            (1..=invoice.items.len() as i32).collect::<Vec<_>>(),
+            invoice
+                .items
+                .iter()
+                .map(|item| item.position)
+                .collect::<Vec<_>>()
+        );
+
+        // the invoice is issued once and can't be changed afterwards
+        let again = Invoice::find_or_issue(&db, user.id, offer_id)
+            .await
+            .unwrap();
+        assert_eq!(invoice.number, again.number);
+        let result = db
+            .run(move |conn| {
+                diesel::update(invoices::table.filter(invoices::user_id.eq(user_id)))
+                    .set(invoices::total.eq(0.0))
+                    .execute(conn)
+            })
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,233 @@
+use crate::db::models::User;
+use crate::db::schema::{users, users_login_links};
+use crate::db::Db;
+use crate::mail::{self, Mail, MailToken, Mailer, TokenPurpose};
+use crate::routes::{error, ApiResult};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::Validate;
+
+/// Minutes a login link is valid
+const LINK_VALID_MINUTES: i64 = 15;
+
+/// The nonce of the last login link sent to a user. Only this link is accepted, so requesting a
+/// new one invalidates the previous links.
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "users_login_links"]
+pub struct LoginLink {
+    user_id: i32,
+    nonce: String,
+    created: NaiveDateTime,
+}
+
+/// The registered email address a login link is sent to
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct LoginLinkRequest {
+    #[validate(email)]
+    pub email: String,
+}
+
+impl LoginLinkRequest {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// The token of a login link
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct LoginLinkToken {
+    pub token: String,
+}
+
+impl LoginLink {
+    /// Send a login link to a registered email address. Unknown addresses are silently ignored,
+    /// so the response doesn't tell who is registered.
+    pub async fn send(db: &Db, mailer: &Mailer, request: LoginLinkRequest) -> ApiResult<()> {
+        request.is_valid()?;
+        let user = match User::find_by_email(db, request.email).await {
+            Some(user) => user.into_inner(),
+            None => return Ok(()),
+        };
+
+        let token = MailToken::new(
+            TokenPurpose::Login,
+            user.id,
+            user.email.clone(),
+            Duration::minutes(LINK_VALID_MINUTES),
+        );
+        let link = LoginLink {
+            user_id: user.id,
+            nonce: token.nonce.clone(),
+            created: Utc::now().naive_utc(),
+        };
+        db.run(move |conn| {
+            diesel::replace_into(users_login_links::table)
+                .values(&link)
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        let mail = Mail {
+            to: user.email,
+            subject: "Your login link".into(),
+            body: format!(
+                "Hello {},\n\nfollow this link within {} minutes to log in:\n\n{}\n",
+                user.firstname,
+                LINK_VALID_MINUTES,
+                mail::link("/login/email", &token.sign())
+            ),
+        };
+        mailer.send(&mail).await.map_err(|e| {
+            error(
+                e,
+                Status::InternalServerError,
+                "Error sending mail, please try again later",
+            )
+        })
+    }
+
+    /// Use a login link. The link has to be the last one sent to the user and the address must
+    /// not have changed since. Since the user proved access to the address, it counts as
+    /// verified. Returns the id of the user.
+    pub async fn redeem(db: &Db, token: LoginLinkToken) -> ApiResult<i32> {
+        let token = MailToken::verify(&token.token, TokenPurpose::Login)
+            .map_err(|e| error(e, Status::Unauthorized, "Invalid or expired login link"))?;
+        let user_id = token.user_id;
+
+        let redeemed = db
+            .run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    let link = users_login_links::table
+                        .find(token.user_id)
+                        .for_update()
+                        .first::<LoginLink>(conn)
+                        .optional()?;
+                    let email = users::table
+                        .find(token.user_id)
+                        .select(users::email)
+                        .first::<String>(conn)
+                        .optional()?;
+                    match (link, email) {
+                        (Some(link), Some(email))
+                            if link.nonce == token.nonce && email == token.email => {}
+                        _ => return Ok(false),
+                    }
+
+                    diesel::delete(users_login_links::table.find(token.user_id)).execute(conn)?;
+                    diesel::update(users::table.find(token.user_id))
+                        .set(users::verified.eq(true))
+                        .execute(conn)?;
+                    Ok(true)
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        if redeemed {
+            Ok(user_id)
+        } else {
+            Err(error(
+                "",
+                Status::Unauthorized,
+                "Invalid or expired login link",
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::NewUser;
+    use crate::mail::Outbox;
+
+    async fn send(db: &Db, mailer: &Mailer, user: &User) {
+        let request = LoginLinkRequest {
+            email: user.email.clone(),
+        };
+        LoginLink::send(db, mailer, request).await.unwrap();
+    }
+
+    async fn redeem(db: &Db, token: String) -> ApiResult<i32> {
+        LoginLink::redeem(db, LoginLinkToken { token }).await
+    }
+
+    #[rocket::async_test]
+    async fn test_redeem_link_once() {
+        let db = fixtures::db().await;
+        let outbox = Outbox::default();
+        let mailer = Mailer::outbox(&outbox);
+        let user = fixtures::user(&db).await;
+
+        send(&db, &mailer, &user).await;
+        let token = outbox.last_token();
+        assert_eq!(user.id, redeem(&db, token.clone()).await.unwrap());
+        assert!(User::is_verified(&db, user.id).await);
+
+        let error = redeem(&db, token).await.unwrap_err();
+        assert_eq!(Status::Unauthorized, error.0);
+    }
+
+    #[rocket::async_test]
+    async fn test_ignore_unknown_address() {
+        let db = fixtures::db().await;
+        let outbox = Outbox::default();
+        let mailer = Mailer::outbox(&outbox);
+        let request = LoginLinkRequest {
+            email: format!("unknown-{}@example.com", rand::random::<u32>()),
+        };
+
+        LoginLink::send(&db, &mailer, request).await.unwrap();
+        assert_eq!(0, outbox.sent());
+    }
+
+    #[rocket::async_test]
+    async fn test_reject_superseded_link() {
+        let db = fixtures::db().await;
+        let outbox = Outbox::default();
+        let mailer = Mailer::outbox(&outbox);
+        let user = fixtures::user(&db).await;
+
+        send(&db, &mailer, &user).await;
+        let superseded = outbox.last_token();
+        send(&db, &mailer, &user).await;
+        let latest = outbox.last_token();
+
+        assert!(redeem(&db, superseded).await.is_err());
+        assert_eq!(user.id, redeem(&db, latest).await.unwrap());
+    }
+
+    #[rocket::async_test]
+    async fn test_reject_link_to_previous_address() {
+        let db = fixtures::db().await;
+        let outbox = Outbox::default();
+        let mailer = Mailer::outbox(&outbox);
+        let user = fixtures::user(&db).await;
+
+        send(&db, &mailer, &user).await;
+        let token = outbox.last_token();
+        let changed = NewUser {
+            firstname: user.firstname.clone(),
+            lastname: user.lastname.clone(),
+            email: format!("changed-{}", user.email),
+            birthday: user.birthday,
+            gender: user.gender.clone(),
+        };
+        User::update_and_return(&db, user.id, changed)
+            .await
+            .unwrap();
+
+        assert!(redeem(&db, token).await.is_err());
+        assert!(!User::is_verified(&db, user.id).await);
+    }
+}
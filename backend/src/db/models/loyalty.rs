@@ -0,0 +1,581 @@
+use crate::db::models::{Booking, BookingStatus, Flight, User};
+use crate::db::schema::{bookings, loyalty_transactions, users};
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel_derive_enum::DbEnum;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::Validate;
+
+/// Points accrued per unit of the currency paid for a booking
+const POINTS_PER_UNIT: f32 = 1.0;
+/// Value of a single point, in units of the currency of the booking it is redeemed for
+pub const POINT_VALUE: f32 = 0.01;
+/// Days of activity, that count towards the tier of a user
+const TIER_PERIOD_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, DbEnum, JsonSchema, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum LoyaltyTransactionKind {
+    /// Points earned with a completed flight
+    Accrual,
+    /// Points redeemed against the price of a booking
+    Redemption,
+    /// Redeemed points credited back, after the booking has been cancelled
+    Reinstatement,
+}
+
+/// Tier of a user, depending on the points accrued within the last 12 months
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq, PartialOrd)]
+#[serde(crate = "rocket::serde")]
+pub enum LoyaltyTier {
+    Basic,
+    Silver,
+    Gold,
+    Platinum,
+}
+
+impl LoyaltyTier {
+    const ALL: [LoyaltyTier; 4] = [
+        LoyaltyTier::Basic,
+        LoyaltyTier::Silver,
+        LoyaltyTier::Gold,
+        LoyaltyTier::Platinum,
+    ];
+
+    /// Points to accrue within 12 months to reach the tier
+    fn threshold(&self) -> i32 {
+        match self {
+            LoyaltyTier::Basic => 0,
+            LoyaltyTier::Silver => 2_000,
+            LoyaltyTier::Gold => 5_000,
+            LoyaltyTier::Platinum => 10_000,
+        }
+    }
+
+    /// Bonus on accrued points, in percent
+    fn bonus(&self) -> i32 {
+        match self {
+            LoyaltyTier::Basic => 0,
+            LoyaltyTier::Silver => 25,
+            LoyaltyTier::Gold => 50,
+            LoyaltyTier::Platinum => 100,
+        }
+    }
+
+    pub fn from_points(points: i32) -> Self {
+        LoyaltyTier::ALL
+            .iter()
+            .rev()
+            .find(|tier| points >= tier.threshold())
+            .copied()
+            .unwrap_or(LoyaltyTier::Basic)
+    }
+
+    fn next(&self) -> Option<Self> {
+        LoyaltyTier::ALL.iter().find(|tier| **tier > *self).copied()
+    }
+}
+
+/// Calculate the points accrued for a fare, including the bonus of the tier
+fn accrued_points(fare: f32, tier: LoyaltyTier) -> i32 {
+    let points = (fare.max(0.0) * POINTS_PER_UNIT).floor() as i32;
+    points + points * tier.bonus() / 100
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "loyalty_transactions"]
+struct InsertableLoyaltyTransaction {
+    user_id: i32,
+    offer_id: i32,
+    flight_id: Option<i32>,
+    kind: LoyaltyTransactionKind,
+    points: i32,
+    description: String,
+    created: NaiveDateTime,
+}
+
+/// An entry of the loyalty ledger of a user. Entries are never changed, corrections are booked as
+/// new entries.
+#[derive(
+    Associations, Clone, Debug, Deserialize, Identifiable, JsonSchema, Queryable, Serialize,
+)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "loyalty_transactions"]
+pub struct LoyaltyTransaction {
+    pub id: i32,
+    pub user_id: i32,
+    pub offer_id: i32,
+    pub flight_id: Option<i32>,
+    pub kind: LoyaltyTransactionKind,
+    /// Positive for credited points, negative for redeemed points
+    pub points: i32,
+    pub description: String,
+    pub created: NaiveDateTime,
+}
+
+/// Balance, tier and history of the loyalty points of a user
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct LoyaltyAccount {
+    pub balance: i32,
+    pub tier: LoyaltyTier,
+    /// Points accrued within the last 12 months, which determine the tier
+    pub tier_points: i32,
+    pub next_tier: Option<LoyaltyTier>,
+    pub points_to_next_tier: Option<i32>,
+    /// Every transaction, latest first
+    pub history: Vec<LoyaltyTransaction>,
+}
+
+impl LoyaltyAccount {
+    fn new(history: Vec<LoyaltyTransaction>, now: NaiveDateTime) -> Self {
+        let balance = history.iter().map(|t| t.points).sum();
+        let tier_points = tier_points(&history, now);
+        let tier = LoyaltyTier::from_points(tier_points);
+        let next_tier = tier.next();
+        LoyaltyAccount {
+            balance,
+            tier,
+            tier_points,
+            next_tier,
+            points_to_next_tier: next_tier.map(|t| t.threshold() - tier_points),
+            history,
+        }
+    }
+}
+
+/// Sum up the points accrued within the tier period before the given time
+fn tier_points(history: &[LoyaltyTransaction], now: NaiveDateTime) -> i32 {
+    let since = now - Duration::days(TIER_PERIOD_DAYS);
+    history
+        .iter()
+        .filter(|t| t.kind == LoyaltyTransactionKind::Accrual && t.created > since)
+        .map(|t| t.points)
+        .sum()
+}
+
+/// Points to redeem against the price of a booking
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct LoyaltyRedemption {
+    #[validate(range(min = 1))]
+    pub points: i32,
+}
+
+impl LoyaltyRedemption {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// Outcome of a redemption, which is evaluated within a transaction
+enum Redemption {
+    Redeemed(LoyaltyTransaction),
+    NotPending,
+    ExceedsPrice,
+    InsufficientPoints,
+}
+
+impl Redemption {
+    fn into_result(self) -> ApiResult<LoyaltyTransaction> {
+        match self {
+            Redemption::Redeemed(transaction) => Ok(transaction),
+            Redemption::NotPending => Err(error(
+                "",
+                Status::Conflict,
+                "Points can only be redeemed for bookings awaiting payment",
+            )),
+            Redemption::ExceedsPrice => Err(error(
+                "",
+                Status::BadRequest,
+                "Points exceed the price of the booking",
+            )),
+            Redemption::InsufficientPoints => {
+                Err(error("", Status::Conflict, "Insufficient loyalty points"))
+            }
+        }
+    }
+}
+
+impl LoyaltyTransaction {
+    fn all_from_user_sync(conn: &diesel::MysqlConnection, user_id: i32) -> QueryResult<Vec<Self>> {
+        LoyaltyTransaction::belonging_to(&User::dummy(user_id))
+            .order(loyalty_transactions::id.desc())
+            .load(conn)
+    }
+
+    pub async fn account(db: &Db, user_id: i32) -> ApiResult<LoyaltyAccount> {
+        db.run(move |conn| LoyaltyTransaction::all_from_user_sync(conn, user_id))
+            .await
+            .map(|history| LoyaltyAccount::new(history, Utc::now().naive_utc()))
+            .map_err(|e| error(e, Status::InternalServerError, ""))
+    }
+
+    fn redeemed_points_sync(
+        conn: &diesel::MysqlConnection,
+        user_id: i32,
+        offer_id: i32,
+    ) -> QueryResult<i32> {
+        loyalty_transactions::table
+            .filter(loyalty_transactions::user_id.eq(user_id))
+            .filter(loyalty_transactions::offer_id.eq(offer_id))
+            .filter(loyalty_transactions::kind.eq_any(vec![
+                LoyaltyTransactionKind::Redemption,
+                LoyaltyTransactionKind::Reinstatement,
+            ]))
+            .select(loyalty_transactions::points)
+            .load::<i32>(conn)
+            .map(|points| -points.iter().sum::<i32>())
+    }
+
+    /// Points redeemed for a booking and not credited back, which are deducted from its price
+    pub async fn redeemed_points(db: &Db, user_id: i32, offer_id: i32) -> i32 {
+        db.run(move |conn| LoyaltyTransaction::redeemed_points_sync(conn, user_id, offer_id))
+            .await
+            .unwrap_or(0)
+    }
+
+    /// Let the points redeemed for a booking follow the booking to another offer. As the ledger is
+    /// append-only, the points are credited back for the old offer and redeemed again for the new
+    /// one. Must be called within the transaction, that moves the booking.
+    pub(super) fn transfer_redemption(
+        conn: &diesel::MysqlConnection,
+        booking: &Booking,
+        to_offer_id: i32,
+    ) -> QueryResult<()> {
+        let points =
+            LoyaltyTransaction::redeemed_points_sync(conn, booking.user_id, booking.offer_id)?;
+        if points == 0 {
+            return Ok(());
+        }
+
+        let now = Utc::now().naive_utc();
+        let transactions = vec![
+            InsertableLoyaltyTransaction {
+                user_id: booking.user_id,
+                offer_id: booking.offer_id,
+                flight_id: None,
+                kind: LoyaltyTransactionKind::Reinstatement,
+                points,
+                description: format!("Rebooking of booking {}", booking.reference),
+                created: now,
+            },
+            InsertableLoyaltyTransaction {
+                user_id: booking.user_id,
+                offer_id: to_offer_id,
+                flight_id: None,
+                kind: LoyaltyTransactionKind::Redemption,
+                points: -points,
+                description: format!("Redeemed for booking {}", booking.reference),
+                created: now,
+            },
+        ];
+        diesel::insert_into(loyalty_transactions::table)
+            .values(&transactions)
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Credit the points for a completed flight to every customer with a paid booking of the
+    /// offer. The fare of a booking is split evenly across the flights of the offer. Customers,
+    /// who already got points for the flight, are skipped.
+    pub async fn accrue_flight(db: &Db, offer_id: i32, flight_id: i32) -> ApiResult<()> {
+        let flights = Flight::all_from_offer(db, offer_id).await.len().max(1);
+        for booking in Booking::all_from_offer(db, offer_id).await {
+            if booking.status != BookingStatus::Confirmed {
+                continue;
+            }
+            let (total, _currency) = booking.total(db).await?;
+            let fare = total / flights as f32;
+            let user_id = booking.user_id;
+            let reference = booking.reference;
+
+            db.run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    let history = LoyaltyTransaction::all_from_user_sync(conn, user_id)?;
+                    if history.iter().any(|t| t.flight_id == Some(flight_id)) {
+                        return Ok(());
+                    }
+                    let now = Utc::now().naive_utc();
+                    let tier = LoyaltyTier::from_points(tier_points(&history, now));
+                    let transaction = InsertableLoyaltyTransaction {
+                        user_id,
+                        offer_id,
+                        flight_id: Some(flight_id),
+                        kind: LoyaltyTransactionKind::Accrual,
+                        points: accrued_points(fare, tier),
+                        description: format!("Flight {} of booking {}", flight_id, reference),
+                        created: now,
+                    };
+                    diesel::insert_into(loyalty_transactions::table)
+                        .values(&transaction)
+                        .execute(conn)
+                        .map(|_| ())
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        }
+        Ok(())
+    }
+
+    /// Redeem points against the price of a pending booking. The balance of the user must cover
+    /// the points and the discount can't exceed the remaining price of the booking.
+    pub async fn redeem(
+        db: &Db,
+        booking: &Booking,
+        redemption: LoyaltyRedemption,
+    ) -> ApiResult<Self> {
+        redemption.is_valid()?;
+
+        if booking.status != BookingStatus::Pending {
+            return Redemption::NotPending.into_result();
+        }
+        // the price before any points, as the total already deducts the points redeemed so far
+        let (total, _currency) = booking.total(db).await?;
+        let redeemed =
+            LoyaltyTransaction::redeemed_points(db, booking.user_id, booking.offer_id).await;
+        let price = total + redeemed as f32 * POINT_VALUE;
+
+        let user_id = booking.user_id;
+        let offer_id = booking.offer_id;
+        let transaction = InsertableLoyaltyTransaction {
+            user_id,
+            offer_id: booking.offer_id,
+            flight_id: None,
+            kind: LoyaltyTransactionKind::Redemption,
+            points: -redemption.points,
+            description: format!("Redeemed for booking {}", booking.reference),
+            created: Utc::now().naive_utc(),
+        };
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                // lock the booking and then the user, so concurrent redemptions can't exceed the
+                // price of the booking or overdraw the balance
+                let status = bookings::table
+                    .find((user_id, offer_id))
+                    .select(bookings::status)
+                    .for_update()
+                    .first::<BookingStatus>(conn)?;
+                if status != BookingStatus::Pending {
+                    return Ok(Redemption::NotPending);
+                }
+                users::table
+                    .find(user_id)
+                    .select(users::id)
+                    .for_update()
+                    .first::<i32>(conn)?;
+
+                let redeemed = LoyaltyTransaction::redeemed_points_sync(conn, user_id, offer_id)?;
+                if (redeemed - transaction.points) as f32 * POINT_VALUE > price {
+                    return Ok(Redemption::ExceedsPrice);
+                }
+                let balance: i32 = loyalty_transactions::table
+                    .filter(loyalty_transactions::user_id.eq(user_id))
+                    .select(loyalty_transactions::points)
+                    .load::<i32>(conn)?
+                    .iter()
+                    .sum();
+                if balance + transaction.points < 0 {
+                    return Ok(Redemption::InsufficientPoints);
+                }
+
+                diesel::insert_into(loyalty_transactions::table)
+                    .values(&transaction)
+                    .execute(conn)?;
+                loyalty_transactions::table
+                    .filter(loyalty_transactions::user_id.eq(user_id))
+                    .order(loyalty_transactions::id.desc())
+                    .first(conn)
+                    .map(Redemption::Redeemed)
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_result()
+    }
+
+    /// Credit the points redeemed for a cancelled booking back to the user. Must be called within
+    /// the transaction, that cancels the booking, so the points are credited back only once.
+    pub(super) fn reinstate(conn: &diesel::MysqlConnection, booking: &Booking) -> QueryResult<()> {
+        let points =
+            LoyaltyTransaction::redeemed_points_sync(conn, booking.user_id, booking.offer_id)?;
+        if points == 0 {
+            return Ok(());
+        }
+
+        let transaction = InsertableLoyaltyTransaction {
+            user_id: booking.user_id,
+            offer_id: booking.offer_id,
+            flight_id: None,
+            kind: LoyaltyTransactionKind::Reinstatement,
+            points,
+            description: format!("Cancellation of booking {}", booking.reference),
+            created: Utc::now().naive_utc(),
+        };
+        diesel::insert_into(loyalty_transactions::table)
+            .values(&transaction)
+            .execute(conn)
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::{NewBooking, RefundReason};
+    use chrono::NaiveDate;
+
+    /// A pending booking of 2 seats for 200 €, by a user with the given points
+    async fn booking(db: &Db, points: i32) -> Booking {
+        let user = fixtures::user(db).await;
+        let departure = NaiveDate::from_ymd(2022, 4, 11).and_hms(12, 0, 0);
+        let offer_id = fixtures::offer(db, &fixtures::route(), departure, 10).await;
+        Booking::create(
+            db,
+            user.id,
+            offer_id,
+            Some(2),
+            None,
+            None,
+            NewBooking::default(),
+        )
+        .await
+        .unwrap();
+
+        let user_id = user.id;
+        db.run(move |conn| {
+            diesel::insert_into(loyalty_transactions::table)
+                .values((
+                    loyalty_transactions::user_id.eq(user_id),
+                    loyalty_transactions::offer_id.eq(offer_id),
+                    loyalty_transactions::kind.eq(LoyaltyTransactionKind::Accrual),
+                    loyalty_transactions::points.eq(points),
+                    loyalty_transactions::description.eq("Test accrual"),
+                    loyalty_transactions::created.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)
+        })
+        .await
+        .unwrap();
+        Booking::find(db, user_id, offer_id).await.unwrap()
+    }
+
+    async fn balance(db: &Db, user_id: i32) -> i32 {
+        LoyaltyTransaction::account(db, user_id)
+            .await
+            .unwrap()
+            .balance
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_redemptions_stay_within_price() {
+        let db = fixtures::db().await;
+        let other = fixtures::db().await;
+        let booking = booking(&db, 30_000).await;
+        let redemption = || LoyaltyRedemption { points: 15_000 };
+
+        // each redemption is within the price of 200 €, both together aren't
+        let (first, second) = rocket::futures::join!(
+            LoyaltyTransaction::redeem(&db, &booking, redemption()),
+            LoyaltyTransaction::redeem(&other, &booking, redemption()),
+        );
+        assert!(first.is_ok() != second.is_ok());
+        let error = [first, second].into_iter().find_map(Result::err).unwrap();
+        assert_eq!(Status::BadRequest, error.0);
+        assert_eq!(
+            15_000,
+            LoyaltyTransaction::redeemed_points(&db, booking.user_id, booking.offer_id).await
+        );
+        assert_eq!(15_000, balance(&db, booking.user_id).await);
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_cancellations_reinstate_once() {
+        let db = fixtures::db().await;
+        let other = fixtures::db().await;
+        let booking = booking(&db, 10_000).await;
+        LoyaltyTransaction::redeem(&db, &booking, LoyaltyRedemption { points: 5_000 })
+            .await
+            .unwrap();
+        assert_eq!(5_000, balance(&db, booking.user_id).await);
+
+        let reason = || RefundReason::CustomerCancellation;
+        let (user_id, offer_id) = (booking.user_id, booking.offer_id);
+        let (first, second) = rocket::futures::join!(
+            Booking::cancel(&db, user_id, offer_id, reason()),
+            Booking::cancel(&other, user_id, offer_id, reason()),
+        );
+        assert!(first.is_ok() != second.is_ok());
+        let error = [first, second].into_iter().find_map(Result::err).unwrap();
+        assert_eq!(Status::Conflict, error.0);
+        assert_eq!(10_000, balance(&db, booking.user_id).await);
+
+        // points can't be redeemed for the cancelled booking
+        let error = LoyaltyTransaction::redeem(&db, &booking, LoyaltyRedemption { points: 1 })
+            .await
+            .unwrap_err();
+        assert_eq!(Status::Conflict, error.0);
+    }
+
+    fn transaction(kind: LoyaltyTransactionKind, points: i32, days_ago: i64) -> LoyaltyTransaction {
+        LoyaltyTransaction {
+            id: 0,
+            user_id: 1,
+            offer_id: 1,
+            flight_id: None,
+            kind,
+            points,
+            description: String::new(),
+            created: now() - Duration::days(days_ago),
+        }
+    }
+
+    fn now() -> NaiveDateTime {
+        NaiveDate::from_ymd(2022, 4, 11).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn test_tier_from_points() {
+        assert_eq!(LoyaltyTier::Basic, LoyaltyTier::from_points(0));
+        assert_eq!(LoyaltyTier::Silver, LoyaltyTier::from_points(2_000));
+        assert_eq!(LoyaltyTier::Gold, LoyaltyTier::from_points(9_999));
+        assert_eq!(LoyaltyTier::Platinum, LoyaltyTier::from_points(25_000));
+    }
+
+    #[test]
+    fn test_accrued_points_with_bonus() {
+        assert_eq!(249, accrued_points(249.99, LoyaltyTier::Basic));
+        assert_eq!(300, accrued_points(200.0, LoyaltyTier::Gold));
+        assert_eq!(0, accrued_points(-10.0, LoyaltyTier::Platinum));
+    }
+
+    #[test]
+    fn test_account_with_rolling_tier() {
+        let account = LoyaltyAccount::new(
+            vec![
+                transaction(LoyaltyTransactionKind::Reinstatement, 500, 1),
+                transaction(LoyaltyTransactionKind::Redemption, -500, 2),
+                transaction(LoyaltyTransactionKind::Accrual, 1_500, 100),
+                transaction(LoyaltyTransactionKind::Accrual, 3_000, 400),
+            ],
+            now(),
+        );
+        assert_eq!(4_500, account.balance);
+        assert_eq!(1_500, account.tier_points);
+        assert_eq!(LoyaltyTier::Basic, account.tier);
+        assert_eq!(Some(LoyaltyTier::Silver), account.next_tier);
+        assert_eq!(Some(500), account.points_to_next_tier);
+    }
+}
@@ -1,20 +1,68 @@
 mod address;
+mod ancillary;
+mod api_token;
 mod booking;
+mod calendar_subscription;
+mod carrier;
+mod check_in;
+mod companion;
+mod credentials;
+mod email_verification;
 mod flight;
-mod github_oauth_user;
+mod invoice;
+mod login_link;
+mod loyalty;
+mod oauth_identity;
+mod passenger;
+mod passkey;
+mod payment;
+mod rebooking;
+mod refund;
 mod role;
 mod session;
+mod ticket;
+mod totp;
+mod travel_document;
 mod user;
 
 pub use address::{Address, NewAddress};
-pub use booking::Booking;
+pub use ancillary::{
+    Ancillary, AncillaryAvailability, AncillaryKindMapping, AncillaryPurchase, BookedAncillary,
+    NewAncillary, NewBookingAncillary,
+};
+pub use api_token::{ApiScope, ApiToken, CreatedApiToken, NewApiToken};
+pub use booking::{
+    Booking, BookingStatus, BookingStatusMapping, BookingWithAncillaries, NewBooking, PriceItem,
+};
+pub use calendar_subscription::CalendarSubscription;
+pub use carrier::{Carrier, FlightCodeshare, NewCarrier};
+pub use check_in::{CheckIn, ManifestEntry, NewCheckIn};
+pub use companion::{Companion, NewCompanion};
+pub use credentials::{Credentials, LoginCredentials, NewCredentials, NewPassword};
+pub use email_verification::{EmailConfirmation, EmailVerification};
 pub use flight::{
-    Currency, CurrencyMapping, Flight, FlightOffer, FlightOfferWithOccupancy, NewFlight,
-    NewFlightOffer,
+    Currency, CurrencyMapping, Flight, FlightOffer, FlightOfferWithOccupancy, FlightStatus,
+    FlightStatusMapping, FlightStatusUpdate, FlightWithCodeshares, NewFlight, NewFlightOffer,
+    OverbookingPolicy, OversoldOffer,
+};
+pub use invoice::{Invoice, InvoiceItem};
+pub use login_link::{LoginLink, LoginLinkRequest, LoginLinkToken};
+pub use loyalty::{
+    LoyaltyAccount, LoyaltyRedemption, LoyaltyTransaction, LoyaltyTransactionKindMapping,
+};
+pub use oauth_identity::{LoginMethods, OAuthIdentity, OAuthRegistrar};
+pub use passenger::{NewPassenger, Passenger, PassengerDetails, PassengerType};
+pub use passkey::{NewPasskey, Passkey};
+pub use payment::{NewPayment, Payment, PaymentStatus, PaymentStatusMapping};
+pub use rebooking::{rebook_offer, rebooking_window, RebookingReport};
+pub use refund::{
+    NewRefund, Refund, RefundReason, RefundReasonMapping, RefundStatus, RefundStatusMapping,
 };
-pub use github_oauth_user::{GitHubOAuthUser, GithubOAuthRegistrar};
 pub use role::{AdminRole, Role, RoleMapping, UserRole};
 pub use session::{NewSession, Session};
+pub use ticket::Ticket;
+pub use totp::{RecoveryCodes, Totp, TotpCode, TotpEnrolment, TotpStatus};
+pub use travel_document::{DocumentTypeMapping, NewTravelDocument, TravelDocument};
 pub use user::{AuthUser, Gender, GenderMapping, NewUser, User};
 
 pub(self) type DbResult = Result<usize, diesel::result::Error>;
@@ -23,5 +71,10 @@ pub fn register_polar_classes(oso: &mut oso::Oso) -> oso::Result<()> {
     user::register_polar_classes(oso)?;
     session::register_polar_classes(oso)?;
     booking::register_polar_classes(oso)?;
+    companion::register_polar_classes(oso)?;
+    oauth_identity::register_polar_classes(oso)?;
+    totp::register_polar_classes(oso)?;
+    passkey::register_polar_classes(oso)?;
+    api_token::register_polar_classes(oso)?;
     address::register_polar_classes(oso)
 }
@@ -0,0 +1,251 @@
+use crate::db::models::User;
+use crate::db::schema::{passkeys, users, users_credentials, users_oauth_identities};
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use crate::session;
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use oso::PolarClass;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::Request;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+
+/// Links an account of an OAuth or OpenID Connect provider to a user. The subject is the id the
+/// provider assigned to the account, like the numeric id of a GitHub user.
+#[derive(
+    Associations,
+    Debug,
+    Clone,
+    Deserialize,
+    Serialize,
+    Queryable,
+    Insertable,
+    JsonSchema,
+    PolarClass,
+)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "users_oauth_identities"]
+pub struct OAuthIdentity {
+    pub provider: String,
+    pub subject: String,
+    #[polar(attribute)]
+    pub user_id: i32,
+}
+
+/// Every way a user is able to log in
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct LoginMethods {
+    pub identities: Vec<OAuthIdentity>,
+    /// Set, if the user is able to log in with email and password
+    pub password: bool,
+}
+
+/// Count the identities, the password and the passkeys of a user. Locks the user, so concurrent unlinks can't
+/// remove the last login method.
+pub(super) fn count_login_methods(
+    conn: &diesel::MysqlConnection,
+    user_id: i32,
+) -> QueryResult<i64> {
+    users::table
+        .find(user_id)
+        .select(users::id)
+        .for_update()
+        .first::<i32>(conn)?;
+    let identities: i64 = users_oauth_identities::table
+        .filter(users_oauth_identities::user_id.eq(user_id))
+        .count()
+        .get_result(conn)?;
+    let passwords: i64 = users_credentials::table
+        .filter(users_credentials::user_id.eq(user_id))
+        .count()
+        .get_result(conn)?;
+    let passkeys: i64 = passkeys::table
+        .filter(passkeys::user_id.eq(user_id))
+        .count()
+        .get_result(conn)?;
+    Ok(identities + passwords + passkeys)
+}
+
+/// Outcome of an unlink, which is evaluated within a transaction
+pub(super) enum Unlink {
+    Done,
+    NotFound,
+    LastMethod,
+}
+
+impl Unlink {
+    pub(super) fn into_result(self) -> ApiResult<()> {
+        match self {
+            Unlink::Done => Ok(()),
+            Unlink::NotFound => Err(error("", Status::NotFound, "Cannot find login method")),
+            Unlink::LastMethod => Err(error(
+                "",
+                Status::Conflict,
+                "At least one login method must remain",
+            )),
+        }
+    }
+}
+
+impl OAuthIdentity {
+    /// Create a dummy identity for a user with the given id. Used within oso policies
+    pub fn dummy_for_user(user_id: i32) -> Self {
+        OAuthIdentity {
+            provider: String::new(),
+            subject: String::new(),
+            user_id,
+        }
+    }
+
+    pub async fn login_methods(db: &Db, user_id: i32) -> ApiResult<LoginMethods> {
+        db.run(move |conn| {
+            let identities = OAuthIdentity::belonging_to(&User::dummy(user_id))
+                .order((
+                    users_oauth_identities::provider,
+                    users_oauth_identities::subject,
+                ))
+                .load(conn)?;
+            let password = users_credentials::table
+                .find(user_id)
+                .count()
+                .get_result::<i64>(conn)?
+                > 0;
+            Ok(LoginMethods {
+                identities,
+                password,
+            })
+        })
+        .await
+        .map_err(|e: DieselError| error(e, Status::InternalServerError, ""))
+    }
+
+    /// Link an account of a provider to a user. An account can only be linked to a single user.
+    pub async fn link(db: &Db, identity: OAuthIdentity) -> ApiResult<Self> {
+        let linked = identity.clone();
+        db.run(move |conn| {
+            diesel::insert_into(users_oauth_identities::table)
+                .values(identity)
+                .execute(conn)
+        })
+        .await
+        .map_err(|e| match e {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                error(e, Status::Conflict, "Account is already linked to a user")
+            }
+            e => error(e, Status::InternalServerError, ""),
+        })?;
+        Ok(linked)
+    }
+
+    /// Remove the link to an account of a provider, unless it is the last login method
+    pub async fn unlink(db: &Db, user_id: i32, provider: String, subject: String) -> ApiResult<()> {
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                if count_login_methods(conn, user_id)? <= 1 {
+                    return Ok(Unlink::LastMethod);
+                }
+                let deleted = diesel::delete(
+                    users_oauth_identities::table
+                        .find((provider, subject))
+                        .filter(users_oauth_identities::user_id.eq(user_id)),
+                )
+                .execute(conn)?;
+                Ok(if deleted == 0 {
+                    Unlink::NotFound
+                } else {
+                    Unlink::Done
+                })
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_result()
+    }
+
+    pub async fn find(db: &Db, provider: String, subject: String) -> Option<Self> {
+        db.run(move |conn| {
+            users_oauth_identities::table
+                .find((provider, subject))
+                .first(conn)
+        })
+        .await
+        .ok()
+    }
+
+    pub async fn save(db: &Db, identity: OAuthIdentity) -> Option<usize> {
+        db.run(move |conn| {
+            diesel::insert_into(users_oauth_identities::table)
+                .values(identity)
+                .execute(conn)
+        })
+        .await
+        .ok()
+    }
+}
+
+/// This schema is used to identify users that are registering a new account, which is connected
+/// to an OAuth provider
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct OAuthRegistrar {
+    pub provider: String,
+    pub subject: String,
+}
+
+impl OAuthRegistrar {
+    pub fn new(provider: &str, subject: String) -> Self {
+        OAuthRegistrar {
+            provider: provider.into(),
+            subject,
+        }
+    }
+
+    /// Link the account of the provider to the given user
+    pub async fn link(self, db: &Db, user_id: i32) -> Option<usize> {
+        OAuthIdentity::save(
+            db,
+            OAuthIdentity {
+                provider: self.provider,
+                subject: self.subject,
+                user_id,
+            },
+        )
+        .await
+    }
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for OAuthRegistrar {
+    type Error = String;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        if let Some(registrar) = session::get_oauth_registrar(request.cookies()).await {
+            Outcome::Success(registrar)
+        } else {
+            Outcome::Failure((Status::Forbidden, "Not logged in".to_owned()))
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for OAuthRegistrar {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+pub(super) fn register_polar_classes(oso: &mut oso::Oso) -> oso::Result<()> {
+    oso.register_class(OAuthIdentity::get_polar_class())
+}
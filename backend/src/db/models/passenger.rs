@@ -0,0 +1,301 @@
+use crate::db::models::{Booking, BookingStatus, Companion, Flight, User};
+use crate::db::schema::passengers;
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use chrono::{Datelike, NaiveDate, Utc};
+use diesel::prelude::*;
+use diesel::MysqlConnection;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::{Validate, ValidationError};
+
+/// Passengers younger than this are infants
+const INFANT_AGE: i32 = 2;
+/// Passengers younger than this are children
+const ADULT_AGE: i32 = 12;
+/// Number of infants a single adult may hold on the lap
+const INFANTS_PER_ADULT: i32 = 1;
+
+/// Type of a passenger, determined by the age on the day of the first departure
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub enum PassengerType {
+    Adult,
+    Child,
+    /// Infants don't get a seat, but travel on the lap of an adult
+    Infant,
+}
+
+/// Age in completed years on the given day
+fn age(birthday: NaiveDate, on: NaiveDate) -> i32 {
+    let years = on.year() - birthday.year();
+    if (on.month(), on.day()) < (birthday.month(), birthday.day()) {
+        years - 1
+    } else {
+        years
+    }
+}
+
+impl PassengerType {
+    pub fn from_birthday(birthday: NaiveDate, departure: NaiveDate) -> Self {
+        match age(birthday, departure) {
+            a if a < INFANT_AGE => PassengerType::Infant,
+            a if a < ADULT_AGE => PassengerType::Child,
+            _ => PassengerType::Adult,
+        }
+    }
+}
+
+/// Check the number of passengers of each type of a booking. Seats include the seats of
+/// children, every booking needs an adult and infants need an adult, whose lap they travel on.
+pub fn check_composition(seats: i32, children: i32, infants: i32) -> Result<(), &'static str> {
+    if seats < 1 || children < 0 || infants < 0 {
+        return Err("Bad number of seats");
+    }
+    let adults = seats - children;
+    if adults < 1 {
+        return Err("Children must be accompanied by an adult");
+    }
+    if infants > adults * INFANTS_PER_ADULT {
+        return Err("Every infant must travel on the lap of an adult");
+    }
+    Ok(())
+}
+
+/// Custom validator function to check that a birthday isn't in the future
+pub(super) fn is_born(birthday: &NaiveDate) -> Result<(), ValidationError> {
+    if *birthday <= Utc::now().naive_utc().date() {
+        Ok(())
+    } else {
+        Err(ValidationError::new("Birthday must not be in the future"))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct NewPassenger {
+    #[validate(length(min = 1, max = 255))]
+    pub firstname: String,
+    #[validate(length(min = 1, max = 255))]
+    pub lastname: String,
+    #[validate(custom = "is_born")]
+    pub birthday: NaiveDate,
+}
+
+impl NewPassenger {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// A passenger given either by name and birthday, or by the id of a saved companion
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(untagged)]
+pub enum PassengerDetails {
+    Companion {
+        #[serde(rename = "companionId")]
+        companion_id: i32,
+    },
+    New(NewPassenger),
+}
+
+impl PassengerDetails {
+    /// Look up the details of a companion, who must belong to the given user
+    pub async fn resolve(self, db: &Db, user_id: i32) -> ApiResult<NewPassenger> {
+        match self {
+            PassengerDetails::New(passenger) => Ok(passenger),
+            PassengerDetails::Companion { companion_id } => {
+                Companion::find(db, user_id, companion_id)
+                    .await
+                    .map(|c| NewPassenger {
+                        firstname: c.firstname,
+                        lastname: c.lastname,
+                        birthday: c.birthday,
+                    })
+                    .ok_or_else(|| error("", Status::NotFound, "Cannot find companion"))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "passengers"]
+struct InsertablePassenger {
+    user_id: i32,
+    offer_id: i32,
+    firstname: String,
+    lastname: String,
+    birthday: Option<NaiveDate>,
+}
+
+impl InsertablePassenger {
+    fn new(user_id: i32, offer_id: i32, new_passenger: &NewPassenger) -> Self {
+        InsertablePassenger {
+            user_id,
+            offer_id,
+            firstname: new_passenger.firstname.clone(),
+            lastname: new_passenger.lastname.clone(),
+            birthday: Some(new_passenger.birthday),
+        }
+    }
+}
+
+/// Add the passengers of a new booking. Must be called within the transaction creating the
+/// booking.
+pub(super) fn insert(
+    conn: &MysqlConnection,
+    user_id: i32,
+    offer_id: i32,
+    new_passengers: &[NewPassenger],
+) -> QueryResult<usize> {
+    let passengers = new_passengers
+        .iter()
+        .map(|p| InsertablePassenger::new(user_id, offer_id, p))
+        .collect::<Vec<_>>();
+    diesel::insert_into(passengers::table)
+        .values(&passengers)
+        .execute(conn)
+}
+
+/// A person travelling on a booking. Every seat of a booking can be assigned to one passenger.
+#[derive(
+    Associations, Clone, Debug, Deserialize, Identifiable, JsonSchema, Queryable, Serialize,
+)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "passengers"]
+pub struct Passenger {
+    pub id: i32,
+    pub user_id: i32,
+    pub offer_id: i32,
+    pub firstname: String,
+    pub lastname: String,
+    pub birthday: Option<NaiveDate>,
+}
+
+impl Passenger {
+    /// Type of the passenger on a flight departing on the given day. Passengers without birthday
+    /// are adults.
+    pub fn passenger_type(&self, departure: NaiveDate) -> PassengerType {
+        self.birthday.map_or(PassengerType::Adult, |b| {
+            PassengerType::from_birthday(b, departure)
+        })
+    }
+
+    pub async fn all_from_booking(db: &Db, user_id: i32, offer_id: i32) -> Vec<Self> {
+        db.run(move |conn| {
+            passengers::table
+                .filter(passengers::user_id.eq(user_id))
+                .filter(passengers::offer_id.eq(offer_id))
+                .order(passengers::id)
+                .load(conn)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+
+    /// Add a passenger to a booking, as long as there is a seat left without a passenger, that
+    /// has been booked for the type of the passenger. Children may take the seat of an adult,
+    /// but not the other way round.
+    pub async fn add(db: &Db, booking: &Booking, new_passenger: NewPassenger) -> ApiResult<Self> {
+        new_passenger.is_valid()?;
+
+        if booking.status == BookingStatus::Cancelled {
+            return Err(error("", Status::Conflict, "Booking has been cancelled"));
+        }
+
+        let departure = Flight::first_departure(db, booking.offer_id)
+            .await
+            .unwrap_or_else(|| Utc::now().naive_utc())
+            .date();
+        let types = Passenger::all_from_booking(db, booking.user_id, booking.offer_id)
+            .await
+            .iter()
+            .map(|p| p.passenger_type(departure))
+            .collect::<Vec<_>>();
+        let count = |t: PassengerType| types.iter().filter(|&&p| p == t).count() as i32;
+        let seated = count(PassengerType::Adult) + count(PassengerType::Child);
+
+        let full = match PassengerType::from_birthday(new_passenger.birthday, departure) {
+            PassengerType::Infant => count(PassengerType::Infant) >= booking.infants,
+            PassengerType::Child => seated >= booking.seats,
+            PassengerType::Adult => {
+                seated >= booking.seats
+                    || count(PassengerType::Adult) >= booking.seats - booking.children
+            }
+        };
+        if full {
+            return Err(error(
+                "",
+                Status::BadRequest,
+                "Every seat of this booking for passengers of this age has been assigned",
+            ));
+        }
+
+        let (user_id, offer_id) = (booking.user_id, booking.offer_id);
+        let passenger = InsertablePassenger::new(user_id, offer_id, &new_passenger);
+
+        db.run(move |conn| {
+            diesel::insert_into(passengers::table)
+                .values(&passenger)
+                .execute(conn)?;
+            passengers::table
+                .filter(passengers::user_id.eq(user_id))
+                .filter(passengers::offer_id.eq(offer_id))
+                .order(passengers::id.desc())
+                .first(conn)
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passenger_type_from_birthday() {
+        let departure = NaiveDate::from_ymd(2022, 3, 21);
+        assert_eq!(
+            PassengerType::Infant,
+            PassengerType::from_birthday(NaiveDate::from_ymd(2021, 6, 1), departure)
+        );
+        assert_eq!(
+            PassengerType::Child,
+            PassengerType::from_birthday(NaiveDate::from_ymd(2020, 3, 21), departure)
+        );
+        assert_eq!(
+            PassengerType::Child,
+            PassengerType::from_birthday(NaiveDate::from_ymd(2010, 3, 22), departure)
+        );
+        assert_eq!(
+            PassengerType::Adult,
+            PassengerType::from_birthday(NaiveDate::from_ymd(2010, 3, 21), departure)
+        );
+    }
+
+    #[test]
+    fn test_check_composition() {
+        assert!(check_composition(1, 0, 0).is_ok());
+        assert!(check_composition(3, 2, 1).is_ok());
+        assert!(check_composition(2, 0, 2).is_ok());
+    }
+
+    #[test]
+    fn test_check_composition_without_adult() {
+        assert!(check_composition(0, 0, 0).is_err());
+        assert!(check_composition(2, 2, 0).is_err());
+    }
+
+    #[test]
+    fn test_check_composition_too_many_infants() {
+        assert!(check_composition(1, 0, 2).is_err());
+        assert!(check_composition(3, 2, 2).is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::db::models::oauth_identity::{count_login_methods, Unlink};
+use crate::db::models::{AuthUser, User};
+use crate::db::schema::passkeys;
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use crate::webauthn::{
+    self, AuthenticationResponse, CredentialDescriptor, RegistrationResponse, RelyingParty,
+    VerifiedAssertion,
+};
+use crate::CONFIG;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use hmac::{Hmac, Mac, NewMac};
+use once_cell::sync::Lazy;
+use oso::PolarClass;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use sha2::Sha256;
+use validator::Validate;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Key deriving the decoy credentials of email addresses without passkeys. Without
+/// `WEBAUTHN_DECOY_SECRET` the key is random, so the decoys change with every start.
+static DECOY_KEY: Lazy<Vec<u8>> = Lazy::new(|| match CONFIG.webauthn_decoy_secret.as_deref() {
+    Some(secret) if !secret.is_empty() => secret.as_bytes().to_vec(),
+    _ => rand::random::<[u8; 32]>().to_vec(),
+});
+
+/// A credential no authenticator holds, which is the same for an email address every time
+fn decoy_descriptor(email: &str) -> CredentialDescriptor {
+    let mut mac = HmacSha256::new_from_slice(&DECOY_KEY).expect("HMAC can take keys of any size");
+    mac.update(email.trim().to_lowercase().as_bytes());
+    CredentialDescriptor::new(webauthn::encode(&mac.finalize().into_bytes()))
+}
+
+/// A passkey, that is able to log in a user. The public key and the signature counter never
+/// leave the server.
+#[derive(
+    Associations,
+    Clone,
+    Debug,
+    Deserialize,
+    Identifiable,
+    JsonSchema,
+    PolarClass,
+    Queryable,
+    Serialize,
+)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "passkeys"]
+pub struct Passkey {
+    pub id: i32,
+    #[polar(attribute)]
+    pub user_id: i32,
+    pub credential_id: String,
+    #[serde(skip)]
+    public_key: String,
+    #[serde(skip)]
+    sign_count: i64,
+    pub name: String,
+    pub created: NaiveDateTime,
+    pub last_used: Option<NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "passkeys"]
+struct InsertablePasskey {
+    user_id: i32,
+    credential_id: String,
+    public_key: String,
+    sign_count: i64,
+    name: String,
+    created: NaiveDateTime,
+}
+
+/// The response of the authenticator to a registration ceremony, along with a name to tell the
+/// passkeys of a user apart, like "Laptop"
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+pub struct NewPasskey {
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+    pub credential: RegistrationResponse,
+}
+
+impl NewPasskey {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+/// Outcome of a passkey login, which is evaluated within a transaction
+enum Assertion {
+    Verified(i32, VerifiedAssertion),
+    Rejected(String),
+}
+
+impl Passkey {
+    /// Create a dummy passkey for a user with the given id. Used within oso policies
+    pub fn dummy_for_user(user_id: i32) -> Self {
+        Passkey {
+            id: 0,
+            user_id,
+            credential_id: String::new(),
+            public_key: String::new(),
+            sign_count: 0,
+            name: String::new(),
+            created: NaiveDateTime::from_timestamp(0, 0),
+            last_used: None,
+        }
+    }
+
+    pub async fn all_from_user(db: &Db, user_id: i32) -> Vec<Self> {
+        db.run(move |conn| {
+            Passkey::belonging_to(&User::dummy(user_id))
+                .order(passkeys::created)
+                .load(conn)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+
+    /// The credentials of a user, as expected by the options of a ceremony
+    pub async fn descriptors(db: &Db, user_id: i32) -> Vec<CredentialDescriptor> {
+        Passkey::all_from_user(db, user_id)
+            .await
+            .into_iter()
+            .map(|passkey| CredentialDescriptor::new(passkey.credential_id))
+            .collect()
+    }
+
+    /// The credentials offered for a login with an email address. Addresses without passkeys,
+    /// whether they are registered or not, get a decoy credential, so the options don't tell
+    /// which addresses are registered.
+    pub async fn login_descriptors(db: &Db, email: String) -> Vec<CredentialDescriptor> {
+        let descriptors = match AuthUser::by_email(db, email.clone()).await {
+            Some(user) => Passkey::descriptors(db, user.id).await,
+            None => Vec::new(),
+        };
+        if descriptors.is_empty() {
+            vec![decoy_descriptor(&email)]
+        } else {
+            descriptors
+        }
+    }
+
+    /// Verify the response of a registration ceremony and store the credential
+    pub async fn create(
+        db: &Db,
+        relying_party: RelyingParty,
+        user_id: i32,
+        new_passkey: NewPasskey,
+        challenge: String,
+    ) -> ApiResult<Self> {
+        new_passkey.is_valid()?;
+        let credential = relying_party
+            .verify_registration(&new_passkey.credential, &challenge)
+            .map_err(|e| error(e, Status::BadRequest, "Invalid passkey"))?;
+
+        let passkey = InsertablePasskey {
+            user_id,
+            credential_id: credential.credential_id,
+            public_key: base64::encode(credential.public_key),
+            sign_count: credential.sign_count.into(),
+            name: new_passkey.name,
+            created: Utc::now().naive_utc(),
+        };
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                diesel::insert_into(passkeys::table)
+                    .values(&passkey)
+                    .execute(conn)?;
+                passkeys::table
+                    .filter(passkeys::credential_id.eq(&passkey.credential_id))
+                    .first(conn)
+            })
+        })
+        .await
+        .map_err(|e| match e {
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                error(e, Status::Conflict, "Passkey is already registered")
+            }
+            e => error(e, Status::InternalServerError, ""),
+        })
+    }
+
+    /// Verify the response of an authentication ceremony against the stored credential and
+    /// update its signature counter. If `user_id` is given, the passkey has to belong to that
+    /// user. Returns the id of the user.
+    pub async fn authenticate(
+        db: &Db,
+        relying_party: RelyingParty,
+        response: AuthenticationResponse,
+        challenge: String,
+        user_id: Option<i32>,
+    ) -> ApiResult<(i32, VerifiedAssertion)> {
+        let assertion = db
+            .run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    let passkey = passkeys::table
+                        .filter(passkeys::credential_id.eq(response.id.trim_end_matches('=')))
+                        .for_update()
+                        .first::<Passkey>(conn)
+                        .optional()?;
+                    let passkey = match passkey {
+                        Some(passkey) if user_id.map_or(true, |id| id == passkey.user_id) => {
+                            passkey
+                        }
+                        _ => return Ok(Assertion::Rejected("unknown credential".into())),
+                    };
+
+                    let public_key = base64::decode(&passkey.public_key).unwrap_or_default();
+                    let verified = match relying_party.verify_authentication(
+                        &response,
+                        &challenge,
+                        &public_key,
+                        passkey.sign_count as u32,
+                    ) {
+                        Ok(verified) => verified,
+                        Err(e) => return Ok(Assertion::Rejected(e.to_string())),
+                    };
+                    diesel::update(passkeys::table.find(passkey.id))
+                        .set((
+                            passkeys::sign_count.eq(i64::from(verified.sign_count)),
+                            passkeys::last_used.eq(Utc::now().naive_utc()),
+                        ))
+                        .execute(conn)?;
+                    Ok(Assertion::Verified(passkey.user_id, verified))
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        match assertion {
+            Assertion::Verified(user_id, verified) => Ok((user_id, verified)),
+            Assertion::Rejected(e) => Err(error(e, Status::Unauthorized, "Invalid passkey")),
+        }
+    }
+
+    /// Remove a passkey of a user, unless it is the last login method
+    pub async fn delete(db: &Db, user_id: i32, passkey_id: i32) -> ApiResult<()> {
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                if count_login_methods(conn, user_id)? <= 1 {
+                    return Ok(Unlink::LastMethod);
+                }
+                let deleted = diesel::delete(
+                    passkeys::table
+                        .find(passkey_id)
+                        .filter(passkeys::user_id.eq(user_id)),
+                )
+                .execute(conn)?;
+                Ok(if deleted == 0 {
+                    Unlink::NotFound
+                } else {
+                    Unlink::Done
+                })
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_result()
+    }
+}
+
+pub(super) fn register_polar_classes(oso: &mut oso::Oso) -> oso::Result<()> {
+    oso.register_class(Passkey::get_polar_class())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+
+    #[rocket::async_test]
+    async fn test_login_descriptors_look_alike() {
+        let db = fixtures::db().await;
+        let user = fixtures::user(&db).await;
+        let unknown = format!("unknown-{}@example.com", rand::random::<u32>());
+
+        // a registered address without passkeys and an unknown one both get a stable decoy
+        for email in [user.email.clone(), unknown] {
+            let descriptors = Passkey::login_descriptors(&db, email.clone()).await;
+            assert_eq!(1, descriptors.len());
+            assert_eq!("public-key", descriptors[0].kind);
+            assert_eq!(43, descriptors[0].id.len());
+            let again = Passkey::login_descriptors(&db, email.to_uppercase()).await;
+            assert_eq!(descriptors[0].id, again[0].id);
+        }
+        assert_ne!(
+            decoy_descriptor("ada@example.com").id,
+            decoy_descriptor("charles@example.com").id
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use super::DbResult;
+use crate::db::models::{Currency, User};
+use crate::db::{schema::payments, Db};
+use crate::payment::Authorization;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_derive_enum::DbEnum;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+
+#[derive(Debug, Clone, Deserialize, Serialize, DbEnum, JsonSchema, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum PaymentStatus {
+    Authorized,
+    Captured,
+    Failed,
+    Refunded,
+}
+
+/// Payment details sent by the client. The token is created by the client side library of the
+/// payment provider, so that no card data ever reaches SkyRocket.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct NewPayment {
+    pub token: String,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "payments"]
+struct InsertablePayment {
+    user_id: i32,
+    offer_id: i32,
+    provider: String,
+    reference: String,
+    amount: f32,
+    currency: Currency,
+    status: PaymentStatus,
+    created: NaiveDateTime,
+}
+
+#[derive(
+    Associations, Clone, Debug, Deserialize, Identifiable, JsonSchema, Queryable, Serialize,
+)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "payments"]
+pub struct Payment {
+    pub id: i32,
+    pub user_id: i32,
+    pub offer_id: i32,
+    pub provider: String,
+    pub reference: String,
+    pub amount: f32,
+    pub currency: Currency,
+    pub status: PaymentStatus,
+    pub created: NaiveDateTime,
+}
+
+impl Payment {
+    pub async fn save(
+        db: &Db,
+        user_id: i32,
+        offer_id: i32,
+        provider: &str,
+        authorization: &Authorization,
+        currency: Currency,
+    ) -> DbResult {
+        let payment = InsertablePayment {
+            user_id,
+            offer_id,
+            provider: provider.into(),
+            reference: authorization.reference.clone(),
+            amount: authorization.amount,
+            currency,
+            status: PaymentStatus::Authorized,
+            created: Utc::now().naive_utc(),
+        };
+
+        db.run(move |conn| {
+            diesel::insert_into(payments::table)
+                .values(&payment)
+                .execute(conn)
+        })
+        .await
+    }
+
+    pub async fn set_status(db: &Db, reference: String, status: PaymentStatus) -> DbResult {
+        db.run(move |conn| {
+            diesel::update(payments::table.filter(payments::reference.eq(reference)))
+                .set(payments::status.eq(status))
+                .execute(conn)
+        })
+        .await
+    }
+
+    pub async fn find_by_reference(db: &Db, reference: String) -> Option<Self> {
+        db.run(move |conn| {
+            payments::table
+                .filter(payments::reference.eq(reference))
+                .first(conn)
+        })
+        .await
+        .ok()
+    }
+
+    pub async fn all_from_booking(db: &Db, user_id: i32, offer_id: i32) -> Vec<Self> {
+        db.run(move |conn| {
+            payments::table
+                .filter(payments::user_id.eq(user_id))
+                .filter(payments::offer_id.eq(offer_id))
+                .load(conn)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+}
@@ -0,0 +1,410 @@
+use super::ancillary;
+use crate::db::models::{
+    Booking, BookingStatus, Flight, FlightOfferWithOccupancy, FlightStatus, LoyaltyTransaction,
+};
+use crate::db::schema::{bookings, check_ins, flights_offers, passengers};
+use crate::db::Db;
+use crate::routes::{error, ApiResult, OfferFilter};
+use chrono::{Duration, NaiveDateTime};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+
+/// Hours an alternative offer may depart before or after the disrupted offer, used if no time
+/// window is given
+const DEFAULT_WINDOW_HOURS: i64 = 48;
+/// Largest time window around the disrupted offer, in hours
+const MAX_WINDOW_HOURS: i64 = 168;
+
+/// Time window of the given hours, defaulting to [`DEFAULT_WINDOW_HOURS`]
+pub fn rebooking_window(hours: Option<i64>) -> ApiResult<Duration> {
+    match hours.unwrap_or(DEFAULT_WINDOW_HOURS) {
+        hours @ 1..=MAX_WINDOW_HOURS => Ok(Duration::hours(hours)),
+        _ => Err(error(
+            "",
+            Status::BadRequest,
+            &format!(
+                "Time window must be between 1 and {} hours",
+                MAX_WINDOW_HOURS
+            ),
+        )),
+    }
+}
+
+/// A booking, that has been moved to an alternative offer
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct Rebooking {
+    pub user_id: i32,
+    pub reference: String,
+    pub seats: i32,
+    pub from_offer_id: i32,
+    pub to_offer_id: i32,
+}
+
+/// A booking, for which no alternative offer has been found
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct UnaccommodatedBooking {
+    pub user_id: i32,
+    pub reference: String,
+    pub seats: i32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct RebookingReport {
+    pub rebooked: Vec<Rebooking>,
+    pub unaccommodated: Vec<UnaccommodatedBooking>,
+}
+
+/// An offer on the same route as the disrupted offer
+struct Alternative {
+    offer_id: i32,
+    seats: i32,
+    departure: NaiveDateTime,
+}
+
+/// Whether an alternative departs within the time window around the original departure
+fn within_window(departure: NaiveDateTime, alternative: NaiveDateTime, window: Duration) -> bool {
+    (alternative - departure).num_seconds().abs() <= window.num_seconds()
+}
+
+/// Whether an offer with the given capacity and occupied seats has room for the seats of a booking
+fn has_room(capacity: i32, occupied: i64, seats: i32) -> bool {
+    capacity as i64 - occupied >= seats as i64
+}
+
+/// Find all offers on the route of the given offer, that depart within the time window and
+/// aren't affected by a cancelled flight. The alternatives are ordered by how close their
+/// departure is to the original departure.
+async fn find_alternatives(
+    db: &Db,
+    offer: &FlightOfferWithOccupancy,
+    window: Duration,
+) -> Vec<Alternative> {
+    let departure = match Flight::first_departure(db, offer.id).await {
+        Some(departure) => departure,
+        None => return Vec::new(),
+    };
+    let candidates = FlightOfferWithOccupancy::get_all(
+        db,
+        OfferFilter {
+            departure_icao: Some(offer.departure_icao.clone()),
+            arrival_icao: Some(offer.arrival_icao.clone()),
+            flight_number: None,
+        },
+    )
+    .await;
+
+    let mut alternatives = Vec::new();
+    for candidate in candidates.into_iter().filter(|c| c.id != offer.id) {
+        let flights = Flight::all_from_offer(db, candidate.id).await;
+        if flights.iter().any(|f| f.status == FlightStatus::Cancelled) {
+            continue;
+        }
+        if let Some(candidate_departure) = flights.iter().map(|f| f.departure_time).min() {
+            if within_window(departure, candidate_departure, window) {
+                alternatives.push(Alternative {
+                    offer_id: candidate.id,
+                    seats: candidate.seats,
+                    departure: candidate_departure,
+                });
+            }
+        }
+    }
+    alternatives.sort_by_key(|a| (a.departure - departure).num_seconds().abs());
+    alternatives
+}
+
+/// Move a booking to another offer within a single transaction, if the offer still has enough
+/// free seats, sells an equivalent for every booked ancillary and the customer hasn't booked it
+/// already. Payments, refunds and passengers follow the booking, the ancillaries are swapped for
+/// those of the new offer and redeemed loyalty points are transferred to it. Check-ins for the old
+/// flights are removed, while the invoice stays as it has been issued.
+async fn move_booking(db: &Db, booking: &Booking, alternative: &Alternative) -> ApiResult<bool> {
+    let booking = booking.clone();
+    let (user_id, offer_id, seats) = (booking.user_id, booking.offer_id, booking.seats);
+    let (to_offer_id, capacity) = (alternative.offer_id, alternative.seats);
+
+    db.run(move |conn| {
+        conn.transaction::<_, DieselError, _>(|| {
+            // lock the alternative offer, so concurrent bookings can't take the free seats
+            flights_offers::table
+                .find(to_offer_id)
+                .select(flights_offers::id)
+                .for_update()
+                .first::<i32>(conn)?;
+
+            let occupied = bookings::table
+                .filter(bookings::offer_id.eq(to_offer_id))
+                .filter(bookings::status.ne(BookingStatus::Cancelled))
+                .select(diesel::dsl::sum(bookings::seats))
+                .first::<Option<i64>>(conn)?
+                .unwrap_or(0);
+            let already_booked = bookings::table
+                .find((user_id, to_offer_id))
+                .count()
+                .get_result::<i64>(conn)?
+                > 0;
+            if !has_room(capacity, occupied, seats) || already_booked {
+                return Ok(false);
+            }
+            let ancillaries = match ancillary::replacements(conn, user_id, offer_id, to_offer_id)? {
+                Some(ancillaries) => ancillaries,
+                None => return Ok(false),
+            };
+
+            let passenger_ids = passengers::table
+                .filter(passengers::user_id.eq(user_id))
+                .filter(passengers::offer_id.eq(offer_id))
+                .select(passengers::id);
+            diesel::delete(check_ins::table.filter(check_ins::passenger_id.eq_any(passenger_ids)))
+                .execute(conn)?;
+            ancillary::release_expired(conn, user_id, offer_id)?;
+            diesel::update(bookings::table.find((user_id, offer_id)))
+                .set((
+                    bookings::offer_id.eq(to_offer_id),
+                    bookings::disrupted.eq(false),
+                ))
+                .execute(conn)?;
+            ancillary::replace(conn, user_id, to_offer_id, &ancillaries)?;
+            LoyaltyTransaction::transfer_redemption(conn, &booking, to_offer_id)?;
+            Ok(true)
+        })
+    })
+    .await
+    .map_err(|e| error(e, Status::InternalServerError, ""))
+}
+
+/// Move every disrupted booking of an offer to an alternative offer on the same route, departing
+/// within the given time window around the original departure
+pub async fn rebook_offer(db: &Db, offer_id: i32, window: Duration) -> ApiResult<RebookingReport> {
+    let offer = FlightOfferWithOccupancy::from_offer_id(db, offer_id)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find flight offer"))?;
+    let alternatives = find_alternatives(db, &offer, window).await;
+
+    let mut report = RebookingReport::default();
+    for booking in Booking::all_from_offer(db, offer_id).await {
+        if !booking.disrupted || booking.status == BookingStatus::Cancelled {
+            continue;
+        }
+
+        let mut rebooked = None;
+        for alternative in &alternatives {
+            if move_booking(db, &booking, alternative).await? {
+                rebooked = Some(alternative.offer_id);
+                break;
+            }
+        }
+
+        match rebooked {
+            Some(to_offer_id) => report.rebooked.push(Rebooking {
+                user_id: booking.user_id,
+                reference: booking.reference,
+                seats: booking.seats,
+                from_offer_id: offer_id,
+                to_offer_id,
+            }),
+            None => report.unaccommodated.push(UnaccommodatedBooking {
+                user_id: booking.user_id,
+                reference: booking.reference,
+                seats: booking.seats,
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::loyalty::LoyaltyTransactionKind;
+    use crate::db::models::{
+        Ancillary, AncillaryKind, BookedAncillary, Invoice, LoyaltyRedemption, NewAncillary,
+        NewBooking, NewBookingAncillary,
+    };
+    use crate::db::schema::loyalty_transactions;
+    use chrono::{NaiveDate, Utc};
+
+    fn departure() -> NaiveDateTime {
+        NaiveDate::from_ymd(2022, 6, 6).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn test_rebooking_window() {
+        assert_eq!(48, rebooking_window(None).unwrap().num_hours());
+        assert_eq!(168, rebooking_window(Some(168)).unwrap().num_hours());
+        assert!(rebooking_window(Some(0)).is_err());
+        assert!(rebooking_window(Some(-24)).is_err());
+        assert!(rebooking_window(Some(169)).is_err());
+        assert!(rebooking_window(Some(i64::MAX)).is_err());
+    }
+
+    #[test]
+    fn test_within_window() {
+        let window = Duration::hours(48);
+        assert!(within_window(departure(), departure(), window));
+        assert!(within_window(departure(), departure() + window, window));
+        assert!(within_window(departure(), departure() - window, window));
+        assert!(!within_window(
+            departure(),
+            departure() + window + Duration::seconds(1),
+            window
+        ));
+        assert!(!within_window(
+            departure(),
+            departure() - window - Duration::seconds(1),
+            window
+        ));
+    }
+
+    #[test]
+    fn test_has_room() {
+        assert!(has_room(100, 98, 2));
+        assert!(!has_room(100, 99, 2));
+        // oversold offers have no room at all
+        assert!(!has_room(100, 102, 1));
+    }
+
+    async fn bag(db: &Db, offer_id: i32, inventory: Option<i32>) -> Ancillary {
+        Ancillary::create(
+            db,
+            offer_id,
+            NewAncillary {
+                kind: AncillaryKind::CheckedBag,
+                description: "Checked bag up to 23 kg".into(),
+                price: 30.0,
+                inventory,
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Book two seats with two bags of the offer, paid partly with 500 loyalty points
+    async fn paid_booking(db: &Db, user_id: i32, offer_id: i32, bag_id: i32) -> Booking {
+        let new_booking = NewBooking {
+            ancillaries: vec![NewBookingAncillary {
+                ancillary_id: bag_id,
+                quantity: 2,
+            }],
+            ..NewBooking::default()
+        };
+        Booking::create(db, user_id, offer_id, Some(2), None, None, new_booking)
+            .await
+            .unwrap();
+        let booking = Booking::find(db, user_id, offer_id).await.unwrap();
+
+        db.run(move |conn| {
+            diesel::insert_into(loyalty_transactions::table)
+                .values((
+                    loyalty_transactions::user_id.eq(user_id),
+                    loyalty_transactions::offer_id.eq(offer_id),
+                    loyalty_transactions::kind.eq(LoyaltyTransactionKind::Accrual),
+                    loyalty_transactions::points.eq(1_000),
+                    loyalty_transactions::description.eq("Test accrual"),
+                    loyalty_transactions::created.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)
+        })
+        .await
+        .unwrap();
+        LoyaltyTransaction::redeem(db, &booking, LoyaltyRedemption { points: 500 })
+            .await
+            .unwrap();
+        Booking::set_status(db, user_id, offer_id, BookingStatus::Confirmed)
+            .await
+            .unwrap();
+        Booking::find(db, user_id, offer_id).await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn test_move_booking_keeps_history() {
+        let db = fixtures::db().await;
+        let user = fixtures::user(&db).await;
+        let route = fixtures::route();
+        let offer_id = fixtures::offer(&db, &route, departure(), 10).await;
+        let to_offer_id = fixtures::offer(&db, &route, departure(), 10).await;
+        let old_bag = bag(&db, offer_id, Some(5)).await;
+        let new_bag = bag(&db, to_offer_id, Some(5)).await;
+
+        let booking = paid_booking(&db, user.id, offer_id, old_bag.id).await;
+        let invoice = Invoice::find_or_issue(&db, user.id, offer_id)
+            .await
+            .unwrap();
+
+        let alternative = Alternative {
+            offer_id: to_offer_id,
+            seats: 10,
+            departure: departure(),
+        };
+        assert!(move_booking(&db, &booking, &alternative).await.unwrap());
+        assert!(Booking::find(&db, user.id, offer_id).await.is_none());
+        let moved = Booking::find(&db, user.id, to_offer_id).await.unwrap();
+        assert_eq!(booking.reference, moved.reference);
+
+        // the invoice is found by the booking, but still shows the offer it has been issued for
+        let rebooked_invoice = Invoice::find_or_issue(&db, user.id, to_offer_id)
+            .await
+            .unwrap();
+        assert_eq!(invoice.number, rebooked_invoice.number);
+        assert_eq!(offer_id, rebooked_invoice.offer_id);
+        assert_eq!(invoice.total, rebooked_invoice.total);
+
+        // the bags are swapped for the bags of the new offer
+        let ancillaries = BookedAncillary::all_from_booking(&db, user.id, to_offer_id).await;
+        assert_eq!(1, ancillaries.len());
+        assert_eq!(new_bag.id, ancillaries[0].ancillary_id);
+        assert_eq!(2, ancillaries[0].quantity);
+
+        // the redeemed points follow the booking, without changing the balance
+        assert_eq!(
+            0,
+            LoyaltyTransaction::redeemed_points(&db, user.id, offer_id).await
+        );
+        assert_eq!(
+            500,
+            LoyaltyTransaction::redeemed_points(&db, user.id, to_offer_id).await
+        );
+        let account = LoyaltyTransaction::account(&db, user.id).await.unwrap();
+        assert_eq!(500, account.balance);
+    }
+
+    #[rocket::async_test]
+    async fn test_move_booking_needs_seats_and_ancillaries() {
+        let db = fixtures::db().await;
+        let user = fixtures::user(&db).await;
+        let route = fixtures::route();
+        let offer_id = fixtures::offer(&db, &route, departure(), 10).await;
+        let full_offer_id = fixtures::offer(&db, &route, departure(), 1).await;
+        let sold_out_offer_id = fixtures::offer(&db, &route, departure(), 10).await;
+        let old_bag = bag(&db, offer_id, None).await;
+        bag(&db, full_offer_id, None).await;
+        bag(&db, sold_out_offer_id, Some(1)).await;
+
+        let booking = paid_booking(&db, user.id, offer_id, old_bag.id).await;
+        for (to_offer_id, seats) in [(full_offer_id, 1), (sold_out_offer_id, 10)] {
+            let alternative = Alternative {
+                offer_id: to_offer_id,
+                seats,
+                departure: departure(),
+            };
+            assert!(!move_booking(&db, &booking, &alternative).await.unwrap());
+        }
+        assert!(Booking::find(&db, user.id, offer_id).await.is_some());
+        assert_eq!(
+            old_bag.id,
+            BookedAncillary::all_from_booking(&db, user.id, offer_id).await[0].ancillary_id
+        );
+    }
+}
@@ -0,0 +1,572 @@
+use super::DbResult;
+use crate::db::models::{Flight, FlightOfferWithOccupancy, Payment, PaymentStatus, User};
+use crate::db::{schema::refunds, Db};
+use crate::payment::PaymentGateway;
+use crate::routes::{error, ApiError, ApiResult};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_derive_enum::DbEnum;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+
+/// Customers cancelling at least this many days before departure get their full money back
+const FULL_REFUND_DAYS: i64 = 7;
+/// Customers cancelling at least this many hours before departure get half their money back
+const HALF_REFUND_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Deserialize, Serialize, DbEnum, JsonSchema, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum RefundReason {
+    CustomerCancellation,
+    FlightCancellation,
+    Manual,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, DbEnum, FromFormField, JsonSchema, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum RefundStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+/// Calculate the amount a customer gets back for a booking, following the fare rules of the
+/// offer. Cancellations by the airline are always refunded completely.
+pub fn calculate_refund(
+    paid: f32,
+    refundable: bool,
+    reason: &RefundReason,
+    departure: NaiveDateTime,
+    now: NaiveDateTime,
+) -> f32 {
+    if *reason == RefundReason::FlightCancellation {
+        return paid;
+    }
+
+    let remaining = departure.signed_duration_since(now);
+    if !refundable || remaining < Duration::hours(HALF_REFUND_HOURS) {
+        0.0
+    } else if remaining < Duration::days(FULL_REFUND_DAYS) {
+        paid / 2.0
+    } else {
+        paid
+    }
+}
+
+/// Manual refund, issued by an administrator
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct NewRefund {
+    pub user_id: i32,
+    pub offer_id: i32,
+    pub amount: f32,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "refunds"]
+struct InsertableRefund {
+    user_id: i32,
+    offer_id: i32,
+    amount: f32,
+    reason: RefundReason,
+    status: RefundStatus,
+    created: NaiveDateTime,
+    updated: NaiveDateTime,
+}
+
+impl InsertableRefund {
+    fn new(
+        user_id: i32,
+        offer_id: i32,
+        amount: f32,
+        reason: RefundReason,
+        status: RefundStatus,
+    ) -> Self {
+        let now = Utc::now().naive_utc();
+        InsertableRefund {
+            user_id,
+            offer_id,
+            amount,
+            reason,
+            status,
+            created: now,
+            updated: now,
+        }
+    }
+}
+
+#[derive(
+    Associations, Clone, Debug, Deserialize, Identifiable, JsonSchema, Queryable, Serialize,
+)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[table_name = "refunds"]
+pub struct Refund {
+    pub id: i32,
+    pub user_id: i32,
+    pub offer_id: i32,
+    pub amount: f32,
+    pub reason: RefundReason,
+    pub status: RefundStatus,
+    pub created: NaiveDateTime,
+    pub updated: NaiveDateTime,
+}
+
+impl Refund {
+    async fn save(db: &Db, refund: InsertableRefund) -> DbResult {
+        db.run(move |conn| {
+            diesel::insert_into(refunds::table)
+                .values(&refund)
+                .execute(conn)
+        })
+        .await
+    }
+
+    async fn last_of_booking(db: &Db, user_id: i32, offer_id: i32) -> Option<Self> {
+        db.run(move |conn| {
+            refunds::table
+                .filter(refunds::user_id.eq(user_id))
+                .filter(refunds::offer_id.eq(offer_id))
+                .order(refunds::id.desc())
+                .first(conn)
+        })
+        .await
+        .ok()
+    }
+
+    /// Change the status of a refund, only if it still has the expected status. Of concurrent
+    /// requests changing the same refund, only one succeeds.
+    async fn transition(db: &Db, id: i32, from: RefundStatus, to: RefundStatus) -> ApiResult<()> {
+        let updated = db
+            .run(move |conn| {
+                diesel::update(refunds::table.find(id).filter(refunds::status.eq(from)))
+                    .set((
+                        refunds::status.eq(to),
+                        refunds::updated.eq(Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        if updated == 0 {
+            return Err(error("", Status::Conflict, "Refund is not pending"));
+        }
+        Ok(())
+    }
+
+    /// Lower the amount of an approved refund to the part the payment provider paid
+    async fn set_amount(db: &Db, id: i32, amount: f32) -> DbResult {
+        db.run(move |conn| {
+            diesel::update(refunds::table.find(id))
+                .set((
+                    refunds::amount.eq(amount),
+                    refunds::updated.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)
+        })
+        .await
+    }
+
+    pub async fn find(db: &Db, id: i32) -> Option<Self> {
+        db.run(move |conn| refunds::table.find(id).first(conn))
+            .await
+            .ok()
+    }
+
+    pub async fn get_all(db: &Db, status: Option<RefundStatus>) -> Vec<Self> {
+        db.run(move |conn| {
+            let mut query = refunds::table.into_boxed();
+
+            if let Some(status) = status {
+                query = query.filter(refunds::status.eq(status));
+            }
+
+            query.order(refunds::id.desc()).load(conn)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+
+    pub async fn all_from_booking(db: &Db, user_id: i32, offer_id: i32) -> Vec<Self> {
+        db.run(move |conn| {
+            refunds::table
+                .filter(refunds::user_id.eq(user_id))
+                .filter(refunds::offer_id.eq(offer_id))
+                .load(conn)
+        })
+        .await
+        .unwrap_or_else(|_| Vec::new())
+    }
+
+    /// The amount that has been paid for a booking and hasn't been refunded or requested to be
+    /// refunded yet
+    pub async fn refundable_amount(db: &Db, user_id: i32, offer_id: i32) -> f32 {
+        let paid: f32 = Payment::all_from_booking(db, user_id, offer_id)
+            .await
+            .iter()
+            .filter(|p| p.status == PaymentStatus::Captured || p.status == PaymentStatus::Refunded)
+            .map(|p| p.amount)
+            .sum();
+        let refunded: f32 = Refund::all_from_booking(db, user_id, offer_id)
+            .await
+            .iter()
+            .filter(|r| r.status != RefundStatus::Rejected)
+            .map(|r| r.amount)
+            .sum();
+
+        paid - refunded
+    }
+
+    /// Calculate the refund for a cancelled booking and store it for approval by an
+    /// administrator. Returns `None`, if the customer doesn't get any money back.
+    pub async fn request(
+        db: &Db,
+        user_id: i32,
+        offer_id: i32,
+        reason: RefundReason,
+    ) -> ApiResult<Option<Self>> {
+        let paid = Refund::refundable_amount(db, user_id, offer_id).await;
+        if paid <= 0.0 {
+            return Ok(None);
+        }
+
+        let offer = FlightOfferWithOccupancy::from_offer_id(db, offer_id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find flight offer"))?;
+        let departure = Flight::first_departure(db, offer_id)
+            .await
+            .unwrap_or_else(|| Utc::now().naive_utc());
+
+        let amount = calculate_refund(
+            paid,
+            offer.refundable,
+            &reason,
+            departure,
+            Utc::now().naive_utc(),
+        );
+        if amount <= 0.0 {
+            return Ok(None);
+        }
+
+        Refund::save(
+            db,
+            InsertableRefund::new(user_id, offer_id, amount, reason, RefundStatus::Pending),
+        )
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        Ok(Refund::last_of_booking(db, user_id, offer_id).await)
+    }
+
+    /// Pay back an approved refund with the payment provider. A booking may have been paid in
+    /// several captures, like for ancillaries added later. Earlier refunds used up the captures
+    /// in the order they were made, so this refund takes what is left of them in the same order.
+    /// On failure, returns the amount that was paid back nonetheless.
+    async fn issue(
+        db: &Db,
+        gateway: &PaymentGateway,
+        refund: &Refund,
+    ) -> Result<(), (f32, ApiError)> {
+        let mut payments = Payment::all_from_booking(db, refund.user_id, refund.offer_id)
+            .await
+            .into_iter()
+            .filter(|p| p.status == PaymentStatus::Captured || p.status == PaymentStatus::Refunded)
+            .collect::<Vec<Payment>>();
+        payments.sort_by_key(|p| p.id);
+        let mut refunded: f32 = Refund::all_from_booking(db, refund.user_id, refund.offer_id)
+            .await
+            .iter()
+            .filter(|r| r.status == RefundStatus::Approved && r.id != refund.id)
+            .map(|r| r.amount)
+            .sum();
+
+        let mut paid = 0.0;
+        for payment in payments {
+            let open = (payment.amount - refunded).max(0.0);
+            refunded = (refunded - payment.amount).max(0.0);
+            let part = open.min(refund.amount - paid);
+            if part < 0.01 {
+                continue;
+            }
+
+            gateway
+                .refund(&payment.reference, part)
+                .await
+                .map_err(|e| {
+                    let e = error(
+                        e,
+                        Status::BadGateway,
+                        "Refund was rejected by the payment provider",
+                    );
+                    (paid, e)
+                })?;
+            paid += part;
+
+            if part >= open {
+                Payment::set_status(db, payment.reference, PaymentStatus::Refunded)
+                    .await
+                    .map_err(|e| (paid, error(e, Status::InternalServerError, "")))?;
+            }
+        }
+
+        if refund.amount - paid >= 0.01 {
+            let e = error("", Status::Conflict, "Booking has no captured payment left");
+            return Err((paid, e));
+        }
+        Ok(())
+    }
+
+    pub async fn approve(db: &Db, gateway: &PaymentGateway, id: i32) -> ApiResult<Self> {
+        let refund = Refund::find(db, id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find refund"))?;
+
+        // the refund is claimed before any money is paid back, so it is paid only once
+        Refund::transition(db, id, RefundStatus::Pending, RefundStatus::Approved).await?;
+        if let Err((paid, e)) = Refund::issue(db, gateway, &refund).await {
+            // a refund, that has been paid partly, stays approved with the amount paid, so the
+            // rest is still refundable and nothing is paid twice
+            if paid > 0.0 {
+                Refund::set_amount(db, id, paid)
+                    .await
+                    .map_err(|e| error(e, Status::InternalServerError, ""))?;
+            } else {
+                Refund::transition(db, id, RefundStatus::Approved, RefundStatus::Pending).await?;
+            }
+            return Err(e);
+        }
+
+        Refund::find(db, id)
+            .await
+            .ok_or_else(|| error("", Status::InternalServerError, ""))
+    }
+
+    pub async fn reject(db: &Db, id: i32) -> ApiResult<Self> {
+        Refund::find(db, id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find refund"))?;
+        Refund::transition(db, id, RefundStatus::Pending, RefundStatus::Rejected).await?;
+
+        Refund::find(db, id)
+            .await
+            .ok_or_else(|| error("", Status::InternalServerError, ""))
+    }
+
+    /// Issue a refund of an arbitrary amount, up to the amount that hasn't been refunded yet
+    pub async fn create_manual(
+        db: &Db,
+        gateway: &PaymentGateway,
+        new_refund: NewRefund,
+    ) -> ApiResult<Self> {
+        let NewRefund {
+            user_id,
+            offer_id,
+            amount,
+        } = new_refund;
+
+        if amount <= 0.0 || amount > Refund::refundable_amount(db, user_id, offer_id).await {
+            return Err(error("", Status::BadRequest, "Bad refund amount"));
+        }
+
+        Refund::save(
+            db,
+            InsertableRefund::new(
+                user_id,
+                offer_id,
+                amount,
+                RefundReason::Manual,
+                RefundStatus::Pending,
+            ),
+        )
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?;
+
+        let refund = Refund::last_of_booking(db, user_id, offer_id)
+            .await
+            .ok_or_else(|| error("", Status::InternalServerError, ""))?;
+
+        match Refund::approve(db, gateway, refund.id).await {
+            Ok(refund) => Ok(refund),
+            Err(e) => {
+                // don't let a failed refund block the amount for further refunds, unless it has been
+                // paid partly
+                Refund::transition(db, refund.id, RefundStatus::Pending, RefundStatus::Rejected)
+                    .await
+                    .ok();
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::fixtures;
+    use crate::db::models::{
+        Ancillary, AncillaryKind, AncillaryPurchase, Booking, NewAncillary, NewBooking,
+        NewBookingAncillary, NewPayment,
+    };
+    use chrono::NaiveDate;
+
+    fn departure() -> NaiveDateTime {
+        NaiveDate::from_ymd(2022, 6, 1).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn test_refund_long_before_departure() {
+        let now = departure() - Duration::days(30);
+        assert_eq!(
+            200.0,
+            calculate_refund(
+                200.0,
+                true,
+                &RefundReason::CustomerCancellation,
+                departure(),
+                now
+            )
+        );
+    }
+
+    #[test]
+    fn test_refund_within_a_week_of_departure() {
+        let now = departure() - Duration::days(2);
+        assert_eq!(
+            100.0,
+            calculate_refund(
+                200.0,
+                true,
+                &RefundReason::CustomerCancellation,
+                departure(),
+                now
+            )
+        );
+    }
+
+    #[test]
+    fn test_refund_on_day_of_departure() {
+        let now = departure() - Duration::hours(3);
+        assert_eq!(
+            0.0,
+            calculate_refund(
+                200.0,
+                true,
+                &RefundReason::CustomerCancellation,
+                departure(),
+                now
+            )
+        );
+    }
+
+    #[test]
+    fn test_refund_non_refundable_fare() {
+        let now = departure() - Duration::days(30);
+        assert_eq!(
+            0.0,
+            calculate_refund(
+                200.0,
+                false,
+                &RefundReason::CustomerCancellation,
+                departure(),
+                now
+            )
+        );
+    }
+
+    #[test]
+    fn test_refund_flight_cancellation() {
+        let now = departure() - Duration::hours(1);
+        assert_eq!(
+            200.0,
+            calculate_refund(
+                200.0,
+                false,
+                &RefundReason::FlightCancellation,
+                departure(),
+                now
+            )
+        );
+    }
+
+    /// A booking paid in two captures, 200 € for the seats and 30 € for a bag added afterwards
+    async fn paid_booking(db: &Db, gateway: &PaymentGateway) -> (i32, i32) {
+        let user = fixtures::user(db).await;
+        let departure = Utc::now().naive_utc() + Duration::days(30);
+        let offer_id = fixtures::offer(db, &fixtures::route(), departure, 10).await;
+        Booking::create(
+            db,
+            user.id,
+            offer_id,
+            Some(2),
+            None,
+            None,
+            NewBooking::default(),
+        )
+        .await
+        .unwrap();
+        let payment = NewPayment {
+            token: "tok_visa".into(),
+        };
+        Booking::pay(db, gateway, user.id, offer_id, payment)
+            .await
+            .unwrap();
+
+        let bag = Ancillary::create(
+            db,
+            offer_id,
+            NewAncillary {
+                kind: AncillaryKind::CheckedBag,
+                description: "Checked bag up to 23 kg".into(),
+                price: 30.0,
+                inventory: None,
+            },
+        )
+        .await
+        .unwrap();
+        let booking = Booking::find(db, user.id, offer_id).await.unwrap();
+        let purchase = AncillaryPurchase {
+            ancillaries: vec![NewBookingAncillary {
+                ancillary_id: bag.id,
+                quantity: 1,
+            }],
+            token: Some("tok_visa".into()),
+        };
+        Booking::add_ancillaries(db, gateway, &booking, purchase)
+            .await
+            .unwrap();
+        (user.id, offer_id)
+    }
+
+    #[rocket::async_test]
+    async fn test_concurrent_approvals_refund_every_capture_once() {
+        let db = fixtures::db().await;
+        let other = fixtures::db().await;
+        let gateway = PaymentGateway::mock();
+        let (user_id, offer_id) = paid_booking(&db, &gateway).await;
+        let refund = Booking::cancel(&db, user_id, offer_id, RefundReason::FlightCancellation)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(230.0, refund.amount);
+
+        let (first, second) = rocket::futures::join!(
+            Refund::approve(&db, &gateway, refund.id),
+            Refund::approve(&other, &gateway, refund.id),
+        );
+        assert!(first.is_ok() != second.is_ok());
+        let error = [first, second].into_iter().find_map(Result::err).unwrap();
+        assert_eq!(Status::Conflict, error.0);
+
+        let refund = Refund::find(&db, refund.id).await.unwrap();
+        assert_eq!(RefundStatus::Approved, refund.status);
+        assert_eq!(230.0, refund.amount);
+        let payments = Payment::all_from_booking(&db, user_id, offer_id).await;
+        assert_eq!(2, payments.len());
+        assert!(payments.iter().all(|p| p.status == PaymentStatus::Refunded));
+        assert_eq!(0.0, Refund::refundable_amount(&db, user_id, offer_id).await);
+    }
+}
@@ -1,7 +1,6 @@
-use crate::db::models::User;
+use crate::db::models::{AuthUser, User};
 use crate::db::schema::users_roles;
 use crate::db::Db;
-use crate::session;
 use diesel::prelude::*;
 use diesel_derive_enum::DbEnum;
 use oso::{PolarValue, ToPolar};
@@ -72,15 +71,6 @@ impl UserRole {
     }
 }
 
-/// simple helper function to check if the current user (if any) has a specified role
-async fn session_has_role(request: &Request<'_>, role: Role) -> bool {
-    if let Some(user) = session::get_user_from_session(request.cookies()).await {
-        user.roles.contains(&role)
-    } else {
-        false
-    }
-}
-
 /// Dummy role, used for RBAC of Rocket API endpoints
 pub struct AdminRole {}
 
@@ -89,7 +79,11 @@ impl<'a> FromRequest<'a> for AdminRole {
     type Error = String;
 
     async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
-        if session_has_role(request, Role::Admin).await {
+        let is_admin = match request.guard::<AuthUser>().await {
+            Outcome::Success(user) => user.roles.contains(&Role::Admin),
+            _ => false,
+        };
+        if is_admin {
             Outcome::Success(AdminRole {})
         } else {
             Outcome::Forward(())
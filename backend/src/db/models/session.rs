@@ -1,5 +1,4 @@
-use super::DbResult;
-use crate::db::models::{AuthUser, User};
+use crate::db::models::{AuthUser, Role, Totp, User};
 use crate::db::{schema::sessions, Db};
 use crate::routes::{error, ApiResult, UserAgent};
 use crate::session as browser_session;
@@ -106,12 +105,17 @@ impl Session {
         Session::delete_by_redis_key(db, key).await
     }
 
+    /// Create the session of a user, who has passed every factor of the login. Administrators,
+    /// that haven't enabled two-factor authentication, get a session without the admin role.
     pub async fn save<'a>(
         db: &Db,
         cookies: &CookieJar<'_>,
         ua: UserAgent,
-        user: AuthUser,
-    ) -> DbResult {
+        mut user: AuthUser,
+    ) -> ApiResult<()> {
+        if user.roles.contains(&Role::Admin) && !Totp::is_enabled(db, user.id).await? {
+            user.roles.retain(|role| *role != Role::Admin);
+        }
         let redis_key = browser_session::set_user(cookies, user.clone()).await;
         let new_session = NewSession {
             redis_key,
@@ -124,6 +128,14 @@ impl Session {
                 .execute(conn)
         })
         .await
+        .map(|_| ())
+        .map_err(|e| {
+            error(
+                e,
+                Status::InternalServerError,
+                "Error saving session, please try again later",
+            )
+        })
     }
 }
 
@@ -0,0 +1,162 @@
+use super::carrier::split_flight_number;
+use crate::bcbp::{self, BoardingPass};
+use crate::db::models::{Booking, BookingStatus, CheckIn, Flight, Passenger};
+use crate::db::Db;
+use crate::pdf::{Document, Font, Page};
+use crate::routes::{error, ApiResult};
+use chrono::NaiveDateTime;
+use qrcode::{Color, QrCode};
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+
+/// Airline designator printed on tickets of flights without a flight number
+const CARRIER: &str = "SR";
+/// Edge length of the QR code on the PDF in points
+const QR_CODE_SIZE: f32 = 180.0;
+
+/// An electronic ticket of a single passenger for a single flight of a booking
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct Ticket {
+    pub passenger_id: i32,
+    pub flight_id: i32,
+    pub passenger_name: String,
+    pub booking_reference: String,
+    pub flight_number: String,
+    pub departure_icao: String,
+    pub departure_time: NaiveDateTime,
+    pub arrival_icao: String,
+    pub arrival_time: NaiveDateTime,
+    pub seat: Option<String>,
+    pub checked_in: bool,
+    /// Boarding pass data in the IATA BCBP format, also encoded in the QR code of the PDF
+    pub bcbp: String,
+}
+
+impl Ticket {
+    fn new(
+        booking: &Booking,
+        passenger: &Passenger,
+        flight: &Flight,
+        check_in: Option<&CheckIn>,
+    ) -> Self {
+        let seat = check_in.and_then(|c| c.seat.clone());
+        let fallback = format!("{:04}", flight.id % 10000);
+        let (carrier, number) = flight
+            .flight_number
+            .as_deref()
+            .and_then(split_flight_number)
+            .unwrap_or((CARRIER, fallback.as_str()));
+        let bcbp = bcbp::encode(&BoardingPass {
+            firstname: passenger.firstname.clone(),
+            lastname: passenger.lastname.clone(),
+            pnr: booking.reference.clone(),
+            from: flight.departure_icao.clone(),
+            to: flight.arrival_icao.clone(),
+            carrier: carrier.into(),
+            flight_number: number.into(),
+            date: flight.departure_time.date(),
+            seat: seat.clone(),
+            sequence: check_in.map(|c| c.sequence),
+        });
+
+        Ticket {
+            passenger_id: passenger.id,
+            flight_id: flight.id,
+            passenger_name: format!("{} {}", passenger.firstname, passenger.lastname),
+            booking_reference: booking.reference.clone(),
+            flight_number: format!("{}{}", carrier, number),
+            departure_icao: flight.departure_icao.clone(),
+            departure_time: flight.departure_time,
+            arrival_icao: flight.arrival_icao.clone(),
+            arrival_time: flight.arrival_time,
+            seat,
+            checked_in: check_in.is_some(),
+            bcbp,
+        }
+    }
+
+    /// Issue the tickets of a paid booking, one for every passenger on every flight
+    pub async fn all_from_booking(db: &Db, booking: &Booking) -> ApiResult<Vec<Self>> {
+        if booking.status != BookingStatus::Confirmed {
+            return Err(error("", Status::NotFound, "Booking hasn't been paid yet"));
+        }
+
+        let passengers = Passenger::all_from_booking(db, booking.user_id, booking.offer_id).await;
+        let mut flights = Flight::all_from_offer(db, booking.offer_id).await;
+        flights.sort_by_key(|f| f.departure_time);
+        let check_ins = CheckIn::all_from_booking(db, booking.user_id, booking.offer_id).await;
+
+        Ok(passengers
+            .iter()
+            .flat_map(|p| {
+                let check_ins = &check_ins;
+                flights.iter().map(move |f| {
+                    let check_in = check_ins
+                        .iter()
+                        .find(|c| c.passenger_id == p.id && c.flight_id == f.id);
+                    Ticket::new(booking, p, f, check_in)
+                })
+            })
+            .collect())
+    }
+
+    /// Render tickets as PDF document, with one page per ticket
+    pub fn to_pdf(tickets: &[Self]) -> ApiResult<Vec<u8>> {
+        let mut document = Document::new();
+
+        for ticket in tickets {
+            let mut page = Page::new();
+            page.text(50.0, 70.0, 20.0, Font::Bold, "Boarding Pass");
+            page.text(400.0, 70.0, 12.0, Font::Bold, &ticket.flight_number);
+            page.line(50.0, 85.0, 495.0);
+
+            let rows = [
+                ("Passenger", ticket.passenger_name.clone()),
+                ("Booking reference", ticket.booking_reference.clone()),
+                ("From", ticket.departure_icao.clone()),
+                (
+                    "Departure",
+                    ticket.departure_time.format("%Y-%m-%d %H:%M").to_string(),
+                ),
+                ("To", ticket.arrival_icao.clone()),
+                (
+                    "Arrival",
+                    ticket.arrival_time.format("%Y-%m-%d %H:%M").to_string(),
+                ),
+                (
+                    "Seat",
+                    ticket
+                        .seat
+                        .clone()
+                        .unwrap_or_else(|| "Assigned at the gate".into()),
+                ),
+            ];
+            let mut y = 110.0;
+            for (label, value) in rows {
+                page.text(50.0, y, 10.0, Font::Bold, label);
+                page.text(160.0, y, 10.0, Font::Regular, &value);
+                y += 18.0;
+            }
+
+            let code = QrCode::new(ticket.bcbp.as_bytes())
+                .map_err(|e| error(e, Status::InternalServerError, ""))?;
+            let width = code.width();
+            let module = QR_CODE_SIZE / width as f32;
+            for (i, color) in code.to_colors().into_iter().enumerate() {
+                if color == Color::Dark {
+                    let x = 365.0 + (i % width) as f32 * module;
+                    let y = 110.0 + (i / width) as f32 * module;
+                    page.rect(x, y, module, module);
+                }
+            }
+
+            document.add_page(page);
+        }
+
+        Ok(document.to_bytes())
+    }
+}
@@ -0,0 +1,566 @@
+use crate::crypto;
+use crate::db::schema::{users_recovery_codes, users_totp};
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use hmac::{Hmac, Mac, NewMac};
+use oso::PolarClass;
+use qrcode::{Color, QrCode};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::{RawStr, Status};
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// Name of the service shown in authenticator apps
+const ISSUER: &str = "SkyRocket";
+/// Length of the shared secret in bytes, as recommended by RFC 4226
+const SECRET_LENGTH: usize = 20;
+/// Seconds a code is valid
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Steps before and after the current one, that are accepted to allow for clock drift
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Length of a recovery code, without the separator
+const RECOVERY_CODE_LENGTH: usize = 10;
+/// Border around the QR code in modules
+const QR_CODE_QUIET_ZONE: usize = 4;
+/// Wrong second factors in a row, after which the second factor of the user gets locked
+const MAX_FAILED_ATTEMPTS: i32 = 5;
+/// Minutes the second factor stays locked after too many wrong codes
+const LOCKOUT_MINUTES: i64 = 15;
+
+/// Encode bytes as base32 (RFC 4648) without padding, as expected by authenticator apps
+fn base32(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(5) {
+        let mut block = [0u8; 8];
+        block[3..3 + chunk.len()].copy_from_slice(chunk);
+        let bits = u64::from_be_bytes(block);
+        for i in 0..(chunk.len() * 8 + 4) / 5 {
+            encoded.push(ALPHABET[((bits >> (35 - i * 5)) & 0x1f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+/// HMAC-based one-time password (RFC 4226) for the given counter
+fn hotp(secret: &[u8], counter: u64) -> String {
+    let mut mac = HmacSha1::new_from_slice(secret).expect("HMAC can take keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Compare two codes in constant time
+fn codes_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Find the time step (RFC 6238) the code belongs to, within the allowed clock drift
+fn matching_step(secret: &[u8], code: &str, timestamp: i64) -> Option<i64> {
+    let current = timestamp / STEP_SECONDS;
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+        .filter(|step| *step >= 0)
+        .find(|step| codes_equal(&hotp(secret, *step as u64), code.trim()))
+}
+
+/// URI of the key, that authenticator apps read from the QR code
+fn provisioning_uri(secret: &[u8], account: &str) -> String {
+    let label = format!("{}:{}", ISSUER, account);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        RawStr::new(&label).percent_encode(),
+        base32(secret),
+        ISSUER,
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// Render the data as QR code in SVG format
+fn qr_code_svg(data: &str) -> Result<String, qrcode::types::QrError> {
+    let code = QrCode::new(data.as_bytes())?;
+    let width = code.width();
+    let size = width + 2 * QR_CODE_QUIET_ZONE;
+    let path: String = code
+        .to_colors()
+        .into_iter()
+        .enumerate()
+        .filter(|(_, color)| *color == Color::Dark)
+        .map(|(i, _)| {
+            format!(
+                "M{},{}h1v1h-1z",
+                i % width + QR_CODE_QUIET_ZONE,
+                i / width + QR_CODE_QUIET_ZONE
+            )
+        })
+        .collect();
+    Ok(format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {size} {size}" shape-rendering="crispEdges"><rect width="{size}" height="{size}" fill="#fff"/><path d="{path}" fill="#000"/></svg>"##,
+        size = size,
+        path = path
+    ))
+}
+
+/// Recovery codes are random and long, so a SHA-256 hash is sufficient. Separators and case are
+/// ignored, to make typing them easier.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn new_recovery_code() -> String {
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(RECOVERY_CODE_LENGTH)
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .collect();
+    let (first, second) = code.split_at(RECOVERY_CODE_LENGTH / 2);
+    format!("{}-{}", first, second)
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "users_recovery_codes"]
+struct RecoveryCode {
+    user_id: i32,
+    code_hash: String,
+}
+
+/// The key to add to an authenticator app, either by scanning the QR code or by entering the
+/// secret manually
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct TotpEnrolment {
+    /// The shared secret in base32
+    pub secret: String,
+    /// The `otpauth://` provisioning URI
+    pub uri: String,
+    /// The provisioning URI as QR code in SVG format
+    pub qr_code: String,
+}
+
+/// A code of the authenticator app, or a recovery code
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct TotpCode {
+    pub code: String,
+}
+
+/// Single use codes to log in without the authenticator app. They are only shown once.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    pub codes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct TotpStatus {
+    pub enabled: bool,
+    pub recovery_codes_left: i64,
+}
+
+/// The TOTP secret of a user. The secret is encrypted and never leaves the server again after the
+/// enrolment.
+#[derive(Clone, Debug, Insertable, Queryable, PolarClass)]
+#[table_name = "users_totp"]
+pub struct Totp {
+    #[polar(attribute)]
+    pub user_id: i32,
+    secret: String,
+    enabled: bool,
+    last_used_step: Option<i64>,
+    failed_attempts: i32,
+    locked_until: Option<NaiveDateTime>,
+    created: NaiveDateTime,
+}
+
+/// Outcome of a code check, which is evaluated within a transaction
+enum Check {
+    Valid,
+    Invalid,
+    NotEnrolled,
+    AlreadyEnabled,
+    Locked,
+}
+
+impl Check {
+    fn into_result(self) -> ApiResult<()> {
+        match self {
+            Check::Valid => Ok(()),
+            Check::Invalid => Err(error("", Status::Unauthorized, "Invalid code")),
+            Check::NotEnrolled => Err(error(
+                "",
+                Status::NotFound,
+                "Two-factor authentication isn't enrolled",
+            )),
+            Check::AlreadyEnabled => Err(error(
+                "",
+                Status::Conflict,
+                "Two-factor authentication is already enabled",
+            )),
+            Check::Locked => Err(error(
+                "",
+                Status::TooManyRequests,
+                "Too many invalid codes, please try again later",
+            )),
+        }
+    }
+}
+
+impl Totp {
+    /// Create a dummy TOTP secret for a user with the given id. Used within oso policies
+    pub fn dummy_for_user(user_id: i32) -> Self {
+        Totp {
+            user_id,
+            secret: String::new(),
+            enabled: false,
+            last_used_step: None,
+            failed_attempts: 0,
+            locked_until: None,
+            created: NaiveDateTime::from_timestamp(0, 0),
+        }
+    }
+
+    fn decrypted_secret(&self) -> Result<Vec<u8>, String> {
+        base64::decode(crypto::decrypt(&self.secret)?).map_err(|e| e.to_string())
+    }
+
+    /// Check a code against the secret. Every code is accepted only once.
+    fn check_code(&self, conn: &MysqlConnection, code: &str) -> Result<Check, DieselError> {
+        let secret = match self.decrypted_secret() {
+            Ok(secret) => secret,
+            Err(e) => {
+                eprintln! { "Failed to decrypt TOTP secret: {}", e };
+                return Ok(Check::Invalid);
+            }
+        };
+        let step = match matching_step(&secret, code, Utc::now().timestamp()) {
+            Some(step) if self.last_used_step.map_or(true, |last| step > last) => step,
+            _ => return Ok(Check::Invalid),
+        };
+        diesel::update(users_totp::table.find(self.user_id))
+            .set((
+                users_totp::enabled.eq(true),
+                users_totp::last_used_step.eq(step),
+            ))
+            .execute(conn)?;
+        Ok(Check::Valid)
+    }
+
+    fn find_for_update(conn: &MysqlConnection, user_id: i32) -> QueryResult<Option<Self>> {
+        users_totp::table
+            .find(user_id)
+            .for_update()
+            .first::<Totp>(conn)
+            .optional()
+    }
+
+    /// Delete a recovery code of the user. Returns whether the code existed.
+    fn use_recovery_code(conn: &MysqlConnection, user_id: i32, code: &str) -> QueryResult<bool> {
+        diesel::delete(
+            users_recovery_codes::table
+                .filter(users_recovery_codes::user_id.eq(user_id))
+                .filter(users_recovery_codes::code_hash.eq(hash_recovery_code(code))),
+        )
+        .execute(conn)
+        .map(|deleted| deleted > 0)
+    }
+
+    /// Whether the user has to enter a code when logging in. Only a user without a secret has it
+    /// disabled, any other error is returned, so a login can't skip the second factor.
+    pub async fn is_enabled(db: &Db, user_id: i32) -> ApiResult<bool> {
+        db.run(move |conn| {
+            users_totp::table
+                .find(user_id)
+                .select(users_totp::enabled)
+                .first::<bool>(conn)
+                .optional()
+        })
+        .await
+        .map(|enabled| enabled.unwrap_or(false))
+        .map_err(|e| error(e, Status::InternalServerError, ""))
+    }
+
+    pub async fn status(db: &Db, user_id: i32) -> ApiResult<TotpStatus> {
+        let enabled = Totp::is_enabled(db, user_id).await?;
+        let recovery_codes_left = db
+            .run(move |conn| {
+                users_recovery_codes::table
+                    .filter(users_recovery_codes::user_id.eq(user_id))
+                    .count()
+                    .get_result(conn)
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        Ok(TotpStatus {
+            enabled,
+            recovery_codes_left,
+        })
+    }
+
+    /// Create a new secret for a user. Two-factor authentication is enabled, once the user
+    /// confirms the enrolment with a code of the authenticator app.
+    pub async fn enrol(db: &Db, user_id: i32, account: String) -> ApiResult<TotpEnrolment> {
+        let secret: [u8; SECRET_LENGTH] = rand::thread_rng().gen();
+        let uri = provisioning_uri(&secret, &account);
+        let enrolment = TotpEnrolment {
+            secret: base32(&secret),
+            qr_code: qr_code_svg(&uri).map_err(|e| error(e, Status::InternalServerError, ""))?,
+            uri,
+        };
+        let totp = Totp {
+            user_id,
+            secret: crypto::encrypt(&base64::encode(secret))
+                .map_err(|e| error(e, Status::InternalServerError, ""))?,
+            enabled: false,
+            last_used_step: None,
+            failed_attempts: 0,
+            locked_until: None,
+            created: Utc::now().naive_utc(),
+        };
+
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                if let Some(existing) = Totp::find_for_update(conn, user_id)? {
+                    if existing.enabled {
+                        return Ok(Check::AlreadyEnabled);
+                    }
+                }
+                diesel::replace_into(users_totp::table)
+                    .values(&totp)
+                    .execute(conn)?;
+                Ok(Check::Valid)
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_result()?;
+        Ok(enrolment)
+    }
+
+    /// Enable two-factor authentication with the first code of the authenticator app. Returns new
+    /// recovery codes.
+    pub async fn confirm(db: &Db, user_id: i32, code: TotpCode) -> ApiResult<RecoveryCodes> {
+        let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+            .map(|_| new_recovery_code())
+            .collect();
+        let recovery_codes: Vec<RecoveryCode> = codes
+            .iter()
+            .map(|code| RecoveryCode {
+                user_id,
+                code_hash: hash_recovery_code(code),
+            })
+            .collect();
+
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                let totp = match Totp::find_for_update(conn, user_id)? {
+                    Some(totp) if totp.enabled => return Ok(Check::AlreadyEnabled),
+                    Some(totp) => totp,
+                    None => return Ok(Check::NotEnrolled),
+                };
+                let check = totp.check_code(conn, &code.code)?;
+                if let Check::Valid = check {
+                    diesel::delete(
+                        users_recovery_codes::table
+                            .filter(users_recovery_codes::user_id.eq(user_id)),
+                    )
+                    .execute(conn)?;
+                    diesel::insert_into(users_recovery_codes::table)
+                        .values(&recovery_codes)
+                        .execute(conn)?;
+                }
+                Ok(check)
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_result()?;
+        Ok(RecoveryCodes { codes })
+    }
+
+    /// Check the second factor of a login, either a code of the authenticator app or a recovery
+    /// code. A recovery code is used up. After `MAX_FAILED_ATTEMPTS` wrong codes in a row, the
+    /// second factor of the user is locked for `LOCKOUT_MINUTES`, no matter how many logins the
+    /// codes were entered in.
+    pub async fn verify(db: &Db, user_id: i32, code: TotpCode) -> ApiResult<()> {
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                let totp = match Totp::find_for_update(conn, user_id)? {
+                    Some(totp) if totp.enabled => totp,
+                    _ => return Ok(Check::NotEnrolled),
+                };
+
+                let now = Utc::now().naive_utc();
+                if totp.locked_until.map_or(false, |until| until > now) {
+                    return Ok(Check::Locked);
+                }
+
+                let row = users_totp::table.find(user_id);
+                let valid = match totp.check_code(conn, &code.code)? {
+                    Check::Valid => true,
+                    _ => Totp::use_recovery_code(conn, user_id, &code.code)?,
+                };
+                if valid {
+                    diesel::update(row)
+                        .set((
+                            users_totp::failed_attempts.eq(0),
+                            users_totp::locked_until.eq(None::<NaiveDateTime>),
+                        ))
+                        .execute(conn)?;
+                    return Ok(Check::Valid);
+                }
+
+                let failed_attempts = totp.failed_attempts + 1;
+                if failed_attempts >= MAX_FAILED_ATTEMPTS {
+                    diesel::update(row)
+                        .set((
+                            users_totp::failed_attempts.eq(0),
+                            users_totp::locked_until.eq(now + Duration::minutes(LOCKOUT_MINUTES)),
+                        ))
+                        .execute(conn)?;
+                } else {
+                    diesel::update(row)
+                        .set(users_totp::failed_attempts.eq(failed_attempts))
+                        .execute(conn)?;
+                }
+                Ok(Check::Invalid)
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_result()
+    }
+
+    /// Remove the secret and the recovery codes of a user
+    pub async fn disable(db: &Db, user_id: i32) -> ApiResult<()> {
+        db.run(move |conn| {
+            conn.transaction::<_, DieselError, _>(|| {
+                diesel::delete(
+                    users_recovery_codes::table.filter(users_recovery_codes::user_id.eq(user_id)),
+                )
+                .execute(conn)?;
+                diesel::delete(users_totp::table.find(user_id)).execute(conn)
+            })
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))
+        .and_then(|deleted| {
+            if deleted == 0 {
+                Check::NotEnrolled.into_result()
+            } else {
+                Ok(())
+            }
+        })
+    }
+}
+
+pub(super) fn register_polar_classes(oso: &mut oso::Oso) -> oso::Result<()> {
+    oso.register_class(Totp::get_polar_class())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Secret of the test vectors in RFC 4226 and RFC 6238
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_base32() {
+        assert_eq!("", base32(b""));
+        assert_eq!("MY", base32(b"f"));
+        assert_eq!("MZXW6", base32(b"foob"));
+        assert_eq!("MZXW6YQ", base32(b"fooba"));
+        assert_eq!("MZXW6YTBOI", base32(b"foobar"));
+    }
+
+    #[test]
+    fn test_hotp_rfc_4226() {
+        assert_eq!("755224", hotp(RFC_SECRET, 0));
+        assert_eq!("287082", hotp(RFC_SECRET, 1));
+        assert_eq!("520489", hotp(RFC_SECRET, 9));
+    }
+
+    #[test]
+    fn test_totp_rfc_6238() {
+        // the RFC lists 8 digit codes, these are the last 6 digits
+        assert_eq!(Some(1), matching_step(RFC_SECRET, "287082", 59));
+        assert_eq!(
+            Some(37037036),
+            matching_step(RFC_SECRET, "081804", 1111111109)
+        );
+        assert_eq!(
+            Some(41152263),
+            matching_step(RFC_SECRET, "005924", 1234567890)
+        );
+    }
+
+    #[test]
+    fn test_totp_allows_clock_drift() {
+        let code = hotp(RFC_SECRET, 100);
+        assert_eq!(Some(100), matching_step(RFC_SECRET, &code, 99 * 30));
+        assert_eq!(Some(100), matching_step(RFC_SECRET, &code, 101 * 30 + 29));
+        assert_eq!(None, matching_step(RFC_SECRET, &code, 102 * 30));
+        assert_eq!(None, matching_step(RFC_SECRET, "12345", 100 * 30));
+    }
+
+    #[test]
+    fn test_provisioning_uri() {
+        let uri = provisioning_uri(b"foobar", "jane@example.com");
+        assert!(uri.starts_with("otpauth://totp/SkyRocket"));
+        assert!(uri.contains("secret=MZXW6YTBOI&issuer=SkyRocket"));
+        assert!(qr_code_svg(&uri).unwrap().starts_with("<svg"));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let code = new_recovery_code();
+        assert_eq!(RECOVERY_CODE_LENGTH + 1, code.len());
+        assert_eq!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&code.replace('-', "").to_uppercase())
+        );
+        assert_ne!(
+            hash_recovery_code(&code),
+            hash_recovery_code(&new_recovery_code())
+        );
+    }
+}
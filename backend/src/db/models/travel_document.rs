@@ -0,0 +1,237 @@
+use crate::crypto;
+use crate::db::models::{Booking, BookingStatus, Flight, Passenger};
+use crate::db::schema::travel_documents;
+use crate::db::Db;
+use crate::routes::{error, ApiResult};
+use chrono::{NaiveDate, Utc};
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel_derive_enum::DbEnum;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rocket::http::Status;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use validator::Validate;
+
+/// Characters of a document number, that stay visible when it is redacted
+const VISIBLE_CHARS: usize = 3;
+
+/// Regex to validate an ISO 3166-1 alpha-3 country code, as printed on travel documents
+static RE_COUNTRY: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z]{3}$").unwrap());
+/// Regex to validate a document number
+static RE_DOCUMENT_NUMBER: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[A-Z0-9]{5,20}$").unwrap());
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, DbEnum, JsonSchema, PartialEq)]
+#[serde(crate = "rocket::serde")]
+pub enum DocumentType {
+    Passport,
+    IdentityCard,
+    Visa,
+}
+
+/// Mask every character of a document number, except for the last ones
+fn redact(number: &str) -> String {
+    let visible = number.len().min(VISIBLE_CHARS);
+    format!(
+        "{}{}",
+        "*".repeat(number.len() - visible),
+        &number[number.len() - visible..]
+    )
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, Validate)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct NewTravelDocument {
+    pub document_type: DocumentType,
+    #[validate(regex = "RE_DOCUMENT_NUMBER")]
+    pub number: String,
+    /// ISO 3166-1 alpha-3 code of the issuing country, like `DEU`
+    #[validate(regex = "RE_COUNTRY")]
+    pub issuing_country: String,
+    /// ISO 3166-1 alpha-3 code of the nationality of the holder
+    #[validate(regex = "RE_COUNTRY")]
+    pub nationality: String,
+    pub expiry: NaiveDate,
+}
+
+impl NewTravelDocument {
+    pub fn is_valid(&self) -> ApiResult<()> {
+        self.validate()
+            .map_err(|e| error(e.clone(), Status::BadRequest, &e.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "travel_documents"]
+struct InsertableTravelDocument {
+    passenger_id: i32,
+    document_type: DocumentType,
+    number: String,
+    issuing_country: String,
+    nationality: String,
+    expiry: NaiveDate,
+}
+
+/// A travel document as stored, with an encrypted number
+#[derive(Clone, Debug, Queryable)]
+struct TravelDocumentRecord {
+    id: i32,
+    passenger_id: i32,
+    document_type: DocumentType,
+    number: String,
+    issuing_country: String,
+    nationality: String,
+    expiry: NaiveDate,
+}
+
+/// Travel document of a passenger, required for international flights
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct TravelDocument {
+    pub id: i32,
+    pub passenger_id: i32,
+    pub document_type: DocumentType,
+    /// Document number, redacted for everyone but administrators
+    pub number: String,
+    pub issuing_country: String,
+    pub nationality: String,
+    pub expiry: NaiveDate,
+}
+
+impl TravelDocument {
+    fn decrypt(record: TravelDocumentRecord) -> ApiResult<Self> {
+        let number = crypto::decrypt(&record.number)
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        Ok(TravelDocument {
+            id: record.id,
+            passenger_id: record.passenger_id,
+            document_type: record.document_type,
+            number,
+            issuing_country: record.issuing_country,
+            nationality: record.nationality,
+            expiry: record.expiry,
+        })
+    }
+
+    /// Mask the document number, so it can be shown to customers
+    pub fn redacted(self) -> Self {
+        TravelDocument {
+            number: redact(&self.number),
+            ..self
+        }
+    }
+
+    pub async fn all_from_passenger(db: &Db, passenger_id: i32) -> ApiResult<Vec<Self>> {
+        db.run(move |conn| {
+            travel_documents::table
+                .filter(travel_documents::passenger_id.eq(passenger_id))
+                .order(travel_documents::id)
+                .load::<TravelDocumentRecord>(conn)
+        })
+        .await
+        .map_err(|e| error(e, Status::InternalServerError, ""))?
+        .into_iter()
+        .map(TravelDocument::decrypt)
+        .collect()
+    }
+
+    /// Store a travel document of a passenger of a booking. The document must be valid until the
+    /// last flight of the booking has arrived.
+    pub async fn create(
+        db: &Db,
+        booking: &Booking,
+        passenger_id: i32,
+        new_document: NewTravelDocument,
+    ) -> ApiResult<Self> {
+        new_document.is_valid()?;
+
+        if booking.status == BookingStatus::Cancelled {
+            return Err(error("", Status::Conflict, "Booking has been cancelled"));
+        }
+        Passenger::all_from_booking(db, booking.user_id, booking.offer_id)
+            .await
+            .iter()
+            .find(|p| p.id == passenger_id)
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find passenger"))?;
+
+        let travel_date = Flight::all_from_offer(db, booking.offer_id)
+            .await
+            .iter()
+            .map(|f| f.arrival_time.date())
+            .max()
+            .unwrap_or_else(|| Utc::now().naive_utc().date());
+        if new_document.expiry < travel_date {
+            return Err(error(
+                "",
+                Status::BadRequest,
+                "Travel document expires before the date of travel",
+            ));
+        }
+
+        let number = crypto::encrypt(&new_document.number)
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        let document = InsertableTravelDocument {
+            passenger_id,
+            document_type: new_document.document_type,
+            number,
+            issuing_country: new_document.issuing_country,
+            nationality: new_document.nationality,
+            expiry: new_document.expiry,
+        };
+
+        let record = db
+            .run(move |conn| {
+                conn.transaction::<_, DieselError, _>(|| {
+                    diesel::insert_into(travel_documents::table)
+                        .values(&document)
+                        .execute(conn)?;
+                    travel_documents::table
+                        .filter(travel_documents::passenger_id.eq(passenger_id))
+                        .order(travel_documents::id.desc())
+                        .first::<TravelDocumentRecord>(conn)
+                })
+            })
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+        TravelDocument::decrypt(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!("******T47", redact("C01X00T47"));
+        assert_eq!("AB", redact("AB"));
+    }
+
+    #[test]
+    fn test_new_travel_document() {
+        let document = NewTravelDocument {
+            document_type: DocumentType::Passport,
+            number: "C01X00T47".into(),
+            issuing_country: "DEU".into(),
+            nationality: "DEU".into(),
+            expiry: NaiveDate::from_ymd(2030, 1, 1),
+        };
+        assert!(document.validate().is_ok());
+        assert!(NewTravelDocument {
+            number: "c01x00t47".into(),
+            ..document.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(NewTravelDocument {
+            issuing_country: "DE".into(),
+            ..document
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use super::api_token::{user_from_bearer_token, ApiScope};
 use super::DbResult;
 use crate::db::models::role::Role;
 use crate::db::models::UserRole;
@@ -6,6 +7,7 @@ use crate::routes::{error, ApiResult};
 use crate::session;
 use chrono::{NaiveDate, Utc};
 use diesel::prelude::*;
+use diesel::result::Error as DieselError;
 use diesel_derive_enum::DbEnum;
 use oso::{Oso, PolarClass};
 use rocket::http::Status;
@@ -82,6 +84,8 @@ pub struct User {
     pub email: String,
     pub birthday: NaiveDate,
     pub gender: Gender,
+    /// Whether the user followed the link sent to the email address
+    pub verified: bool,
 }
 
 impl User {
@@ -94,6 +98,7 @@ impl User {
             email: String::new(),
             birthday: NaiveDate::from_yo(1970, 1),
             gender: Gender::Male,
+            verified: false,
         }
     }
 
@@ -117,11 +122,24 @@ impl User {
             .ok()
     }
 
+    /// Update a user. A changed email address has to be verified again.
     pub async fn update_and_return(db: &Db, id: i32, new_user: NewUser) -> Option<Json<User>> {
         db.run(move |conn| {
-            diesel::update(users::table.filter(users::id.eq(id)))
-                .set(new_user)
-                .execute(conn)
+            conn.transaction::<_, DieselError, _>(|| {
+                let email: String = users::table
+                    .find(id)
+                    .select(users::email)
+                    .for_update()
+                    .first(conn)?;
+                if email != new_user.email {
+                    diesel::update(users::table.find(id))
+                        .set(users::verified.eq(false))
+                        .execute(conn)?;
+                }
+                diesel::update(users::table.filter(users::id.eq(id)))
+                    .set(new_user)
+                    .execute(conn)
+            })
         })
         .await
         .ok()?;
@@ -147,6 +165,12 @@ impl User {
             .ok()
     }
 
+    pub async fn is_verified(db: &Db, id: i32) -> bool {
+        db.run(move |conn| users::table.find(id).select(users::verified).first(conn))
+            .await
+            .unwrap_or(false)
+    }
+
     pub async fn find_by_email(db: &Db, email: String) -> Option<Json<Self>> {
         db.run(move |conn| users::table.filter(users::email.eq(email)).first(conn))
             .await
@@ -167,6 +191,9 @@ pub struct AuthUser {
     pub gender: Gender,
     #[polar(attribute)]
     pub roles: Vec<Role>,
+    /// Scopes of the API token, if the user was authenticated by one instead of a session
+    #[serde(skip)]
+    pub token_scopes: Option<Vec<ApiScope>>,
 }
 
 impl AuthUser {
@@ -203,6 +230,7 @@ impl AuthUser {
             birthday: user.birthday,
             gender: user.gender.clone(),
             roles,
+            token_scopes: None,
         }
     }
 }
@@ -212,6 +240,12 @@ impl<'a> FromRequest<'a> for AuthUser {
     type Error = String;
 
     async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        if let Some(outcome) = user_from_bearer_token(request).await {
+            return match outcome {
+                Ok(user) => Outcome::Success(user),
+                Err(e) => Outcome::Failure(e),
+            };
+        }
         if let Some(user) = session::get_user_from_session(request.cookies()).await {
             Outcome::Success(user)
         } else {
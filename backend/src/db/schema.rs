@@ -11,14 +11,108 @@ table! {
 }
 
 table! {
+    use diesel::sql_types::{Float, Integer, Nullable, Varchar};
+    use crate::db::models::AncillaryKindMapping;
+    ancillaries (id) {
+        id -> Integer,
+        offer_id -> Integer,
+        kind -> AncillaryKindMapping,
+        description -> Varchar,
+        price -> Float,
+        inventory -> Nullable<Integer>,
+    }
+}
+
+table! {
+    ancillaries_reservations (id) {
+        id -> Integer,
+        reference -> Char,
+        user_id -> Integer,
+        offer_id -> Integer,
+        ancillary_id -> Integer,
+        quantity -> Integer,
+        expires -> Datetime,
+    }
+}
+
+table! {
+    api_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        name -> Varchar,
+        token_hash -> Char,
+        scopes -> Varchar,
+        created -> Datetime,
+        expires -> Datetime,
+        last_used -> Nullable<Datetime>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Bool, Char, Datetime, Integer, Nullable};
+    use crate::db::models::BookingStatusMapping;
     bookings (user_id, offer_id) {
         user_id -> Integer,
         offer_id -> Integer,
         seats -> Integer,
+        status -> BookingStatusMapping,
+        reference -> Char,
+        disrupted -> Bool,
+        children -> Integer,
+        infants -> Integer,
+        paying_since -> Nullable<Datetime>,
+    }
+}
+
+table! {
+    bookings_ancillaries (user_id, offer_id, ancillary_id) {
+        user_id -> Integer,
+        offer_id -> Integer,
+        ancillary_id -> Integer,
+        quantity -> Integer,
     }
 }
 
 table! {
+    calendar_subscriptions (user_id) {
+        user_id -> Integer,
+        token_hash -> Char,
+        created -> Datetime,
+    }
+}
+
+table! {
+    carriers (id) {
+        id -> Integer,
+        iata -> Char,
+        icao -> Char,
+        name -> Varchar,
+    }
+}
+
+table! {
+    check_ins (passenger_id, flight_id) {
+        passenger_id -> Integer,
+        flight_id -> Integer,
+        seat -> Nullable<Varchar>,
+        sequence -> Integer,
+        checked_in -> Datetime,
+    }
+}
+
+table! {
+    companions (id) {
+        id -> Integer,
+        user_id -> Integer,
+        firstname -> Varchar,
+        lastname -> Varchar,
+        birthday -> Date,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Datetime, Integer, Nullable, Varchar};
+    use crate::db::models::FlightStatusMapping;
     flights (id) {
         id -> Integer,
         offer_id -> Integer,
@@ -26,22 +120,42 @@ table! {
         departure_time -> Datetime,
         arrival_icao -> Varchar,
         arrival_time -> Datetime,
+        status -> FlightStatusMapping,
+        estimated_departure_time -> Nullable<Datetime>,
+        estimated_arrival_time -> Nullable<Datetime>,
+        diverted_icao -> Nullable<Varchar>,
+        carrier_id -> Nullable<Integer>,
+        flight_number -> Nullable<Varchar>,
     }
 }
 
 table! {
-    use diesel::sql_types::{Float, Integer};
+    flights_codeshares (flight_id, flight_number) {
+        flight_id -> Integer,
+        carrier_id -> Integer,
+        flight_number -> Varchar,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Bool, Float, Integer, Nullable};
     use crate::db::models::CurrencyMapping;
     flights_offers (id) {
         id -> Integer,
         seats -> Integer,
         price -> Float,
         currency -> CurrencyMapping,
+        refundable -> Bool,
+        cancelled -> Bool,
+        overbooking_percentage -> Nullable<Float>,
+        overbooking_limit -> Nullable<Integer>,
+        child_price -> Nullable<Float>,
+        infant_price -> Nullable<Float>,
     }
 }
 
 table! {
-    use diesel::sql_types::{BigInt, Float, Integer, Varchar};
+    use diesel::sql_types::{BigInt, Bool, Float, Integer, Nullable, Varchar};
     use crate::db::models::CurrencyMapping;
     flights_offers_with_occupancy (id) {
         id -> Integer,
@@ -51,6 +165,115 @@ table! {
         currency -> CurrencyMapping,
         departure_icao -> Varchar,
         arrival_icao -> Varchar,
+        refundable -> Bool,
+        cancelled -> Bool,
+        overbooking_percentage -> Nullable<Float>,
+        overbooking_limit -> Nullable<Integer>,
+        child_price -> Nullable<Float>,
+        infant_price -> Nullable<Float>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Char, Datetime, Float, Integer, Nullable, Varchar};
+    use crate::db::models::CurrencyMapping;
+    invoices (id) {
+        id -> Integer,
+        user_id -> Integer,
+        offer_id -> Integer,
+        booking_reference -> Char,
+        supplement -> Integer,
+        issuer -> Varchar,
+        buyer_name -> Varchar,
+        buyer_email -> Varchar,
+        buyer_address -> Nullable<Varchar>,
+        net -> Float,
+        vat_rate -> Float,
+        vat -> Float,
+        total -> Float,
+        currency -> CurrencyMapping,
+        issued -> Datetime,
+    }
+}
+
+table! {
+    invoices_items (invoice_id, position) {
+        invoice_id -> Integer,
+        position -> Integer,
+        description -> Varchar,
+        quantity -> Integer,
+        unit_price -> Float,
+        amount -> Float,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Datetime, Integer, Nullable, Varchar};
+    use crate::db::models::LoyaltyTransactionKindMapping;
+    loyalty_transactions (id) {
+        id -> Integer,
+        user_id -> Integer,
+        offer_id -> Integer,
+        flight_id -> Nullable<Integer>,
+        kind -> LoyaltyTransactionKindMapping,
+        points -> Integer,
+        description -> Varchar,
+        created -> Datetime,
+    }
+}
+
+table! {
+    passengers (id) {
+        id -> Integer,
+        user_id -> Integer,
+        offer_id -> Integer,
+        firstname -> Varchar,
+        lastname -> Varchar,
+        birthday -> Nullable<Date>,
+    }
+}
+
+table! {
+    passkeys (id) {
+        id -> Integer,
+        user_id -> Integer,
+        credential_id -> Varchar,
+        public_key -> Varchar,
+        sign_count -> Bigint,
+        name -> Varchar,
+        created -> Datetime,
+        last_used -> Nullable<Datetime>,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Datetime, Float, Integer, Varchar};
+    use crate::db::models::{CurrencyMapping, PaymentStatusMapping};
+    payments (id) {
+        id -> Integer,
+        user_id -> Integer,
+        offer_id -> Integer,
+        provider -> Varchar,
+        reference -> Varchar,
+        amount -> Float,
+        currency -> CurrencyMapping,
+        status -> PaymentStatusMapping,
+        created -> Datetime,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Datetime, Float, Integer};
+    use crate::db::models::{RefundReasonMapping, RefundStatusMapping};
+    refunds (id) {
+        id -> Integer,
+        user_id -> Integer,
+        offer_id -> Integer,
+        amount -> Float,
+        reason -> RefundReasonMapping,
+        status -> RefundStatusMapping,
+        created -> Datetime,
+        updated -> Datetime,
     }
 }
 
@@ -65,7 +288,21 @@ table! {
 }
 
 table! {
-    use diesel::sql_types::{Date, Integer, Varchar};
+    use diesel::sql_types::{Char, Date, Integer, Varchar};
+    use crate::db::models::DocumentTypeMapping;
+    travel_documents (id) {
+        id -> Integer,
+        passenger_id -> Integer,
+        document_type -> DocumentTypeMapping,
+        number -> Varchar,
+        issuing_country -> Char,
+        nationality -> Char,
+        expiry -> Date,
+    }
+}
+
+table! {
+    use diesel::sql_types::{Bool, Date, Integer, Varchar};
     use crate::db::models::GenderMapping;
     users (id) {
         id -> Integer,
@@ -74,13 +311,48 @@ table! {
         email -> Varchar,
         birthday -> Date,
         gender -> GenderMapping,
+        verified -> Bool,
     }
 }
 
 table! {
-    users_oauth_github (github_id) {
+    users_credentials (user_id) {
         user_id -> Integer,
-        github_id -> Integer,
+        password_hash -> Varchar,
+        failed_attempts -> Integer,
+        locked_until -> Nullable<Datetime>,
+    }
+}
+
+table! {
+    users_email_verifications (user_id) {
+        user_id -> Integer,
+        email -> Varchar,
+        nonce -> Char,
+        created -> Datetime,
+    }
+}
+
+table! {
+    users_login_links (user_id) {
+        user_id -> Integer,
+        nonce -> Char,
+        created -> Datetime,
+    }
+}
+
+table! {
+    users_oauth_identities (provider, subject) {
+        provider -> Varchar,
+        subject -> Varchar,
+        user_id -> Integer,
+    }
+}
+
+table! {
+    users_recovery_codes (user_id, code_hash) {
+        user_id -> Integer,
+        code_hash -> Char,
     }
 }
 
@@ -93,21 +365,81 @@ table! {
     }
 }
 
+table! {
+    users_totp (user_id) {
+        user_id -> Integer,
+        secret -> Varchar,
+        enabled -> Bool,
+        last_used_step -> Nullable<Bigint>,
+        failed_attempts -> Integer,
+        locked_until -> Nullable<Datetime>,
+        created -> Datetime,
+    }
+}
+
 joinable!(addresses -> users (user_id));
+joinable!(ancillaries -> flights_offers (offer_id));
+joinable!(ancillaries_reservations -> ancillaries (ancillary_id));
+joinable!(api_tokens -> users (user_id));
 joinable!(bookings -> flights_offers (offer_id));
 joinable!(bookings -> users (user_id));
+joinable!(bookings_ancillaries -> ancillaries (ancillary_id));
+joinable!(calendar_subscriptions -> users (user_id));
+joinable!(check_ins -> flights (flight_id));
+joinable!(check_ins -> passengers (passenger_id));
+joinable!(companions -> users (user_id));
+joinable!(flights -> carriers (carrier_id));
 joinable!(flights -> flights_offers (offer_id));
+joinable!(flights_codeshares -> carriers (carrier_id));
+joinable!(flights_codeshares -> flights (flight_id));
+joinable!(invoices -> users (user_id));
+joinable!(invoices_items -> invoices (invoice_id));
+joinable!(loyalty_transactions -> flights (flight_id));
+joinable!(loyalty_transactions -> users (user_id));
+joinable!(passengers -> users (user_id));
+joinable!(passkeys -> users (user_id));
+joinable!(payments -> users (user_id));
+joinable!(refunds -> users (user_id));
 joinable!(sessions -> users (user_id));
-joinable!(users_oauth_github -> users (user_id));
+joinable!(travel_documents -> passengers (passenger_id));
+joinable!(users_credentials -> users (user_id));
+joinable!(users_email_verifications -> users (user_id));
+joinable!(users_login_links -> users (user_id));
+joinable!(users_oauth_identities -> users (user_id));
+joinable!(users_recovery_codes -> users (user_id));
 joinable!(users_roles -> users (user_id));
+joinable!(users_totp -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
     addresses,
+    ancillaries,
+    ancillaries_reservations,
+    api_tokens,
     bookings,
+    bookings_ancillaries,
+    calendar_subscriptions,
+    carriers,
+    check_ins,
+    companions,
     flights,
+    flights_codeshares,
     flights_offers,
+    flights_offers_with_occupancy,
+    invoices,
+    invoices_items,
+    loyalty_transactions,
+    passengers,
+    passkeys,
+    payments,
+    refunds,
     sessions,
+    travel_documents,
     users,
-    users_oauth_github,
+    users_credentials,
+    users_email_verifications,
+    users_login_links,
+    users_oauth_identities,
+    users_recovery_codes,
     users_roles,
+    users_totp,
 );
@@ -0,0 +1,180 @@
+//! A fake GitHub OAuth server for tests, so the login and registration can be tested without
+//! internet access. The server implements the token exchange and the user endpoint of GitHub.
+//! Tests issue codes for fake users directly, instead of logging in on an authorize page.
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::serde::json::{json, Json, Value};
+use rocket::serde::{Deserialize, Serialize};
+use rocket::{Request, State};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, TcpListener};
+use std::sync::{Arc, Mutex};
+
+/// A GitHub account known to the fake server
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct FakeUser {
+    pub id: i64,
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Default)]
+struct Accounts {
+    /// Users and PKCE challenges by issued code
+    codes: HashMap<String, (FakeUser, Option<String>)>,
+    /// Users by issued access token
+    tokens: HashMap<String, FakeUser>,
+}
+
+/// Credentials the fake server accepts
+struct Client {
+    id: String,
+    secret: String,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct AccessTokenRequest {
+    client_id: String,
+    client_secret: String,
+    code: String,
+    code_verifier: Option<String>,
+}
+
+/// The access token sent as `Authorization: token <token>`
+struct AccessToken(String);
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for AccessToken {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        match request
+            .headers()
+            .get_one("authorization")
+            .and_then(|h| h.strip_prefix("token "))
+        {
+            Some(token) => Outcome::Success(AccessToken(token.into())),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+fn random_string() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+#[post("/login/oauth/access_token", data = "<request>")]
+fn access_token(
+    accounts: &State<Arc<Mutex<Accounts>>>,
+    client: &State<Client>,
+    request: Json<AccessTokenRequest>,
+) -> Result<Json<Value>, Status> {
+    if request.client_id != client.id || request.client_secret != client.secret {
+        return Err(Status::Unauthorized);
+    }
+
+    let mut accounts = accounts.lock().unwrap();
+    let (user, challenge) = accounts
+        .codes
+        .remove(&request.code)
+        .ok_or(Status::Unauthorized)?;
+    if let Some(challenge) = challenge {
+        let verifier = request.code_verifier.as_deref().unwrap_or_default();
+        let expected =
+            base64::encode_config(Sha256::digest(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+        if expected != challenge {
+            return Err(Status::Unauthorized);
+        }
+    }
+
+    let token = random_string();
+    accounts.tokens.insert(token.clone(), user);
+    Ok(Json(json!({
+        "access_token": token,
+        "scope": "user:email",
+        "token_type": "bearer",
+    })))
+}
+
+#[get("/user")]
+fn user(
+    accounts: &State<Arc<Mutex<Accounts>>>,
+    token: AccessToken,
+) -> Result<Json<FakeUser>, Status> {
+    accounts
+        .lock()
+        .unwrap()
+        .tokens
+        .get(&token.0)
+        .cloned()
+        .map(Json)
+        .ok_or(Status::Unauthorized)
+}
+
+/// A running fake OAuth server, which serves the login and the API of GitHub on the same URL
+pub struct FakeOAuthServer {
+    pub url: String,
+    accounts: Arc<Mutex<Accounts>>,
+}
+
+impl FakeOAuthServer {
+    /// Start the server on a free local port, accepting the given client credentials
+    pub async fn spawn(client_id: &str, client_secret: &str) -> Self {
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|listener| listener.local_addr())
+            .map(|addr| addr.port())
+            .expect("free port");
+        let accounts = Arc::new(Mutex::new(Accounts::default()));
+
+        let config = rocket::Config {
+            address: Ipv4Addr::LOCALHOST.into(),
+            port,
+            log_level: rocket::config::LogLevel::Off,
+            ..rocket::Config::debug_default()
+        };
+        let server = rocket::custom(config)
+            .manage(accounts.clone())
+            .manage(Client {
+                id: client_id.into(),
+                secret: client_secret.into(),
+            })
+            .mount("/", routes![access_token, user]);
+        rocket::tokio::spawn(server.launch());
+
+        // wait until the server accepts connections
+        let url = format!("http://127.0.0.1:{}", port);
+        for _ in 0..50 {
+            if rocket::tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                break;
+            }
+            rocket::tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        FakeOAuthServer { url, accounts }
+    }
+
+    /// Issue a code for a user, as GitHub does after the user logged in. The code can only be
+    /// exchanged with the verifier of the given PKCE challenge.
+    pub fn issue_code(&self, user: FakeUser, challenge: Option<String>) -> String {
+        let code = random_string();
+        self.accounts
+            .lock()
+            .unwrap()
+            .codes
+            .insert(code.clone(), (user, challenge));
+        code
+    }
+}
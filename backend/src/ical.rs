@@ -0,0 +1,147 @@
+//! Minimal iCalendar (RFC 5545) writer, sufficient to publish events with UTC times.
+
+use chrono::NaiveDateTime;
+
+/// Maximum length of a content line in octets, excluding the line break
+const MAX_LINE_LENGTH: usize = 75;
+
+/// A single calendar event, with start and end given in UTC
+pub struct Event {
+    pub uid: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    pub summary: String,
+    pub location: String,
+    pub description: String,
+}
+
+/// Escape a value of the TEXT type
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn format_time(time: &NaiveDateTime) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Fold a content line, so that no line exceeds 75 octets. Continuation lines start with a space.
+fn fold_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_LENGTH {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
+
+/// A calendar, containing any number of events
+pub struct Calendar {
+    name: String,
+    events: Vec<Event>,
+}
+
+impl Calendar {
+    pub fn new(name: &str) -> Self {
+        Calendar {
+            name: name.into(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn add_event(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// Serialize the calendar, with `stamp` being the time the calendar has been created
+    pub fn to_ics(&self, stamp: &NaiveDateTime) -> String {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".into(),
+            "PRODID:-//SkyRocket//Bookings//EN".into(),
+            "CALSCALE:GREGORIAN".into(),
+            "METHOD:PUBLISH".into(),
+            format!("X-WR-CALNAME:{}", escape_text(&self.name)),
+        ];
+
+        for event in &self.events {
+            lines.extend([
+                "BEGIN:VEVENT".to_string(),
+                format!("UID:{}", event.uid),
+                format!("DTSTAMP:{}", format_time(stamp)),
+                format!("DTSTART:{}", format_time(&event.start)),
+                format!("DTEND:{}", format_time(&event.end)),
+                format!("SUMMARY:{}", escape_text(&event.summary)),
+                format!("LOCATION:{}", escape_text(&event.location)),
+                format!("DESCRIPTION:{}", escape_text(&event.description)),
+                "END:VEVENT".into(),
+            ]);
+        }
+        lines.push("END:VCALENDAR".into());
+
+        lines.iter().map(|l| fold_line(l)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_text() {
+        assert_eq!("a\\, b\\; c\\\\d\\ne", escape_text("a, b; c\\d\r\ne"));
+    }
+
+    #[test]
+    fn test_fold_line() {
+        let line = "D".repeat(80);
+        let folded = fold_line(&line);
+        assert_eq!(
+            format!("{}\r\n {}\r\n", "D".repeat(75), "D".repeat(5)),
+            folded
+        );
+    }
+
+    #[test]
+    fn test_fold_line_multibyte() {
+        let folded = fold_line(&"ü".repeat(40));
+        assert!(folded.split("\r\n").all(|l| l.len() <= MAX_LINE_LENGTH));
+    }
+
+    #[test]
+    fn test_calendar() {
+        let mut calendar = Calendar::new("Flights");
+        calendar.add_event(Event {
+            uid: "ABC123-1@skyrocket".into(),
+            start: NaiveDateTime::from_timestamp(1644829200, 0),
+            end: NaiveDateTime::from_timestamp(1644850800, 0),
+            summary: "Flight KJFK - KLAX".into(),
+            location: "KJFK - KLAX".into(),
+            description: "Booking reference: ABC123".into(),
+        });
+
+        let ics = calendar.to_ics(&NaiveDateTime::from_timestamp(0, 0));
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(ics.contains("\r\nDTSTAMP:19700101T000000Z\r\n"));
+        assert!(ics.contains("\r\nDTSTART:20220214T090000Z\r\n"));
+        assert!(ics.contains("\r\nDTEND:20220214T150000Z\r\n"));
+        assert!(ics.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+    }
+}
@@ -0,0 +1,55 @@
+#[macro_use]
+extern crate rocket;
+#[macro_use]
+extern crate rocket_sync_db_pools;
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+
+pub mod bcbp;
+pub mod crypto;
+pub mod db;
+pub mod http;
+pub mod ical;
+pub mod mail;
+pub mod oidc;
+pub mod oso;
+pub mod payment;
+pub mod pdf;
+pub mod rate_limit;
+pub mod session;
+pub mod webauthn;
+
+mod config;
+#[cfg(any(test, feature = "testing"))]
+pub mod fake_oauth;
+mod routes;
+
+pub use config::{GitHubConfig, CONFIG};
+
+use rocket::{Build, Rocket};
+
+/// Set up rocket with the configuration of the environment
+pub fn rocket() -> Rocket<Build> {
+    // load config and .env file, which is also required by the db module
+    config::init();
+    build(CONFIG.github.clone())
+}
+
+/// Set up rocket with the given GitHub OAuth app, so tests are able to use a fake server
+pub fn build(github: Option<GitHubConfig>) -> Rocket<Build> {
+    config::init();
+    // init session storage with redis connection
+    session::init();
+    // check the key for encrypted values
+    crypto::init();
+    // initialize and start rocket server
+    routes::init()
+        .manage(github)
+        .manage(oso::init())
+        .manage(payment::init())
+        .manage(mail::init())
+        .manage(rate_limit::init())
+        .attach(db::stage())
+}
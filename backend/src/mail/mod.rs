@@ -0,0 +1,108 @@
+//! Sending of emails, like the links to verify an email address. The transport has to be chosen
+//! with `MAIL_TRANSPORT`: `log` writes mails to the log, which is enough for development, and
+//! `smtp` delivers mails to the server given by `SMTP_URL`, with `MAIL_FROM` as sender. Links
+//! point to the frontend configured with `FRONTEND_URL`.
+
+use crate::CONFIG;
+use std::ops::Deref;
+
+mod log;
+#[cfg(test)]
+mod outbox;
+mod smtp;
+mod token;
+
+pub use self::log::LogSender;
+#[cfg(test)]
+pub use outbox::Outbox;
+pub use smtp::SmtpSender;
+pub use token::{MailToken, TokenPurpose};
+
+/// Errors of a mail transport are reported as plain message
+pub type MailResult<T> = Result<T, String>;
+
+/// A plain text mail to a single recipient
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Interface every mail transport has to implement
+#[rocket::async_trait]
+pub trait MailSender: Send + Sync {
+    async fn send(&self, mail: &Mail) -> MailResult<()>;
+}
+
+/// The configured mail transport, managed by rocket
+pub struct Mailer {
+    sender: Box<dyn MailSender>,
+}
+
+impl Deref for Mailer {
+    type Target = dyn MailSender;
+
+    fn deref(&self) -> &Self::Target {
+        self.sender.as_ref()
+    }
+}
+
+#[cfg(test)]
+impl Mailer {
+    /// Mailer, that keeps the mails in the given outbox, for tests that follow the links
+    pub fn outbox(outbox: &Outbox) -> Self {
+        Mailer {
+            sender: Box::new(outbox.clone()),
+        }
+    }
+}
+
+pub type MailerState = rocket::State<Mailer>;
+
+/// Link to a page of the frontend, that hands the token over to the API
+pub fn link(path: &str, token: &str) -> String {
+    format!(
+        "{}{}?token={}",
+        CONFIG
+            .frontend_url
+            .as_deref()
+            .unwrap_or("http://localhost:4200")
+            .trim_end_matches('/'),
+        path,
+        token
+    )
+}
+
+pub fn init() -> Mailer {
+    token::init();
+    let sender: Box<dyn MailSender> = match CONFIG.mail_transport.as_deref() {
+        Some("log") => Box::new(LogSender),
+        Some("smtp") => Box::new(
+            SmtpSender::new(
+                CONFIG
+                    .smtp_url
+                    .as_deref()
+                    .unwrap_or("smtp://localhost:1025"),
+                CONFIG
+                    .mail_from
+                    .as_deref()
+                    .unwrap_or("SkyRocket <noreply@localhost>"),
+            )
+            .unwrap_or_else(|e| {
+                eprintln! { "Invalid SMTP settings: {}", e };
+                std::process::exit(1);
+            }),
+        ),
+        None => {
+            eprintln! { "MAIL_TRANSPORT must be set to log or smtp" };
+            std::process::exit(1);
+        }
+        Some(name) => {
+            eprintln! { "Unknown mail transport: {}", name };
+            std::process::exit(1);
+        }
+    };
+
+    Mailer { sender }
+}
@@ -0,0 +1,30 @@
+use super::{Mail, MailResult, MailSender};
+use std::sync::{Arc, Mutex};
+
+/// Mail transport of the tests, that keeps the mails in memory
+#[derive(Clone, Default)]
+pub struct Outbox {
+    mails: Arc<Mutex<Vec<Mail>>>,
+}
+
+impl Outbox {
+    /// Token of the link in the last mail
+    pub fn last_token(&self) -> String {
+        let mails = self.mails.lock().unwrap();
+        let body = &mails.last().expect("a mail has been sent").body;
+        body.split("token=").nth(1).expect("a link").trim().into()
+    }
+
+    /// Number of mails sent so far
+    pub fn sent(&self) -> usize {
+        self.mails.lock().unwrap().len()
+    }
+}
+
+#[rocket::async_trait]
+impl MailSender for Outbox {
+    async fn send(&self, mail: &Mail) -> MailResult<()> {
+        self.mails.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! Tokens, that are sent by mail. They are signed with HMAC-SHA256 using the secret configured
+//! with `MAIL_TOKEN_SECRET`, so their content can't be forged. Each token carries a random nonce,
+//! that is stored on the server until the token is used, which makes tokens single-use.
+
+use crate::CONFIG;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use once_cell::sync::Lazy;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use rocket::serde::{Deserialize, Serialize};
+
+const NONCE_LENGTH: usize = 32;
+
+static SECRET: Lazy<Vec<u8>> = Lazy::new(|| match CONFIG.mail_token_secret.as_deref() {
+    Some(secret) if !secret.is_empty() => secret.as_bytes().to_vec(),
+    _ => {
+        eprintln! { "MAIL_TOKEN_SECRET must be set to sign the links sent by mail" };
+        std::process::exit(1);
+    }
+});
+
+/// Make sure the secret is configured, before any link is sent
+pub fn init() {
+    Lazy::force(&SECRET);
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+/// What a token may be used for, so a token can't be used for anything else
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    VerifyEmail,
+    Login,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct MailToken {
+    pub purpose: TokenPurpose,
+    pub user_id: i32,
+    pub email: String,
+    pub nonce: String,
+    exp: i64,
+}
+
+impl MailToken {
+    /// Create a token with a random nonce, that expires after the given duration
+    pub fn new(purpose: TokenPurpose, user_id: i32, email: String, valid_for: Duration) -> Self {
+        MailToken {
+            purpose,
+            user_id,
+            email,
+            nonce: random_string(NONCE_LENGTH),
+            exp: (Utc::now() + valid_for).timestamp(),
+        }
+    }
+
+    fn sign_with(&self, secret: &[u8]) -> String {
+        encode(&Header::default(), self, &EncodingKey::from_secret(secret))
+            .expect("Claims of a mail token can always be serialized")
+    }
+
+    fn verify_with(token: &str, purpose: TokenPurpose, secret: &[u8]) -> Result<Self, String> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.leeway = 0;
+        let token = decode::<MailToken>(token, &DecodingKey::from_secret(secret), &validation)
+            .map_err(|e| e.to_string())?
+            .claims;
+        if token.purpose != purpose {
+            return Err("token has a different purpose".into());
+        }
+        Ok(token)
+    }
+
+    pub fn sign(&self) -> String {
+        self.sign_with(&SECRET)
+    }
+
+    /// Check the signature, the expiry and the purpose of a token. Whether the token was used
+    /// already, has to be checked by the caller with the nonce.
+    pub fn verify(token: &str, purpose: TokenPurpose) -> Result<Self, String> {
+        MailToken::verify_with(token, purpose, &SECRET)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"secret";
+
+    fn token() -> MailToken {
+        MailToken::new(
+            TokenPurpose::VerifyEmail,
+            1,
+            "ada@example.com".into(),
+            Duration::hours(1),
+        )
+    }
+
+    #[test]
+    fn test_verify_signed_token() {
+        let token = token();
+        let signed = token.sign_with(KEY);
+        assert_eq!(
+            Ok(token),
+            MailToken::verify_with(&signed, TokenPurpose::VerifyEmail, KEY)
+        );
+    }
+
+    #[test]
+    fn test_reject_other_secret() {
+        let signed = token().sign_with(b"other secret");
+        assert!(MailToken::verify_with(&signed, TokenPurpose::VerifyEmail, KEY).is_err());
+    }
+
+    #[test]
+    fn test_reject_tampered_token() {
+        let signed = token().sign_with(KEY);
+        let forged = MailToken {
+            user_id: 2,
+            ..token()
+        }
+        .sign_with(b"other secret");
+        let tampered = format!(
+            "{}.{}",
+            forged.rsplit_once('.').unwrap().0,
+            signed.rsplit_once('.').unwrap().1
+        );
+        assert!(MailToken::verify_with(&tampered, TokenPurpose::VerifyEmail, KEY).is_err());
+    }
+
+    #[test]
+    fn test_reject_other_purpose() {
+        let signed = token().sign_with(KEY);
+        assert!(MailToken::verify_with(&signed, TokenPurpose::Login, KEY).is_err());
+    }
+
+    #[test]
+    fn test_reject_expired_token() {
+        let signed = MailToken {
+            exp: Utc::now().timestamp() - 1,
+            ..token()
+        }
+        .sign_with(KEY);
+        assert!(MailToken::verify_with(&signed, TokenPurpose::VerifyEmail, KEY).is_err());
+    }
+}
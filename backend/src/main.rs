@@ -1,29 +1,4 @@
-#[macro_use]
-extern crate rocket;
-#[macro_use]
-extern crate rocket_sync_db_pools;
-#[macro_use]
-extern crate diesel;
-#[macro_use]
-extern crate diesel_migrations;
-
-pub mod db;
-pub mod http;
-pub mod oso;
-pub mod session;
-
-mod config;
-mod routes;
-
-pub use config::CONFIG;
-
-// setting up rocket
-#[launch]
+#[rocket::launch]
 fn rocket() -> _ {
-    // load config and .env file, which is also required by the db module
-    config::init();
-    // init session storage with redis connection
-    session::init();
-    // initialize and start rocket server
-    routes::init().manage(oso::init()).attach(db::stage())
+    backend::rocket()
 }
@@ -52,8 +52,13 @@ fn init_oso_arc() -> Result<OsoArc> {
     oso.load_files(vec![
         "security/users.polar",
         "security/addresses.polar",
+        "security/companions.polar",
         "security/bookings.polar",
         "security/sessions.polar",
+        "security/identities.polar",
+        "security/totp.polar",
+        "security/passkeys.polar",
+        "security/api_tokens.polar",
     ])?;
 
     Ok(OsoArc {
@@ -71,7 +76,9 @@ pub fn init() -> OsoArc {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::db::models::{Address, AuthUser, User};
+    use crate::db::models::{
+        Address, ApiToken, AuthUser, Booking, Companion, OAuthIdentity, Passkey, Totp, User,
+    };
     use once_cell::sync::Lazy;
 
     static OSO: Lazy<OsoArc> = Lazy::new(init);
@@ -203,4 +210,248 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn test_user_update_own_bookings() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(AuthUser::dummy(1), OsoAction::Update, Booking::dummy(1))
+        );
+    }
+
+    #[test]
+    fn test_user_update_other_bookings() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(AuthUser::dummy(1), OsoAction::Update, Booking::dummy(2))
+        );
+    }
+
+    #[test]
+    fn test_admin_user_update_own_bookings() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Update,
+                Booking::dummy(1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_update_other_bookings() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Update,
+                Booking::dummy(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_user_read_own_companions() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy(1),
+                OsoAction::Read,
+                Companion::dummy_for_user(1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_user_update_other_companions() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(
+                AuthUser::dummy(1),
+                OsoAction::Update,
+                Companion::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_delete_other_companions() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Delete,
+                Companion::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_user_create_own_identities() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy(1),
+                OsoAction::Create,
+                OAuthIdentity::dummy_for_user(1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_create_other_identities() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Create,
+                OAuthIdentity::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_user_delete_other_identities() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(
+                AuthUser::dummy(1),
+                OsoAction::Delete,
+                OAuthIdentity::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_read_other_identities() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Read,
+                OAuthIdentity::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_create_other_totp() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Create,
+                Totp::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_delete_other_totp() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Delete,
+                Totp::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_user_delete_other_totp() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(
+                AuthUser::dummy(1),
+                OsoAction::Delete,
+                Totp::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_user_create_own_passkeys() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy(1),
+                OsoAction::Create,
+                Passkey::dummy_for_user(1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_create_other_passkeys() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Create,
+                Passkey::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_delete_other_passkeys() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Delete,
+                Passkey::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_user_create_own_api_tokens() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy(1),
+                OsoAction::Create,
+                ApiToken::dummy_for_user(1)
+            )
+        );
+    }
+
+    #[test]
+    fn test_user_read_other_api_tokens() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(
+                AuthUser::dummy(1),
+                OsoAction::Read,
+                ApiToken::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_create_other_api_tokens() {
+        assert_eq!(
+            false,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Create,
+                ApiToken::dummy_for_user(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_admin_user_delete_other_api_tokens() {
+        assert_eq!(
+            true,
+            OSO.is_allowed(
+                AuthUser::dummy_admin(1),
+                OsoAction::Delete,
+                ApiToken::dummy_for_user(2)
+            )
+        );
+    }
 }
@@ -0,0 +1,266 @@
+use super::{
+    Authorization, PaymentError, PaymentProvider, PaymentRequest, PaymentResult, WebhookEvent,
+};
+use hmac::{Hmac, Mac, NewMac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Every token starting with this prefix is declined by the mock gateway
+pub const DECLINED_TOKEN_PREFIX: &str = "tok_declined";
+
+#[derive(Debug, Clone, PartialEq)]
+enum MockState {
+    Authorized,
+    Captured,
+}
+
+#[derive(Debug, Clone)]
+struct MockTransaction {
+    state: MockState,
+    amount: f32,
+    refunded: f32,
+}
+
+/// Payment provider that keeps all transactions in memory. It accepts every payment token, except
+/// tokens starting with `tok_declined`. Webhook payloads are signed with a base64 encoded
+/// HMAC-SHA256 of the configured secret.
+pub struct MockGateway {
+    secret: Vec<u8>,
+    transactions: Mutex<HashMap<String, MockTransaction>>,
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+impl MockGateway {
+    /// Create a new gateway. Without a secret, a random one is generated, so that webhooks can't
+    /// be forged.
+    pub fn new(secret: String) -> Self {
+        let secret = if secret.is_empty() {
+            random_string(32)
+        } else {
+            secret
+        };
+
+        MockGateway {
+            secret: secret.into_bytes(),
+            transactions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC can take keys of any size")
+    }
+
+    /// Sign a webhook payload the same way the gateway expects it
+    pub fn sign(&self, payload: &[u8]) -> String {
+        let mut mac = self.mac();
+        mac.update(payload);
+        base64::encode(mac.finalize().into_bytes())
+    }
+}
+
+#[rocket::async_trait]
+impl PaymentProvider for MockGateway {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn authorize(&self, request: &PaymentRequest) -> PaymentResult<Authorization> {
+        if request.token.starts_with(DECLINED_TOKEN_PREFIX) {
+            return Err(PaymentError::Declined);
+        }
+        if request.amount <= 0.0 {
+            return Err(PaymentError::InvalidAmount);
+        }
+
+        let reference = format!("mock_{}", random_string(24));
+        self.transactions.lock().unwrap().insert(
+            reference.clone(),
+            MockTransaction {
+                state: MockState::Authorized,
+                amount: request.amount,
+                refunded: 0.0,
+            },
+        );
+
+        Ok(Authorization {
+            reference,
+            amount: request.amount,
+        })
+    }
+
+    async fn capture(&self, reference: &str, amount: f32) -> PaymentResult<()> {
+        let mut transactions = self.transactions.lock().unwrap();
+        let transaction = transactions
+            .get_mut(reference)
+            .ok_or(PaymentError::UnknownTransaction)?;
+
+        if transaction.state != MockState::Authorized {
+            return Err(PaymentError::InvalidState);
+        }
+        if amount <= 0.0 || amount > transaction.amount {
+            return Err(PaymentError::InvalidAmount);
+        }
+
+        transaction.state = MockState::Captured;
+        transaction.amount = amount;
+        Ok(())
+    }
+
+    async fn refund(&self, reference: &str, amount: f32) -> PaymentResult<()> {
+        let mut transactions = self.transactions.lock().unwrap();
+        let transaction = transactions
+            .get_mut(reference)
+            .ok_or(PaymentError::UnknownTransaction)?;
+
+        if transaction.state != MockState::Captured {
+            return Err(PaymentError::InvalidState);
+        }
+        if amount <= 0.0 || transaction.refunded + amount > transaction.amount {
+            return Err(PaymentError::InvalidAmount);
+        }
+
+        transaction.refunded += amount;
+        Ok(())
+    }
+
+    fn verify_webhook(&self, payload: &[u8], signature: &str) -> PaymentResult<WebhookEvent> {
+        let signature = base64::decode(signature).map_err(|_| PaymentError::InvalidSignature)?;
+        let mut mac = self.mac();
+        mac.update(payload);
+        mac.verify(&signature)
+            .map_err(|_| PaymentError::InvalidSignature)?;
+
+        rocket::serde::json::from_slice(payload)
+            .map_err(|e| PaymentError::InvalidPayload(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::Currency;
+    use crate::payment::charge;
+
+    fn request(token: &str, amount: f32) -> PaymentRequest {
+        PaymentRequest {
+            amount,
+            currency: Currency::Euro,
+            token: token.into(),
+            description: "Test booking".into(),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_charge_captures_payment() {
+        let gateway = MockGateway::new("secret".into());
+        let authorization = charge(&gateway, &request("tok_visa", 120.0)).await.unwrap();
+
+        assert_eq!(120.0, authorization.amount);
+        assert_eq!(
+            Err(PaymentError::InvalidState),
+            gateway.capture(&authorization.reference, 120.0).await
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_declined_token() {
+        let gateway = MockGateway::new("secret".into());
+        assert_eq!(
+            Err(PaymentError::Declined),
+            gateway
+                .authorize(&request("tok_declined_insufficient_funds", 120.0))
+                .await
+                .map(|a| a.amount)
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_capture_unknown_transaction() {
+        let gateway = MockGateway::new("secret".into());
+        assert_eq!(
+            Err(PaymentError::UnknownTransaction),
+            gateway.capture("mock_unknown", 10.0).await
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_capture_more_than_authorized() {
+        let gateway = MockGateway::new("secret".into());
+        let authorization = gateway.authorize(&request("tok_visa", 50.0)).await.unwrap();
+        assert_eq!(
+            Err(PaymentError::InvalidAmount),
+            gateway.capture(&authorization.reference, 50.5).await
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_refund_requires_capture() {
+        let gateway = MockGateway::new("secret".into());
+        let authorization = gateway.authorize(&request("tok_visa", 50.0)).await.unwrap();
+        assert_eq!(
+            Err(PaymentError::InvalidState),
+            gateway.refund(&authorization.reference, 10.0).await
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_partial_refunds_up_to_captured_amount() {
+        let gateway = MockGateway::new("secret".into());
+        let authorization = charge(&gateway, &request("tok_visa", 100.0)).await.unwrap();
+
+        assert_eq!(Ok(()), gateway.refund(&authorization.reference, 60.0).await);
+        assert_eq!(Ok(()), gateway.refund(&authorization.reference, 40.0).await);
+        assert_eq!(
+            Err(PaymentError::InvalidAmount),
+            gateway.refund(&authorization.reference, 0.5).await
+        );
+    }
+
+    #[test]
+    fn test_verify_signed_webhook() {
+        let gateway = MockGateway::new("secret".into());
+        let payload = br#"{"type":"captured","reference":"mock_abc"}"#;
+
+        assert_eq!(
+            Ok(WebhookEvent::Captured {
+                reference: "mock_abc".into()
+            }),
+            gateway.verify_webhook(payload, &gateway.sign(payload))
+        );
+    }
+
+    #[test]
+    fn test_verify_tampered_webhook() {
+        let gateway = MockGateway::new("secret".into());
+        let signature = gateway.sign(br#"{"type":"failed","reference":"mock_abc"}"#);
+
+        assert_eq!(
+            Err(PaymentError::InvalidSignature),
+            gateway.verify_webhook(br#"{"type":"captured","reference":"mock_abc"}"#, &signature)
+        );
+    }
+
+    #[test]
+    fn test_verify_webhook_of_other_gateway() {
+        let gateway = MockGateway::new("secret".into());
+        let other = MockGateway::new(String::new());
+        let payload = br#"{"type":"captured","reference":"mock_abc"}"#;
+
+        assert_eq!(
+            Err(PaymentError::InvalidSignature),
+            gateway.verify_webhook(payload, &other.sign(payload))
+        );
+    }
+}
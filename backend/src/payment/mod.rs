@@ -0,0 +1,144 @@
+use crate::db::models::Currency;
+use crate::CONFIG;
+use rocket::serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+
+mod mock;
+
+pub use mock::MockGateway;
+
+/// Errors that can be reported by a payment provider
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaymentError {
+    /// The payment method of the customer was declined
+    Declined,
+    /// The provider doesn't know the given transaction reference
+    UnknownTransaction,
+    /// The transaction is in a state that doesn't allow the requested operation
+    InvalidState,
+    /// The amount exceeds the authorized or captured amount of the transaction
+    InvalidAmount,
+    /// The signature of a webhook payload doesn't match its content
+    InvalidSignature,
+    /// The webhook payload couldn't be parsed
+    InvalidPayload(String),
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaymentError::Declined => write!(f, "payment was declined"),
+            PaymentError::UnknownTransaction => write!(f, "unknown transaction"),
+            PaymentError::InvalidState => write!(f, "transaction is in an invalid state"),
+            PaymentError::InvalidAmount => write!(f, "invalid amount"),
+            PaymentError::InvalidSignature => write!(f, "invalid webhook signature"),
+            PaymentError::InvalidPayload(e) => write!(f, "invalid webhook payload: {}", e),
+        }
+    }
+}
+
+pub type PaymentResult<T> = Result<T, PaymentError>;
+
+/// Everything a provider needs to know to charge a customer
+#[derive(Debug, Clone)]
+pub struct PaymentRequest {
+    pub amount: f32,
+    pub currency: Currency,
+    /// Tokenized payment method, as created by the provider's client side library
+    pub token: String,
+    pub description: String,
+}
+
+/// An authorized transaction. The amount is reserved, but not yet transferred.
+#[derive(Debug, Clone)]
+pub struct Authorization {
+    /// Identifies the transaction at the provider
+    pub reference: String,
+    pub amount: f32,
+}
+
+/// Asynchronous notifications sent by a provider about the state of a transaction
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(crate = "rocket::serde")]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum WebhookEvent {
+    Captured { reference: String },
+    Failed { reference: String },
+    Refunded { reference: String, amount: f32 },
+}
+
+/// Interface every payment provider has to implement. Amounts are always given in the currency
+/// the transaction was authorized with.
+#[rocket::async_trait]
+pub trait PaymentProvider: Send + Sync {
+    /// Name of the provider, stored alongside every payment
+    fn name(&self) -> &'static str;
+
+    /// Reserve the requested amount on the customers payment method
+    async fn authorize(&self, request: &PaymentRequest) -> PaymentResult<Authorization>;
+
+    /// Transfer a previously authorized amount
+    async fn capture(&self, reference: &str, amount: f32) -> PaymentResult<()>;
+
+    /// Pay back (parts of) a captured amount
+    async fn refund(&self, reference: &str, amount: f32) -> PaymentResult<()>;
+
+    /// Check the signature of a webhook payload and parse the contained event
+    fn verify_webhook(&self, payload: &[u8], signature: &str) -> PaymentResult<WebhookEvent>;
+}
+
+/// Authorize the requested amount and capture it right away
+pub async fn charge(
+    provider: &dyn PaymentProvider,
+    request: &PaymentRequest,
+) -> PaymentResult<Authorization> {
+    let authorization = provider.authorize(request).await?;
+    provider
+        .capture(&authorization.reference, authorization.amount)
+        .await?;
+    Ok(authorization)
+}
+
+/// The configured payment provider, managed by rocket
+pub struct PaymentGateway {
+    provider: Box<dyn PaymentProvider>,
+}
+
+impl Deref for PaymentGateway {
+    type Target = dyn PaymentProvider;
+
+    fn deref(&self) -> &Self::Target {
+        self.provider.as_ref()
+    }
+}
+
+#[cfg(test)]
+impl PaymentGateway {
+    /// Gateway of the mock provider, for tests that charge bookings
+    pub fn mock() -> Self {
+        PaymentGateway {
+            provider: Box::new(MockGateway::new("secret".into())),
+        }
+    }
+}
+
+pub type PaymentState = rocket::State<PaymentGateway>;
+
+pub fn init() -> PaymentGateway {
+    let provider: Box<dyn PaymentProvider> = match CONFIG.payment_provider.as_deref() {
+        Some("mock") => Box::new(MockGateway::new(
+            CONFIG.payment_webhook_secret.clone().unwrap_or_default(),
+        )),
+        None => {
+            eprintln! { "PAYMENT_PROVIDER must be set, the only provider is mock" };
+            std::process::exit(1);
+        }
+        Some(name) => {
+            eprintln! { "Unknown payment provider: {}", name };
+            std::process::exit(1);
+        }
+    };
+
+    PaymentGateway { provider }
+}
@@ -0,0 +1,200 @@
+//! Minimal PDF writer, sufficient to render simple text documents with lines and filled rectangles
+//! on A4 pages using the standard Helvetica fonts.
+
+/// Width of an A4 page in points
+pub const PAGE_WIDTH: f32 = 595.0;
+/// Height of an A4 page in points
+pub const PAGE_HEIGHT: f32 = 842.0;
+
+#[derive(Clone, Copy)]
+pub enum Font {
+    Regular,
+    Bold,
+}
+
+impl Font {
+    fn resource_name(&self) -> &'static str {
+        match self {
+            Font::Regular => "F1",
+            Font::Bold => "F2",
+        }
+    }
+}
+
+/// A single page. Coordinates are given in points, with the origin in the top left corner.
+#[derive(Default)]
+pub struct Page {
+    content: String,
+}
+
+/// Encode a string for use within a PDF string literal. The standard fonts use the
+/// WinAnsiEncoding, so characters outside of Latin-1 (except for the Euro sign) are replaced.
+fn encode_text(text: &str) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                encoded.push(b'\\');
+                encoded.push(c as u8);
+            }
+            '€' => encoded.push(0x80),
+            ' '..='~' | '\u{a0}'..='\u{ff}' => encoded.push(c as u32 as u8),
+            _ => encoded.push(b'?'),
+        }
+    }
+    encoded
+}
+
+impl Page {
+    pub fn new() -> Self {
+        Page::default()
+    }
+
+    /// Draw a line of text, with `y` being the baseline
+    pub fn text(&mut self, x: f32, y: f32, size: f32, font: Font, text: &str) {
+        let encoded = encode_text(text)
+            .into_iter()
+            .map(|b| {
+                if b.is_ascii() {
+                    (b as char).to_string()
+                } else {
+                    format!("\\{:03o}", b)
+                }
+            })
+            .collect::<String>();
+
+        self.content.push_str(&format!(
+            "BT /{} {:.1} Tf {:.2} {:.2} Td ({}) Tj ET\n",
+            font.resource_name(),
+            size,
+            x,
+            PAGE_HEIGHT - y,
+            encoded
+        ));
+    }
+
+    /// Draw a horizontal line
+    pub fn line(&mut self, x: f32, y: f32, width: f32) {
+        self.content.push_str(&format!(
+            "0.5 w {:.2} {:.2} m {:.2} {:.2} l S\n",
+            x,
+            PAGE_HEIGHT - y,
+            x + width,
+            PAGE_HEIGHT - y
+        ));
+    }
+
+    /// Draw a filled rectangle, with `x` and `y` being its top left corner
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        self.content.push_str(&format!(
+            "{:.2} {:.2} {:.2} {:.2} re f\n",
+            x,
+            PAGE_HEIGHT - y - height,
+            width,
+            height
+        ));
+    }
+}
+
+#[derive(Default)]
+pub struct Document {
+    pages: Vec<Page>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Document::default()
+    }
+
+    pub fn add_page(&mut self, page: Page) {
+        self.pages.push(page);
+    }
+
+    /// Serialize the document
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // objects 1 to 4 are the catalog, the page tree and both fonts, followed by a page
+        // object and a content stream for every page
+        let mut objects: Vec<String> = vec![
+            "<< /Type /Catalog /Pages 2 0 R >>".into(),
+            format!(
+                "<< /Type /Pages /Kids [{}] /Count {} >>",
+                (0..self.pages.len())
+                    .map(|i| format!("{} 0 R", 5 + 2 * i))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+                self.pages.len()
+            ),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+                .into(),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+                .into(),
+        ];
+
+        for (i, page) in self.pages.iter().enumerate() {
+            objects.push(format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                6 + 2 * i
+            ));
+            objects.push(format!(
+                "<< /Length {} >>\nstream\n{}endstream",
+                page.content.len(),
+                page.content
+            ));
+        }
+
+        let mut out = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).into_bytes());
+        }
+
+        let xref = out.len();
+        out.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+        for offset in offsets {
+            out.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+        }
+        out.extend(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                xref
+            )
+            .into_bytes(),
+        );
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_special_characters() {
+        assert_eq!(b"\\(a\\) \\\\".to_vec(), encode_text("(a) \\"));
+    }
+
+    #[test]
+    fn test_encode_non_ascii_characters() {
+        assert_eq!(vec![0x80, b' ', 0xfc, b'?'], encode_text("€ ü→"));
+    }
+
+    #[test]
+    fn test_document_structure() {
+        let mut page = Page::new();
+        page.text(50.0, 50.0, 12.0, Font::Regular, "Hello");
+        let mut document = Document::new();
+        document.add_page(page);
+
+        let bytes = String::from_utf8(document.to_bytes()).unwrap();
+        assert!(bytes.starts_with("%PDF-1.4\n"));
+        assert!(bytes.contains("(Hello) Tj"));
+        assert!(bytes.contains("/Count 1"));
+        assert!(bytes.ends_with("%%EOF\n"));
+    }
+}
@@ -0,0 +1,82 @@
+//! Rate limiting of endpoints, that send mails or are otherwise prone to abuse. Hits are counted
+//! in memory within fixed windows, so limits apply per instance of the backend.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counts the hits of each key, like an email address or an IP address, within a window
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    hits: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            limit,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn hit_at(&self, key: &str, now: Instant) -> bool {
+        let mut hits = self.hits.lock().unwrap();
+        // forget keys of past windows, so the map doesn't grow forever
+        hits.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+
+        let (_, count) = hits.entry(key.to_string()).or_insert((now, 0));
+        if *count >= self.limit {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    /// Count a hit of the key. Returns `false`, if the key already reached its limit within the
+    /// current window.
+    pub fn hit(&self, key: &str) -> bool {
+        self.hit_at(key, Instant::now())
+    }
+}
+
+/// Limits of the login by links sent by mail. The limit per address stops flooding the inbox of
+/// a user, the limit per IP address stops a client from mailing many users.
+pub struct LoginLimits {
+    pub login_links_per_email: RateLimiter,
+    pub login_links_per_ip: RateLimiter,
+}
+
+pub type LoginLimitsState = rocket::State<LoginLimits>;
+
+pub fn init() -> LoginLimits {
+    LoginLimits {
+        login_links_per_email: RateLimiter::new(3, Duration::from_secs(15 * 60)),
+        login_links_per_ip: RateLimiter::new(10, Duration::from_secs(60 * 60)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_hits_within_window() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(limiter.hit_at("ada@example.com", now));
+        assert!(limiter.hit_at("ada@example.com", now));
+        assert!(!limiter.hit_at("ada@example.com", now));
+        assert!(limiter.hit_at("grace@example.com", now));
+    }
+
+    #[test]
+    fn test_reset_after_window() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+        assert!(limiter.hit_at("127.0.0.1", now));
+        assert!(!limiter.hit_at("127.0.0.1", now + Duration::from_secs(59)));
+        assert!(limiter.hit_at("127.0.0.1", now + Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,55 @@
+use crate::db::models::{ApiToken, AuthUser, CreatedApiToken, NewApiToken};
+use crate::db::Db;
+use crate::oso::{OsoAction, OsoState};
+use crate::routes::{error, require_session, ApiResult};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+#[openapi(tag = "API Tokens")]
+#[get("/<id>/api-tokens")]
+async fn read(oso: &OsoState, actor: AuthUser, db: Db, id: i32) -> ApiResult<Json<Vec<ApiToken>>> {
+    if oso.is_allowed(actor, OsoAction::Read, ApiToken::dummy_for_user(id)) {
+        Ok(Json(ApiToken::all_from_user(&db, id).await))
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Create a personal API token. The token is part of the response only this once, afterwards
+/// it is sent as `Authorization: Bearer <token>` header.
+#[openapi(tag = "API Tokens")]
+#[post("/<id>/api-tokens", data = "<new_token>")]
+async fn create(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    new_token: Json<NewApiToken>,
+) -> ApiResult<Json<CreatedApiToken>> {
+    require_session(&actor)?;
+    if !oso.is_allowed(actor, OsoAction::Create, ApiToken::dummy_for_user(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    ApiToken::create(&db, id, new_token.into_inner())
+        .await
+        .map(Json)
+}
+
+#[openapi(tag = "API Tokens")]
+#[delete("/<id>/api-tokens/<token_id>")]
+async fn delete(oso: &OsoState, actor: AuthUser, db: Db, id: i32, token_id: i32) -> ApiResult<()> {
+    require_session(&actor)?;
+    if oso.is_allowed(actor, OsoAction::Delete, ApiToken::dummy_for_user(id)) {
+        ApiToken::revoke(&db, id, token_id).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: read, create, delete]
+}
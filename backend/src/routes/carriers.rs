@@ -0,0 +1,25 @@
+use crate::db::models::{AdminRole, AuthUser, Carrier, NewCarrier};
+use crate::db::Db;
+use crate::routes::ApiResult;
+use rocket::serde::json::Json;
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+#[openapi(tag = "Carriers")]
+#[post("/", data = "<new_carrier>")]
+async fn create(_r: AdminRole, db: Db, new_carrier: Json<NewCarrier>) -> ApiResult<Json<Carrier>> {
+    Carrier::create(&db, new_carrier.into_inner())
+        .await
+        .map(Json)
+}
+
+#[openapi(tag = "Carriers")]
+#[get("/")]
+async fn read(_actor: AuthUser, db: Db) -> ApiResult<Json<Vec<Carrier>>> {
+    Ok(Json(Carrier::get_all(&db).await))
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: create, read]
+}
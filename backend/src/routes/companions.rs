@@ -0,0 +1,76 @@
+use crate::db::models::{AuthUser, Companion, NewCompanion};
+use crate::db::Db;
+use crate::oso::{OsoAction, OsoState};
+use crate::routes::{error, ApiResult};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+#[openapi(tag = "Companions")]
+#[post("/<id>/companions", data = "<new_companion>")]
+async fn create(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    new_companion: Json<NewCompanion>,
+) -> ApiResult<Json<Companion>> {
+    if oso.is_allowed(actor, OsoAction::Create, Companion::dummy_for_user(id)) {
+        Companion::create(&db, id, new_companion.into_inner())
+            .await
+            .map(Json)
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+#[openapi(tag = "Companions")]
+#[get("/<id>/companions")]
+async fn read(oso: &OsoState, actor: AuthUser, db: Db, id: i32) -> ApiResult<Json<Vec<Companion>>> {
+    if oso.is_allowed(actor, OsoAction::Read, Companion::dummy_for_user(id)) {
+        Ok(Json(Companion::all_from_user(&db, id).await))
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+#[openapi(tag = "Companions")]
+#[put("/<id>/companions/<companion_id>", data = "<new_companion>")]
+async fn update(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    companion_id: i32,
+    new_companion: Json<NewCompanion>,
+) -> ApiResult<Json<Companion>> {
+    if oso.is_allowed(actor, OsoAction::Update, Companion::dummy_for_user(id)) {
+        Companion::update(&db, id, companion_id, new_companion.into_inner())
+            .await
+            .map(Json)
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+#[openapi(tag = "Companions")]
+#[delete("/<id>/companions/<companion_id>")]
+async fn delete(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    companion_id: i32,
+) -> ApiResult<()> {
+    if oso.is_allowed(actor, OsoAction::Delete, Companion::dummy_for_user(id)) {
+        Companion::delete(&db, id, companion_id).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: read, create, update, delete]
+}
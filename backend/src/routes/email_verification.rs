@@ -0,0 +1,42 @@
+use crate::db::models::{AuthUser, EmailConfirmation, EmailVerification, User};
+use crate::db::Db;
+use crate::mail::MailerState;
+use crate::oso::{OsoAction, OsoState};
+use crate::routes::{error, ApiResult};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+/// Send another verification link to the email address of a user. Previous links become
+/// invalid.
+#[openapi(tag = "Email Verification")]
+#[post("/<id>/email-verification")]
+async fn request(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    mailer: &MailerState,
+    id: i32,
+) -> ApiResult<()> {
+    if oso.is_allowed(actor, OsoAction::Update, User::dummy(id)) {
+        EmailVerification::request(&db, mailer, id).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Verify an email address with the token of the link sent to it. Doesn't require a login, since
+/// the link may be opened in another browser.
+#[openapi(tag = "Email Verification")]
+#[post("/email-verification", data = "<confirmation>")]
+async fn confirm(db: Db, confirmation: Json<EmailConfirmation>) -> ApiResult<Json<User>> {
+    EmailVerification::confirm(&db, confirmation.into_inner())
+        .await
+        .map(Json)
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: request, confirm]
+}
@@ -0,0 +1,123 @@
+use super::login::{verify_provider_code, GitHubState};
+use crate::db::models::{AuthUser, Credentials, LoginMethods, NewPassword, OAuthIdentity};
+use crate::db::Db;
+use crate::oso::{OsoAction, OsoState};
+use crate::routes::{error, require_session, ApiResult};
+use rocket::http::{CookieJar, Status};
+use rocket::serde::json::Json;
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+/// Returns the linked accounts of providers, and whether the user has a password
+#[openapi(tag = "Identities")]
+#[get("/<id>/identities")]
+async fn read(oso: &OsoState, actor: AuthUser, db: Db, id: i32) -> ApiResult<Json<LoginMethods>> {
+    if oso.is_allowed(actor, OsoAction::Read, OAuthIdentity::dummy_for_user(id)) {
+        OAuthIdentity::login_methods(&db, id).await.map(Json)
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Link the account of a provider to the user
+///
+/// The login attempt is started with `GET /users/login/oauth` like a regular login, but the code
+/// and state the provider redirected back with are sent here instead of the login endpoint.
+///
+/// # Arguments
+///
+/// * `provider` - The name of the provider, like `github`
+/// * `code` - The OAuth code received by the provider
+/// * `state` - The state of the login attempt, as returned by the provider
+#[openapi(tag = "Identities")]
+#[post("/<id>/identities/<provider>?<code>&<state>", rank = 2)]
+#[allow(clippy::too_many_arguments)]
+async fn create(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    github: &GitHubState,
+    cookies: &CookieJar<'_>,
+    id: i32,
+    provider: String,
+    code: String,
+    state: String,
+) -> ApiResult<Json<OAuthIdentity>> {
+    require_session(&actor)?;
+    if !oso.is_allowed(actor, OsoAction::Create, OAuthIdentity::dummy_for_user(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let (provider, subject) =
+        verify_provider_code(github, cookies, &provider, code, &state).await?;
+    OAuthIdentity::link(
+        &db,
+        OAuthIdentity {
+            provider,
+            subject,
+            user_id: id,
+        },
+    )
+    .await
+    .map(Json)
+}
+
+/// Add a password to the user, so the user is able to log in with email and password
+#[openapi(tag = "Identities")]
+#[post("/<id>/identities/password", data = "<new_password>")]
+async fn create_password(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    new_password: Json<NewPassword>,
+) -> ApiResult<()> {
+    require_session(&actor)?;
+    if oso.is_allowed(actor, OsoAction::Create, OAuthIdentity::dummy_for_user(id)) {
+        Credentials::link(&db, id, new_password.into_inner()).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Unlink the account of a provider. The last login method of a user can't be removed.
+#[openapi(tag = "Identities")]
+#[delete("/<id>/identities/<provider>/<subject>")]
+async fn delete(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    provider: String,
+    subject: String,
+) -> ApiResult<()> {
+    require_session(&actor)?;
+    if oso.is_allowed(actor, OsoAction::Delete, OAuthIdentity::dummy_for_user(id)) {
+        OAuthIdentity::unlink(&db, id, provider, subject).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Remove the password of the user. The last login method of a user can't be removed.
+#[openapi(tag = "Identities")]
+#[delete("/<id>/identities/password")]
+async fn delete_password(oso: &OsoState, actor: AuthUser, db: Db, id: i32) -> ApiResult<()> {
+    require_session(&actor)?;
+    if oso.is_allowed(actor, OsoAction::Delete, OAuthIdentity::dummy_for_user(id)) {
+        Credentials::unlink(&db, id).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![
+        settings: read,
+        create,
+        create_password,
+        delete,
+        delete_password
+    ]
+}
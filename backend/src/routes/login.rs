@@ -1,57 +1,192 @@
 use super::{error, ApiResult};
-use super::{GitHubAccessTokenRequest, GitHubAccessTokenResponse, OAuthProviders};
+use super::{GitHubAccessTokenRequest, GitHubAccessTokenResponse, OAuthProvider, OAuthProviders};
 use crate::db::models::Session;
-use crate::db::models::{AuthUser, Gender, GitHubOAuthUser, NewUser};
+use crate::db::models::{
+    AuthUser, Credentials, EmailVerification, Gender, LoginCredentials, LoginLink,
+    LoginLinkRequest, LoginLinkToken, NewCredentials, NewUser, OAuthIdentity, OAuthRegistrar,
+    Passkey, Role, Totp, TotpCode,
+};
 use crate::db::Db;
-use crate::routes::UserAgent;
+use crate::mail::MailerState;
+use crate::oidc::LoginAttempt;
+use crate::rate_limit::LoginLimitsState;
+use crate::routes::{ClientIp, UserAgent};
 use crate::session;
-use crate::{http, CONFIG};
+use crate::webauthn::{
+    AuthenticationResponse, Ceremony, PendingCeremony, RelyingParty, RequestOptions,
+};
+use crate::{http, oidc, GitHubConfig, CONFIG};
 use chrono::NaiveDate;
 use rocket::http::uri::fmt::Query;
 use rocket::http::uri::fmt::UriDisplay;
 use rocket::http::{CookieJar, Status};
 use rocket::response::Responder;
 use rocket::serde::json::{Json, Value};
+use rocket::serde::{Deserialize, Serialize};
 use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::okapi::openapi3::{OpenApi, Responses};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
 use rocket_okapi::response::OpenApiResponderInner;
 use rocket_okapi::util::add_schema_response;
 use rocket_okapi::{openapi, openapi_get_routes_spec, settings::OpenApiSettings, Result};
+use std::collections::HashMap;
+
+/// Name of GitHub within the OAuth identities
+const GITHUB: &str = "github";
+
+/// The GitHub OAuth app, if it is configured
+pub(super) type GitHubState = rocket::State<Option<GitHubConfig>>;
 
 #[derive(UriDisplayQuery)]
 struct GitHubOAuth<'a> {
     pub client_id: &'a str,
     pub scope: &'a str,
+    pub state: &'a str,
+    pub code_challenge: &'a str,
+    pub code_challenge_method: &'a str,
 }
 
 /// Returns a list of all configured OAuth providers and their corresponding redirect url's
+///
+/// Every call starts a new login attempt with a random `state` and PKCE challenge per provider,
+/// which is stored in the session store for 10 minutes and bound to the browser by a cookie.
 #[openapi(tag = "Login")]
 #[get("/oauth")]
-async fn oauth_list() -> Json<OAuthProviders> {
+async fn oauth_list(github: &GitHubState, cookies: &CookieJar<'_>) -> Json<OAuthProviders> {
+    let mut attempts = HashMap::new();
     let github_url: Option<String>;
 
-    match &CONFIG.oauth_github_client_id {
-        &None => github_url = None,
-        Some(value) => {
+    match github.inner() {
+        None => github_url = None,
+        Some(github) => {
+            let attempt = LoginAttempt::new();
+            let challenge = attempt.challenge();
             let params = GitHubOAuth {
-                client_id: value,
+                client_id: &github.client_id,
                 scope: "user:email",
+                state: &attempt.state,
+                code_challenge: &challenge,
+                code_challenge_method: "S256",
             };
             github_url = Some(format!(
-                "https://github.com/login/oauth/authorize?{}",
-                &params as &dyn UriDisplay<Query>
+                "{}/login/oauth/authorize?{}",
+                github.url, &params as &dyn UriDisplay<Query>
             ));
+            attempts.insert(GITHUB.to_string(), attempt);
+        }
+    }
+
+    let mut providers: Vec<OAuthProvider> = github_url
+        .iter()
+        .map(|url| OAuthProvider {
+            name: GITHUB.into(),
+            url: url.clone(),
+        })
+        .collect();
+    for provider in CONFIG.oauth_providers.iter() {
+        // providers, that can't be reached right now, are left out
+        let attempt = LoginAttempt::new();
+        match oidc::authorize_url(provider, &attempt).await {
+            Ok(url) => {
+                providers.push(OAuthProvider {
+                    name: provider.name.clone(),
+                    url,
+                });
+                attempts.insert(provider.name.clone(), attempt);
+            }
+            Err(e) => eprintln! { "OAuth provider {} is unavailable: {}", provider.name, e },
         }
     }
 
-    Json(OAuthProviders { github: github_url })
+    if !attempts.is_empty() {
+        session::set_oauth_attempts(cookies, attempts).await;
+    }
+
+    Json(OAuthProviders {
+        github: github_url,
+        providers,
+    })
+}
+
+/// Save the session of a user, that passed every factor of the login
+async fn save_session(
+    db: &Db,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+    user_id: i32,
+) -> ApiResult<AuthUser> {
+    let user = AuthUser::by_user_id(db, user_id)
+        .await
+        .ok_or_else(|| error("", Status::InternalServerError, ""))?;
+    Session::save(db, cookies, ua, user.clone()).await?;
+    Ok(user)
+}
+
+/// The factors, that are able to complete a login
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct SecondFactorRequired {
+    pub methods: Vec<String>,
+}
+
+/// Either the logged in user, or the login awaits a second factor
+#[derive(Responder)]
+enum LoginOutcome {
+    LoggedIn(Json<AuthUser>),
+    #[response(status = 202)]
+    SecondFactorRequired(Json<SecondFactorRequired>),
+}
+
+#[rocket::async_trait]
+impl OpenApiResponderInner for LoginOutcome {
+    fn responses(gen: &mut OpenApiGenerator) -> Result<Responses> {
+        let mut response = Responses::default();
+        add_schema_response(
+            &mut response,
+            200,
+            "application/json",
+            gen.json_schema::<AuthUser>(),
+        )?;
+        add_schema_response(
+            &mut response,
+            202,
+            "application/json",
+            gen.json_schema::<SecondFactorRequired>(),
+        )?;
+        Ok(response)
+    }
+}
+
+/// Log in a user, that passed the first factor. Users with two-factor authentication only get a
+/// session after entering a code with `POST /users/login/second-factor` or using a passkey with
+/// `POST /users/login/second-factor/passkey`.
+async fn start_session(
+    db: &Db,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+    user_id: i32,
+) -> ApiResult<LoginOutcome> {
+    if Totp::is_enabled(db, user_id).await? {
+        let mut methods = vec!["totp".to_string()];
+        if !Passkey::all_from_user(db, user_id).await.is_empty() {
+            methods.push("passkey".into());
+        }
+        session::set_pending_login(cookies, user_id).await;
+        return Ok(LoginOutcome::SecondFactorRequired(Json(
+            SecondFactorRequired { methods },
+        )));
+    }
+    save_session(db, cookies, ua, user_id)
+        .await
+        .map(|user| LoginOutcome::LoggedIn(Json(user)))
 }
 
 /// Either returns an already registrated user, or some userdata that can be used to prefill forms
 /// in case the user isn't registered yet.
 #[derive(Responder)]
 enum RegistratedOrNewUser {
-    Registrated(Json<AuthUser>),
+    Registrated(LoginOutcome),
     New(Json<NewUser>),
 }
 
@@ -65,6 +200,12 @@ impl OpenApiResponderInner for RegistratedOrNewUser {
             "application/json",
             gen.json_schema::<AuthUser>(),
         )?;
+        add_schema_response(
+            &mut response,
+            202,
+            "application/json",
+            gen.json_schema::<SecondFactorRequired>(),
+        )?;
         add_schema_response(
             &mut response,
             200,
@@ -75,68 +216,110 @@ impl OpenApiResponderInner for RegistratedOrNewUser {
     }
 }
 
-/// Login endpoint for GitHub OAuth
-///
-/// The returned user variable has an id of null, iff the GitHub user hasn't
-/// registered yet. If has user has registered previously, a valid id is returned.
-/// In every other case (e.g. internal server error), nothing is returned.
-///
-/// # Arguments
-///
-/// * `code` - The OAuth code recevied by GitHub.
-#[openapi(tag = "Login")]
-#[post("/oauth/github?<code>")]
-async fn login_github(
-    db: Db,
-    code: String,
+/// Find the login attempt a provider redirected back from. Attempts are used only once, so the
+/// callback of an expired attempt, a foreign state or a replayed state are rejected.
+async fn take_attempt(
     cookies: &CookieJar<'_>,
-    ua: UserAgent,
-) -> ApiResult<RegistratedOrNewUser> {
+    provider: &str,
+    state: &str,
+) -> ApiResult<LoginAttempt> {
+    session::take_oauth_attempt(cookies, provider)
+        .await
+        .filter(|attempt| attempt.matches(state))
+        .ok_or_else(|| error("", Status::Unauthorized, "Invalid or expired OAuth state"))
+}
+
+/// Exchange a GitHub code and fetch the data of the GitHub user
+async fn github_user(
+    github: &GitHubConfig,
+    code: String,
+    attempt: &LoginAttempt,
+) -> ApiResult<Value> {
     // validate token received from GitHub
     let oauth_res = http::post::<GitHubAccessTokenResponse, GitHubAccessTokenRequest>(
-        "https://github.com/login/oauth/access_token",
+        &format!("{}/login/oauth/access_token", github.url),
         &GitHubAccessTokenRequest {
-            client_id: CONFIG
-                .oauth_github_client_id
-                .as_ref()
-                .ok_or_else(|| error("", Status::InternalServerError, ""))?,
-            client_secret: CONFIG
-                .oauth_github_client_secret
-                .as_ref()
-                .ok_or_else(|| error("", Status::InternalServerError, ""))?,
+            client_id: &github.client_id,
+            client_secret: &github.client_secret,
             code,
+            code_verifier: &attempt.verifier,
         },
     )
     .await
     .map_err(|e| error(e, Status::Unauthorized, "Failed to validate OAuth code"))?;
 
     // fetch GitHub user data
-    let user_res = http::get::<Value>("https://api.github.com/user", &oauth_res.access_token)
+    http::get::<Value>(&format!("{}/user", github.api_url), &oauth_res.access_token)
         .await
-        .map_err(|e| error(e, Status::Unauthorized, "Failed to validate OAuth code"))?;
+        .map_err(|e| error(e, Status::Unauthorized, "Failed to validate OAuth code"))
+}
 
-    let github_id = user_res
+fn github_id(user_res: &Value) -> ApiResult<String> {
+    Ok(user_res
         .get("id")
         .ok_or_else(|| error("", Status::InternalServerError, ""))?
         .as_i64()
-        .ok_or_else(|| error("", Status::InternalServerError, ""))? as i32;
+        .ok_or_else(|| error("", Status::InternalServerError, ""))?
+        .to_string())
+}
 
-    if let Some(github_user) = GitHubOAuthUser::find_by_id(&db, github_id).await {
-        let user = AuthUser::by_user_id(&db, github_user.user_id)
-            .await
-            .ok_or_else(|| error("", Status::InternalServerError, ""))?;
-        Session::save(&db, cookies, ua, user.clone())
-            .await
-            .map_err(|e| {
-                error(
-                    e,
-                    Status::InternalServerError,
-                    "Error saving session, please try again later",
-                )
-            })?;
-        Ok(RegistratedOrNewUser::Registrated(Json(user)))
+/// Complete a login attempt at GitHub or an OpenID Connect provider, without logging in. Returns
+/// the name of the provider and the subject of the account.
+pub(super) async fn verify_provider_code(
+    github: &GitHubState,
+    cookies: &CookieJar<'_>,
+    provider: &str,
+    code: String,
+    state: &str,
+) -> ApiResult<(String, String)> {
+    if let (GITHUB, Some(github)) = (provider, github.inner()) {
+        let attempt = take_attempt(cookies, GITHUB, state).await?;
+        let user_res = github_user(github, code, &attempt).await?;
+        return Ok((GITHUB.into(), github_id(&user_res)?));
+    }
+
+    let provider = oidc::provider(provider)
+        .ok_or_else(|| error("", Status::NotFound, "Unknown OAuth provider"))?;
+    let attempt = take_attempt(cookies, &provider.name, state).await?;
+    let claims = oidc::login(provider, &code, &attempt)
+        .await
+        .map_err(|e| error(e, Status::Unauthorized, "Failed to validate OAuth code"))?;
+    Ok((provider.name.clone(), claims.sub))
+}
+
+/// Login endpoint for GitHub OAuth
+///
+/// The returned user variable has an id of null, iff the GitHub user hasn't
+/// registered yet. If has user has registered previously, a valid id is returned.
+/// In every other case (e.g. internal server error), nothing is returned.
+///
+/// # Arguments
+///
+/// * `code` - The OAuth code recevied by GitHub.
+/// * `state` - The state of the login attempt, as returned by GitHub.
+#[openapi(tag = "Login")]
+#[post("/oauth/github?<code>&<state>")]
+async fn login_github(
+    db: Db,
+    github: &GitHubState,
+    code: String,
+    state: String,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+) -> ApiResult<RegistratedOrNewUser> {
+    let github = github
+        .inner()
+        .as_ref()
+        .ok_or_else(|| error("", Status::NotFound, "Unknown OAuth provider"))?;
+    let attempt = take_attempt(cookies, GITHUB, &state).await?;
+    let user_res = github_user(github, code, &attempt).await?;
+    let github_id = github_id(&user_res)?;
+
+    if let Some(identity) = OAuthIdentity::find(&db, GITHUB.into(), github_id.clone()).await {
+        let outcome = start_session(&db, cookies, ua, identity.user_id).await?;
+        Ok(RegistratedOrNewUser::Registrated(outcome))
     } else {
-        session::set_github_id(cookies, github_id).await;
+        session::set_oauth_registrar(cookies, OAuthRegistrar::new(GITHUB, github_id)).await;
         let mut iter = user_res
             .get("name")
             .ok_or_else(|| error("", Status::InternalServerError, ""))?
@@ -158,6 +341,289 @@ async fn login_github(
     }
 }
 
+/// Login endpoint for OpenID Connect providers, configured with `OAUTH_PROVIDERS`
+///
+/// Like the GitHub login, either the registered user is returned, or the data of the ID token to
+/// prefill the registration.
+///
+/// # Arguments
+///
+/// * `provider` - The name of the provider
+/// * `code` - The OAuth code received by the provider
+/// * `state` - The state of the login attempt, as returned by the provider
+#[openapi(tag = "Login")]
+#[post("/oauth/<provider>?<code>&<state>", rank = 2)]
+async fn login_oidc(
+    db: Db,
+    provider: String,
+    code: String,
+    state: String,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+) -> ApiResult<RegistratedOrNewUser> {
+    let provider = oidc::provider(&provider)
+        .ok_or_else(|| error("", Status::NotFound, "Unknown OAuth provider"))?;
+    let attempt = take_attempt(cookies, &provider.name, &state).await?;
+    let claims = oidc::login(provider, &code, &attempt)
+        .await
+        .map_err(|e| error(e, Status::Unauthorized, "Failed to validate OAuth code"))?;
+
+    if let Some(identity) =
+        OAuthIdentity::find(&db, provider.name.clone(), claims.sub.clone()).await
+    {
+        let outcome = start_session(&db, cookies, ua, identity.user_id).await?;
+        return Ok(RegistratedOrNewUser::Registrated(outcome));
+    }
+
+    session::set_oauth_registrar(cookies, OAuthRegistrar::new(&provider.name, claims.sub)).await;
+    let name = claims.name.unwrap_or_default();
+    let mut iter = name.splitn(2, ' ');
+    Ok(RegistratedOrNewUser::New(Json(NewUser {
+        firstname: claims
+            .given_name
+            .unwrap_or_else(|| iter.next().unwrap_or("").into()),
+        lastname: claims
+            .family_name
+            .unwrap_or_else(|| iter.next().unwrap_or("").into()),
+        birthday: NaiveDate::from_yo(1970, 1),
+        gender: Gender::Male,
+        email: claims.email.unwrap_or_default(),
+    })))
+}
+
+/// Register a new user with email and password. The user is logged in right away.
+#[openapi(tag = "Login")]
+#[post("/register", data = "<new_credentials>")]
+async fn register_password(
+    db: Db,
+    mailer: &MailerState,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+    new_credentials: Json<NewCredentials>,
+) -> ApiResult<Json<AuthUser>> {
+    let user = Credentials::register(&db, new_credentials.into_inner()).await?;
+
+    // add administrator rights if user was first user
+    if user.is_first(&db).await {
+        user.attach_role(&db, Role::Admin)
+            .await
+            .map_err(|e| error(e, Status::InternalServerError, ""))?;
+    }
+
+    // send the link to verify the email address, the user may request another one if it fails
+    EmailVerification::request(&db, mailer, user.id).await.ok();
+
+    save_session(&db, cookies, ua, user.id).await.map(Json)
+}
+
+/// Login endpoint for email and password. Repeated failed logins lock the account for a while.
+#[openapi(tag = "Login")]
+#[post("/password", data = "<login>")]
+async fn login_password(
+    db: Db,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+    login: Json<LoginCredentials>,
+) -> ApiResult<LoginOutcome> {
+    let user_id = Credentials::verify(&db, login.into_inner()).await?;
+    start_session(&db, cookies, ua, user_id).await
+}
+
+/// Send a link to log in to a registered email address. The response is the same for unknown
+/// addresses. Each address gets at most 3 links within 15 minutes, each client at most 10 links
+/// per hour.
+#[openapi(tag = "Login")]
+#[post("/email", data = "<request>")]
+async fn login_email_request(
+    db: Db,
+    mailer: &MailerState,
+    limits: &LoginLimitsState,
+    ip: ClientIp,
+    request: Json<LoginLinkRequest>,
+) -> ApiResult<()> {
+    request.is_valid()?;
+    let email = request.email.trim().to_lowercase();
+    if !limits.login_links_per_ip.hit(&ip.0) || !limits.login_links_per_email.hit(&email) {
+        return Err(error(
+            "",
+            Status::TooManyRequests,
+            "Too many login links requested, please try again later",
+        ));
+    }
+    LoginLink::send(&db, mailer, request.into_inner()).await
+}
+
+/// Log in with the token of a link sent by `POST /users/login/email`. Links are valid for 15
+/// minutes and work only once. Users with two-factor authentication still need a second factor.
+#[openapi(tag = "Login")]
+#[post("/email/confirmation", data = "<token>")]
+async fn login_email(
+    db: Db,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+    token: Json<LoginLinkToken>,
+) -> ApiResult<LoginOutcome> {
+    let user_id = LoginLink::redeem(&db, token.into_inner()).await?;
+    start_session(&db, cookies, ua, user_id).await
+}
+
+/// Complete a login, that awaits a second factor, with a code of the authenticator app or a
+/// recovery code. After 5 wrong codes in a row, the second factor of the user is locked for 15
+/// minutes.
+#[openapi(tag = "Login")]
+#[post("/second-factor", data = "<code>")]
+async fn login_second_factor(
+    db: Db,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+    code: Json<TotpCode>,
+) -> ApiResult<Json<AuthUser>> {
+    let pending = session::get_pending_login(cookies)
+        .await
+        .ok_or_else(|| error("", Status::Unauthorized, "No login awaits a second factor"))?;
+    Totp::verify(&db, pending.user_id, code.into_inner()).await?;
+    session::finish_pending_login(cookies).await;
+    save_session(&db, cookies, ua, pending.user_id)
+        .await
+        .map(Json)
+}
+
+fn relying_party() -> ApiResult<RelyingParty> {
+    RelyingParty::from_config().map_err(|e| error(e, Status::InternalServerError, ""))
+}
+
+/// Find the passkey ceremony the authenticator responded to. Ceremonies are used only once.
+async fn take_ceremony(cookies: &CookieJar<'_>, ceremony: Ceremony) -> ApiResult<PendingCeremony> {
+    session::take_webauthn_ceremony(cookies)
+        .await
+        .filter(|pending| pending.ceremony == ceremony)
+        .ok_or_else(|| {
+            error(
+                "",
+                Status::Unauthorized,
+                "Invalid or expired passkey challenge",
+            )
+        })
+}
+
+/// Start a login with a passkey. Returns the options for `navigator.credentials.get()`, the
+/// challenge is valid for 5 minutes.
+///
+/// # Arguments
+///
+/// * `email` - Limits the login to the passkeys of this user. Without it, the browser offers
+///   every passkey it stores for this site. Addresses without passkeys get a decoy credential,
+///   so the options look alike for registered and unknown addresses.
+#[openapi(tag = "Login")]
+#[post("/passkey/options?<email>")]
+async fn login_passkey_options(
+    db: Db,
+    cookies: &CookieJar<'_>,
+    email: Option<String>,
+) -> ApiResult<Json<RequestOptions>> {
+    let relying_party = relying_party()?;
+    let allow_credentials = match email {
+        Some(email) => Passkey::login_descriptors(&db, email).await,
+        None => Vec::new(),
+    };
+    let pending = PendingCeremony::new(Ceremony::Login);
+    let options = relying_party.request_options(&pending, allow_credentials);
+    session::set_webauthn_ceremony(cookies, pending).await;
+    Ok(Json(options))
+}
+
+/// Login endpoint for passkeys, with the response of `navigator.credentials.get()`
+///
+/// If the authenticator verified the user, like by PIN or biometrics, the passkey counts as both
+/// factors. Otherwise users with two-factor authentication still have to enter a second factor.
+#[openapi(tag = "Login")]
+#[post("/passkey", data = "<credential>")]
+async fn login_passkey(
+    db: Db,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+    credential: Json<AuthenticationResponse>,
+) -> ApiResult<LoginOutcome> {
+    let pending = take_ceremony(cookies, Ceremony::Login).await?;
+    let (user_id, assertion) = Passkey::authenticate(
+        &db,
+        relying_party()?,
+        credential.into_inner(),
+        pending.challenge,
+        None,
+    )
+    .await?;
+
+    if assertion.user_verified {
+        save_session(&db, cookies, ua, user_id)
+            .await
+            .map(|user| LoginOutcome::LoggedIn(Json(user)))
+    } else {
+        start_session(&db, cookies, ua, user_id).await
+    }
+}
+
+/// Start a passkey ceremony for a login, that awaits a second factor. Returns the options for
+/// `navigator.credentials.get()`.
+#[openapi(tag = "Login")]
+#[post("/second-factor/passkey/options")]
+async fn second_factor_passkey_options(
+    db: Db,
+    cookies: &CookieJar<'_>,
+) -> ApiResult<Json<RequestOptions>> {
+    let pending_login = session::get_pending_login(cookies)
+        .await
+        .ok_or_else(|| error("", Status::Unauthorized, "No login awaits a second factor"))?;
+    let relying_party = relying_party()?;
+    let pending = PendingCeremony::new(Ceremony::SecondFactor(pending_login.user_id));
+    let options = relying_party.request_options(
+        &pending,
+        Passkey::descriptors(&db, pending_login.user_id).await,
+    );
+    session::set_webauthn_ceremony(cookies, pending).await;
+    Ok(Json(options))
+}
+
+/// Complete a login, that awaits a second factor, with a passkey of the user
+#[openapi(tag = "Login")]
+#[post("/second-factor/passkey", data = "<credential>")]
+async fn login_second_factor_passkey(
+    db: Db,
+    cookies: &CookieJar<'_>,
+    ua: UserAgent,
+    credential: Json<AuthenticationResponse>,
+) -> ApiResult<Json<AuthUser>> {
+    let pending_login = session::get_pending_login(cookies)
+        .await
+        .ok_or_else(|| error("", Status::Unauthorized, "No login awaits a second factor"))?;
+    let pending = take_ceremony(cookies, Ceremony::SecondFactor(pending_login.user_id)).await?;
+    Passkey::authenticate(
+        &db,
+        relying_party()?,
+        credential.into_inner(),
+        pending.challenge,
+        Some(pending_login.user_id),
+    )
+    .await?;
+    session::finish_pending_login(cookies).await;
+    save_session(&db, cookies, ua, pending_login.user_id)
+        .await
+        .map(Json)
+}
+
 pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
-    openapi_get_routes_spec![settings: oauth_list, login_github]
+    openapi_get_routes_spec![
+        settings: oauth_list,
+        login_github,
+        login_oidc,
+        register_password,
+        login_password,
+        login_email_request,
+        login_email,
+        login_second_factor,
+        login_passkey_options,
+        login_passkey,
+        second_factor_passkey_options,
+        login_second_factor_passkey
+    ]
 }
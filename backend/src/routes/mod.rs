@@ -1,4 +1,6 @@
-use rocket::http::Status;
+use crate::db::models::AuthUser;
+use crate::CONFIG;
+use rocket::http::{MediaType, Status};
 use rocket::request::{FromRequest, Outcome};
 use rocket::response::status;
 use rocket::serde::json::Json;
@@ -7,16 +9,29 @@ use rocket::Request;
 use rocket::{Build, Rocket};
 use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::mount_endpoints_and_merged_docs;
+use rocket_okapi::okapi::openapi3::{MediaType as OpenApiMediaType, Responses};
 use rocket_okapi::okapi::schemars;
 use rocket_okapi::okapi::schemars::JsonSchema;
 use rocket_okapi::request::OpenApiFromRequest;
 use rocket_okapi::request::RequestHeaderInput;
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::add_content_response;
+use std::net::IpAddr;
 
 mod addresses;
+mod api_tokens;
+mod carriers;
+mod companions;
 mod docs;
+mod email_verification;
+mod identities;
 mod login;
 mod offers;
+mod passkeys;
+mod payments;
+mod refunds;
 mod sessions;
+mod two_factor;
 mod users;
 
 #[derive(Deserialize, Serialize, JsonSchema)]
@@ -42,6 +57,20 @@ where
     )
 }
 
+/// Login methods, second factors, API tokens and the email address are only managed within a
+/// session, so a leaked API token can't take over the account, no matter its scopes
+pub fn require_session(actor: &AuthUser) -> ApiResult<()> {
+    if actor.token_scopes.is_some() {
+        Err(error(
+            "",
+            Status::Forbidden,
+            "API tokens can't manage the login of a user",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
 #[derive(FromForm, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct OfferFilter {
@@ -49,6 +78,9 @@ pub struct OfferFilter {
     pub departure_icao: Option<String>,
     #[field(name = "arrivalIcao")]
     pub arrival_icao: Option<String>,
+    /// Operating or codeshare flight number of any flight of the offer, like `LH400`
+    #[field(name = "flightNumber")]
+    pub flight_number: Option<String>,
 }
 
 /// This schema provides redirect url's for all configured OAuth providers. If a provider is not
@@ -58,6 +90,17 @@ pub struct OfferFilter {
 pub struct OAuthProviders {
     /// Redirect url for GitHub OAuth
     pub github: Option<String>,
+    /// All configured providers, including GitHub
+    pub providers: Vec<OAuthProvider>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "rocket::serde")]
+pub struct OAuthProvider {
+    /// Name of the provider, which is part of the login endpoint `/v1/users/login/oauth/<name>`
+    pub name: String,
+    /// Redirect url for the login with the provider
+    pub url: String,
 }
 
 #[derive(Deserialize, Serialize, JsonSchema)]
@@ -66,6 +109,7 @@ pub struct GitHubAccessTokenRequest<'a> {
     pub client_id: &'a str,
     pub client_secret: &'a str,
     pub code: String,
+    pub code_verifier: &'a str,
 }
 
 #[derive(Deserialize, Serialize, JsonSchema)]
@@ -107,6 +151,116 @@ impl<'r> OpenApiFromRequest<'r> for UserAgent {
     }
 }
 
+/// IP address of the client. The `X-Real-IP` header is only honoured, if the connection comes
+/// from one of the `TRUSTED_PROXIES`, since any client is able to send it.
+pub struct ClientIp(pub String);
+
+/// The address of the connection, unless it is a trusted proxy, that reported the real one
+fn client_ip(
+    remote: Option<IpAddr>,
+    real_ip: Option<IpAddr>,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    match remote {
+        Some(remote) if trusted_proxies.contains(&remote) => real_ip.or(Some(remote)),
+        remote => remote,
+    }
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for ClientIp {
+    type Error = String;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        let remote = request.remote().map(|remote| remote.ip());
+        match client_ip(remote, request.real_ip(), &CONFIG.trusted_proxies) {
+            Some(ip) => Outcome::Success(ClientIp(ip.to_string())),
+            None => Outcome::Failure((
+                Status::BadRequest,
+                "IP address of the client is unknown".into(),
+            )),
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for ClientIp {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// Response format requested by the client using the `Accept` header. Used by endpoints, that
+/// are able to render their data as document.
+#[derive(PartialEq)]
+pub enum ResponseFormat {
+    Json,
+    Pdf,
+}
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for ResponseFormat {
+    type Error = String;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.accept() {
+            Some(accept) if accept.preferred().media_type() == &MediaType::PDF => {
+                Outcome::Success(ResponseFormat::Pdf)
+            }
+            _ => Outcome::Success(ResponseFormat::Json),
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for ResponseFormat {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// A rendered PDF document
+#[derive(Responder)]
+#[response(content_type = "application/pdf")]
+pub struct Pdf(pub Vec<u8>);
+
+impl OpenApiResponderInner for Pdf {
+    fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        add_content_response(
+            &mut responses,
+            200,
+            "application/pdf",
+            OpenApiMediaType::default(),
+        )?;
+        Ok(responses)
+    }
+}
+
+/// A rendered iCalendar document
+#[derive(Responder)]
+#[response(content_type = "text/calendar")]
+pub struct ICalendar(pub String);
+
+impl OpenApiResponderInner for ICalendar {
+    fn responses(_gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Responses::default();
+        add_content_response(
+            &mut responses,
+            200,
+            "text/calendar",
+            OpenApiMediaType::default(),
+        )?;
+        Ok(responses)
+    }
+}
+
 pub fn init() -> Rocket<Build> {
     let mut rocket = rocket::build().attach(docs::stage());
 
@@ -116,10 +270,40 @@ pub fn init() -> Rocket<Build> {
         rocket, "/v1".to_owned(), openapi_settings,
         "/users" => users::get_routes_and_docs(&openapi_settings),
         "/users" => addresses::get_routes_and_docs(&openapi_settings),
+        "/users" => companions::get_routes_and_docs(&openapi_settings),
         "/users" => sessions::get_routes_and_docs(&openapi_settings),
+        "/users" => identities::get_routes_and_docs(&openapi_settings),
+        "/users" => two_factor::get_routes_and_docs(&openapi_settings),
+        "/users" => passkeys::get_routes_and_docs(&openapi_settings),
+        "/users" => api_tokens::get_routes_and_docs(&openapi_settings),
+        "/users" => email_verification::get_routes_and_docs(&openapi_settings),
         "/offers" => offers::get_routes_and_docs(&openapi_settings),
+        "/carriers" => carriers::get_routes_and_docs(&openapi_settings),
+        "/payments" => payments::get_routes_and_docs(&openapi_settings),
+        "/refunds" => refunds::get_routes_and_docs(&openapi_settings),
         "/users/login" => login::get_routes_and_docs(&openapi_settings),
     };
 
     rocket
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_ip_of_trusted_proxy() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let spoofed: IpAddr = "198.51.100.1".parse().unwrap();
+
+        assert_eq!(Some(client), client_ip(Some(proxy), Some(client), &[proxy]));
+        assert_eq!(Some(proxy), client_ip(Some(proxy), None, &[proxy]));
+        assert_eq!(
+            Some(client),
+            client_ip(Some(client), Some(spoofed), &[proxy])
+        );
+        assert_eq!(Some(client), client_ip(Some(client), Some(spoofed), &[]));
+        assert_eq!(None, client_ip(None, Some(spoofed), &[proxy]));
+    }
+}
@@ -1,9 +1,13 @@
 use super::OfferFilter;
 use crate::db::models::{
-    AdminRole, AuthUser, Booking, Flight, FlightOffer, FlightOfferWithOccupancy, NewFlight,
-    NewFlightOffer,
+    rebook_offer, rebooking_window, AdminRole, Ancillary, AncillaryAvailability, AuthUser, Booking,
+    BookingWithAncillaries, CheckIn, Flight, FlightOffer, FlightOfferWithOccupancy,
+    FlightStatusUpdate, FlightWithCodeshares, ManifestEntry, NewAncillary, NewBooking, NewFlight,
+    NewFlightOffer, NewPayment, OverbookingPolicy, OversoldOffer, Payment, RebookingReport, Refund,
+    RefundReason, User,
 };
 use crate::db::Db;
+use crate::payment::PaymentState;
 use crate::routes::{error, ApiResult};
 use rocket::http::Status;
 use rocket::serde::json::Json;
@@ -29,6 +33,14 @@ async fn create_offer(
         .ok_or_else(|| error("", Status::InternalServerError, ""))
 }
 
+/// Cancel a flight offer and all of its bookings. Refunds for every paid booking are created and
+/// must be approved afterwards.
+#[openapi(tag = "Flights")]
+#[delete("/<id>")]
+async fn cancel_offer(_r: AdminRole, db: Db, id: i32) -> ApiResult<Json<Vec<Refund>>> {
+    Booking::cancel_offer(&db, id).await.map(Json)
+}
+
 #[openapi(tag = "Flights")]
 #[get("/?<filter..>")]
 async fn read_offer(
@@ -39,28 +51,127 @@ async fn read_offer(
     Ok(Json(FlightOfferWithOccupancy::get_all(&db, filter).await))
 }
 
+/// List all offers with more booked seats than physical seats, whose check-in has been opened
+#[openapi(tag = "Flights")]
+#[get("/oversold")]
+async fn read_oversold_offers(_r: AdminRole, db: Db) -> ApiResult<Json<Vec<OversoldOffer>>> {
+    Ok(Json(FlightOfferWithOccupancy::oversold(&db).await))
+}
+
+/// Configure how many seats of an offer are sold above its capacity
+#[openapi(tag = "Flights")]
+#[put("/<id>/overbooking", data = "<policy>")]
+async fn update_offer_overbooking(
+    _r: AdminRole,
+    db: Db,
+    id: i32,
+    policy: Json<OverbookingPolicy>,
+) -> ApiResult<()> {
+    FlightOffer::set_overbooking(&db, id, policy.into_inner()).await
+}
+
 #[openapi(tag = "Flights")]
 #[get("/raw")]
 async fn read_offer_raw(_r: AdminRole, db: Db) -> ApiResult<Json<Vec<FlightOffer>>> {
     Ok(Json(FlightOffer::get_all(&db).await))
 }
 
+/// Book seats of an offer. If passengers are given, the seats are derived from their age:
+/// children below 12 years pay the child price, infants below 2 years travel on the lap of an adult
+/// without a seat. Ancillaries of the offer may be bought along with the seats.
 #[openapi(tag = "Flights")]
-#[post("/<id>/bookings?<seats>")]
-async fn create_offer_booking(actor: AuthUser, db: Db, id: i32, seats: i32) -> ApiResult<()> {
-    Booking::create(&db, actor.id, id, seats).await
+#[post("/<id>/bookings?<seats>&<children>&<infants>", data = "<new_booking>")]
+async fn create_offer_booking(
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    seats: Option<i32>,
+    children: Option<i32>,
+    infants: Option<i32>,
+    new_booking: Option<Json<NewBooking>>,
+) -> ApiResult<()> {
+    if !User::is_verified(&db, actor.id).await {
+        return Err(error(
+            "",
+            Status::Forbidden,
+            "Email address must be verified before booking",
+        ));
+    }
+    let new_booking = new_booking.map(Json::into_inner).unwrap_or_default();
+    Booking::create(&db, actor.id, id, seats, children, infants, new_booking).await
+}
+
+/// Pay for the pending booking of the current user. The booking is confirmed as soon as the
+/// payment has been captured.
+#[openapi(tag = "Flights")]
+#[post("/<id>/bookings/payment", data = "<new_payment>")]
+async fn pay_offer_booking(
+    actor: AuthUser,
+    db: Db,
+    gateway: &PaymentState,
+    id: i32,
+    new_payment: Json<NewPayment>,
+) -> ApiResult<Json<Payment>> {
+    Booking::pay(&db, gateway, actor.id, id, new_payment.into_inner())
+        .await
+        .map(Json)
+}
+
+/// Cancel the booking of the current user. Returns the refund the user is entitled to according
+/// to the fare rules, if any.
+#[openapi(tag = "Flights")]
+#[delete("/<id>/bookings")]
+async fn cancel_offer_booking(actor: AuthUser, db: Db, id: i32) -> ApiResult<Json<Option<Refund>>> {
+    Booking::cancel(&db, actor.id, id, RefundReason::CustomerCancellation)
+        .await
+        .map(Json)
 }
 
 #[openapi(tag = "Flights")]
 #[get("/<id>/bookings")]
-async fn read_offer_bookings(_r: AdminRole, db: Db, id: i32) -> ApiResult<Json<Vec<Booking>>> {
-    Ok(Json(Booking::all_from_offer(&db, id).await))
+async fn read_offer_bookings(
+    _r: AdminRole,
+    db: Db,
+    id: i32,
+) -> ApiResult<Json<Vec<BookingWithAncillaries>>> {
+    let bookings = Booking::all_from_offer(&db, id).await;
+    BookingWithAncillaries::from_bookings(&db, bookings)
+        .await
+        .map(Json)
+}
+
+/// Offer a service like checked bags or meals, that customers buy along with their seats
+#[openapi(tag = "Flights")]
+#[post("/<id>/ancillaries", data = "<new_ancillary>")]
+async fn create_ancillary(
+    _r: AdminRole,
+    db: Db,
+    id: i32,
+    new_ancillary: Json<NewAncillary>,
+) -> ApiResult<Json<Ancillary>> {
+    Ancillary::create(&db, id, new_ancillary.into_inner())
+        .await
+        .map(Json)
+}
+
+#[openapi(tag = "Flights")]
+#[get("/<id>/ancillaries")]
+async fn read_ancillaries(
+    _actor: AuthUser,
+    db: Db,
+    id: i32,
+) -> ApiResult<Json<Vec<AncillaryAvailability>>> {
+    Ok(Json(Ancillary::all_from_offer(&db, id).await))
 }
 
 #[openapi(tag = "Flights")]
 #[get("/<id>/flights")]
-async fn read_flights(_actor: AuthUser, db: Db, id: i32) -> ApiResult<Json<Vec<Flight>>> {
-    Ok(Json(Flight::all_from_offer(&db, id).await))
+async fn read_flights(
+    _actor: AuthUser,
+    db: Db,
+    id: i32,
+) -> ApiResult<Json<Vec<FlightWithCodeshares>>> {
+    Ok(Json(Flight::all_from_offer_with_codeshares(&db, id).await))
 }
 
 #[openapi(tag = "Flights")]
@@ -85,22 +196,70 @@ async fn create_flights(
         flight.is_valid()?;
     }
 
-    FlightOffer::save_flights(&db, id, new_flights)
+    FlightOffer::save_flights(&db, id, new_flights.into_inner()).await
+}
+
+/// Update the operational status of a flight. Cancelling a flight flags every booking of the
+/// offer as disrupted, so it can be rebooked or cancelled with a full refund.
+#[openapi(tag = "Flights")]
+#[put("/<id>/flights/<flight_id>/status", data = "<update>")]
+async fn update_flight_status(
+    _r: AdminRole,
+    db: Db,
+    id: i32,
+    flight_id: i32,
+    update: Json<FlightStatusUpdate>,
+) -> ApiResult<Json<Flight>> {
+    Flight::update_status(&db, id, flight_id, update.into_inner())
         .await
-        .map_or_else(
-            |e| Err(error(e, Status::InternalServerError, "")),
-            |_res| Ok(()),
-        )
+        .map(Json)
+}
+
+/// Move every disrupted booking of an offer to an alternative offer on the same route, that
+/// departs at most `hours` (1 to 168, default 48) before or after the offer and has enough free
+/// seats
+#[openapi(tag = "Flights")]
+#[post("/<id>/rebookings?<hours>")]
+async fn rebook_offer_bookings(
+    _r: AdminRole,
+    db: Db,
+    id: i32,
+    hours: Option<i64>,
+) -> ApiResult<Json<RebookingReport>> {
+    let window = rebooking_window(hours)?;
+    rebook_offer(&db, id, window).await.map(Json)
+}
+
+/// List the passengers of a flight, along with their check-in
+#[openapi(tag = "Flights")]
+#[get("/<id>/flights/<flight_id>/manifest")]
+async fn read_flight_manifest(
+    _r: AdminRole,
+    db: Db,
+    id: i32,
+    flight_id: i32,
+) -> ApiResult<Json<Vec<ManifestEntry>>> {
+    CheckIn::manifest(&db, id, flight_id).await.map(Json)
 }
 
 pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
     openapi_get_routes_spec![
         settings: create_offer,
+        cancel_offer,
         read_offer,
         read_offer_raw,
+        read_oversold_offers,
+        update_offer_overbooking,
         create_offer_booking,
+        pay_offer_booking,
+        cancel_offer_booking,
         read_offer_bookings,
+        create_ancillary,
+        read_ancillaries,
         create_flights,
-        read_flights
+        read_flights,
+        update_flight_status,
+        rebook_offer_bookings,
+        read_flight_manifest
     ]
 }
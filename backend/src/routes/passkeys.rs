@@ -0,0 +1,112 @@
+use crate::db::models::{AuthUser, NewPasskey, Passkey};
+use crate::db::Db;
+use crate::oso::{OsoAction, OsoState};
+use crate::routes::{error, require_session, ApiResult};
+use crate::session;
+use crate::webauthn::{self, Ceremony, CreationOptions, PendingCeremony, RelyingParty, UserEntity};
+use rocket::http::{CookieJar, Status};
+use rocket::serde::json::Json;
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+#[openapi(tag = "Passkeys")]
+#[get("/<id>/passkeys")]
+async fn read(oso: &OsoState, actor: AuthUser, db: Db, id: i32) -> ApiResult<Json<Vec<Passkey>>> {
+    if oso.is_allowed(actor, OsoAction::Read, Passkey::dummy_for_user(id)) {
+        Ok(Json(Passkey::all_from_user(&db, id).await))
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Start the registration of a passkey. Returns the options for `navigator.credentials.create()`,
+/// the challenge is valid for 5 minutes.
+#[openapi(tag = "Passkeys")]
+#[post("/<id>/passkeys/options")]
+async fn create_options(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    cookies: &CookieJar<'_>,
+    id: i32,
+) -> ApiResult<Json<CreationOptions>> {
+    require_session(&actor)?;
+    let user = UserEntity {
+        id: webauthn::encode(actor.id.to_string().as_bytes()),
+        name: actor.email.clone(),
+        display_name: format!("{} {}", actor.firstname, actor.lastname),
+    };
+    if !oso.is_allowed(actor, OsoAction::Create, Passkey::dummy_for_user(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let relying_party =
+        RelyingParty::from_config().map_err(|e| error(e, Status::InternalServerError, ""))?;
+    let pending = PendingCeremony::new(Ceremony::Registration(id));
+    let options =
+        relying_party.creation_options(&pending, user, Passkey::descriptors(&db, id).await);
+    session::set_webauthn_ceremony(cookies, pending).await;
+    Ok(Json(options))
+}
+
+/// Register a passkey with the response of `navigator.credentials.create()`
+#[openapi(tag = "Passkeys")]
+#[post("/<id>/passkeys", data = "<new_passkey>")]
+async fn create(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    cookies: &CookieJar<'_>,
+    id: i32,
+    new_passkey: Json<NewPasskey>,
+) -> ApiResult<Json<Passkey>> {
+    require_session(&actor)?;
+    if !oso.is_allowed(actor, OsoAction::Create, Passkey::dummy_for_user(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let pending = session::take_webauthn_ceremony(cookies)
+        .await
+        .filter(|pending| pending.ceremony == Ceremony::Registration(id))
+        .ok_or_else(|| {
+            error(
+                "",
+                Status::BadRequest,
+                "Invalid or expired passkey challenge",
+            )
+        })?;
+    let relying_party =
+        RelyingParty::from_config().map_err(|e| error(e, Status::InternalServerError, ""))?;
+    Passkey::create(
+        &db,
+        relying_party,
+        id,
+        new_passkey.into_inner(),
+        pending.challenge,
+    )
+    .await
+    .map(Json)
+}
+
+/// Remove a passkey. The last login method of a user can't be removed.
+#[openapi(tag = "Passkeys")]
+#[delete("/<id>/passkeys/<passkey_id>")]
+async fn delete(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    passkey_id: i32,
+) -> ApiResult<()> {
+    require_session(&actor)?;
+    if oso.is_allowed(actor, OsoAction::Delete, Passkey::dummy_for_user(id)) {
+        Passkey::delete(&db, id, passkey_id).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: read, create_options, create, delete]
+}
@@ -0,0 +1,61 @@
+use crate::db::models::Booking;
+use crate::db::Db;
+use crate::payment::PaymentState;
+use crate::routes::{error, ApiResult};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+/// Signature of a webhook payload, sent by the payment provider
+struct WebhookSignature(String);
+
+#[rocket::async_trait]
+impl<'a> FromRequest<'a> for WebhookSignature {
+    type Error = String;
+
+    async fn from_request(request: &'a Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one("x-signature") {
+            Some(signature) => Outcome::Success(WebhookSignature(signature.to_string())),
+            None => Outcome::Failure((
+                Status::Unauthorized,
+                "X-Signature header must be defined".into(),
+            )),
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for WebhookSignature {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// Webhook endpoint for the payment provider, used to notify about asynchronous state changes of
+/// payments
+#[openapi(tag = "Payments")]
+#[post("/webhook", data = "<payload>")]
+async fn webhook(
+    db: Db,
+    gateway: &PaymentState,
+    signature: WebhookSignature,
+    payload: String,
+) -> ApiResult<()> {
+    let event = gateway
+        .verify_webhook(payload.as_bytes(), &signature.0)
+        .map_err(|e| error(e, Status::Unauthorized, "Invalid webhook"))?;
+
+    Booking::apply_webhook_event(&db, event).await
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: webhook]
+}
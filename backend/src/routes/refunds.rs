@@ -0,0 +1,51 @@
+use crate::db::models::{AdminRole, NewRefund, Refund, RefundStatus};
+use crate::db::Db;
+use crate::payment::PaymentState;
+use crate::routes::ApiResult;
+use rocket::serde::json::Json;
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+/// Issue a manual refund for a booking. The amount may not exceed the amount, that hasn't been
+/// refunded yet.
+#[openapi(tag = "Refunds")]
+#[post("/", data = "<new_refund>")]
+async fn create(
+    _r: AdminRole,
+    db: Db,
+    gateway: &PaymentState,
+    new_refund: Json<NewRefund>,
+) -> ApiResult<Json<Refund>> {
+    Refund::create_manual(&db, gateway, new_refund.into_inner())
+        .await
+        .map(Json)
+}
+
+#[openapi(tag = "Refunds")]
+#[get("/?<status>")]
+async fn read(_r: AdminRole, db: Db, status: Option<RefundStatus>) -> ApiResult<Json<Vec<Refund>>> {
+    Ok(Json(Refund::get_all(&db, status).await))
+}
+
+/// Approve a pending refund and pay back the money with the payment provider
+#[openapi(tag = "Refunds")]
+#[post("/<id>/approve")]
+async fn approve(
+    _r: AdminRole,
+    db: Db,
+    gateway: &PaymentState,
+    id: i32,
+) -> ApiResult<Json<Refund>> {
+    Refund::approve(&db, gateway, id).await.map(Json)
+}
+
+#[openapi(tag = "Refunds")]
+#[post("/<id>/reject")]
+async fn reject(_r: AdminRole, db: Db, id: i32) -> ApiResult<Json<Refund>> {
+    Refund::reject(&db, id).await.map(Json)
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: create, read, approve, reject]
+}
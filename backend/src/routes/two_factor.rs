@@ -0,0 +1,82 @@
+use crate::db::models::{AuthUser, RecoveryCodes, Role, Totp, TotpCode, TotpEnrolment, TotpStatus};
+use crate::db::Db;
+use crate::oso::{OsoAction, OsoState};
+use crate::routes::{error, require_session, ApiResult};
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use rocket_okapi::{
+    okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
+};
+
+/// Returns whether two-factor authentication is enabled and how many recovery codes are left
+#[openapi(tag = "Two-Factor Authentication")]
+#[get("/<id>/totp")]
+async fn read(oso: &OsoState, actor: AuthUser, db: Db, id: i32) -> ApiResult<Json<TotpStatus>> {
+    if oso.is_allowed(actor, OsoAction::Read, Totp::dummy_for_user(id)) {
+        Totp::status(&db, id).await.map(Json)
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Start the enrolment of an authenticator app. Returns the secret along with a provisioning URI
+/// and its QR code. Starting again replaces a secret, that hasn't been confirmed yet.
+#[openapi(tag = "Two-Factor Authentication")]
+#[post("/<id>/totp")]
+async fn create(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+) -> ApiResult<Json<TotpEnrolment>> {
+    require_session(&actor)?;
+    let account = actor.email.clone();
+    if oso.is_allowed(actor, OsoAction::Create, Totp::dummy_for_user(id)) {
+        Totp::enrol(&db, id, account).await.map(Json)
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Enable two-factor authentication with the first code of the authenticator app. Returns the
+/// recovery codes, which are only shown once.
+#[openapi(tag = "Two-Factor Authentication")]
+#[post("/<id>/totp/confirmation", data = "<code>")]
+async fn confirm(
+    oso: &OsoState,
+    actor: AuthUser,
+    db: Db,
+    id: i32,
+    code: Json<TotpCode>,
+) -> ApiResult<Json<RecoveryCodes>> {
+    require_session(&actor)?;
+    if oso.is_allowed(actor, OsoAction::Create, Totp::dummy_for_user(id)) {
+        Totp::confirm(&db, id, code.into_inner()).await.map(Json)
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Disable two-factor authentication. Admins may reset it for users, that lost their
+/// authenticator app, but can't disable their own.
+#[openapi(tag = "Two-Factor Authentication")]
+#[delete("/<id>/totp")]
+async fn delete(oso: &OsoState, actor: AuthUser, db: Db, id: i32) -> ApiResult<()> {
+    require_session(&actor)?;
+    if actor.id == id && actor.roles.contains(&Role::Admin) {
+        return Err(error(
+            "",
+            Status::Conflict,
+            "Two-factor authentication is required for administrators",
+        ));
+    }
+    if oso.is_allowed(actor, OsoAction::Delete, Totp::dummy_for_user(id)) {
+        Totp::disable(&db, id).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, OpenApi) {
+    openapi_get_routes_spec![settings: read, create, confirm, delete]
+}
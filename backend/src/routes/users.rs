@@ -1,13 +1,21 @@
 use crate::db::models::{
-    AdminRole, AuthUser, Booking, GitHubOAuthUser, GithubOAuthRegistrar, NewUser, Role, Session,
-    User,
+    AdminRole, AncillaryPurchase, AuthUser, BookedAncillary, Booking, BookingWithAncillaries,
+    CalendarSubscription, CheckIn, EmailVerification, Invoice, LoyaltyAccount, LoyaltyRedemption,
+    LoyaltyTransaction, NewCheckIn, NewTravelDocument, NewUser, OAuthRegistrar, Passenger,
+    PassengerDetails, Role, Session, Ticket, TravelDocument, User,
 };
 use crate::db::Db;
+use crate::mail::MailerState;
 use crate::oso::{OsoAction, OsoState};
-use crate::routes::{error, ApiResult, UserAgent};
+use crate::payment::PaymentState;
+use crate::routes::{error, require_session, ApiResult, ICalendar, Pdf, ResponseFormat, UserAgent};
 use crate::session;
 use rocket::http::{CookieJar, Status};
 use rocket::serde::json::Json;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::response::OpenApiResponderInner;
+use rocket_okapi::util::add_schema_response;
 use rocket_okapi::{
     okapi::openapi3::OpenApi, openapi, openapi_get_routes_spec, settings::OpenApiSettings,
 };
@@ -15,8 +23,9 @@ use rocket_okapi::{
 #[openapi(tag = "Users")]
 #[post("/", data = "<new_user>")]
 async fn create(
-    actor: GithubOAuthRegistrar,
+    actor: OAuthRegistrar,
     db: Db,
+    mailer: &MailerState,
     ua: UserAgent,
     cookies: &CookieJar<'_>,
     new_user: Json<NewUser>,
@@ -30,16 +39,11 @@ async fn create(
         .await
         .ok_or_else(|| error("", Status::InternalServerError, ""))?;
 
-    // link user to GitHub OAuth account
-    GitHubOAuthUser::save(
-        &db,
-        GitHubOAuthUser {
-            user_id: user.id,
-            github_id: actor.github_id,
-        },
-    )
-    .await
-    .ok_or_else(|| error("", Status::InternalServerError, ""))?;
+    // link user to the account of the OAuth provider
+    actor
+        .link(&db, user.id)
+        .await
+        .ok_or_else(|| error("", Status::InternalServerError, ""))?;
 
     // add administrator rights if user was first user
     if user.is_first(&db).await {
@@ -48,23 +52,18 @@ async fn create(
             .map_err(|e| error(e, Status::InternalServerError, ""))?;
     }
 
+    // send the link to verify the email address, the user may request another one if it fails
+    EmailVerification::request(&db, mailer, user.id).await.ok();
+
     // fetch permissions of the given user
     let auth_user = AuthUser::by_user_id(&db, user.id)
         .await
         .ok_or_else(|| error("", Status::InternalServerError, ""))?;
 
-    // revoke GitHub ID session
+    // revoke OAuth registration session
     session::revoke(cookies).await;
     // save authenticated user session
-    Session::save(&db, cookies, ua, auth_user.clone())
-        .await
-        .map_err(|e| {
-            error(
-                e,
-                Status::InternalServerError,
-                "Error saving session, please try again later",
-            )
-        })?;
+    Session::save(&db, cookies, ua, auth_user.clone()).await?;
 
     Ok(Json(auth_user))
 }
@@ -105,16 +104,31 @@ async fn update(
     actor: AuthUser,
     oso: &OsoState,
     db: Db,
+    mailer: &MailerState,
     id: i32,
     new_user: Json<NewUser>,
 ) -> ApiResult<Json<User>> {
+    let in_session = require_session(&actor);
     if oso.is_allowed(actor, OsoAction::Update, User::dummy(id)) {
         // validate user object
         new_user.clone().is_valid()?;
 
-        User::update_and_return(&db, id, new_user.clone())
+        let previous = User::find_by_id(&db, id)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find user"))?;
+        // the email address is a login method, since login links are sent to it
+        if new_user.email != previous.email {
+            in_session?;
+        }
+        let user = User::update_and_return(&db, id, new_user.clone())
             .await
-            .ok_or_else(|| error("", Status::InternalServerError, ""))
+            .ok_or_else(|| error("", Status::InternalServerError, ""))?;
+
+        // a changed email address has to be verified again
+        if user.email != previous.email {
+            EmailVerification::request(&db, mailer, id).await.ok();
+        }
+        Ok(user)
     } else {
         Err(error("", Status::Forbidden, "Forbidden"))
     }
@@ -134,11 +148,8 @@ async fn delete(actor: AuthUser, oso: &OsoState, db: Db, id: i32) -> ApiResult<(
 
 #[openapi(tag = "Users")]
 #[get("/profile")]
-async fn profile(cookies: &CookieJar<'_>) -> ApiResult<Json<AuthUser>> {
-    session::get_user_from_session(cookies).await.map_or(
-        Err(error("", Status::Forbidden, "You are not logged in")),
-        |u| Ok(Json(u)),
-    )
+async fn profile(actor: AuthUser) -> Json<AuthUser> {
+    Json(actor)
 }
 
 #[openapi(tag = "Users")]
@@ -148,14 +159,418 @@ async fn read_bookings(
     oso: &OsoState,
     db: Db,
     id: i32,
-) -> ApiResult<Json<Vec<Booking>>> {
+) -> ApiResult<Json<Vec<BookingWithAncillaries>>> {
+    if oso.is_allowed(actor, OsoAction::Read, Booking::dummy(id)) {
+        let bookings = Booking::all_from_user(&db, id).await;
+        BookingWithAncillaries::from_bookings(&db, bookings)
+            .await
+            .map(Json)
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Retrieve every flight of the bookings of a user as iCalendar, using the secret token of the
+/// calendar subscription URL instead of a session
+#[openapi(tag = "Users")]
+#[get("/<id>/bookings.ics?<token>")]
+async fn read_bookings_calendar_by_token(db: Db, id: i32, token: String) -> ApiResult<ICalendar> {
+    if CalendarSubscription::verify(&db, id, token).await {
+        Ok(ICalendar(Booking::calendar_from_user(&db, id).await))
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Retrieve every flight of the bookings of a user as iCalendar
+#[openapi(tag = "Users")]
+#[get("/<id>/bookings.ics", rank = 2)]
+async fn read_bookings_calendar(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+) -> ApiResult<ICalendar> {
     if oso.is_allowed(actor, OsoAction::Read, Booking::dummy(id)) {
-        Ok(Json(Booking::all_from_user(&db, id).await))
+        Ok(ICalendar(Booking::calendar_from_user(&db, id).await))
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// Create a secret calendar subscription URL, that works without a session. A previously created
+/// URL stops working.
+#[openapi(tag = "Users")]
+#[post("/<id>/calendar-subscription")]
+async fn rotate_calendar_subscription(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+) -> ApiResult<Json<CalendarSubscription>> {
+    if oso.is_allowed(actor, OsoAction::Update, User::dummy(id)) {
+        CalendarSubscription::rotate(&db, id).await.map(Json)
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+#[openapi(tag = "Users")]
+#[delete("/<id>/calendar-subscription")]
+async fn delete_calendar_subscription(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+) -> ApiResult<()> {
+    if oso.is_allowed(actor, OsoAction::Update, User::dummy(id)) {
+        CalendarSubscription::revoke(&db, id).await
+    } else {
+        Err(error("", Status::Forbidden, "Forbidden"))
+    }
+}
+
+/// An invoice, either as JSON or rendered as PDF
+#[derive(Responder)]
+enum InvoiceResponse {
+    Json(Json<Invoice>),
+    Pdf(Pdf),
+}
+
+impl OpenApiResponderInner for InvoiceResponse {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Pdf::responses(gen)?;
+        add_schema_response(
+            &mut responses,
+            200,
+            "application/json",
+            gen.json_schema::<Invoice>(),
+        )?;
+        Ok(responses)
+    }
+}
+
+/// Retrieve the invoice of a paid booking, or with `supplement` one of its supplementary invoices
+/// for ancillaries bought later on. The invoice is rendered as PDF, if the client prefers
+/// `application/pdf` in its `Accept` header.
+#[openapi(tag = "Users")]
+#[get("/<id>/bookings/<reference>/invoice?<supplement>")]
+async fn read_booking_invoice(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+    supplement: Option<i32>,
+    format: ResponseFormat,
+) -> ApiResult<InvoiceResponse> {
+    if !oso.is_allowed(actor, OsoAction::Read, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    let invoice = match supplement {
+        None | Some(0) => Invoice::find_or_issue(&db, booking.user_id, booking.offer_id).await?,
+        Some(supplement) => Invoice::find(&db, booking.user_id, booking.offer_id, supplement)
+            .await
+            .ok_or_else(|| error("", Status::NotFound, "Cannot find invoice"))?,
+    };
+
+    Ok(match format {
+        ResponseFormat::Pdf => InvoiceResponse::Pdf(Pdf(invoice.to_pdf())),
+        ResponseFormat::Json => InvoiceResponse::Json(Json(invoice)),
+    })
+}
+
+/// List the invoice of a booking along with its supplementary invoices
+#[openapi(tag = "Users")]
+#[get("/<id>/bookings/<reference>/invoices")]
+async fn read_booking_invoices(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+) -> ApiResult<Json<Vec<Invoice>>> {
+    if !oso.is_allowed(actor, OsoAction::Read, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    Ok(Json(
+        Invoice::all_from_booking(&db, booking.user_id, booking.offer_id).await,
+    ))
+}
+
+#[openapi(tag = "Users")]
+#[get("/<id>/bookings/<reference>/passengers")]
+async fn read_booking_passengers(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+) -> ApiResult<Json<Vec<Passenger>>> {
+    if !oso.is_allowed(actor, OsoAction::Read, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    Ok(Json(
+        Passenger::all_from_booking(&db, booking.user_id, booking.offer_id).await,
+    ))
+}
+
+/// Assign a passenger to one of the seats of a booking. The passenger is either given by name and
+/// birthday, or by the id of a saved companion.
+#[openapi(tag = "Users")]
+#[post("/<id>/bookings/<reference>/passengers", data = "<new_passenger>")]
+async fn create_booking_passenger(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+    new_passenger: Json<PassengerDetails>,
+) -> ApiResult<Json<Passenger>> {
+    if !oso.is_allowed(actor, OsoAction::Update, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    let new_passenger = new_passenger.into_inner().resolve(&db, id).await?;
+    Passenger::add(&db, &booking, new_passenger).await.map(Json)
+}
+
+/// List the travel documents of a passenger. Document numbers are redacted, unless the current user
+/// is an administrator.
+#[openapi(tag = "Users")]
+#[get("/<id>/bookings/<reference>/passengers/<passenger_id>/documents")]
+async fn read_passenger_documents(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+    passenger_id: i32,
+) -> ApiResult<Json<Vec<TravelDocument>>> {
+    let is_admin = actor.roles.contains(&Role::Admin);
+    if !oso.is_allowed(actor, OsoAction::Read, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    Passenger::all_from_booking(&db, booking.user_id, booking.offer_id)
+        .await
+        .iter()
+        .find(|p| p.id == passenger_id)
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find passenger"))?;
+
+    let documents = TravelDocument::all_from_passenger(&db, passenger_id).await?;
+    Ok(Json(if is_admin {
+        documents
+    } else {
+        documents
+            .into_iter()
+            .map(TravelDocument::redacted)
+            .collect()
+    }))
+}
+
+/// Store a travel document of a passenger, that is valid on the date of travel. The document number
+/// is encrypted and redacted in the response, unless the current user is an administrator.
+#[openapi(tag = "Users")]
+#[post(
+    "/<id>/bookings/<reference>/passengers/<passenger_id>/documents",
+    data = "<new_document>"
+)]
+async fn create_passenger_document(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+    passenger_id: i32,
+    new_document: Json<NewTravelDocument>,
+) -> ApiResult<Json<TravelDocument>> {
+    let is_admin = actor.roles.contains(&Role::Admin);
+    if !oso.is_allowed(actor, OsoAction::Update, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    let document =
+        TravelDocument::create(&db, &booking, passenger_id, new_document.into_inner()).await?;
+    Ok(Json(if is_admin {
+        document
+    } else {
+        document.redacted()
+    }))
+}
+
+/// Buy ancillaries for a booking. Ancillaries of a paid booking are charged right away, using the
+/// given payment token.
+#[openapi(tag = "Users")]
+#[post("/<id>/bookings/<reference>/ancillaries", data = "<purchase>")]
+async fn create_booking_ancillaries(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    gateway: &PaymentState,
+    id: i32,
+    reference: String,
+    purchase: Json<AncillaryPurchase>,
+) -> ApiResult<Json<Vec<BookedAncillary>>> {
+    if !oso.is_allowed(actor, OsoAction::Update, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    Booking::add_ancillaries(&db, gateway, &booking, purchase.into_inner())
+        .await
+        .map(Json)
+}
+
+/// Redeem loyalty points against the price of a booking, that is awaiting payment
+#[openapi(tag = "Users")]
+#[post(
+    "/<id>/bookings/<reference>/loyalty-redemptions",
+    data = "<redemption>"
+)]
+async fn create_booking_loyalty_redemption(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+    redemption: Json<LoyaltyRedemption>,
+) -> ApiResult<Json<LoyaltyTransaction>> {
+    if !oso.is_allowed(actor, OsoAction::Update, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    LoyaltyTransaction::redeem(&db, &booking, redemption.into_inner())
+        .await
+        .map(Json)
+}
+
+/// Show the balance and tier of the loyalty points of a user, along with every transaction
+#[openapi(tag = "Users")]
+#[get("/<id>/loyalty")]
+async fn read_loyalty(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+) -> ApiResult<Json<LoyaltyAccount>> {
+    if oso.is_allowed(actor, OsoAction::Read, User::dummy(id)) {
+        LoyaltyTransaction::account(&db, id).await.map(Json)
     } else {
         Err(error("", Status::Forbidden, "Forbidden"))
     }
 }
 
+/// Check in a passenger for one of the flights of a paid booking. The check-in opens
+/// `CHECKIN_OPENS_HOURS` before departure and closes `CHECKIN_CLOSES_MINUTES` before departure.
+#[allow(clippy::too_many_arguments)]
+#[openapi(tag = "Users")]
+#[post(
+    "/<id>/bookings/<reference>/passengers/<passenger_id>/flights/<flight_id>/check-in",
+    data = "<new_check_in>"
+)]
+async fn create_passenger_check_in(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+    passenger_id: i32,
+    flight_id: i32,
+    new_check_in: Json<NewCheckIn>,
+) -> ApiResult<Json<CheckIn>> {
+    if !oso.is_allowed(actor, OsoAction::Update, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    CheckIn::create(
+        &db,
+        &booking,
+        passenger_id,
+        flight_id,
+        new_check_in.into_inner(),
+    )
+    .await
+    .map(Json)
+}
+
+/// Tickets, either as JSON or rendered as PDF
+#[derive(Responder)]
+enum TicketsResponse {
+    Json(Json<Vec<Ticket>>),
+    Pdf(Pdf),
+}
+
+impl OpenApiResponderInner for TicketsResponse {
+    fn responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        let mut responses = Pdf::responses(gen)?;
+        add_schema_response(
+            &mut responses,
+            200,
+            "application/json",
+            gen.json_schema::<Vec<Ticket>>(),
+        )?;
+        Ok(responses)
+    }
+}
+
+/// Retrieve the e-tickets of a paid booking, one for every passenger on every flight. The tickets
+/// are rendered as PDF boarding passes with a BCBP QR code, if the client prefers
+/// `application/pdf` in its `Accept` header.
+#[openapi(tag = "Users")]
+#[get("/<id>/bookings/<reference>/tickets")]
+async fn read_booking_tickets(
+    actor: AuthUser,
+    oso: &OsoState,
+    db: Db,
+    id: i32,
+    reference: String,
+    format: ResponseFormat,
+) -> ApiResult<TicketsResponse> {
+    if !oso.is_allowed(actor, OsoAction::Read, Booking::dummy(id)) {
+        return Err(error("", Status::Forbidden, "Forbidden"));
+    }
+
+    let booking = Booking::find_by_reference(&db, id, reference)
+        .await
+        .ok_or_else(|| error("", Status::NotFound, "Cannot find booking"))?;
+    let tickets = Ticket::all_from_booking(&db, &booking).await?;
+
+    Ok(match format {
+        ResponseFormat::Pdf => TicketsResponse::Pdf(Pdf(Ticket::to_pdf(&tickets)?)),
+        ResponseFormat::Json => TicketsResponse::Json(Json(tickets)),
+    })
+}
+
 #[openapi(tag = "Login")]
 #[post("/logout")]
 async fn logout(db: Db, cookies: &CookieJar<'_>) -> ApiResult<()> {
@@ -171,6 +586,21 @@ pub fn get_routes_and_docs(settings: &OpenApiSettings) -> (Vec<rocket::Route>, O
         update,
         delete,
         read_bookings,
+        read_bookings_calendar_by_token,
+        read_bookings_calendar,
+        rotate_calendar_subscription,
+        delete_calendar_subscription,
+        read_booking_invoice,
+        read_booking_invoices,
+        read_booking_passengers,
+        create_booking_passenger,
+        create_booking_ancillaries,
+        create_booking_loyalty_redemption,
+        read_loyalty,
+        read_passenger_documents,
+        create_passenger_document,
+        create_passenger_check_in,
+        read_booking_tickets,
         profile,
         logout
     ]
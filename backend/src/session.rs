@@ -1,24 +1,64 @@
-use crate::db::models::AuthUser;
+use crate::db::models::{AuthUser, OAuthRegistrar};
+use crate::oidc::LoginAttempt;
+use crate::webauthn::PendingCeremony;
 use crate::CONFIG;
 use async_redis_session::RedisSessionStore;
 use async_session::{Session, SessionStore};
 use once_cell::sync::Lazy;
 use rocket::http::{Cookie, CookieJar, SameSite};
-use rocket::serde::Serialize;
+use rocket::serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
 
-pub static REDIS: Lazy<RedisSessionStore> = Lazy::new(load);
+/// Client for Redis, shared by the session store and commands the store doesn't offer
+static REDIS_CLIENT: Lazy<redis::Client> = Lazy::new(connect);
+
+pub static REDIS: Lazy<RedisSessionStore> =
+    Lazy::new(|| RedisSessionStore::from_client(REDIS_CLIENT.clone()));
 
 const COOKIE_NAME: &str = "session";
 const SESSION_USER_NAME: &str = "user";
-const SESSION_GITHUB_ID_NAME: &str = "github_id";
+const SESSION_OAUTH_REGISTRAR_NAME: &str = "oauth_registrar";
+const OAUTH_COOKIE_NAME: &str = "oauth";
+const SESSION_OAUTH_ATTEMPTS_NAME: &str = "oauth_attempts";
+/// Seconds a login with an OAuth provider may take
+const OAUTH_ATTEMPT_SECONDS: u64 = 600;
+const SECOND_FACTOR_COOKIE_NAME: &str = "second_factor";
+const SESSION_SECOND_FACTOR_NAME: &str = "second_factor";
+/// Seconds a user has to enter the second factor after the first one
+const SECOND_FACTOR_SECONDS: u64 = 300;
+
+const WEBAUTHN_COOKIE_NAME: &str = "webauthn";
+const SESSION_WEBAUTHN_NAME: &str = "webauthn_ceremony";
+/// Seconds a passkey ceremony may take
+const WEBAUTHN_SECONDS: u64 = 300;
+
+/// A login, that passed the first factor and awaits the second one
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PendingLogin {
+    pub user_id: i32,
+}
 
 /// initialize redis with connection string from config
-fn load() -> RedisSessionStore {
+fn connect() -> redis::Client {
     let url = CONFIG
         .redis_url
         .as_ref()
         .expect("Failed to connect to Redis");
-    RedisSessionStore::new(url.to_string()).unwrap()
+    redis::Client::open(url.as_str()).unwrap()
+}
+
+/// Delete a session from Redis. Returns whether the session still existed, so of several
+/// requests deleting the same session only one gets `true`.
+async fn delete_session(session: &Session) -> Option<bool> {
+    let mut connection = REDIS_CLIENT.get_async_connection().await.ok()?;
+    let deleted: u32 = redis::cmd("DEL")
+        .arg(session.id())
+        .query_async(&mut connection)
+        .await
+        .ok()?;
+    Some(deleted == 1)
 }
 
 /// safe a secure cookie to the users browsers
@@ -81,17 +121,142 @@ pub async fn set_user(cookies: &CookieJar<'_>, user: AuthUser) -> String {
     redis_key
 }
 
-/// Try to find an existing user by its session.
+/// Remember the account of an OAuth provider, that isn't linked to a user yet, until the user
+/// has registered.
 ///
 /// # Arguments
 ///
 /// * `cookies` - The cookie jar of the users browser, which holds the redis key
-/// * `id` - The users GitHub ID
-pub async fn set_github_id(cookies: &CookieJar<'_>, id: i32) {
-    let redis_key = add_redis_session(SESSION_GITHUB_ID_NAME, id).await;
+/// * `registrar` - The provider and subject of the account
+pub async fn set_oauth_registrar(cookies: &CookieJar<'_>, registrar: OAuthRegistrar) {
+    let redis_key = add_redis_session(SESSION_OAUTH_REGISTRAR_NAME, registrar).await;
     add_browser_cookie(cookies, COOKIE_NAME, redis_key.unwrap());
 }
 
+/// Store the state and PKCE verifier of a login with every OAuth provider, until the provider
+/// redirects back or the attempt expires. The session is bound to the browser by a separate
+/// cookie, so it doesn't replace a session of a logged in user.
+///
+/// # Arguments
+///
+/// * `cookies` - The cookie jar of the users browser
+/// * `attempts` - The login attempts by name of the provider
+pub async fn set_oauth_attempts(cookies: &CookieJar<'_>, attempts: HashMap<String, LoginAttempt>) {
+    let mut session = Session::new();
+    session.expire_in(Duration::from_secs(OAUTH_ATTEMPT_SECONDS));
+    session
+        .insert(SESSION_OAUTH_ATTEMPTS_NAME, attempts)
+        .unwrap();
+    if let Some(redis_key) = REDIS.store_session(session).await.unwrap() {
+        add_browser_cookie(cookies, OAUTH_COOKIE_NAME, redis_key);
+    }
+}
+
+/// Retrieve the login attempt with a provider and destroy the stored attempts, so the state can't
+/// be used twice. Returns `None`, if there's no attempt, it has expired or a concurrent request
+/// has taken the attempts first.
+///
+/// # Arguments
+///
+/// * `cookies` - The cookie jar of the users browser
+/// * `provider` - The name of the provider
+pub async fn take_oauth_attempt(cookies: &CookieJar<'_>, provider: &str) -> Option<LoginAttempt> {
+    let key = cookies
+        .get_private(OAUTH_COOKIE_NAME)
+        .map(|c| c.value().to_string())?;
+    cookies.remove_private(Cookie::named(OAUTH_COOKIE_NAME));
+
+    let session = get_redis_session(key).await?;
+    let mut attempts: HashMap<String, LoginAttempt> = session.get(SESSION_OAUTH_ATTEMPTS_NAME)?;
+    delete_session(&session)
+        .await?
+        .then(|| attempts.remove(provider))?
+}
+
+/// Remember a user, that passed the first factor of a login, until the second factor is entered
+/// or the login expires. No user session is created until then.
+///
+/// # Arguments
+///
+/// * `cookies` - The cookie jar of the users browser
+/// * `user_id` - The id of the user logging in
+pub async fn set_pending_login(cookies: &CookieJar<'_>, user_id: i32) {
+    let mut session = Session::new();
+    session.expire_in(Duration::from_secs(SECOND_FACTOR_SECONDS));
+    session
+        .insert(SESSION_SECOND_FACTOR_NAME, PendingLogin { user_id })
+        .unwrap();
+    if let Some(redis_key) = REDIS.store_session(session).await.unwrap() {
+        add_browser_cookie(cookies, SECOND_FACTOR_COOKIE_NAME, redis_key);
+    }
+}
+
+async fn get_pending_session(cookies: &CookieJar<'_>) -> Option<(Session, PendingLogin)> {
+    let key = cookies
+        .get_private(SECOND_FACTOR_COOKIE_NAME)
+        .map(|c| c.value().to_string())?;
+    let session = get_redis_session(key).await?;
+    let pending = session.get(SESSION_SECOND_FACTOR_NAME)?;
+    Some((session, pending))
+}
+
+/// Try to find a login awaiting the second factor. Returns `None`, if there's no such login or it
+/// has expired.
+///
+/// # Arguments
+///
+/// * `cookies` - The cookie jar of the users browser
+pub async fn get_pending_login(cookies: &CookieJar<'_>) -> Option<PendingLogin> {
+    get_pending_session(cookies)
+        .await
+        .map(|(_session, pending)| pending)
+}
+
+/// Destroy a pending login, once the second factor was entered
+///
+/// # Arguments
+///
+/// * `cookies` - The cookie jar of the users browser
+pub async fn finish_pending_login(cookies: &CookieJar<'_>) {
+    if let Some((session, _pending)) = get_pending_session(cookies).await {
+        REDIS.destroy_session(session).await.ok();
+    }
+    cookies.remove_private(Cookie::named(SECOND_FACTOR_COOKIE_NAME));
+}
+
+/// Store the challenge of a passkey ceremony until the authenticator responds. A browser has only
+/// one ceremony at a time, starting another one replaces it.
+///
+/// # Arguments
+///
+/// * `cookies` - The cookie jar of the users browser
+/// * `pending` - The ceremony along with its challenge
+pub async fn set_webauthn_ceremony(cookies: &CookieJar<'_>, pending: PendingCeremony) {
+    let mut session = Session::new();
+    session.expire_in(Duration::from_secs(WEBAUTHN_SECONDS));
+    session.insert(SESSION_WEBAUTHN_NAME, pending).unwrap();
+    if let Some(redis_key) = REDIS.store_session(session).await.unwrap() {
+        add_browser_cookie(cookies, WEBAUTHN_COOKIE_NAME, redis_key);
+    }
+}
+
+/// Retrieve the passkey ceremony of the browser and destroy it, so its challenge can't be used
+/// twice. Returns `None`, if there's no ceremony, it has expired or a concurrent request took it.
+///
+/// # Arguments
+///
+/// * `cookies` - The cookie jar of the users browser
+pub async fn take_webauthn_ceremony(cookies: &CookieJar<'_>) -> Option<PendingCeremony> {
+    let key = cookies
+        .get_private(WEBAUTHN_COOKIE_NAME)
+        .map(|c| c.value().to_string())?;
+    cookies.remove_private(Cookie::named(WEBAUTHN_COOKIE_NAME));
+
+    let session = get_redis_session(key).await?;
+    let pending = session.get(SESSION_WEBAUTHN_NAME)?;
+    delete_session(&session).await?.then(|| pending)
+}
+
 /// Try to find an existing user by its session.
 ///
 /// # Arguments
@@ -102,14 +267,16 @@ pub async fn get_user_from_session(cookies: &CookieJar<'_>) -> Option<AuthUser>
     get_redis_session(key).await?.get(SESSION_USER_NAME)
 }
 
-/// Try to find an existing user by its session.
+/// Try to find the account of an OAuth provider, that is about to register, by its session.
 ///
 /// # Arguments
 ///
 /// * `cookies` - The cookie jar of the users browser
-pub async fn get_github_id(cookies: &CookieJar<'_>) -> Option<i32> {
+pub async fn get_oauth_registrar(cookies: &CookieJar<'_>) -> Option<OAuthRegistrar> {
     let key = get_redis_session_key(cookies)?;
-    get_redis_session(key).await?.get(SESSION_GITHUB_ID_NAME)
+    get_redis_session(key)
+        .await?
+        .get(SESSION_OAUTH_REGISTRAR_NAME)
 }
 
 /// Destroy the current user session. Session is removed in redis and the users browser
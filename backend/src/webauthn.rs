@@ -0,0 +1,735 @@
+//! Passkeys (WebAuthn) as first or second factor of a login. The relying party is configured with
+//! `WEBAUTHN_RP_ID`, the domain of the frontend, and `WEBAUTHN_ORIGIN`, the origin the frontend is
+//! served from. Only ES256 credentials without attestation are supported, which every platform
+//! authenticator and security key offers.
+//!
+//! Binary values are exchanged as base64url strings, like `PublicKeyCredential.toJSON()` does.
+
+use crate::CONFIG;
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use rand::Rng;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde_cbor::Value;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Name of the service shown by authenticators
+const RP_NAME: &str = "SkyRocket";
+/// Random bytes of a challenge
+const CHALLENGE_LENGTH: usize = 32;
+/// Milliseconds the browser waits for the authenticator
+const TIMEOUT_MILLISECONDS: u32 = 300_000;
+/// COSE algorithm identifier of ECDSA with P-256 and SHA-256
+const ES256: i128 = -7;
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Errors that can occur while verifying a response of an authenticator
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebauthnError {
+    /// `WEBAUTHN_RP_ID` or `WEBAUTHN_ORIGIN` isn't configured
+    MissingConfig,
+    /// A value isn't valid base64url, CBOR or JSON
+    Malformed(String),
+    /// The client data doesn't belong to this ceremony, challenge or origin
+    InvalidClientData(String),
+    /// The authenticator data doesn't belong to this relying party or lacks user presence
+    InvalidAuthenticatorData(String),
+    /// The credential uses another algorithm than ES256, or an attestation
+    UnsupportedCredential(String),
+    InvalidSignature,
+    /// The signature counter didn't increase, the authenticator may have been cloned
+    CounterRegression,
+}
+
+impl fmt::Display for WebauthnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebauthnError::MissingConfig => {
+                write!(f, "WEBAUTHN_RP_ID or WEBAUTHN_ORIGIN is not configured")
+            }
+            WebauthnError::Malformed(e) => write!(f, "malformed response: {}", e),
+            WebauthnError::InvalidClientData(e) => write!(f, "invalid client data: {}", e),
+            WebauthnError::InvalidAuthenticatorData(e) => {
+                write!(f, "invalid authenticator data: {}", e)
+            }
+            WebauthnError::UnsupportedCredential(e) => write!(f, "unsupported credential: {}", e),
+            WebauthnError::InvalidSignature => write!(f, "invalid signature"),
+            WebauthnError::CounterRegression => write!(f, "signature counter did not increase"),
+        }
+    }
+}
+
+pub type WebauthnResult<T> = Result<T, WebauthnError>;
+
+fn decode(value: &str) -> WebauthnResult<Vec<u8>> {
+    base64::decode_config(value.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+        .map_err(|e| WebauthnError::Malformed(e.to_string()))
+}
+
+pub fn encode(value: &[u8]) -> String {
+    base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+}
+
+/// What a ceremony was started for, so a response can't be used for another purpose
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub enum Ceremony {
+    /// Registration of a passkey by the user with the given id
+    Registration(i32),
+    /// Login with a passkey as first factor
+    Login,
+    /// Second factor of a pending login of the user with the given id
+    SecondFactor(i32),
+}
+
+/// A ceremony, which is stored in the session store until the authenticator responds
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct PendingCeremony {
+    pub ceremony: Ceremony,
+    pub challenge: String,
+}
+
+impl PendingCeremony {
+    pub fn new(ceremony: Ceremony) -> Self {
+        let mut challenge = [0u8; CHALLENGE_LENGTH];
+        rand::thread_rng().fill(&mut challenge);
+        PendingCeremony {
+            ceremony,
+            challenge: encode(&challenge),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub id: String,
+}
+
+impl CredentialDescriptor {
+    pub fn new(id: String) -> Self {
+        CredentialDescriptor {
+            kind: "public-key".into(),
+            id,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RpEntity {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub alg: i64,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    pub resident_key: String,
+    pub user_verification: String,
+}
+
+/// Options for `navigator.credentials.create()`
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub rp: RpEntity,
+    pub user: UserEntity,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    pub timeout: u32,
+    pub attestation: String,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub authenticator_selection: AuthenticatorSelection,
+}
+
+/// Options for `navigator.credentials.get()`
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub timeout: u32,
+    pub rp_id: String,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub user_verification: String,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+/// The response of `navigator.credentials.create()`
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct RegistrationResponse {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+/// The response of `navigator.credentials.get()`
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AuthenticationResponse {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    /// The attested credential data and extensions
+    rest: &'a [u8],
+}
+
+/// A credential, that passed the registration ceremony
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedCredential {
+    /// The id of the credential in base64url
+    pub credential_id: String,
+    /// The public key as uncompressed SEC1 point
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+/// An assertion, that passed the authentication ceremony
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedAssertion {
+    pub sign_count: u32,
+    /// Set, if the authenticator verified the user, like by PIN or biometrics
+    pub user_verified: bool,
+}
+
+/// Parse a COSE key (RFC 8152) and return it as uncompressed SEC1 point. Trailing extension data
+/// is ignored.
+fn parse_cose_key(data: &[u8]) -> WebauthnResult<Vec<u8>> {
+    let mut deserializer = serde_cbor::Deserializer::from_slice(data);
+    let key = Value::deserialize(&mut deserializer)
+        .map_err(|e| WebauthnError::Malformed(e.to_string()))?;
+    let map = match key {
+        Value::Map(map) => map,
+        _ => return Err(WebauthnError::Malformed("COSE key is not a map".into())),
+    };
+    let get = |label: i128| map.get(&Value::Integer(label));
+
+    match (get(1), get(3), get(-1)) {
+        (Some(Value::Integer(2)), Some(Value::Integer(ES256)), Some(Value::Integer(1))) => {}
+        _ => {
+            return Err(WebauthnError::UnsupportedCredential(
+                "only ES256 keys are supported".into(),
+            ))
+        }
+    }
+    let coordinate = |label: i128| match get(label) {
+        Some(Value::Bytes(bytes)) if bytes.len() == 32 => Ok(bytes.clone()),
+        _ => Err(WebauthnError::Malformed("invalid coordinate".into())),
+    };
+
+    let point = [vec![0x04], coordinate(-2)?, coordinate(-3)?].concat();
+    VerifyingKey::from_sec1_bytes(&point)
+        .map_err(|_| WebauthnError::UnsupportedCredential("invalid public key".into()))?;
+    Ok(point)
+}
+
+/// The relying party, which verifies the responses of authenticators
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    pub fn from_config() -> WebauthnResult<Self> {
+        match (&CONFIG.webauthn_rp_id, &CONFIG.webauthn_origin) {
+            (Some(id), Some(origin)) => Ok(RelyingParty {
+                id: id.clone(),
+                origin: origin.clone(),
+            }),
+            _ => Err(WebauthnError::MissingConfig),
+        }
+    }
+
+    pub fn creation_options(
+        &self,
+        pending: &PendingCeremony,
+        user: UserEntity,
+        exclude_credentials: Vec<CredentialDescriptor>,
+    ) -> CreationOptions {
+        CreationOptions {
+            rp: RpEntity {
+                id: self.id.clone(),
+                name: RP_NAME.into(),
+            },
+            user,
+            challenge: pending.challenge.clone(),
+            pub_key_cred_params: vec![CredentialParameters {
+                kind: "public-key".into(),
+                alg: ES256 as i64,
+            }],
+            timeout: TIMEOUT_MILLISECONDS,
+            attestation: "none".into(),
+            exclude_credentials,
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "preferred".into(),
+                user_verification: "preferred".into(),
+            },
+        }
+    }
+
+    pub fn request_options(
+        &self,
+        pending: &PendingCeremony,
+        allow_credentials: Vec<CredentialDescriptor>,
+    ) -> RequestOptions {
+        RequestOptions {
+            challenge: pending.challenge.clone(),
+            timeout: TIMEOUT_MILLISECONDS,
+            rp_id: self.id.clone(),
+            allow_credentials,
+            user_verification: "preferred".into(),
+        }
+    }
+
+    fn check_client_data(
+        &self,
+        client_data: &[u8],
+        kind: &str,
+        challenge: &str,
+    ) -> WebauthnResult<()> {
+        let client_data: ClientData = rocket::serde::json::from_slice(client_data)
+            .map_err(|e| WebauthnError::Malformed(e.to_string()))?;
+        if client_data.kind != kind {
+            return Err(WebauthnError::InvalidClientData(format!(
+                "unexpected type {}",
+                client_data.kind
+            )));
+        }
+        if client_data.challenge != challenge {
+            return Err(WebauthnError::InvalidClientData(
+                "challenge mismatch".into(),
+            ));
+        }
+        if client_data.origin != self.origin {
+            return Err(WebauthnError::InvalidClientData(format!(
+                "unexpected origin {}",
+                client_data.origin
+            )));
+        }
+        Ok(())
+    }
+
+    fn parse_authenticator_data<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> WebauthnResult<AuthenticatorData<'a>> {
+        if data.len() < 37 {
+            return Err(WebauthnError::Malformed(
+                "authenticator data is too short".into(),
+            ));
+        }
+        if data[..32] != Sha256::digest(self.id.as_bytes())[..] {
+            return Err(WebauthnError::InvalidAuthenticatorData(
+                "RP ID hash mismatch".into(),
+            ));
+        }
+        let flags = data[32];
+        if flags & FLAG_USER_PRESENT == 0 {
+            return Err(WebauthnError::InvalidAuthenticatorData(
+                "user wasn't present".into(),
+            ));
+        }
+        Ok(AuthenticatorData {
+            flags,
+            sign_count: u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+            rest: &data[37..],
+        })
+    }
+
+    /// Verify the response of a registration ceremony with the given challenge
+    pub fn verify_registration(
+        &self,
+        response: &RegistrationResponse,
+        challenge: &str,
+    ) -> WebauthnResult<VerifiedCredential> {
+        let client_data = decode(&response.response.client_data_json)?;
+        self.check_client_data(&client_data, "webauthn.create", challenge)?;
+
+        let attestation: Value =
+            serde_cbor::from_slice(&decode(&response.response.attestation_object)?)
+                .map_err(|e| WebauthnError::Malformed(e.to_string()))?;
+        let attestation = match attestation {
+            Value::Map(map) => map,
+            _ => return Err(WebauthnError::Malformed("attestation is not a map".into())),
+        };
+        match attestation.get(&Value::Text("fmt".into())) {
+            Some(Value::Text(fmt)) if fmt == "none" => {}
+            _ => {
+                return Err(WebauthnError::UnsupportedCredential(
+                    "only attestation format none is supported".into(),
+                ))
+            }
+        }
+        let auth_data = match attestation.get(&Value::Text("authData".into())) {
+            Some(Value::Bytes(bytes)) => bytes,
+            _ => {
+                return Err(WebauthnError::Malformed(
+                    "missing authenticator data".into(),
+                ))
+            }
+        };
+
+        let auth_data = self.parse_authenticator_data(auth_data)?;
+        if auth_data.flags & FLAG_ATTESTED_CREDENTIAL == 0 || auth_data.rest.len() < 18 {
+            return Err(WebauthnError::Malformed(
+                "missing attested credential".into(),
+            ));
+        }
+        // skip the AAGUID, since there's no attestation to trust it
+        let length = u16::from_be_bytes([auth_data.rest[16], auth_data.rest[17]]) as usize;
+        if auth_data.rest.len() < 18 + length {
+            return Err(WebauthnError::Malformed(
+                "credential id is too short".into(),
+            ));
+        }
+        let credential_id = encode(&auth_data.rest[18..18 + length]);
+        if credential_id != response.id.trim_end_matches('=') {
+            return Err(WebauthnError::Malformed("credential id mismatch".into()));
+        }
+
+        Ok(VerifiedCredential {
+            credential_id,
+            public_key: parse_cose_key(&auth_data.rest[18 + length..])?,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Verify the response of an authentication ceremony with the given challenge, against the
+    /// public key and the last signature counter of the registered credential
+    pub fn verify_authentication(
+        &self,
+        response: &AuthenticationResponse,
+        challenge: &str,
+        public_key: &[u8],
+        sign_count: u32,
+    ) -> WebauthnResult<VerifiedAssertion> {
+        let client_data = decode(&response.response.client_data_json)?;
+        self.check_client_data(&client_data, "webauthn.get", challenge)?;
+
+        let raw_auth_data = decode(&response.response.authenticator_data)?;
+        let auth_data = self.parse_authenticator_data(&raw_auth_data)?;
+
+        let key = VerifyingKey::from_sec1_bytes(public_key)
+            .map_err(|_| WebauthnError::UnsupportedCredential("invalid public key".into()))?;
+        let signature = Signature::from_der(&decode(&response.response.signature)?)
+            .map_err(|_| WebauthnError::InvalidSignature)?;
+        let signed = [&raw_auth_data[..], &Sha256::digest(&client_data)[..]].concat();
+        key.verify(&signed, &signature)
+            .map_err(|_| WebauthnError::InvalidSignature)?;
+
+        // authenticators without a counter always report 0
+        if (auth_data.sign_count != 0 || sign_count != 0) && auth_data.sign_count <= sign_count {
+            return Err(WebauthnError::CounterRegression);
+        }
+
+        Ok(VerifiedAssertion {
+            sign_count: auth_data.sign_count,
+            user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::SigningKey;
+    use rand::rngs::OsRng;
+    use rocket::serde::json::json;
+    use std::collections::BTreeMap;
+
+    const RP_ID: &str = "skyrocket.example";
+    const ORIGIN: &str = "https://skyrocket.example";
+
+    fn relying_party() -> RelyingParty {
+        RelyingParty {
+            id: RP_ID.into(),
+            origin: ORIGIN.into(),
+        }
+    }
+
+    /// A software authenticator, that creates ES256 credentials without attestation
+    struct SoftAuthenticator {
+        key: SigningKey,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+        flags: u8,
+    }
+
+    impl SoftAuthenticator {
+        fn new() -> Self {
+            let mut credential_id = vec![0u8; 16];
+            rand::thread_rng().fill(&mut credential_id[..]);
+            SoftAuthenticator {
+                key: SigningKey::random(&mut OsRng),
+                credential_id,
+                sign_count: 0,
+                flags: FLAG_USER_PRESENT | FLAG_USER_VERIFIED,
+            }
+        }
+
+        fn client_data(kind: &str, challenge: &str, origin: &str) -> Vec<u8> {
+            json!({ "type": kind, "challenge": challenge, "origin": origin })
+                .to_string()
+                .into_bytes()
+        }
+
+        fn authenticator_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+            [
+                &Sha256::digest(rp_id.as_bytes())[..],
+                &[flags],
+                &self.sign_count.to_be_bytes()[..],
+            ]
+            .concat()
+        }
+
+        fn cose_key(&self) -> Vec<u8> {
+            let point = VerifyingKey::from(&self.key).to_encoded_point(false);
+            let mut key = BTreeMap::new();
+            key.insert(Value::Integer(1), Value::Integer(2));
+            key.insert(Value::Integer(3), Value::Integer(ES256));
+            key.insert(Value::Integer(-1), Value::Integer(1));
+            key.insert(
+                Value::Integer(-2),
+                Value::Bytes(point.x().unwrap().to_vec()),
+            );
+            key.insert(
+                Value::Integer(-3),
+                Value::Bytes(point.y().unwrap().to_vec()),
+            );
+            serde_cbor::to_vec(&Value::Map(key)).unwrap()
+        }
+
+        fn register(&self, challenge: &str, origin: &str) -> RegistrationResponse {
+            let auth_data = [
+                self.authenticator_data(RP_ID, self.flags | FLAG_ATTESTED_CREDENTIAL),
+                vec![0u8; 16],
+                (self.credential_id.len() as u16).to_be_bytes().to_vec(),
+                self.credential_id.clone(),
+                self.cose_key(),
+            ]
+            .concat();
+            let mut attestation = BTreeMap::new();
+            attestation.insert(Value::Text("fmt".into()), Value::Text("none".into()));
+            attestation.insert(Value::Text("attStmt".into()), Value::Map(BTreeMap::new()));
+            attestation.insert(Value::Text("authData".into()), Value::Bytes(auth_data));
+
+            RegistrationResponse {
+                id: encode(&self.credential_id),
+                response: AttestationResponse {
+                    client_data_json: encode(&Self::client_data(
+                        "webauthn.create",
+                        challenge,
+                        origin,
+                    )),
+                    attestation_object: encode(
+                        &serde_cbor::to_vec(&Value::Map(attestation)).unwrap(),
+                    ),
+                },
+            }
+        }
+
+        fn authenticate(&mut self, challenge: &str, rp_id: &str) -> AuthenticationResponse {
+            self.sign_count += 1;
+            let client_data = Self::client_data("webauthn.get", challenge, ORIGIN);
+            let auth_data = self.authenticator_data(rp_id, self.flags);
+            let signed = [&auth_data[..], &Sha256::digest(&client_data)[..]].concat();
+            let signature: Signature = self.key.sign(&signed);
+
+            AuthenticationResponse {
+                id: encode(&self.credential_id),
+                response: AssertionResponse {
+                    client_data_json: encode(&client_data),
+                    authenticator_data: encode(&auth_data),
+                    signature: encode(signature.to_der().as_bytes()),
+                    user_handle: None,
+                },
+            }
+        }
+    }
+
+    fn register(authenticator: &SoftAuthenticator) -> VerifiedCredential {
+        let pending = PendingCeremony::new(Ceremony::Registration(1));
+        relying_party()
+            .verify_registration(
+                &authenticator.register(&pending.challenge, ORIGIN),
+                &pending.challenge,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_registration() {
+        let authenticator = SoftAuthenticator::new();
+        let credential = register(&authenticator);
+
+        assert_eq!(
+            encode(&authenticator.credential_id),
+            credential.credential_id
+        );
+        assert_eq!(65, credential.public_key.len());
+        assert_eq!(0, credential.sign_count);
+    }
+
+    #[test]
+    fn test_registration_rejects_wrong_challenge_and_origin() {
+        let authenticator = SoftAuthenticator::new();
+        let pending = PendingCeremony::new(Ceremony::Registration(1));
+        let other = PendingCeremony::new(Ceremony::Registration(1));
+
+        assert!(matches!(
+            relying_party().verify_registration(
+                &authenticator.register(&other.challenge, ORIGIN),
+                &pending.challenge
+            ),
+            Err(WebauthnError::InvalidClientData(_))
+        ));
+        assert!(matches!(
+            relying_party().verify_registration(
+                &authenticator.register(&pending.challenge, "https://evil.example"),
+                &pending.challenge
+            ),
+            Err(WebauthnError::InvalidClientData(_))
+        ));
+    }
+
+    #[test]
+    fn test_authentication() {
+        let mut authenticator = SoftAuthenticator::new();
+        let credential = register(&authenticator);
+        let pending = PendingCeremony::new(Ceremony::Login);
+
+        let assertion = relying_party()
+            .verify_authentication(
+                &authenticator.authenticate(&pending.challenge, RP_ID),
+                &pending.challenge,
+                &credential.public_key,
+                credential.sign_count,
+            )
+            .unwrap();
+        assert_eq!(1, assertion.sign_count);
+        assert!(assertion.user_verified);
+    }
+
+    #[test]
+    fn test_authentication_rejects_other_key_and_rp() {
+        let mut authenticator = SoftAuthenticator::new();
+        let other = register(&SoftAuthenticator::new());
+        let pending = PendingCeremony::new(Ceremony::Login);
+
+        assert_eq!(
+            Err(WebauthnError::InvalidSignature),
+            relying_party().verify_authentication(
+                &authenticator.authenticate(&pending.challenge, RP_ID),
+                &pending.challenge,
+                &other.public_key,
+                0,
+            )
+        );
+        assert!(matches!(
+            relying_party().verify_authentication(
+                &authenticator.authenticate(&pending.challenge, "evil.example"),
+                &pending.challenge,
+                &register(&authenticator).public_key,
+                0,
+            ),
+            Err(WebauthnError::InvalidAuthenticatorData(_))
+        ));
+    }
+
+    #[test]
+    fn test_authentication_rejects_counter_regression() {
+        let mut authenticator = SoftAuthenticator::new();
+        let credential = register(&authenticator);
+        let pending = PendingCeremony::new(Ceremony::Login);
+
+        let response = authenticator.authenticate(&pending.challenge, RP_ID);
+        assert_eq!(
+            Err(WebauthnError::CounterRegression),
+            relying_party().verify_authentication(
+                &response,
+                &pending.challenge,
+                &credential.public_key,
+                5,
+            )
+        );
+    }
+
+    #[test]
+    fn test_authentication_without_user_verification() {
+        let mut authenticator = SoftAuthenticator::new();
+        authenticator.flags = FLAG_USER_PRESENT;
+        let credential = register(&authenticator);
+        let pending = PendingCeremony::new(Ceremony::Login);
+
+        let assertion = relying_party()
+            .verify_authentication(
+                &authenticator.authenticate(&pending.challenge, RP_ID),
+                &pending.challenge,
+                &credential.public_key,
+                credential.sign_count,
+            )
+            .unwrap();
+        assert!(!assertion.user_verified);
+    }
+}
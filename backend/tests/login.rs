@@ -0,0 +1,308 @@
+//! Integration tests of the login. They need the database and Redis of the `.env` file, GitHub is
+//! replaced by a fake OAuth server, that is started by the tests.
+
+use backend::db::models::{
+    AuthUser, Gender, LoginCredentials, LoginLinkRequest, NewCredentials, NewUser, RecoveryCodes,
+    TotpCode, TotpEnrolment,
+};
+use backend::fake_oauth::{FakeOAuthServer, FakeUser};
+use backend::GitHubConfig;
+use chrono::{NaiveDate, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use rocket::http::{Header, Status};
+use rocket::local::asynchronous::{Client, LocalResponse};
+use rocket::serde::json::Value;
+use sha1::Sha1;
+
+const CLIENT_ID: &str = "skyrocket-tests";
+const CLIENT_SECRET: &str = "skyrocket-secret";
+const PASSWORD: &str = "correct horse battery";
+
+/// Start the backend with GitHub pointed to the fake server
+async fn client(server: &FakeOAuthServer) -> Client {
+    let github = GitHubConfig {
+        client_id: CLIENT_ID.into(),
+        client_secret: CLIENT_SECRET.into(),
+        url: server.url.clone(),
+        api_url: server.url.clone(),
+    };
+    Client::tracked(backend::build(Some(github))).await.unwrap()
+}
+
+/// Extract a parameter from the query of a URL
+fn query_param(url: &str, name: &str) -> Option<String> {
+    url.split_once('?')?.1.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+/// Start a login attempt and return the state and PKCE challenge of GitHub
+async fn start_login(client: &Client) -> (String, String) {
+    let providers = client
+        .get("/v1/users/login/oauth")
+        .dispatch()
+        .await
+        .into_json::<Value>()
+        .await
+        .unwrap();
+    let url = providers["github"].as_str().unwrap();
+    (
+        query_param(url, "state").unwrap(),
+        query_param(url, "code_challenge").unwrap(),
+    )
+}
+
+/// Decode a base32 secret (RFC 4648) without padding, like an authenticator app does
+fn base32_decode(encoded: &str) -> Vec<u8> {
+    let mut bits = 0u32;
+    let mut count = 0;
+    let mut bytes = Vec::new();
+    for c in encoded.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'2'..=b'7' => c - b'2' + 26,
+            _ => panic!("invalid base32 character {}", c as char),
+        };
+        bits = (bits << 5 | value as u32) & 0xffff;
+        count += 5;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    bytes
+}
+
+/// Code of an authenticator app (RFC 6238) for the given time step
+fn totp(secret: &[u8], step: i64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).unwrap();
+    mac.update(&(step as u64).to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+    format!("{:06}", binary % 1_000_000)
+}
+
+async fn login_password<'c>(client: &'c Client, email: &str) -> LocalResponse<'c> {
+    client
+        .post("/v1/users/login/password")
+        .header(Header::new("User-Agent", "skyrocket-tests"))
+        .json(&LoginCredentials {
+            email: email.into(),
+            password: PASSWORD.into(),
+        })
+        .dispatch()
+        .await
+}
+
+async fn second_factor<'c>(client: &'c Client, code: &str) -> LocalResponse<'c> {
+    client
+        .post("/v1/users/login/second-factor")
+        .header(Header::new("User-Agent", "skyrocket-tests"))
+        .json(&TotpCode { code: code.into() })
+        .dispatch()
+        .await
+}
+
+/// Request a login link from the given address, with a spoofed `X-Real-IP` header
+async fn request_login_link(client: &Client, email: &str) -> Status {
+    client
+        .post("/v1/users/login/email")
+        .remote("203.0.113.7:4000".parse().unwrap())
+        .header(Header::new(
+            "X-Real-IP",
+            format!("198.51.100.{}", rand::random::<u8>()),
+        ))
+        .json(&LoginLinkRequest {
+            email: email.into(),
+        })
+        .dispatch()
+        .await
+        .status()
+}
+
+async fn login<'c>(client: &'c Client, code: &str, state: &str) -> LocalResponse<'c> {
+    client
+        .post(format!(
+            "/v1/users/login/oauth/github?code={}&state={}",
+            code, state
+        ))
+        .header(Header::new("User-Agent", "skyrocket-tests"))
+        .dispatch()
+        .await
+}
+
+#[rocket::async_test]
+async fn test_github_login_flows() {
+    let server = FakeOAuthServer::spawn(CLIENT_ID, CLIENT_SECRET).await;
+    let client = client(&server).await;
+
+    let id = rand::random::<u32>() as i64;
+    let github_user = FakeUser {
+        id,
+        name: "Ada Lovelace".into(),
+        email: format!("ada-{}@example.com", id),
+    };
+
+    // a new user gets the GitHub data to prefill the registration
+    let (state, challenge) = start_login(&client).await;
+    let code = server.issue_code(github_user.clone(), Some(challenge));
+    let response = login(&client, &code, &state).await;
+    assert_eq!(Status::Ok, response.status());
+    let new_user = response.into_json::<NewUser>().await.unwrap();
+    assert_eq!("Ada", new_user.firstname);
+    assert_eq!("Lovelace", new_user.lastname);
+    assert_eq!(github_user.email, new_user.email);
+
+    // registering links the GitHub account and logs the user in
+    let response = client
+        .post("/v1/users")
+        .header(Header::new("User-Agent", "skyrocket-tests"))
+        .json(&NewUser {
+            birthday: NaiveDate::from_ymd(1990, 12, 10),
+            ..new_user
+        })
+        .dispatch()
+        .await;
+    assert_eq!(Status::Ok, response.status());
+    let registered = response.into_json::<AuthUser>().await.unwrap();
+    let profile = client
+        .get("/v1/users/profile")
+        .dispatch()
+        .await
+        .into_json::<AuthUser>()
+        .await
+        .unwrap();
+    assert_eq!(registered.id, profile.id);
+
+    // a returning user gets a session right away
+    client.post("/v1/users/logout").dispatch().await;
+    let (state, challenge) = start_login(&client).await;
+    let code = server.issue_code(github_user.clone(), Some(challenge));
+    let response = login(&client, &code, &state).await;
+    assert_eq!(Status::Ok, response.status());
+    let user = response.into_json::<AuthUser>().await.unwrap();
+    assert_eq!(registered.id, user.id);
+
+    // the state of an attempt can't be used twice
+    let code = server.issue_code(github_user, None);
+    let response = login(&client, &code, &state).await;
+    assert_eq!(Status::Unauthorized, response.status());
+}
+
+#[rocket::async_test]
+async fn test_second_factor_login() {
+    let client = Client::tracked(backend::build(None)).await.unwrap();
+    let email = format!("ada-{}@example.com", rand::random::<u32>());
+
+    let user = client
+        .post("/v1/users/login/register")
+        .header(Header::new("User-Agent", "skyrocket-tests"))
+        .json(&NewCredentials {
+            user: NewUser {
+                firstname: "Ada".into(),
+                lastname: "Lovelace".into(),
+                email: email.clone(),
+                birthday: NaiveDate::from_ymd(1990, 12, 10),
+                gender: Gender::Female,
+            },
+            password: PASSWORD.into(),
+        })
+        .dispatch()
+        .await
+        .into_json::<AuthUser>()
+        .await
+        .unwrap();
+    let enrolment = client
+        .post(format!("/v1/users/{}/totp", user.id))
+        .dispatch()
+        .await
+        .into_json::<TotpEnrolment>()
+        .await
+        .unwrap();
+    let secret = base32_decode(&enrolment.secret);
+    let step = Utc::now().timestamp() / 30;
+    let recovery_codes = client
+        .post(format!("/v1/users/{}/totp/confirmation", user.id))
+        .json(&TotpCode {
+            code: totp(&secret, step),
+        })
+        .dispatch()
+        .await
+        .into_json::<RecoveryCodes>()
+        .await
+        .unwrap();
+    client.post("/v1/users/logout").dispatch().await;
+
+    // the password only starts the login, there's no session until the second factor
+    let response = login_password(&client, &email).await;
+    assert_eq!(Status::Accepted, response.status());
+    let methods = response.into_json::<Value>().await.unwrap();
+    assert_eq!(vec!["totp"], methods["methods"].as_array().unwrap().clone());
+    let response = client.get("/v1/users/profile").dispatch().await;
+    assert_eq!(Status::Forbidden, response.status());
+
+    // a code can't be used twice, the next one completes the login
+    let response = second_factor(&client, &totp(&secret, step)).await;
+    assert_eq!(Status::Unauthorized, response.status());
+    let response = second_factor(&client, &totp(&secret, step + 1)).await;
+    assert_eq!(Status::Ok, response.status());
+    let profile = client
+        .get("/v1/users/profile")
+        .dispatch()
+        .await
+        .into_json::<AuthUser>()
+        .await
+        .unwrap();
+    assert_eq!(user.id, profile.id);
+
+    // a recovery code works once
+    client.post("/v1/users/logout").dispatch().await;
+    login_password(&client, &email).await;
+    let response = second_factor(&client, &totp(&secret, step + 1)).await;
+    assert_eq!(Status::Unauthorized, response.status());
+    let response = second_factor(&client, &recovery_codes.codes[0]).await;
+    assert_eq!(Status::Ok, response.status());
+    client.post("/v1/users/logout").dispatch().await;
+    login_password(&client, &email).await;
+    let response = second_factor(&client, &recovery_codes.codes[0]).await;
+    assert_eq!(Status::Unauthorized, response.status());
+
+    // wrong codes are counted across logins, until even a valid code is rejected
+    for _ in 0..2 {
+        second_factor(&client, "000000").await;
+    }
+    login_password(&client, &email).await;
+    for _ in 0..2 {
+        second_factor(&client, "000000").await;
+    }
+    let response = second_factor(&client, &recovery_codes.codes[1]).await;
+    assert_eq!(Status::TooManyRequests, response.status());
+}
+
+#[rocket::async_test]
+async fn test_login_link_rate_limits() {
+    let client = Client::tracked(backend::build(None)).await.unwrap();
+    let email = format!("unknown-{}@example.com", rand::random::<u32>());
+
+    // 3 links per address, unknown addresses are limited the same way
+    for _ in 0..3 {
+        assert_eq!(Status::Ok, request_login_link(&client, &email).await);
+    }
+    assert_eq!(
+        Status::TooManyRequests,
+        request_login_link(&client, &email).await
+    );
+
+    // 10 links per IP address of the connection, no matter the X-Real-IP header
+    for i in 0..6 {
+        let email = format!("unknown-{}-{}@example.com", i, rand::random::<u32>());
+        assert_eq!(Status::Ok, request_login_link(&client, &email).await);
+    }
+    let email = format!("unknown-{}@example.com", rand::random::<u32>());
+    assert_eq!(
+        Status::TooManyRequests,
+        request_login_link(&client, &email).await
+    );
+}